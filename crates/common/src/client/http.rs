@@ -1,6 +1,7 @@
 use redgold_schema::message::{Request, Response};
-use redgold_schema::{error_info, structs, ErrorInfoContext, RgResult, SafeOption};
-use redgold_schema::structs::{AboutNodeRequest, AboutNodeResponse, Address, AddressInfo, CurrencyAmount, ErrorInfo, GetActivePartyKeyRequest, GetPeersInfoRequest, HashSearchRequest, HashSearchResponse, NetworkEnvironment, NodeMetadata, PublicKey, Seed, SubmitTransactionRequest, SubmitTransactionResponse, Transaction};
+use redgold_schema::{error_info, error_message, structs, ErrorInfoContext, RgResult, SafeOption};
+use redgold_schema::structs::{AboutNodeRequest, AboutNodeResponse, Address, AddressInfo, CurrencyAmount, ErrorCode, ErrorInfo, GetActivePartyKeyRequest, Hash, GetPeersInfoRequest, HashSearchRequest, HashSearchResponse, NetworkEnvironment, NodeMetadata, ObservationProof, PublicKey, Seed, SubmitTransactionRequest, SubmitTransactionResponse, Transaction, TransactionInfo};
+use crate::client::verify::{score_confirmation, ConfirmationScore, ProofVerifier, SignerDirectory};
 use std::time::Duration;
 use redgold_schema::explorer::DetailedAddress;
 use std::collections::HashMap;
@@ -13,7 +14,8 @@ use redgold_schema::helpers::easy_json::{EasyJson, EasyJsonDeser};
 use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
 use redgold_schema::observability::errors::{EnhanceErrorInfo, Loggable};
 use redgold_schema::party::party_internal_data::PartyInternalData;
-use redgold_schema::proto_serde::ProtoSerde;
+use redgold_schema::proto_serde::{ProtoHashable, ProtoSerde};
+use redgold_schema::seeds::get_seeds_by_env;
 use redgold_schema::util::lang_util::WithMaxLengthString;
 
 
@@ -369,4 +371,80 @@ impl RgHttpClient {
         Ok(self.proto_post_request(request, None, None).await?.hash_search_response.safe_get()?.clone())
     }
 
+    /// Observation transactions are returned as transaction info or as a bare observation
+    /// depending on how the node stored them.
+    pub async fn observation_transaction(&self, hash: &Hash) -> RgResult<Option<Transaction>> {
+        let response = self.query_hash(hash.hex()).await?;
+        Ok(response.transaction_info.and_then(|t| t.transaction).or(response.observation))
+    }
+
+    /// Signers known from the hardcoded seeds, extended with the peers this node reports. Peer
+    /// transactions are verified and only seeds rate peers, so the node can't vouch for itself.
+    pub async fn signer_directory(
+        &self,
+        network: &NetworkEnvironment,
+        verifier: &dyn ProofVerifier
+    ) -> RgResult<SignerDirectory> {
+        let mut directory = SignerDirectory::from_seeds(&get_seeds_by_env(network));
+        let peers = self.get_peers().await?.get_peers_info_response.ok_msg("Missing get_peers_info_response")?;
+        for info in peers.self_info.iter().chain(peers.peer_info.iter()) {
+            if let Some(tx) = info.latest_peer_transaction.as_ref() {
+                directory.with_peer_transaction(tx, verifier).log_error().ok();
+            }
+        }
+        Ok(directory)
+    }
+
+    /// Verify observation proofs for a hash independently of the node that served them. The
+    /// observation transactions are fetched from this node, but are checked against the proofs.
+    pub async fn verify_confirmation(
+        &self,
+        hash: &Hash,
+        proofs: &Vec<ObservationProof>,
+        directory: &SignerDirectory,
+        verifier: &dyn ProofVerifier
+    ) -> RgResult<ConfirmationScore> {
+        let mut observation_txs = HashMap::new();
+        for h in proofs.iter().flat_map(|p| p.observation_hash.as_ref()) {
+            if observation_txs.contains_key(h) {
+                continue;
+            }
+            if let Some(tx) = self.observation_transaction(h).await? {
+                observation_txs.insert(h.clone(), tx);
+            }
+        }
+        Ok(score_confirmation(hash, proofs, &observation_txs, directory, verifier))
+    }
+
+    pub async fn query_hash_verified(
+        &self,
+        hash: &Hash,
+        directory: &SignerDirectory,
+        verifier: &dyn ProofVerifier
+    ) -> RgResult<(TransactionInfo, ConfirmationScore)> {
+        let info = self.query_hash(hash.hex()).await?.transaction_info.ok_msg("Transaction not found")?;
+        let tx = info.transaction.safe_get_msg("Missing transaction")?;
+        if &tx.calculate_hash() != hash {
+            return Err(error_message(ErrorCode::ObservationProofInvalid, "Node returned a different transaction"))
+                .with_detail("hash", hash.hex());
+        }
+        let score = self.verify_confirmation(hash, &info.observation_proofs, directory, verifier).await?;
+        Ok((info, score))
+    }
+
+    /// Submit synchronously and score the observation proofs returned with the response.
+    pub async fn send_transaction_verified(
+        &self,
+        t: &Transaction,
+        directory: &SignerDirectory,
+        verifier: &dyn ProofVerifier
+    ) -> RgResult<(SubmitTransactionResponse, ConfirmationScore)> {
+        let response = self.send_transaction(t, true).await?;
+        let proofs = response.query_transaction_response.as_ref()
+            .map(|q| q.observation_proofs.clone())
+            .unwrap_or_default();
+        let score = self.verify_confirmation(&t.calculate_hash(), &proofs, directory, verifier).await?;
+        Ok((response, score))
+    }
+
 }
\ No newline at end of file
@@ -1 +1,2 @@
-pub mod http;
\ No newline at end of file
+pub mod http;
+pub mod verify;
\ No newline at end of file
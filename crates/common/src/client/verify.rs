@@ -0,0 +1,305 @@
+use redgold_schema::proto_serde::ProtoHashable;
+use redgold_schema::structs::{ErrorCode, Hash, MerkleProof, ObservationProof, PeerId, PeerMetadata, Proof, PublicKey, Seed, State, Transaction};
+use redgold_schema::{error_message, RgResult, SafeOption};
+use std::collections::{HashMap, HashSet};
+
+/// Signature checks are implemented alongside the key types, which depend on this crate.
+pub trait ProofVerifier: Send + Sync {
+    fn verify_proof(&self, proof: &Proof, hash: &Hash) -> RgResult<()>;
+}
+
+fn invalid<T>(msg: impl Into<String>) -> RgResult<T> {
+    Err(error_message(ErrorCode::ObservationProofInvalid, msg))
+}
+
+/// Known node signers and the trust of the peers operating them. Seeds are trusted at their
+/// configured rating, other peers at the ratings seeds have labeled them with.
+#[derive(Clone, Debug, Default)]
+pub struct SignerDirectory {
+    node_peers: HashMap<PublicKey, PeerId>,
+    trust: HashMap<PeerId, f64>,
+    seeds: HashSet<PeerId>,
+}
+
+impl SignerDirectory {
+
+    pub fn from_seeds(seeds: &Vec<Seed>) -> Self {
+        let mut d = Self::default();
+        for s in seeds {
+            let Some(pk) = s.public_key.as_ref() else { continue };
+            let peer = s.peer_id.clone().unwrap_or(PeerId::from_pk(pk.clone()));
+            let trust = s.trust.first().and_then(|t| t.maybe_label()).unwrap_or(0.0).clamp(0.0, 1.0);
+            d.node_peers.insert(pk.clone(), peer.clone());
+            d.trust.insert(peer.clone(), trust);
+            d.seeds.insert(peer);
+        }
+        d
+    }
+
+    pub fn with_trust(&mut self, peer: &PeerId, trust: f64) -> &mut Self {
+        self.trust.insert(peer.clone(), trust.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Add the node keys a peer has declared in its signed peer transaction. If the peer is a
+    /// seed, its labels rate the other peers, scaled by the seed's own trust.
+    pub fn with_peer_transaction(&mut self, tx: &Transaction, verifier: &dyn ProofVerifier) -> RgResult<()> {
+        let pm = tx.peer_data()?;
+        let peer = pm.peer_id.safe_get_msg("Missing peer id")?;
+        let peer_pk = peer.peer_id.safe_get_msg("Missing peer id public key")?;
+        let proofs = tx.inputs.iter().flat_map(|i| i.proof.iter()).collect::<Vec<&Proof>>();
+        if proofs.is_empty() {
+            return invalid("Peer transaction is unsigned");
+        }
+        let signable = tx.signable_hash();
+        for p in proofs {
+            if p.public_key.as_ref() != Some(peer_pk) {
+                return invalid("Peer transaction not signed by its peer id");
+            }
+            verifier.verify_proof(p, &signable)?;
+        }
+        self.with_peer_metadata(&pm);
+        Ok(())
+    }
+
+    fn with_peer_metadata(&mut self, pm: &PeerMetadata) {
+        let Some(peer) = pm.peer_id.as_ref() else { return };
+        // A node key keeps the first peer that claimed it, seeds are added first, so another
+        // peer can't take over a seed's node and its trust by declaring the same key.
+        for nmd in &pm.node_metadata {
+            if let Some(pk) = nmd.public_key.as_ref() {
+                self.node_peers.entry(pk.clone()).or_insert(peer.clone());
+            }
+        }
+        if !self.seeds.contains(peer) {
+            return;
+        }
+        let seed_trust = self.trust_of(peer);
+        for label in &pm.labels {
+            let Some(rated) = label.peer_id.as_ref() else { continue };
+            if self.seeds.contains(rated) {
+                continue;
+            }
+            let rating = label.trust_data.first().and_then(|t| t.maybe_label()).unwrap_or(0.0).clamp(0.0, 1.0);
+            let trust = self.trust.entry(rated.clone()).or_insert(0.0);
+            *trust = trust.max(seed_trust * rating);
+        }
+    }
+
+    pub fn peer_of(&self, node: &PublicKey) -> Option<&PeerId> {
+        self.node_peers.get(node)
+    }
+
+    pub fn trust_of(&self, peer: &PeerId) -> f64 {
+        self.trust.get(peer).cloned().unwrap_or(0.0)
+    }
+
+    pub fn total_trust(&self) -> f64 {
+        self.trust.values().sum()
+    }
+}
+
+/// An observation of the transaction whose inclusion and signature have been checked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifiedObservation {
+    pub node: PublicKey,
+    pub peer: Option<PeerId>,
+    pub state: State,
+    pub observation_hash: Hash,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ConfirmationScore {
+    pub hash: Option<Hash>,
+    pub verified: Vec<VerifiedObservation>,
+    /// Proofs that failed verification, with the reason.
+    pub invalid: Vec<String>,
+    /// Accepted observations from distinct known peers, weighted by trust as a fraction of all
+    /// known trust.
+    pub score: f64,
+}
+
+impl ConfirmationScore {
+    pub fn confirmed(&self, threshold: f64) -> bool {
+        self.score >= threshold
+    }
+}
+
+fn verify_merkle_inclusion(merkle: &MerkleProof, leaf: &Hash) -> RgResult<()> {
+    if merkle.leaf.as_ref() != Some(leaf) {
+        return invalid("Merkle proof leaf does not match observation metadata");
+    }
+    if merkle.nodes.is_empty() || merkle.nodes.len() % 2 != 0 {
+        return invalid("Malformed merkle proof");
+    }
+    merkle.verify()
+}
+
+/// Verify an observation proof for `hash` against the observation transaction it claims to come
+/// from, returning the signing node key. Nothing here relies on the node that served the proof.
+pub fn verify_observation_proof(
+    hash: &Hash,
+    proof: &ObservationProof,
+    observation_tx: &Transaction,
+    verifier: &dyn ProofVerifier,
+) -> RgResult<PublicKey> {
+    let metadata = proof.metadata.safe_get_msg("Missing observation metadata")?;
+    if metadata.observed_hash.as_ref() != Some(hash) {
+        return invalid("Observation is for a different hash");
+    }
+    let merkle = proof.merkle_proof.safe_get_msg("Missing merkle proof")?;
+    verify_merkle_inclusion(merkle, &metadata.calculate_hash())?;
+
+    let observation_hash = proof.observation_hash.safe_get_msg("Missing observation hash")?;
+    if &observation_tx.calculate_hash() != observation_hash {
+        return invalid("Observation transaction does not match observation hash");
+    }
+    if observation_tx.observation()?.merkle_root != merkle.root {
+        return invalid("Merkle root does not match observation transaction");
+    }
+    let signer = proof.proof.safe_get_msg("Missing observation signature")?;
+    let node = observation_tx.observation_public_key()?;
+    if signer.public_key.as_ref() != Some(node) {
+        return invalid("Observation signed by a key other than the observing node");
+    }
+    verifier.verify_proof(signer, &observation_tx.signable_hash())?;
+    Ok(node.clone())
+}
+
+/// Score observation proofs for `hash`, with observation transactions looked up by hash.
+pub fn score_confirmation(
+    hash: &Hash,
+    proofs: &Vec<ObservationProof>,
+    observation_txs: &HashMap<Hash, Transaction>,
+    directory: &SignerDirectory,
+    verifier: &dyn ProofVerifier,
+) -> ConfirmationScore {
+    let mut res = ConfirmationScore { hash: Some(hash.clone()), ..Default::default() };
+    let mut counted = HashSet::new();
+    for p in proofs {
+        let verified = p.observation_hash.as_ref()
+            .and_then(|h| observation_txs.get(h))
+            .ok_or_else(|| error_message(ErrorCode::ObservationProofInvalid, "Observation transaction not found"))
+            .and_then(|tx| verify_observation_proof(hash, p, tx, verifier));
+        let node = match verified {
+            Ok(n) => n,
+            Err(e) => {
+                res.invalid.push(e.message);
+                continue;
+            }
+        };
+        let peer = directory.peer_of(&node).cloned();
+        let state = p.metadata.as_ref().map(|m| m.state()).unwrap_or(State::Pending);
+        if let Some(peer) = peer.as_ref().filter(|_| state == State::Accepted) {
+            if counted.insert(peer.clone()) {
+                res.score += directory.trust_of(peer);
+            }
+        }
+        res.verified.push(VerifiedObservation {
+            node,
+            peer,
+            state,
+            observation_hash: p.observation_hash.clone().expect("verified"),
+        });
+    }
+    let total = directory.total_trust();
+    res.score = if total > 0.0 { res.score / total } else { 0.0 };
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+    use redgold_schema::structs::{Input, Observation, ObservationMetadata, Output, StandardData, UtxoId};
+    use redgold_schema::util::merkle;
+
+    // Signatures are covered by the key implementations, this only checks the signer is present.
+    struct PresentVerifier;
+
+    impl ProofVerifier for PresentVerifier {
+        fn verify_proof(&self, proof: &Proof, _hash: &Hash) -> RgResult<()> {
+            proof.public_key.as_ref().map(|_| ()).ok_or(error_message(ErrorCode::IncorrectSignature, "missing"))
+        }
+    }
+
+    fn observation_tx(node: &PublicKey, observed: &Vec<Hash>) -> Transaction {
+        let observations = observed.iter().map(|h| {
+            let mut m = ObservationMetadata::default();
+            m.observed_hash = Some(h.clone());
+            m.set_state(State::Accepted);
+            m
+        }).collect::<Vec<_>>();
+        let leafs = observations.iter().map(|m| m.calculate_hash()).collect::<Vec<_>>();
+        let parent = UtxoId::new(&Hash::from_string_calculate("parent"), 0);
+        let observation = Observation {
+            merkle_root: Some(merkle::build_root(leafs).expect("root").root),
+            observations,
+            parent_id: Some(parent.clone()),
+            ancestor_merkle_roots: vec![],
+        };
+        let mut input = Input::default();
+        input.utxo_id = Some(parent);
+        input.proof = vec![Proof { signature: None, public_key: Some(node.clone()) }];
+        let mut tx = Transaction::default();
+        tx.inputs.push(input);
+        tx.outputs.push(Output::from_data(StandardData::observation(observation)));
+        tx
+    }
+
+    #[test]
+    fn scores_verified_observations_by_seed_trust() {
+        let hash = Hash::from_string_calculate("tx");
+        let other = Hash::from_string_calculate("other");
+        let seed_node = PublicKey::from_bytes_direct_ecdsa(vec![2; 33]);
+        let unknown_node = PublicKey::from_bytes_direct_ecdsa(vec![3; 33]);
+        let mut seed = Seed::default();
+        seed.public_key = Some(seed_node.clone());
+        seed.trust = vec![redgold_schema::structs::TrustData::from_label(0.8)];
+        let directory = SignerDirectory::from_seeds(&vec![seed]);
+
+        let seed_tx = observation_tx(&seed_node, &vec![hash.clone(), other.clone()]);
+        let unknown_tx = observation_tx(&unknown_node, &vec![hash.clone()]);
+        let mut proofs = seed_tx.build_observation_proofs().expect("proofs");
+        proofs.extend(unknown_tx.build_observation_proofs().expect("proofs"));
+        let txs = vec![seed_tx, unknown_tx].into_iter().map(|t| (t.hash_or(), t)).collect();
+
+        let only_hash = proofs.iter()
+            .filter(|p| p.metadata.as_ref().and_then(|m| m.observed_hash.as_ref()) == Some(&hash))
+            .cloned()
+            .collect::<Vec<_>>();
+        let score = score_confirmation(&hash, &only_hash, &txs, &directory, &PresentVerifier);
+        assert_eq!(score.verified.len(), 2);
+        assert!(score.invalid.is_empty());
+        assert_eq!(score.score, 1.0);
+
+        let mut tampered = only_hash.clone();
+        tampered[0].merkle_proof.as_mut().expect("merkle").leaf = Some(other.clone());
+        let score = score_confirmation(&hash, &tampered, &txs, &directory, &PresentVerifier);
+        assert_eq!(score.invalid.len(), 1);
+        assert_eq!(score.score, 0.0);
+    }
+
+    #[test]
+    fn peer_metadata_does_not_reassign_known_nodes() {
+        let seed_node = PublicKey::from_bytes_direct_ecdsa(vec![2; 33]);
+        let mut seed = Seed::default();
+        seed.public_key = Some(seed_node.clone());
+        seed.trust = vec![redgold_schema::structs::TrustData::from_label(0.8)];
+        let mut directory = SignerDirectory::from_seeds(&vec![seed]);
+        let seed_peer = directory.peer_of(&seed_node).cloned().expect("seed peer");
+
+        let other_node = PublicKey::from_bytes_direct_ecdsa(vec![3; 33]);
+        let other_peer = PeerId::from_pk(PublicKey::from_bytes_direct_ecdsa(vec![4; 33]));
+        let mut pm = PeerMetadata::default();
+        pm.peer_id = Some(other_peer.clone());
+        for pk in [&seed_node, &other_node] {
+            let mut nmd = redgold_schema::structs::NodeMetadata::default();
+            nmd.public_key = Some(pk.clone());
+            pm.node_metadata.push(nmd);
+        }
+        directory.with_peer_metadata(&pm);
+        assert_eq!(directory.peer_of(&seed_node), Some(&seed_peer));
+        assert_eq!(directory.peer_of(&other_node), Some(&other_peer));
+    }
+}
@@ -13,6 +13,9 @@ use tokio_stream::wrappers::IntervalStream;
 use tokio_stream::StreamExt;
 use redgold_schema::helpers::easy_json::EasyJson;
 use redgold_schema::observability::errors::{EnhanceErrorInfo, Loggable};
+use redgold_keys::btc::btc_wallet::{electrum_recent_blocks, get_all_tx_electrum};
+use redgold_schema::structs::Address;
+use itertools::Itertools;
 
 #[derive(Clone, Default)]
 pub struct BtcDaq {
@@ -38,18 +41,47 @@ impl IntervalFoldOrReceive<RgResult<TimestampedBitcoinTransaction>> for BtcDaq {
                     &addrs, t
                 ).log_error();
                 if let Ok(btt) = btt {
-                    self.daq.add_new_tx(btt.clone());
+                    self.daq.record_new_tx(btt.clone(), None).await?;
                 }
             }
             _ => {
-                // TODO: Implement historical backfill if needed
+                self.backfill_historical().await.log_error().ok();
             }
         }
         Ok(())
     }
 }
 
+/// Recent blocks rechecked on every backfill, a reorg deeper than this is not detected.
+pub const BTC_REORG_CHECK_DEPTH: usize = 12;
+
 impl BtcDaq {
+
+    /// Record recent block hashes to detect reorgs, then fetch each subscribed address history
+    /// from electrum, keeping only transactions past the address checkpoint.
+    pub async fn backfill_historical(&mut self) -> RgResult<()> {
+        let network = self.daq.network.clone();
+        let blocks = tokio::task::spawn_blocking(move || electrum_recent_blocks(&network, BTC_REORG_CHECK_DEPTH))
+            .await.error_info("Failed to join electrum block request")??;
+        for (height, hash) in blocks {
+            self.daq.record_block(height, hash).await?;
+        }
+        for a in self.daq.subscribed_address_filter.clone_read() {
+            let checkpoint = self.daq.backfill_checkpoint(&a).await?;
+            let network = self.daq.network.clone();
+            let address = Address::from_bitcoin_external(&a);
+            let txs = tokio::task::spawn_blocking(move || get_all_tx_electrum(&network, &address))
+                .await.error_info("Failed to join electrum history request")?
+                .map(|txs| txs.into_iter()
+                    .filter(|t| match (checkpoint, t.block_number) {
+                        (Some(c), Some(b)) => b > c,
+                        _ => true
+                    })
+                    .collect_vec());
+            self.daq.record_historical_backfill(&a, txs).await?;
+        }
+        Ok(())
+    }
     pub async fn from_btc_provider_stream(
         &self,
         e: RgResult<BitcoinWsProvider>,
@@ -85,7 +117,9 @@ impl BtcDaq {
         let nc = nc.clone();
         let daq = self.clone();
         tokio::spawn(async move {
-            let daq = daq;
+            let mut daq = daq;
+            daq.daq.network = nc.network.clone();
+            daq.daq.load_persisted().await?;
             let nc = nc;
             Self::retry_loop(daq, &nc).await
         })
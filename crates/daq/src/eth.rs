@@ -20,9 +20,14 @@ use redgold_schema::util::lang_util::JsonCombineResult;
 #[derive(Clone, Default)]
 pub struct EthDaq {
     pub daq: ExternalDaq,
-    pub historical_access_api_key: String
+    pub historical_access_api_key: String,
+    // Connected provider, used to recheck recent block hashes for reorgs
+    pub provider: Option<EthereumWsProvider>,
 }
 
+/// Recent blocks rechecked on every interval, a reorg deeper than this is not detected.
+pub const ETH_REORG_CHECK_DEPTH: u64 = 32;
+
 
 
 #[async_trait]
@@ -31,6 +36,10 @@ impl IntervalFoldOrReceive<RgResult<TimestampedEthereumTransaction>> for EthDaq
         match message {
             Either::Left(t) => {
                 let t = t?;
+                let block_hash = t.tx.block_hash.map(|h| format!("{:?}", h));
+                if let (Some(n), Some(h)) = (t.tx.block_number, block_hash.clone()) {
+                    self.daq.record_block(n.as_u64(), h).await?;
+                }
                 let addrs = self.daq.subscribed_address_filter.read();
                 let t_addrs = t.addrs();
                 if !t_addrs.iter().any(|a| addrs.contains(a)) {
@@ -42,10 +51,11 @@ impl IntervalFoldOrReceive<RgResult<TimestampedEthereumTransaction>> for EthDaq
                     &addrs, t
                 ).log_error();
                 if let Ok(ett) = ett {
-                    self.daq.add_new_tx(ett.clone());
+                    self.daq.record_new_tx(ett.clone(), block_hash).await?;
                 }
             }
             _ => {
+                self.check_recent_blocks().await.log_error().ok();
                 for (a, res) in self.daq.historical_transactions.read().iter() {
                     if res.is_err() {
                         self.add_address_and_backfill_historical(a.clone()).await?;
@@ -60,6 +70,16 @@ impl IntervalFoldOrReceive<RgResult<TimestampedEthereumTransaction>> for EthDaq
 
 impl EthDaq {
 
+    /// Record the recent block hashes, so a reorg is detected even when the replaced blocks
+    /// held no transactions for a subscribed address.
+    pub async fn check_recent_blocks(&mut self) -> RgResult<()> {
+        let Some(provider) = self.provider.clone() else { return Ok(()) };
+        for (height, hash) in provider.recent_blocks(ETH_REORG_CHECK_DEPTH).await? {
+            self.daq.record_block(height, hash).await?;
+        }
+        Ok(())
+    }
+
     pub async fn add_address_and_backfill_historical(&mut self, address: String) -> RgResult<()> {
         let mut res = self.daq.subscribed_address_filter.clone_read();
         if !res.contains(&address) {
@@ -69,8 +89,10 @@ impl EthDaq {
         let eth = EthHistoricalClient::new_from_key(
             &self.daq.network, self.historical_access_api_key.clone()
         )?;
-        let tx = eth.get_all_tx_with_retries(&address, None, None, None).await;
-        self.daq.add_historical_backfill(&address, tx);
+        // Resume from the last backfilled block, the store holds everything before it
+        let start_block = self.daq.backfill_checkpoint(&address).await?;
+        let tx = eth.get_all_tx_with_retries(&address, start_block, None, None).await;
+        self.daq.record_historical_backfill(&address, tx).await?;
         Ok(())
     }
 
@@ -87,6 +109,7 @@ impl EthDaq {
         let provider = e?;
 
         let mut s = self.clone();
+        s.provider = Some(provider.clone());
         let filter = s.daq.subscribed_address_filter.clone();
         let addrs = filter.read();
         for a in addrs.iter() {
@@ -131,7 +154,9 @@ impl EthDaq {
         let nc = nc.clone();
         let daq = self.clone();
         tokio::spawn(async move {
-            let daq = daq;
+            let mut daq = daq;
+            daq.daq.network = nc.network.clone();
+            daq.daq.load_persisted().await?;
             let nc = nc;
             Self::retry_loop(daq, &nc).await
         })
@@ -1,5 +1,7 @@
+use metrics::counter;
 use redgold_common_no_wasm::arc_swap_wrapper::WriteOneReadAll;
-use redgold_schema::structs::NetworkEnvironment;
+use redgold_data::external_event_store::ExternalEventStore;
+use redgold_schema::structs::{NetworkEnvironment, SupportedCurrency};
 use redgold_schema::tx::external_tx::ExternalTimedTransaction;
 use redgold_schema::RgResult;
 use std::collections::HashMap;
@@ -7,9 +9,15 @@ use std::collections::HashMap;
 #[derive(Clone, Default)]
 pub struct ExternalDaq {
     pub network: NetworkEnvironment,
+    pub currency: SupportedCurrency,
     pub subscribed_address_filter: WriteOneReadAll<Vec<String>>,
     pub recent_transactions: WriteOneReadAll<HashMap<String, Vec<ExternalTimedTransaction>>>,
-    pub historical_transactions: WriteOneReadAll<HashMap<String, RgResult<Vec<ExternalTimedTransaction>>>>
+    pub historical_transactions: WriteOneReadAll<HashMap<String, RgResult<Vec<ExternalTimedTransaction>>>>,
+    // Persists observed transactions and blocks so they survive restarts, without it the DAQ
+    // only keeps results in memory.
+    pub store: Option<ExternalEventStore>,
+    // Last block recorded, to avoid rechecking the store for every transaction in a block
+    pub last_block: WriteOneReadAll<Option<(u64, String)>>,
 }
 
 impl ExternalDaq {
@@ -56,4 +64,99 @@ impl ExternalDaq {
         self.historical_transactions.write(historical_tx);
     }
 
-}
\ No newline at end of file
+    pub fn with_store(currency: SupportedCurrency, store: ExternalEventStore) -> Self {
+        let mut daq = Self::default();
+        daq.currency = currency;
+        daq.store = Some(store);
+        daq
+    }
+
+    /// Restore previously persisted transactions as historical data and subscribe to their addresses.
+    pub async fn load_persisted(&mut self) -> RgResult<()> {
+        let Some(store) = self.store.clone() else { return Ok(()) };
+        let mut filter = self.subscribed_address_filter.clone_read();
+        let mut historical_tx = (*self.historical_transactions.read()).clone();
+        for a in store.query_addresses(self.currency).await? {
+            let txs = store.query_transactions(self.currency, &a).await?;
+            historical_tx.insert(a.clone(), Ok(txs));
+            if !filter.contains(&a) {
+                filter.push(a);
+            }
+        }
+        self.historical_transactions.write(historical_tx);
+        self.subscribed_address_filter.write(filter);
+        Ok(())
+    }
+
+    pub async fn record_new_tx(&mut self, tx: ExternalTimedTransaction, block_hash: Option<String>) -> RgResult<()> {
+        self.add_new_tx(tx.clone());
+        if let Some(store) = self.store.as_ref() {
+            store.insert_transaction(&tx.other_address, &tx, block_hash.as_ref()).await?;
+        }
+        Ok(())
+    }
+
+    /// Record a block seen on the chain, undoing transactions from orphaned blocks on a reorg.
+    pub async fn record_block(&mut self, height: u64, hash: String) -> RgResult<()> {
+        if self.last_block.read().as_ref() == Some(&(height, hash.clone())) {
+            return Ok(());
+        }
+        if let Some(store) = self.store.as_ref() {
+            let orphaned = store.observe_block(self.currency, height as i64, &hash).await?;
+            if orphaned > 0 {
+                counter!("redgold_daq_reorg_orphaned_tx").increment(orphaned);
+                self.orphan_from(height);
+            }
+        }
+        self.last_block.write(Some((height, hash)));
+        Ok(())
+    }
+
+    // Unconfirm any in memory transaction at or above a reorganized height
+    fn orphan_from(&mut self, height: u64) {
+        let orphan = |txs: &mut Vec<ExternalTimedTransaction>| {
+            for t in txs.iter_mut() {
+                if t.block_number.map(|b| b >= height).unwrap_or(false) {
+                    t.block_number = None;
+                    t.timestamp = None;
+                }
+            }
+        };
+        let mut recent_tx = (*self.recent_transactions.read()).clone();
+        recent_tx.values_mut().for_each(orphan);
+        self.recent_transactions.write(recent_tx);
+        let mut historical_tx = (*self.historical_transactions.read()).clone();
+        historical_tx.values_mut().flat_map(|r| r.as_mut().ok()).for_each(orphan);
+        self.historical_transactions.write(historical_tx);
+    }
+
+    /// Block to resume an address backfill from, if one has completed before.
+    pub async fn backfill_checkpoint(&self, a: &String) -> RgResult<Option<u64>> {
+        let Some(store) = self.store.as_ref() else { return Ok(None) };
+        Ok(store.query_checkpoint(self.currency, a).await?.map(|h| h as u64))
+    }
+
+    /// Persist the result of an incremental backfill and advance its checkpoint, the historical
+    /// view is rebuilt from everything persisted for the address.
+    pub async fn record_historical_backfill(&mut self, a: &String, txs: RgResult<Vec<ExternalTimedTransaction>>) -> RgResult<()> {
+        let Some(store) = self.store.clone() else {
+            self.add_historical_backfill(a, txs);
+            return Ok(());
+        };
+        let Ok(new_txs) = txs else {
+            self.add_historical_backfill(a, txs);
+            return Ok(());
+        };
+        for t in new_txs.iter() {
+            store.insert_transaction(a, t, None).await?;
+        }
+        if let Some(max_block) = new_txs.iter().flat_map(|t| t.block_number).max() {
+            let previous = store.query_checkpoint(self.currency, a).await?.unwrap_or(0);
+            store.set_checkpoint(self.currency, a, (max_block as i64).max(previous)).await?;
+        }
+        let all = store.query_transactions(self.currency, a).await?;
+        self.add_historical_backfill(a, Ok(all));
+        Ok(())
+    }
+
+}
@@ -0,0 +1,16 @@
+CREATE TABLE IF NOT EXISTS block
+(
+    height  INTEGER PRIMARY KEY NOT NULL,
+    hash    BLOB NOT NULL,
+    time    INTEGER NOT NULL,
+    block_proto  BLOB NOT NULL
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS block_hash
+    ON block (hash);
+
+CREATE TABLE IF NOT EXISTS block_transaction
+(
+    transaction_hash  BLOB PRIMARY KEY NOT NULL,
+    height  INTEGER NOT NULL
+);
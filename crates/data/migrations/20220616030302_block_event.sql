@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS block_event
+(
+    sequence  INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    height  INTEGER NOT NULL,
+    hash    BLOB NOT NULL,
+    removed INTEGER NOT NULL
+);
@@ -0,0 +1,30 @@
+CREATE TABLE IF NOT EXISTS external_transaction
+(
+    currency  INTEGER NOT NULL,
+    tx_id  TEXT NOT NULL,
+    address  TEXT NOT NULL,
+    block_height  INTEGER,
+    block_hash  TEXT,
+    time  INTEGER,
+    tx_json  TEXT NOT NULL,
+    PRIMARY KEY (currency, tx_id, address)
+);
+
+CREATE INDEX IF NOT EXISTS external_transaction_block
+    ON external_transaction (currency, block_height);
+
+CREATE TABLE IF NOT EXISTS external_block
+(
+    currency  INTEGER NOT NULL,
+    height  INTEGER NOT NULL,
+    hash  TEXT NOT NULL,
+    PRIMARY KEY (currency, height)
+);
+
+CREATE TABLE IF NOT EXISTS external_checkpoint
+(
+    currency  INTEGER NOT NULL,
+    address  TEXT NOT NULL,
+    block_height  INTEGER NOT NULL,
+    PRIMARY KEY (currency, address)
+);
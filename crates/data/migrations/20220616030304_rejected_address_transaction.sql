@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS rejected_address_transaction (
+                                    address BLOB NOT NULL,
+                                    tx_hash BLOB NOT NULL,
+                                    time INTEGER NOT NULL,
+                                    PRIMARY KEY (address, tx_hash)
+);
+
+CREATE INDEX IF NOT EXISTS rejected_address_transaction_tx_hash
+    ON rejected_address_transaction (tx_hash);
@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS pending_acceptance (
+                                    signed_hash BLOB PRIMARY KEY,
+                                    transaction_proto BLOB NOT NULL,
+                                    expiry INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS pending_acceptance_expiry
+    ON pending_acceptance (expiry);
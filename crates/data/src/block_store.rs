@@ -0,0 +1,170 @@
+use crate::DataStoreContext;
+use redgold_schema::helpers::easy_json::EasyJson;
+use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+use redgold_schema::observability::errors::EnhanceErrorInfo;
+use redgold_schema::proto_serde::ProtoSerde;
+use redgold_schema::structs::{Block, Hash};
+use redgold_schema::{ErrorInfoContext, RgResult};
+use sqlx::Sqlite;
+
+#[derive(Clone)]
+pub struct BlockStore {
+    pub ctx: DataStoreContext
+}
+
+impl BlockStore {
+
+    /// Store a formed block along with the height index for each of its transactions.
+    /// Every change at a height is appended to the block event log, replacing a block at an
+    /// existing height records its removal before the new block is added. All writes happen in a
+    /// single sqlite transaction so the event log never disagrees with the stored blocks.
+    pub async fn insert_block(&self, block: &Block) -> RgResult<()> {
+        let mut pool = self.ctx.pool().await?;
+        let mut sqlite_tx = DataStoreContext::map_err_sqlx(pool.begin().await)?;
+        match self.insert_block_inner(block, &mut sqlite_tx).await {
+            Ok(_) => {
+                sqlite_tx.commit().await.error_info("Sqlite commit failure")?;
+                Ok(())
+            }
+            Err(e) => {
+                sqlite_tx.rollback().await.error_info("Rollback failure").with_detail("original_error", e.json_or())?;
+                Err(e)
+            }
+        }
+    }
+
+    async fn insert_block_inner(&self, block: &Block, sqlite_tx: &mut sqlx::Transaction<'_, Sqlite>) -> RgResult<()> {
+        let height = block.height;
+        let hash = block.hash_or().vec();
+        let existing = sqlx::query!(
+            r#"SELECT hash FROM block WHERE height = ?1"#,
+            height
+        )
+            .fetch_optional(&mut **sqlite_tx)
+            .await;
+        let existing = DataStoreContext::map_err_sqlx(existing)?.map(|r| r.hash);
+        if existing.as_ref() == Some(&hash) {
+            return Ok(());
+        }
+        if let Some(old) = existing {
+            Self::insert_block_event(height, old, true, sqlite_tx).await?;
+        }
+        let rows = sqlx::query!(
+            r#"DELETE FROM block_transaction WHERE height = ?1"#,
+            height
+        )
+            .execute(&mut **sqlite_tx)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?;
+        let time = block.time()?;
+        let ser = block.proto_serialize();
+        let rows = sqlx::query!(
+            r#"INSERT OR REPLACE INTO block (height, hash, time, block_proto) VALUES (?1, ?2, ?3, ?4)"#,
+            height, hash, time, ser
+        )
+            .execute(&mut **sqlite_tx)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?;
+        for t in &block.transactions {
+            let tx_hash = t.hash_or().vec();
+            let rows = sqlx::query!(
+                r#"INSERT OR REPLACE INTO block_transaction (transaction_hash, height) VALUES (?1, ?2)"#,
+                tx_hash, height
+            )
+                .execute(&mut **sqlite_tx)
+                .await;
+            DataStoreContext::map_err_sqlx(rows)?;
+        }
+        Self::insert_block_event(height, hash, false, sqlite_tx).await
+    }
+
+    async fn insert_block_event(
+        height: i64, hash: Vec<u8>, removed: bool, sqlite_tx: &mut sqlx::Transaction<'_, Sqlite>
+    ) -> RgResult<()> {
+        let rows = sqlx::query!(
+            r#"INSERT INTO block_event (height, hash, removed) VALUES (?1, ?2, ?3)"#,
+            height, hash, removed
+        )
+            .execute(&mut **sqlite_tx)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?;
+        Ok(())
+    }
+
+    /// Block events in sequence order as (sequence, height, hash, removed)
+    pub async fn query_block_events(&self, offset: i64, limit: i64) -> RgResult<Vec<(i64, i64, Hash, bool)>> {
+        let mut pool = self.ctx.pool().await?;
+        let rows = sqlx::query!(
+            r#"SELECT sequence, height, hash, removed FROM block_event WHERE sequence >= ?1 ORDER BY sequence ASC LIMIT ?2"#,
+            offset, limit
+        )
+            .fetch_all(&mut *pool)
+            .await;
+        let mut res = vec![];
+        for r in DataStoreContext::map_err_sqlx(rows)? {
+            res.push((r.sequence, r.height, Hash::new_from_proto(r.hash)?, r.removed > 0));
+        }
+        Ok(res)
+    }
+
+    pub async fn query_max_block_event_sequence(&self) -> RgResult<Option<i64>> {
+        let mut pool = self.ctx.pool().await?;
+        let rows = sqlx::query!(
+            r#"SELECT MAX(sequence) as "sequence?: i64" FROM block_event"#
+        )
+            .fetch_one(&mut *pool)
+            .await;
+        Ok(DataStoreContext::map_err_sqlx(rows)?.sequence)
+    }
+
+    pub async fn query_block_height(&self, height: i64) -> RgResult<Option<Block>> {
+        let mut pool = self.ctx.pool().await?;
+        let rows = sqlx::query!(
+            r#"SELECT block_proto FROM block WHERE height = ?1"#,
+            height
+        )
+            .fetch_optional(&mut *pool)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?
+            .map(|r| Block::proto_deserialize(r.block_proto))
+            .transpose()
+    }
+
+    pub async fn query_block_hash(&self, hash: &Hash) -> RgResult<Option<Block>> {
+        let mut pool = self.ctx.pool().await?;
+        let h = hash.vec();
+        let rows = sqlx::query!(
+            r#"SELECT block_proto FROM block WHERE hash = ?1"#,
+            h
+        )
+            .fetch_optional(&mut *pool)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?
+            .map(|r| Block::proto_deserialize(r.block_proto))
+            .transpose()
+    }
+
+    pub async fn query_last_block(&self) -> RgResult<Option<Block>> {
+        let mut pool = self.ctx.pool().await?;
+        let rows = sqlx::query!(
+            r#"SELECT block_proto FROM block ORDER BY height DESC LIMIT 1"#
+        )
+            .fetch_optional(&mut *pool)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?
+            .map(|r| Block::proto_deserialize(r.block_proto))
+            .transpose()
+    }
+
+    pub async fn query_transaction_block_height(&self, transaction_hash: &Hash) -> RgResult<Option<i64>> {
+        let mut pool = self.ctx.pool().await?;
+        let h = transaction_hash.vec();
+        let rows = sqlx::query!(
+            r#"SELECT height FROM block_transaction WHERE transaction_hash = ?1"#,
+            h
+        )
+            .fetch_optional(&mut *pool)
+            .await;
+        Ok(DataStoreContext::map_err_sqlx(rows)?.map(|r| r.height))
+    }
+}
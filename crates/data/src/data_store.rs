@@ -35,6 +35,8 @@ use crate::schema::structs::{
     Address, ErrorInfo,
 };
 use crate::state_store::StateStore;
+use crate::block_store::BlockStore;
+use crate::external_event_store::ExternalEventStore;
 use crate::utxo_store::UtxoStore;
 
 #[derive(Clone)]
@@ -50,7 +52,9 @@ pub struct DataStore {
     pub ctx: DataStoreContext,
     pub state: StateStore,
     pub utxo: UtxoStore,
-    pub price_time: PriceTimeStore
+    pub price_time: PriceTimeStore,
+    pub block: BlockStore,
+    pub external_event: ExternalEventStore,
 }
 
 impl DataStore {
@@ -112,12 +116,13 @@ impl DataStore {
     // }
 
     // TODO: Do this as a single sqlite transaction.
+    /// Returns the acceptance position of the transaction, see `query_accepted_after_position`.
                                 pub async fn accept_transaction(&self,
                                     tx: &Transaction,
                                     time: i64,
                                     rejection_reason: Option<ErrorInfo>,
                                     update_utxo: bool
-    ) -> RgResult<()> {
+    ) -> RgResult<i64> {
 
         counter!("redgold_transaction_accept_called").increment(1);
 
@@ -131,9 +136,9 @@ impl DataStore {
             .with_detail("update_utxo", update_utxo.to_string());
         // result
         let final_result = match result {
-            Ok(_) => {
+            Ok(position) => {
                 sqlite_tx.commit().await.error_info("Sqlite commit failure")?;
-                Ok(())
+                Ok(position)
             }
             Err(e) => {
                 match sqlite_tx.rollback().await.error_info("Rollback failure").with_detail("original_error", e.json_or()) {
@@ -149,12 +154,12 @@ impl DataStore {
         &self,
         tx: &Transaction, time: i64, rejection_reason: Option<ErrorInfo>, update_utxo: bool,
         sqlite_tx: &mut sqlx::Transaction<'_, Sqlite>
-    ) -> RgResult<()> {
+    ) -> RgResult<i64> {
         let insert_result = self.insert_transaction(
             tx, time, rejection_reason.clone(), update_utxo, sqlite_tx
         ).await;
 
-        insert_result?;
+        let position = insert_result?;
 
         if rejection_reason.is_none() {
             for utxo_id in tx.input_utxo_ids() {
@@ -164,7 +169,7 @@ impl DataStore {
                 }
             }
         }
-        Ok(())
+        Ok(position)
     }
 
 
@@ -365,6 +370,54 @@ impl DataStore {
         // } {}
         Ok(())
     }
+
+    /// Write a transactionally consistent copy of the store to a new file, safe to run while
+    /// the node is live.
+    pub async fn snapshot_to(&self, path: &PathBuf) -> RgResult<()> {
+        let p = path.to_str().ok_msg("Invalid snapshot path")?.to_string();
+        DataStoreContext::map_err_sqlx(sqlx::query("VACUUM INTO ?1")
+            .bind(p)
+            .execute(&mut *self.ctx.pool().await?)
+            .await)?;
+        Ok(())
+    }
+
+    /// Check the store for corruption, missing migrations and dangling utxo references,
+    /// returning a description of each problem found.
+    pub async fn verify_consistency(&self) -> RgResult<Vec<String>> {
+        let mut problems = vec![];
+        let mut pool = self.ctx.pool().await?;
+        let rows = DataStoreContext::map_err_sqlx(sqlx::query("PRAGMA integrity_check")
+            .fetch_all(&mut *pool)
+            .await)?;
+        for r in rows {
+            let res: String = DataStoreContext::map_err_sqlx(r.try_get(0))?;
+            if res != "ok" {
+                problems.push(format!("integrity_check: {}", res));
+            }
+        }
+        let expected = sqlx::migrate!("./migrations").migrations.len() as i64;
+        let row = DataStoreContext::map_err_sqlx(sqlx::query(
+            r#"SELECT COUNT(*) as applied FROM _sqlx_migrations WHERE success = 1"#
+        )
+            .fetch_one(&mut *pool)
+            .await)?;
+        let applied: i64 = DataStoreContext::map_err_sqlx(row.try_get("applied"))?;
+        if applied != expected {
+            problems.push(format!("Applied migrations {} does not match expected {}", applied, expected));
+        }
+        let row = DataStoreContext::map_err_sqlx(sqlx::query(
+            r#"SELECT COUNT(*) as dangling FROM utxo u
+            LEFT JOIN transactions t ON u.transaction_hash = t.hash WHERE t.hash IS NULL"#
+        )
+            .fetch_one(&mut *pool)
+            .await)?;
+        let dangling: i64 = DataStoreContext::map_err_sqlx(row.try_get("dangling"))?;
+        if dangling > 0 {
+            problems.push(format!("{} utxo entries reference missing transactions", dangling));
+        }
+        Ok(problems)
+    }
 }
 
 impl DataStore {
@@ -447,6 +500,8 @@ WHERE
             multiparty_store: MultipartyStore { ctx: ctx.clone() },
             observation: ObservationStore { ctx: ctx.clone() },
             state: StateStore { ctx: ctx.clone() },
+            block: BlockStore { ctx: ctx.clone() },
+            external_event: ExternalEventStore { ctx: ctx.clone() },
             price_time: PriceTimeStore { ctx },
         }
     }
@@ -0,0 +1,200 @@
+use crate::DataStoreContext;
+use redgold_schema::helpers::easy_json::{EasyJson, EasyJsonDeser};
+use redgold_schema::structs::SupportedCurrency;
+use redgold_schema::tx::external_tx::ExternalTimedTransaction;
+use redgold_schema::observability::errors::EnhanceErrorInfo;
+use redgold_schema::{ErrorInfoContext, RgResult};
+use sqlx::Sqlite;
+
+/// Block hashes below this depth from the tip are no longer retained for reorg detection.
+pub const MAX_EXTERNAL_REORG_DEPTH: i64 = 1000;
+
+#[derive(Clone)]
+pub struct ExternalEventStore {
+    pub ctx: DataStoreContext
+}
+
+impl ExternalEventStore {
+
+    /// Persist a transaction observed for an address, replacing any previous record of it.
+    pub async fn insert_transaction(
+        &self,
+        address: &String,
+        tx: &ExternalTimedTransaction,
+        block_hash: Option<&String>
+    ) -> RgResult<()> {
+        let mut pool = self.ctx.pool().await?;
+        let currency = tx.currency as i32;
+        let block_height = tx.block_number.map(|b| b as i64);
+        let time = tx.timestamp.map(|t| t as i64);
+        let tx_json = tx.json_or();
+        let block_hash = block_hash.cloned();
+        let rows = sqlx::query!(
+            r#"INSERT OR REPLACE INTO external_transaction
+            (currency, tx_id, address, block_height, block_hash, time, tx_json)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)"#,
+            currency, tx.tx_id, address, block_height, block_hash, time, tx_json
+        )
+            .execute(&mut *pool)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?;
+        Ok(())
+    }
+
+    /// All persisted transactions for an address, oldest first. Transactions whose block was
+    /// orphaned by a reorg are returned unconfirmed until they are seen in a block again.
+    pub async fn query_transactions(&self, currency: SupportedCurrency, address: &String) -> RgResult<Vec<ExternalTimedTransaction>> {
+        let mut pool = self.ctx.pool().await?;
+        let c = currency as i32;
+        let rows = sqlx::query!(
+            r#"SELECT block_height, tx_json FROM external_transaction
+            WHERE currency = ?1 AND address = ?2 ORDER BY time ASC"#,
+            c, address
+        )
+            .fetch_all(&mut *pool)
+            .await;
+        let rows_m = DataStoreContext::map_err_sqlx(rows)?;
+        let mut res = vec![];
+        for row in rows_m {
+            let mut tx: ExternalTimedTransaction = row.tx_json.json_from()?;
+            if tx.block_number.is_some() && row.block_height.is_none() {
+                // Orphaned by a reorg, no longer confirmed
+                tx.timestamp = None;
+            }
+            tx.block_number = row.block_height.map(|h| h as u64);
+            res.push(tx);
+        }
+        Ok(res)
+    }
+
+    pub async fn query_addresses(&self, currency: SupportedCurrency) -> RgResult<Vec<String>> {
+        let mut pool = self.ctx.pool().await?;
+        let c = currency as i32;
+        let rows = sqlx::query!(
+            r#"SELECT DISTINCT address FROM external_transaction WHERE currency = ?1"#,
+            c
+        )
+            .fetch_all(&mut *pool)
+            .await;
+        Ok(DataStoreContext::map_err_sqlx(rows)?.into_iter().map(|r| r.address).collect())
+    }
+
+    /// Record the hash of a block at a height. When a different hash was already recorded
+    /// there the chain has reorganized: every block from that height up is discarded, the
+    /// transactions they contained lose their block, and backfill checkpoints are rewound
+    /// so they are fetched again. Returns the number of transactions orphaned.
+    pub async fn observe_block(&self, currency: SupportedCurrency, height: i64, hash: &String) -> RgResult<u64> {
+        let mut pool = self.ctx.pool().await?;
+        let mut sqlite_tx = DataStoreContext::map_err_sqlx(pool.begin().await)?;
+        match Self::observe_block_inner(currency, height, hash, &mut sqlite_tx).await {
+            Ok(orphaned) => {
+                sqlite_tx.commit().await.error_info("Sqlite commit failure")?;
+                Ok(orphaned)
+            }
+            Err(e) => {
+                sqlite_tx.rollback().await.error_info("Rollback failure").with_detail("original_error", e.json_or())?;
+                Err(e)
+            }
+        }
+    }
+
+    async fn observe_block_inner(
+        currency: SupportedCurrency,
+        height: i64,
+        hash: &String,
+        sqlite_tx: &mut sqlx::Transaction<'_, Sqlite>
+    ) -> RgResult<u64> {
+        let c = currency as i32;
+        let rows = sqlx::query!(
+            r#"SELECT hash FROM external_block WHERE currency = ?1 AND height = ?2"#,
+            c, height
+        )
+            .fetch_optional(&mut **sqlite_tx)
+            .await;
+        let existing = DataStoreContext::map_err_sqlx(rows)?.map(|r| r.hash);
+        if existing.as_ref() == Some(hash) {
+            return Ok(0);
+        }
+        let mut orphaned = 0;
+        if existing.is_some() {
+            let rows = sqlx::query!(
+                r#"DELETE FROM external_block WHERE currency = ?1 AND height >= ?2"#,
+                c, height
+            )
+                .execute(&mut **sqlite_tx)
+                .await;
+            DataStoreContext::map_err_sqlx(rows)?;
+            let rows = sqlx::query!(
+                r#"UPDATE external_transaction SET block_height = NULL, block_hash = NULL
+                WHERE currency = ?1 AND block_height >= ?2"#,
+                c, height
+            )
+                .execute(&mut **sqlite_tx)
+                .await;
+            orphaned = DataStoreContext::map_err_sqlx(rows)?.rows_affected();
+            let rewound = height - 1;
+            let rows = sqlx::query!(
+                r#"UPDATE external_checkpoint SET block_height = ?3
+                WHERE currency = ?1 AND block_height >= ?2"#,
+                c, height, rewound
+            )
+                .execute(&mut **sqlite_tx)
+                .await;
+            DataStoreContext::map_err_sqlx(rows)?;
+        }
+        let rows = sqlx::query!(
+            r#"INSERT OR REPLACE INTO external_block (currency, height, hash) VALUES (?1, ?2, ?3)"#,
+            c, height, hash
+        )
+            .execute(&mut **sqlite_tx)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?;
+        let cutoff = height - MAX_EXTERNAL_REORG_DEPTH;
+        let rows = sqlx::query!(
+            r#"DELETE FROM external_block WHERE currency = ?1 AND height < ?2"#,
+            c, cutoff
+        )
+            .execute(&mut **sqlite_tx)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?;
+        Ok(orphaned)
+    }
+
+    /// Highest block height observed for a chain.
+    pub async fn query_tip(&self, currency: SupportedCurrency) -> RgResult<Option<i64>> {
+        let mut pool = self.ctx.pool().await?;
+        let c = currency as i32;
+        let rows = sqlx::query!(
+            r#"SELECT MAX(height) as "height?: i64" FROM external_block WHERE currency = ?1"#,
+            c
+        )
+            .fetch_one(&mut *pool)
+            .await;
+        Ok(DataStoreContext::map_err_sqlx(rows)?.height)
+    }
+
+    pub async fn query_checkpoint(&self, currency: SupportedCurrency, address: &String) -> RgResult<Option<i64>> {
+        let mut pool = self.ctx.pool().await?;
+        let c = currency as i32;
+        let rows = sqlx::query!(
+            r#"SELECT block_height FROM external_checkpoint WHERE currency = ?1 AND address = ?2"#,
+            c, address
+        )
+            .fetch_optional(&mut *pool)
+            .await;
+        Ok(DataStoreContext::map_err_sqlx(rows)?.map(|r| r.block_height))
+    }
+
+    pub async fn set_checkpoint(&self, currency: SupportedCurrency, address: &String, block_height: i64) -> RgResult<()> {
+        let mut pool = self.ctx.pool().await?;
+        let c = currency as i32;
+        let rows = sqlx::query!(
+            r#"INSERT OR REPLACE INTO external_checkpoint (currency, address, block_height) VALUES (?1, ?2, ?3)"#,
+            c, address, block_height
+        )
+            .execute(&mut *pool)
+            .await;
+        DataStoreContext::map_err_sqlx(rows)?;
+        Ok(())
+    }
+}
@@ -21,9 +21,12 @@ pub mod utxo_store;
 pub mod parquet_export;
 pub mod parquet_min_index;
 pub mod parquet_full_index;
+pub mod parquet_lake;
 pub mod transaction_insert;
 pub mod address_transaction;
 pub mod transaction_observability;
+pub mod block_store;
+pub mod external_event_store;
 mod price_time;
 
 #[derive(Clone)]
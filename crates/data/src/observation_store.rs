@@ -107,6 +107,21 @@ impl ObservationStore {
         Ok(res)
     }
 
+    /// Observations in insertion order after `after`, paired with their positions (the table rowid).
+    pub async fn query_observations_after_position(&self, after: i64, limit: i64) -> RgResult<Vec<(i64, Transaction)>> {
+        let rows = DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"SELECT rowid AS "position!: i64", observation_proto FROM observation
+            WHERE rowid > ?1 ORDER BY rowid ASC LIMIT ?2"#,
+            after,
+            limit
+        )
+            .fetch_all(&mut *self.ctx.pool().await?)
+            .await)?;
+        rows.into_iter()
+            .map(|row| Ok((row.position, Transaction::proto_deserialize(row.observation_proto)?)))
+            .collect()
+    }
+
     pub async fn accepted_time_observation_hashes(
         &self,
         start: i64,
@@ -228,6 +243,26 @@ impl ObservationStore {
         Ok(res)
     }
 
+    /// Observation edges in insertion order after `after`, paired with their positions.
+    pub async fn query_observation_edges_after_position(&self, after: i64, limit: i64) -> RgResult<Vec<(i64, ObservationEdge)>> {
+        let rows = DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"SELECT rowid AS "position!: i64", edge, time FROM observation_edge
+            WHERE rowid > ?1 ORDER BY rowid ASC LIMIT ?2"#,
+            after,
+            limit
+        )
+            .fetch_all(&mut *self.ctx.pool().await?)
+            .await)?;
+        let mut res = vec![];
+        for row in rows {
+            let mut edge = ObservationEdge::default();
+            edge.observation_proof = Some(ObservationProof::proto_deserialize(row.edge)?);
+            edge.time = row.time;
+            res.push((row.position, edge));
+        }
+        Ok(res)
+    }
+
     pub async fn select_observation_edge(&self, observed_hash: &Hash) -> Result<Vec<ObservationProof>, ErrorInfo> {
         let mut pool = self.ctx.pool().await?;
         let bytes = observed_hash.vec();
@@ -35,9 +35,8 @@ fn translate_tx<'a>(tx: &Transaction) -> RgResult<Row<'a>> {
     let hash = tx.hash_proto_bytes();
     let signable_hash = tx.signable_hash().vec();
     let signed_hash = tx.signed_hash().vec();
-    // TODO: Distinguish these cases
-    let counterparty_hash: Option<Vec<u8>> = None;
-    let confirmation_hash: Option<Vec<u8>> = None;
+    let counterparty_hash = tx.counter_party_hash().map(|h| h.vec());
+    let confirmation_hash = tx.confirmation_signing_hash().map(|h| h.vec());
     let first_input_address = tx.first_input_address().map(|a| a.proto_serialize());
     let first_output_address = tx.first_output_address_non_input_or_fee().map(|a| a.proto_serialize());
     let transaction_type = tx.transaction_type().ok().map(|t| t as i32);
@@ -0,0 +1,629 @@
+use crate::data_store::DataStore;
+use async_trait::async_trait;
+use itertools::Itertools;
+use log::info;
+use metrics::counter;
+use polars::datatypes::{AnyValue, DataType, Field, TimeUnit};
+use polars::frame::row::Row;
+use polars::prelude::*;
+use redgold_schema::helpers::easy_json::{EasyJson, EasyJsonDeser};
+use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+use redgold_schema::structs::{Address, Hash, Input, ObservationEdge, PriceSource, PriceTime, SupportedCurrency, Transaction};
+use redgold_schema::util::times::ToTimeString;
+use redgold_schema::{ErrorInfoContext, RgResult};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+// Each export query reads at most this many source rows, so the first export of a long chain
+// doesn't load everything at once and progress is checkpointed as it goes.
+const PAGE_ROWS: i64 = 10_000;
+// Lakes written before version 1 were checkpointed by row time and are rebuilt.
+const LAKE_VERSION: u32 = 1;
+const CHECKPOINT_FILE: &str = "_checkpoint.json";
+const SCHEMA_FILE: &str = "_schema.json";
+
+/// Column types used in the lake, all hashes are hex and all addresses rendered strings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LakeType {
+    Time,
+    String,
+    Int64,
+    Int32,
+    Float64,
+    Boolean,
+}
+
+impl LakeType {
+    fn dtype(&self) -> DataType {
+        match self {
+            LakeType::Time => DataType::Datetime(TimeUnit::Milliseconds, None),
+            LakeType::String => DataType::String,
+            LakeType::Int64 => DataType::Int64,
+            LakeType::Int32 => DataType::Int32,
+            LakeType::Float64 => DataType::Float64,
+            LakeType::Boolean => DataType::Boolean,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            LakeType::Time => "timestamp_millis",
+            LakeType::String => "string",
+            LakeType::Int64 => "int64",
+            LakeType::Int32 => "int32",
+            LakeType::Float64 => "float64",
+            LakeType::Boolean => "boolean",
+        }
+    }
+}
+
+pub struct LakeColumn {
+    pub name: &'static str,
+    pub kind: LakeType,
+    pub nullable: bool,
+    pub doc: &'static str,
+}
+
+const fn col(name: &'static str, kind: LakeType, nullable: bool, doc: &'static str) -> LakeColumn {
+    LakeColumn { name, kind, nullable, doc }
+}
+
+/// A normalized table, written as `<table>/year=YYYY/month=MM/part-<page start>.parquet`.
+pub struct LakeTable {
+    pub name: &'static str,
+    pub doc: &'static str,
+    pub columns: &'static [LakeColumn],
+}
+
+pub const TRANSACTIONS: LakeTable = LakeTable {
+    name: "transactions",
+    doc: "One row per accepted transaction.",
+    columns: &[
+        col("time", LakeType::Time, false, "Transaction time"),
+        col("hash", LakeType::String, false, "Transaction hash"),
+        col("signable_hash", LakeType::String, false, "Hash signed by inputs"),
+        col("signed_hash", LakeType::String, false, "Hash including input proofs"),
+        col("counterparty_hash", LakeType::String, true, "Hash signed by counter-parties, if any signed"),
+        col("confirmation_hash", LakeType::String, true, "Hash signed by confirmations, if any signed"),
+        col("transaction_type", LakeType::String, true, "TransactionType name"),
+        col("is_test", LakeType::Boolean, false, "Test transaction flag"),
+        col("input_count", LakeType::Int32, false, "Number of inputs"),
+        col("output_count", LakeType::Int32, false, "Number of outputs"),
+        col("total_amount", LakeType::Int64, false, "Sum of output amounts in base units"),
+        col("remainder_amount", LakeType::Int64, false, "Amount returned to input addresses in base units"),
+        col("first_input_address", LakeType::String, true, "Address of the first input"),
+        col("first_output_address", LakeType::String, true, "First output address that isn't change or a fee"),
+        col("contract_type", LakeType::String, true, "StandardContractType name of the first contract output"),
+    ],
+};
+
+pub const INPUTS: LakeTable = LakeTable {
+    name: "inputs",
+    doc: "One row per transaction input.",
+    columns: &[
+        col("time", LakeType::Time, false, "Spending transaction time"),
+        col("transaction_hash", LakeType::String, false, "Spending transaction hash"),
+        col("input_index", LakeType::Int32, false, "Position of the input in the transaction"),
+        col("utxo_transaction_hash", LakeType::String, true, "Transaction hash of the output spent"),
+        col("utxo_output_index", LakeType::Int64, true, "Index of the output spent"),
+        col("address", LakeType::String, true, "Address spent from"),
+        col("is_multisig", LakeType::Boolean, false, "Whether the input carries a multisig address descriptor"),
+        col("proof_count", LakeType::Int32, false, "Number of signatures on the input"),
+        col("signer_public_key", LakeType::String, true, "Public key of the first signature"),
+    ],
+};
+
+pub const OUTPUTS: LakeTable = LakeTable {
+    name: "outputs",
+    doc: "One row per transaction output.",
+    columns: &[
+        col("time", LakeType::Time, false, "Transaction time"),
+        col("transaction_hash", LakeType::String, false, "Transaction hash"),
+        col("output_index", LakeType::Int32, false, "Position of the output in the transaction"),
+        col("address", LakeType::String, true, "Destination address"),
+        col("amount", LakeType::Int64, true, "Amount in base units"),
+        col("is_fee", LakeType::Boolean, false, "Whether the output pays a fee"),
+        col("is_change", LakeType::Boolean, false, "Whether the output returns to an input address"),
+        col("output_type", LakeType::String, true, "OutputType name"),
+        col("contract_type", LakeType::String, true, "StandardContractType name"),
+    ],
+};
+
+pub const UTXO_SPENDS: LakeTable = LakeTable {
+    name: "utxo_spends",
+    doc: "One row per output consumed, joins outputs to the inputs that spent them.",
+    columns: &[
+        col("time", LakeType::Time, false, "Spending transaction time"),
+        col("spent_transaction_hash", LakeType::String, false, "Transaction hash of the output spent"),
+        col("spent_output_index", LakeType::Int64, false, "Index of the output spent"),
+        col("spending_transaction_hash", LakeType::String, false, "Spending transaction hash"),
+        col("spending_input_index", LakeType::Int32, false, "Index of the spending input"),
+    ],
+};
+
+pub const OBSERVATIONS: LakeTable = LakeTable {
+    name: "observations",
+    doc: "One row per observation transaction, the merkle root of a batch of observed hashes.",
+    columns: &[
+        col("time", LakeType::Time, false, "Observation time"),
+        col("hash", LakeType::String, false, "Observation transaction hash"),
+        col("public_key", LakeType::String, true, "Observing node public key"),
+        col("height", LakeType::Int64, true, "Height in the observing node's observation chain"),
+        col("merkle_root", LakeType::String, true, "Merkle root of the observed metadata"),
+        col("observed_count", LakeType::Int32, false, "Number of hashes observed"),
+        col("parent_transaction_hash", LakeType::String, true, "Previous observation in the node's chain"),
+    ],
+};
+
+pub const OBSERVATION_EDGES: LakeTable = LakeTable {
+    name: "observation_edges",
+    doc: "One row per observed hash within an observation, with its inclusion in the merkle root.",
+    columns: &[
+        col("time", LakeType::Time, false, "Time the edge was stored"),
+        col("observation_hash", LakeType::String, false, "Observation transaction hash"),
+        col("observed_hash", LakeType::String, false, "Hash observed, usually a transaction"),
+        col("merkle_root", LakeType::String, true, "Merkle root of the observation"),
+        col("leaf_hash", LakeType::String, true, "Merkle leaf of the observation metadata"),
+        col("state", LakeType::String, true, "State name of the observed hash"),
+        col("validation_type", LakeType::String, true, "ValidationType name"),
+        col("signer_public_key", LakeType::String, true, "Observing node public key"),
+    ],
+};
+
+pub const PRICE_TIME: LakeTable = LakeTable {
+    name: "price_time",
+    doc: "One row per recorded price.",
+    columns: &[
+        col("time", LakeType::Time, false, "Price time"),
+        col("source", LakeType::String, true, "PriceSource name"),
+        col("currency", LakeType::String, true, "SupportedCurrency name priced"),
+        col("denomination", LakeType::String, true, "SupportedCurrency name the price is in"),
+        col("price", LakeType::Float64, true, "Price of one unit of currency in denomination"),
+    ],
+};
+
+pub const LAKE_TABLES: [&LakeTable; 7] = [
+    &TRANSACTIONS, &INPUTS, &OUTPUTS, &UTXO_SPENDS, &OBSERVATIONS, &OBSERVATION_EDGES, &PRICE_TIME
+];
+
+impl LakeTable {
+    pub fn schema(&self) -> Schema {
+        Schema::from_iter(self.columns.iter().map(|c| Field::new(c.name, c.kind.dtype())))
+    }
+
+    fn schema_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "table": self.name,
+            "doc": self.doc,
+            "partitioning": ["year", "month"],
+            "columns": self.columns.iter().map(|c| serde_json::json!({
+                "name": c.name,
+                "type": c.kind.name(),
+                "nullable": c.nullable,
+                "doc": c.doc,
+            })).collect_vec(),
+        })
+    }
+}
+
+/// Where each source has been exported up to. Sources are read in insertion order, so every
+/// row at or before the position has been written and anything stored later is picked up by
+/// the next export, whatever its time.
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LakeCheckpoint {
+    #[serde(default)]
+    pub version: u32,
+    #[serde(default)]
+    pub exported_position: HashMap<String, i64>,
+}
+
+impl LakeCheckpoint {
+    pub fn load(root: &Path) -> RgResult<Self> {
+        let path = root.join(CHECKPOINT_FILE);
+        if !path.exists() {
+            return Ok(Self { version: LAKE_VERSION, ..Self::default() });
+        }
+        std::fs::read_to_string(&path).error_info("Failed to read lake checkpoint")?.json_from()
+    }
+
+    /// Written to a temporary file and renamed so an interrupted export never leaves a partial
+    /// checkpoint.
+    pub fn save(&self, root: &Path) -> RgResult<()> {
+        let tmp = root.join(format!("{}.tmp", CHECKPOINT_FILE));
+        std::fs::write(&tmp, self.json_or()).error_info("Failed to write lake checkpoint")?;
+        std::fs::rename(&tmp, root.join(CHECKPOINT_FILE)).error_info("Failed to replace lake checkpoint")
+    }
+}
+
+/// Rows grouped by table, each with the time used to partition it.
+#[derive(Default)]
+struct LakeRows {
+    rows: HashMap<&'static str, Vec<(i64, Row<'static>)>>,
+}
+
+impl LakeRows {
+    fn push(&mut self, table: &LakeTable, time: i64, values: Vec<AnyValue<'static>>) {
+        self.rows.entry(table.name).or_default().push((time, Row::new(values)));
+    }
+}
+
+fn hash_value(h: &Hash) -> AnyValue<'static> {
+    AnyValue::StringOwned(h.hex().into())
+}
+
+fn opt_hash_value(h: Option<&Hash>) -> AnyValue<'static> {
+    h.map(hash_value).unwrap_or(AnyValue::Null)
+}
+
+fn opt_string(s: Option<String>) -> AnyValue<'static> {
+    s.map(|s| AnyValue::StringOwned(s.into())).unwrap_or(AnyValue::Null)
+}
+
+fn opt_address(a: Option<&Address>) -> AnyValue<'static> {
+    opt_string(a.and_then(|a| a.render_string().ok()))
+}
+
+fn time_value(t: i64) -> AnyValue<'static> {
+    AnyValue::Datetime(t, TimeUnit::Milliseconds, &None)
+}
+
+fn enum_name<E: std::fmt::Debug>(e: Option<E>) -> AnyValue<'static> {
+    opt_string(e.map(|e| format!("{:?}", e)))
+}
+
+fn input_address(i: &Input) -> Option<Address> {
+    i.address().ok()
+        .or(i.address_descriptor.as_ref().map(|d| d.to_address()))
+        .or(i.proof.first().and_then(|p| p.public_key.as_ref()).and_then(|pk| pk.address().ok()))
+}
+
+fn transaction_rows(tx: &Transaction, rows: &mut LakeRows) -> RgResult<()> {
+    let time = *tx.time()?;
+    let hash = tx.hash_or();
+    let input_addresses = tx.input_address_set();
+    rows.push(&TRANSACTIONS, time, vec![
+        time_value(time),
+        hash_value(&hash),
+        hash_value(&tx.signable_hash()),
+        hash_value(&tx.signed_hash()),
+        opt_hash_value(tx.counter_party_hash().as_ref()),
+        opt_hash_value(tx.confirmation_signing_hash().as_ref()),
+        enum_name(tx.transaction_type().ok()),
+        AnyValue::Boolean(tx.is_test()),
+        AnyValue::Int32(tx.inputs.len() as i32),
+        AnyValue::Int32(tx.outputs.len() as i32),
+        AnyValue::Int64(tx.total_output_amount()),
+        AnyValue::Int64(tx.remainder_amount()),
+        opt_address(tx.first_input_address().as_ref()),
+        opt_address(tx.first_output_address_non_input_or_fee().as_ref()),
+        enum_name(tx.first_contract_type()),
+    ]);
+    for (idx, i) in tx.inputs.iter().enumerate() {
+        let spent = i.utxo_id.as_ref().and_then(|u| u.transaction_hash.as_ref().map(|h| (h, u.output_index)));
+        rows.push(&INPUTS, time, vec![
+            time_value(time),
+            hash_value(&hash),
+            AnyValue::Int32(idx as i32),
+            opt_hash_value(spent.map(|(h, _)| h)),
+            spent.map(|(_, o)| AnyValue::Int64(o)).unwrap_or(AnyValue::Null),
+            opt_address(input_address(i).as_ref()),
+            AnyValue::Boolean(i.address_descriptor.is_some()),
+            AnyValue::Int32(i.proof.len() as i32),
+            opt_string(i.proof.first().and_then(|p| p.public_key.as_ref()).map(|pk| pk.hex())),
+        ]);
+        if let Some((spent_hash, spent_index)) = spent {
+            rows.push(&UTXO_SPENDS, time, vec![
+                time_value(time),
+                hash_value(spent_hash),
+                AnyValue::Int64(spent_index),
+                hash_value(&hash),
+                AnyValue::Int32(idx as i32),
+            ]);
+        }
+    }
+    for (idx, o) in tx.outputs.iter().enumerate() {
+        let contract_type = o.contract.as_ref()
+            .and_then(|c| c.standard_contract_type)
+            .and_then(redgold_schema::structs::StandardContractType::from_i32);
+        rows.push(&OUTPUTS, time, vec![
+            time_value(time),
+            hash_value(&hash),
+            AnyValue::Int32(idx as i32),
+            opt_address(o.address.as_ref()),
+            o.opt_amount().map(AnyValue::Int64).unwrap_or(AnyValue::Null),
+            AnyValue::Boolean(o.is_fee()),
+            AnyValue::Boolean(o.address.as_ref().map(|a| input_addresses.contains(a)).unwrap_or(false)),
+            enum_name(o.output_type.and_then(redgold_schema::structs::OutputType::from_i32)),
+            enum_name(contract_type),
+        ]);
+    }
+    Ok(())
+}
+
+fn observation_rows(tx: &Transaction, rows: &mut LakeRows) -> RgResult<()> {
+    let time = *tx.time()?;
+    let observation = tx.observation()?;
+    rows.push(&OBSERVATIONS, time, vec![
+        time_value(time),
+        hash_value(&tx.hash_or()),
+        opt_string(tx.observation_public_key().ok().map(|pk| pk.hex())),
+        tx.height().ok().map(AnyValue::Int64).unwrap_or(AnyValue::Null),
+        opt_hash_value(observation.merkle_root.as_ref()),
+        AnyValue::Int32(observation.observations.len() as i32),
+        opt_hash_value(observation.parent_id.as_ref().and_then(|p| p.transaction_hash.as_ref())),
+    ]);
+    Ok(())
+}
+
+fn observation_edge_rows(edge: &ObservationEdge, rows: &mut LakeRows) -> RgResult<()> {
+    let Some(proof) = edge.observation_proof.as_ref() else { return Ok(()) };
+    let (Some(observation_hash), Some(metadata)) = (proof.observation_hash.as_ref(), proof.metadata.as_ref()) else {
+        return Ok(());
+    };
+    let Some(observed_hash) = metadata.observed_hash.as_ref() else { return Ok(()) };
+    let merkle = proof.merkle_proof.as_ref();
+    rows.push(&OBSERVATION_EDGES, edge.time, vec![
+        time_value(edge.time),
+        hash_value(observation_hash),
+        hash_value(observed_hash),
+        opt_hash_value(merkle.and_then(|m| m.root.as_ref())),
+        opt_hash_value(merkle.and_then(|m| m.leaf.as_ref())),
+        enum_name(Some(metadata.state())),
+        enum_name(Some(metadata.observation_type())),
+        opt_string(proof.proof.as_ref().and_then(|p| p.public_key.as_ref()).map(|pk| pk.hex())),
+    ]);
+    Ok(())
+}
+
+fn price_time_rows(p: &PriceTime, rows: &mut LakeRows) {
+    rows.push(&PRICE_TIME, p.time, vec![
+        time_value(p.time),
+        enum_name(PriceSource::from_i32(p.source)),
+        enum_name(SupportedCurrency::from_i32(p.currency)),
+        enum_name(SupportedCurrency::from_i32(p.denomination)),
+        p.price.as_ref().map(|w| AnyValue::Float64(w.to_float())).unwrap_or(AnyValue::Null),
+    ]);
+}
+
+/// Hive partition directory for a row, `year=YYYY/month=MM`.
+pub fn partition_path(time: i64) -> PathBuf {
+    let ym = time.to_year_month_utc();
+    let (year, month) = ym.split_once('_').unwrap_or((ym.as_str(), "00"));
+    PathBuf::from(format!("year={}", year)).join(format!("month={}", month))
+}
+
+/// Write one page of rows. Files are named by the position the page starts after, so
+/// re-exporting a page after an interrupted run replaces its files instead of duplicating rows.
+fn write_page(root: &Path, page_start: i64, rows: LakeRows) -> RgResult<usize> {
+    let mut written = 0;
+    for table in LAKE_TABLES {
+        let Some(table_rows) = rows.rows.get(table.name) else { continue };
+        let schema = table.schema();
+        let by_partition = table_rows.iter().into_group_map_by(|(t, _)| partition_path(*t));
+        for (partition, part_rows) in by_partition {
+            let dir = root.join(table.name).join(partition);
+            std::fs::create_dir_all(&dir).error_info("Failed to create lake partition directory")?;
+            let rows = part_rows.into_iter().map(|(_, r)| r.clone()).collect_vec();
+            let mut df = DataFrame::from_rows_and_schema(&rows, &schema)
+                .error_info("Failed to create DataFrame")?;
+            let file = File::create(dir.join(format!("part-{}.parquet", page_start)))
+                .error_info("Failed to create Parquet file")?;
+            ParquetWriter::new(file)
+                .with_statistics(true)
+                .with_compression(ParquetCompression::Snappy)
+                .finish(&mut df)
+                .error_info("Failed to write DataFrame to Parquet")?;
+            written += rows.len();
+        }
+    }
+    Ok(written)
+}
+
+/// Independently checkpointed inputs to the lake.
+#[derive(Clone, Copy, Debug)]
+enum LakeSource {
+    /// Transactions, inputs, outputs and utxo spends all come from accepted transactions.
+    Transactions,
+    Observations,
+    ObservationEdges,
+    PriceTime,
+}
+
+impl LakeSource {
+    const ALL: [LakeSource; 4] = [
+        LakeSource::Transactions, LakeSource::Observations, LakeSource::ObservationEdges, LakeSource::PriceTime
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            LakeSource::Transactions => "transactions",
+            LakeSource::Observations => "observations",
+            LakeSource::ObservationEdges => "observation_edges",
+            LakeSource::PriceTime => "price_time",
+        }
+    }
+
+    /// Up to a page of rows stored after position `after`, with the position of the last one.
+    /// Queries finish before rows are built so the rows aren't held across awaits.
+    async fn rows(&self, ds: &DataStore, after: i64) -> RgResult<(LakeRows, Option<i64>)> {
+        let mut rows = LakeRows::default();
+        let last = match self {
+            LakeSource::Transactions => {
+                let txs = ds.transaction_store.query_accepted_after_position(after, i64::MAX, PAGE_ROWS).await?;
+                for (_, tx) in &txs {
+                    transaction_rows(tx, &mut rows)?;
+                }
+                txs.last().map(|(p, _)| *p)
+            }
+            LakeSource::Observations => {
+                let observations = ds.observation.query_observations_after_position(after, PAGE_ROWS).await?;
+                for (_, tx) in &observations {
+                    observation_rows(tx, &mut rows)?;
+                }
+                observations.last().map(|(p, _)| *p)
+            }
+            LakeSource::ObservationEdges => {
+                let edges = ds.observation.query_observation_edges_after_position(after, PAGE_ROWS).await?;
+                for (_, edge) in &edges {
+                    observation_edge_rows(edge, &mut rows)?;
+                }
+                edges.last().map(|(p, _)| *p)
+            }
+            LakeSource::PriceTime => {
+                let prices = ds.price_time.select_price_time_after_position(after, PAGE_ROWS).await?;
+                for (_, p) in &prices {
+                    price_time_rows(p, &mut rows);
+                }
+                prices.last().map(|(p, _)| *p)
+            }
+        };
+        Ok((rows, last))
+    }
+}
+
+#[async_trait]
+pub trait ParquetLakeExporter {
+    /// Append everything stored since the last export to the lake at `root`. Rows are exported
+    /// in insertion order, so transactions and peer observations that arrive late with earlier
+    /// times are still picked up. A row replaced in the database is exported again.
+    async fn parquet_lake_export(&self, root: &PathBuf) -> RgResult<LakeCheckpoint>;
+}
+
+#[async_trait]
+impl ParquetLakeExporter for DataStore {
+    async fn parquet_lake_export(&self, root: &PathBuf) -> RgResult<LakeCheckpoint> {
+        std::fs::create_dir_all(root).error_info("Failed to create lake directory")?;
+        let mut checkpoint = LakeCheckpoint::load(root)?;
+        if checkpoint.version < LAKE_VERSION {
+            info!("Rebuilding parquet lake exported by time windows at {}", root.display());
+            for table in LAKE_TABLES {
+                let dir = root.join(table.name);
+                if dir.exists() {
+                    std::fs::remove_dir_all(&dir).error_info("Failed to remove outdated lake table")?;
+                }
+            }
+            checkpoint = LakeCheckpoint { version: LAKE_VERSION, ..LakeCheckpoint::default() };
+            checkpoint.save(root)?;
+        }
+        for table in LAKE_TABLES {
+            let dir = root.join(table.name);
+            std::fs::create_dir_all(&dir).error_info("Failed to create lake table directory")?;
+            std::fs::write(dir.join(SCHEMA_FILE), table.schema_json().to_string())
+                .error_info("Failed to write lake table schema")?;
+        }
+
+        for source in LakeSource::ALL {
+            let mut after = checkpoint.exported_position.get(source.name()).cloned().unwrap_or(0);
+            loop {
+                let (rows, last) = source.rows(self, after).await?;
+                let Some(last) = last else { break };
+                let written = write_page(root, after, rows)?;
+                if written > 0 {
+                    info!("Exported {} {} lake rows after position {}", written, source.name(), after);
+                    counter!("redgold_parquet_lake_rows", "source" => source.name()).increment(written as u64);
+                }
+                checkpoint.exported_position.insert(source.name().to_string(), last);
+                checkpoint.save(root)?;
+                after = last;
+            }
+        }
+        Ok(checkpoint)
+    }
+}
+
+#[test]
+fn transaction_rows_match_table_schemas() {
+    use redgold_schema::structs::{Output, UtxoId};
+    let mut tx = Transaction::default();
+    let mut input = Input::default();
+    input.utxo_id = Some(UtxoId::new(&Hash::from_string_calculate("parent"), 1));
+    tx.inputs.push(input);
+    tx.outputs.push(Output::default());
+    tx.outputs.push(Output::default());
+    tx.struct_metadata = Some(Default::default());
+    tx.struct_metadata.as_mut().unwrap().time = Some(1_700_000_000_000);
+
+    let mut rows = LakeRows::default();
+    transaction_rows(&tx, &mut rows).expect("rows");
+    for (table, expected) in [(&TRANSACTIONS, 1), (&INPUTS, 1), (&OUTPUTS, 2), (&UTXO_SPENDS, 1)] {
+        let table_rows = rows.rows.get(table.name).expect("table rows");
+        assert_eq!(table_rows.len(), expected);
+        let rows = table_rows.iter().map(|(_, r)| r.clone()).collect_vec();
+        let df = DataFrame::from_rows_and_schema(&rows, &table.schema()).expect("dataframe");
+        assert_eq!(df.shape(), (expected, table.columns.len()));
+    }
+    assert_eq!(partition_path(1_700_000_000_000), PathBuf::from("year=2023").join("month=11"));
+}
+
+#[test]
+fn export_resumes_from_checkpoint_and_reexports_idempotently() {
+    use redgold_schema::structs::{Output, PublicKey, TransactionOptions};
+    fn files(dir: &Path, res: &mut Vec<PathBuf>) {
+        for entry in std::fs::read_dir(dir).expect("read lake dir").flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                files(&path, res);
+            } else if path.extension().map(|e| e == "parquet").unwrap_or(false) {
+                res.push(path);
+            }
+        }
+        res.sort();
+    }
+    fn tx_at(time: i64) -> Transaction {
+        let address = Address::from_struct_public(&PublicKey::from_bytes_direct_ecdsa(vec![2; 33])).expect("address");
+        let mut output = Output::default();
+        output.address = Some(address);
+        let mut tx = Transaction::default();
+        tx.outputs.push(output);
+        tx.options = Some(TransactionOptions::default());
+        tx.struct_metadata = Some(Default::default());
+        tx.struct_metadata.as_mut().unwrap().time = Some(time);
+        tx.with_hash();
+        tx
+    }
+
+    let dir = std::env::temp_dir().join(format!("redgold_parquet_lake_{}", redgold_schema::util::times::current_time_millis()));
+    std::fs::create_dir_all(&dir).expect("test dir");
+    let root = dir.join("lake");
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().expect("runtime");
+    runtime.block_on(async {
+        let ds = DataStore::from_file_path(dir.join("data_store.sqlite").to_str().expect("path").to_string()).await;
+        ds.run_migrations().await.expect("migrations");
+        ds.accept_transaction(&tx_at(1_700_000_000_000), 1_700_000_000_000, None, false).await.expect("accept");
+
+        let first = ds.parquet_lake_export(&root).await.expect("export");
+        let position = *first.exported_position.get("transactions").expect("transactions checkpoint");
+        assert_eq!(LakeCheckpoint::load(&root).expect("checkpoint"), first);
+        let mut first_files = vec![];
+        files(&root.join(TRANSACTIONS.name), &mut first_files);
+        assert_eq!(first_files.len(), 1);
+
+        // Nothing new, so nothing is rewritten and the checkpoint stays put.
+        let second = ds.parquet_lake_export(&root).await.expect("export");
+        assert_eq!(second, first);
+        let mut second_files = vec![];
+        files(&root.join(TRANSACTIONS.name), &mut second_files);
+        assert_eq!(second_files, first_files);
+
+        // Accepted after the checkpoint with an earlier time, still exported.
+        ds.accept_transaction(&tx_at(1_600_000_000_000), 1_600_000_000_000, None, false).await.expect("accept");
+        let third = ds.parquet_lake_export(&root).await.expect("export");
+        assert!(*third.exported_position.get("transactions").expect("transactions checkpoint") > position);
+        let mut third_files = vec![];
+        files(&root.join(TRANSACTIONS.name), &mut third_files);
+        assert_eq!(third_files.len(), 2);
+        assert!(third_files.iter().any(|f| f.starts_with(root.join(TRANSACTIONS.name).join(partition_path(1_600_000_000_000)))));
+
+        // Re-exporting the last page after an interrupted run replaces its files.
+        let mut interrupted = third.clone();
+        interrupted.exported_position.insert("transactions".to_string(), position);
+        interrupted.save(&root).expect("save");
+        let fourth = ds.parquet_lake_export(&root).await.expect("export");
+        assert_eq!(fourth, third);
+        let mut fourth_files = vec![];
+        files(&root.join(TRANSACTIONS.name), &mut fourth_files);
+        assert_eq!(fourth_files, third_files);
+    });
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -49,6 +49,29 @@ impl PriceTimeStore {
         Ok(rows_m.last_insert_rowid() as i64)
     }
 
+    /// Prices in insertion order after `after`, paired with their positions (the table rowid).
+    pub async fn select_price_time_after_position(&self, after: i64, limit: i64) -> RgResult<Vec<(i64, PriceTime)>> {
+        let rows = DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"SELECT rowid AS "position!: i64", source, currency, denomination, time, price FROM price_time
+            WHERE rowid > ?1 ORDER BY rowid ASC LIMIT ?2"#,
+            after,
+            limit
+        )
+            .fetch_all(&mut *self.ctx.pool().await?)
+            .await)?;
+        let mut res = vec![];
+        for x in rows {
+            res.push((x.position, PriceTime {
+                source: PriceSource::from_i32(x.source as i32).safe_get()?.clone() as i32,
+                currency: SupportedCurrency::from_i32(x.currency as i32).safe_get()?.clone() as i32,
+                denomination: SupportedCurrency::from_i32(x.denomination as i32).safe_get()?.clone() as i32,
+                time: x.time,
+                price: Some(Weighting::from_float_basis(x.price, 1e8 as i64))
+            }));
+        }
+        Ok(res)
+    }
+
     pub async fn select_price_time_range(&self, start: i64, end: i64) -> RgResult<Vec<PriceTime>> {
         let mut pool = self.ctx.pool().await?;
         let rows = sqlx::query!(
@@ -79,6 +79,39 @@ impl StateStore {
         };
         x.iter().map(|i| ContractStateMarker::proto_deserialize(i.clone())).collect()
     }
+    /// Most recent marker for the address with a time at or before `time`, optionally restricted
+    /// to a selector.
+    pub async fn query_state_at(&self,
+        address: &Address,
+        selector: Option<&StateSelector>,
+        time: i64
+    ) -> Result<Option<ContractStateMarker>, ErrorInfo> {
+
+        let mut pool = self.ctx.pool().await?;
+        let addr = address.vec();
+        let x = if let Some(sel) = selector {
+            let h = sel.calculate_hash().vec();
+            let rows = sqlx::query!(
+            r#"SELECT state FROM state WHERE address = ?1 AND selector_hash = ?2 AND time <= ?3 ORDER BY index_counter DESC LIMIT 1"#,
+            addr,
+            h,
+            time
+            ).fetch_optional(&mut *pool)
+                .await;
+            let rows_m = DataStoreContext::map_err_sqlx(rows)?;
+            rows_m.map(|i| i.state.clone())
+        } else {
+            let rows = sqlx::query!(
+            r#"SELECT state FROM state WHERE address = ?1 AND time <= ?2 ORDER BY index_counter DESC LIMIT 1"#,
+            addr,
+            time
+            ).fetch_optional(&mut *pool)
+                .await;
+            let rows_m = DataStoreContext::map_err_sqlx(rows)?;
+            rows_m.map(|i| i.state.clone())
+        };
+        x.map(|i| ContractStateMarker::proto_deserialize(i)).transpose()
+    }
     pub async fn clean_up(&self,
         address: &Address,
         state_sel: &StateSelector,
@@ -5,6 +5,7 @@ use redgold_schema::proto_serde::ProtoSerde;
 use redgold_schema::structs::{ErrorInfo, Transaction};
 use redgold_schema::RgResult;
 use sqlx::Sqlite;
+use std::collections::HashSet;
 
 impl TransactionStore {
 
@@ -110,12 +111,35 @@ impl TransactionStore {
         (hash, transaction_proto, time, rejection_reason) VALUES (?1, ?2, ?3, ?4)"#,
            hash, ser, time, rejection_ser
         )
-            .execute(&mut *self.ctx.pool().await?)
+            .execute(&mut **sqlite_tx)
             .await;
         let rows_m = DataStoreContext::map_err_sqlx(rows)?;
+        self.insert_rejected_address_transaction(tx, time, sqlite_tx).await?;
         Ok(rows_m.last_insert_rowid())
     }
 
+    /// Index rejected transactions by address for search. Rejected transactions may be malformed,
+    /// so inputs without a resolvable address are skipped rather than failing the insert.
+    async fn insert_rejected_address_transaction(
+        &self, tx: &Transaction, time: i64, sqlite_tx: &mut sqlx::Transaction<'_, Sqlite>
+    ) -> RgResult<()> {
+        let hash = tx.hash_or().vec();
+        let addresses = tx.inputs.iter().filter_map(|i| i.address().ok())
+            .chain(tx.outputs.iter().filter_map(|o| o.address.clone()))
+            .collect::<HashSet<_>>();
+        for address in addresses {
+            let address_vec = address.proto_serialize();
+            let rows = sqlx::query!(
+                r#"INSERT OR REPLACE INTO rejected_address_transaction (address, tx_hash, time) VALUES (?1, ?2, ?3)"#,
+                address_vec, hash, time
+            )
+                .execute(&mut **sqlite_tx)
+                .await;
+            DataStoreContext::map_err_sqlx(rows)?;
+        }
+        Ok(())
+    }
+
 
 
 }
\ No newline at end of file
@@ -98,6 +98,10 @@ impl TransactionStore {
     }
 
     async fn delete_rejected_before(&self, min_time: i64) -> Result<u64, ErrorInfo> {
+        DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"DELETE FROM rejected_address_transaction WHERE time < ?1"#,
+            min_time
+        ).execute(&mut *self.ctx.pool().await?).await)?;
         Ok(DataStoreContext::map_err_sqlx(sqlx::query!(
             r#"DELETE FROM rejected_transactions WHERE time < ?1"#,
             min_time
@@ -66,6 +66,39 @@ impl TransactionStore {
             }).collect()
     }
 
+    /// Accepted transactions in acceptance order with their positions, after `after` and up to
+    /// `until` inclusive. The position is the table rowid, which grows with every insert so it
+    /// follows acceptance rather than the client chosen transaction time.
+    pub async fn query_accepted_after_position(
+        &self,
+        after: i64,
+        until: i64,
+        limit: i64
+    ) -> RgResult<Vec<(i64, Transaction)>> {
+        let rows = DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"SELECT rowid AS "position!: i64", transaction_proto FROM transactions
+            WHERE rowid > ?1 AND rowid <= ?2 ORDER BY rowid ASC LIMIT ?3"#,
+            after,
+            until,
+            limit
+        )
+            .fetch_all(&mut *self.ctx.pool().await?)
+            .await)?;
+        rows.into_iter()
+            .map(|row| Ok((row.position, Transaction::proto_deserialize(row.transaction_proto)?)))
+            .collect()
+    }
+
+    /// Position of the most recently accepted transaction, 0 when there are none.
+    pub async fn max_accepted_position(&self) -> RgResult<i64> {
+        let row = DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"SELECT MAX(rowid) AS "position: i64" FROM transactions"#
+        )
+            .fetch_one(&mut *self.ctx.pool().await?)
+            .await)?;
+        Ok(row.position.unwrap_or(0))
+    }
+
     // This query stream really needs to be done all in the same function to deal with ownership
     // issues. If using this in the future, then do it directly in line.
     // pub async fn transaction_accepted_ordered_stream(
@@ -86,6 +119,17 @@ impl TransactionStore {
     //     Ok(stream)
     // }
 
+    /// Accepted metadata transactions (peer and node updates) from before `end`, oldest first.
+    pub async fn query_accepted_metadata_before(&self, end: i64) -> RgResult<Vec<Transaction>> {
+        DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"SELECT transaction_proto FROM transactions WHERE is_metadata = 1 AND time < ?1 ORDER BY time ASC"#,
+            end
+        )
+            .fetch_all(&mut *self.ctx.pool().await?)
+            .await)?.into_iter().map(|row| Transaction::proto_deserialize(row.transaction_proto))
+            .collect::<RgResult<Vec<Transaction>>>()
+    }
+
     pub async fn query_accepted_transaction(
         &self,
         transaction_hash: &Hash,
@@ -369,6 +413,117 @@ impl TransactionStore {
     }
 
 
+    /// Search accepted and rejected transactions with a time before `max_time`, newest first,
+    /// returning each with its rejection reason along with the total count of matches. With
+    /// `match_any` a transaction matching any supplied filter is returned, otherwise every
+    /// supplied filter must match.
+    pub async fn search_transactions(
+        &self,
+        transaction_hash: Option<&Hash>,
+        address: Option<&Address>,
+        success: Option<bool>,
+        match_any: bool,
+        max_time: i64,
+        limit: i64,
+        offset: i64,
+    ) -> RgResult<(Vec<(Transaction, Option<ErrorInfo>)>, i64)> {
+        let mut pool = self.ctx.pool().await?;
+        let h = transaction_hash.map(|h| h.vec());
+        let a = address.map(|a| a.vec());
+        let rows = sqlx::query!(
+            r#"WITH matched AS (
+                SELECT t.hash, t.transaction_proto, t.time, NULL AS rejection_reason, 1 AS success,
+                    EXISTS (SELECT 1 FROM address_transaction at WHERE at.tx_hash = t.hash AND at.address = ?2) AS has_address
+                FROM transactions t WHERE t.time < ?5
+                UNION ALL
+                SELECT r.hash, r.transaction_proto, r.time, r.rejection_reason, 0 AS success,
+                    EXISTS (SELECT 1 FROM rejected_address_transaction ra WHERE ra.tx_hash = r.hash AND ra.address = ?2) AS has_address
+                FROM rejected_transactions r WHERE r.time < ?5
+            )
+            SELECT transaction_proto as "transaction_proto!: Vec<u8>", rejection_reason as "rejection_reason?: Vec<u8>"
+            FROM matched WHERE CASE WHEN ?4 THEN
+                ((?1 IS NOT NULL AND hash = ?1) OR (?2 IS NOT NULL AND has_address) OR (?3 IS NOT NULL AND success = ?3))
+            ELSE
+                ((?1 IS NULL OR hash = ?1) AND (?2 IS NULL OR has_address) AND (?3 IS NULL OR success = ?3))
+            END
+            ORDER BY time DESC, hash ASC LIMIT ?6 OFFSET ?7"#,
+            h, a, success, match_any, max_time, limit, offset
+        )
+            .fetch_all(&mut *pool)
+            .await;
+        let mut res = vec![];
+        for r in DataStoreContext::map_err_sqlx(rows)? {
+            let tx = Transaction::proto_deserialize(r.transaction_proto)?;
+            let rejection = r.rejection_reason.map(ErrorInfo::proto_deserialize).transpose()?;
+            res.push((tx, rejection));
+        }
+        let count = sqlx::query!(
+            r#"WITH matched AS (
+                SELECT t.hash, 1 AS success,
+                    EXISTS (SELECT 1 FROM address_transaction at WHERE at.tx_hash = t.hash AND at.address = ?2) AS has_address
+                FROM transactions t WHERE t.time < ?5
+                UNION ALL
+                SELECT r.hash, 0 AS success,
+                    EXISTS (SELECT 1 FROM rejected_address_transaction ra WHERE ra.tx_hash = r.hash AND ra.address = ?2) AS has_address
+                FROM rejected_transactions r WHERE r.time < ?5
+            )
+            SELECT COUNT(*) as "count!: i64" FROM matched WHERE CASE WHEN ?4 THEN
+                ((?1 IS NOT NULL AND hash = ?1) OR (?2 IS NOT NULL AND has_address) OR (?3 IS NOT NULL AND success = ?3))
+            ELSE
+                ((?1 IS NULL OR hash = ?1) AND (?2 IS NULL OR has_address) AND (?3 IS NULL OR success = ?3))
+            END"#,
+            h, a, success, match_any, max_time
+        )
+            .fetch_one(&mut *pool)
+            .await;
+        let count = DataStoreContext::map_err_sqlx(count)?.count;
+        Ok((res, count))
+    }
+
+    pub async fn insert_pending_acceptance(&self, tx: &Transaction, expiry: i64) -> RgResult<()> {
+        let signed_hash = tx.signed_hash().vec();
+        let proto = tx.proto_serialize();
+        DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"INSERT OR REPLACE INTO pending_acceptance (signed_hash, transaction_proto, expiry) VALUES (?1, ?2, ?3)"#,
+            signed_hash,
+            proto,
+            expiry
+        )
+            .execute(&mut *self.ctx.pool().await?)
+            .await)?;
+        Ok(())
+    }
+
+    pub async fn delete_pending_acceptance(&self, signed_hash: &Hash) -> RgResult<()> {
+        let bytes = signed_hash.vec();
+        DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"DELETE FROM pending_acceptance WHERE signed_hash = ?1"#,
+            bytes
+        )
+            .execute(&mut *self.ctx.pool().await?)
+            .await)?;
+        Ok(())
+    }
+
+    pub async fn delete_pending_acceptances_expired_before(&self, time: i64) -> RgResult<u64> {
+        let res = DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"DELETE FROM pending_acceptance WHERE expiry < ?1"#,
+            time
+        )
+            .execute(&mut *self.ctx.pool().await?)
+            .await)?;
+        Ok(res.rows_affected())
+    }
+
+    pub async fn query_pending_acceptances(&self) -> RgResult<Vec<Transaction>> {
+        DataStoreContext::map_err_sqlx(sqlx::query!(
+            r#"SELECT transaction_proto FROM pending_acceptance ORDER BY expiry ASC"#
+        )
+            .fetch_all(&mut *self.ctx.pool().await?)
+            .await)?.into_iter().map(|row| Transaction::proto_deserialize(row.transaction_proto))
+            .collect::<RgResult<Vec<Transaction>>>()
+    }
+
     //
     // // This doesn't seem to work correctly, not returning proper xor
     // pub async fn xor_transaction_order(&self, hash: &Hash) -> RgResult<Vec<(Vec<u8>, Vec<u8>)>> {
@@ -2,4 +2,5 @@ mod evm_wrapper;
 mod fork_ref_transact;
 mod fork_ref_transact2;
 mod revm_wrapper;
-mod sol_compile;
\ No newline at end of file
+mod sol_compile;
+pub mod revm_executor;
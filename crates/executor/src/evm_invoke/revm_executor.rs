@@ -0,0 +1,441 @@
+use std::sync::Arc;
+
+use revm::db::InMemoryDB;
+use revm::precompile::{Precompile, PrecompileError, PrecompileResult, StatefulPrecompile};
+use revm::primitives::{keccak256, AccountInfo, Address, Bytecode, Bytes, CreateScheme, EVMError, Env, ExecutionResult as EvmExecutionResult, HaltReason, Output, TransactTo, B256, U256};
+use revm::{ContextPrecompile, Evm, EvmBuilder};
+
+use redgold_schema::exec::ExecutionLimits;
+use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+use redgold_schema::proto_serde::ProtoSerde;
+use redgold_schema::structs::{ErrorCode, EvmContractState, EvmStorageSlot, ExecutionInput, ExecutionResult, StandardData, Transaction};
+use redgold_schema::{bytes_data, error_info, error_message, RgResult};
+
+use crate::host::{HostQueryCache, HostStateReader, HOST_FUNCTION_NAMES};
+
+// Every contract runs in its own isolated database, so it always lives at the same address.
+const CONTRACT_ADDRESS: [u8; 20] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x0c, 0x01
+];
+const REDGOLD_EVM_CHAIN_ID: u64 = 16180;
+
+fn contract_address() -> Address {
+    Address::from(CONTRACT_ADDRESS)
+}
+
+/// Host functions are exposed to EVM contracts as precompiles at consecutive addresses starting
+/// here, in the order of `HOST_FUNCTION_NAMES`. Calldata is a serialized HostQueryRequest and the
+/// return data a serialized HostQueryResponse, same as the wasm host ABI.
+pub const HOST_PRECOMPILE_BASE: u16 = 0x0c10;
+
+pub fn host_precompile_address(index: usize) -> Address {
+    let mut a = [0u8; 20];
+    a[18..].copy_from_slice(&(HOST_PRECOMPILE_BASE + index as u16).to_be_bytes());
+    Address::from(a)
+}
+
+/// Charged like the wasm host functions, the host call fuel plus request and response bytes.
+struct HostPrecompile {
+    function_name: &'static str,
+    cache: HostQueryCache,
+    host_call_fuel: u64,
+    fuel_per_byte: u64,
+}
+
+impl StatefulPrecompile for HostPrecompile {
+    fn call(&self, bytes: &Bytes, gas_limit: u64, _env: &Env) -> PrecompileResult {
+        // The request is paid for before the host is queried, so a call without the gas for it
+        // never reaches the database.
+        let request_cost = self.host_call_fuel
+            .saturating_add(self.fuel_per_byte.saturating_mul(bytes.len() as u64));
+        if request_cost > gas_limit {
+            return Err(PrecompileError::OutOfGas);
+        }
+        let response = self.cache.respond(self.function_name, bytes.to_vec())
+            .map_err(|e| PrecompileError::Other(e.message))?;
+        let cost = request_cost.saturating_add(self.fuel_per_byte.saturating_mul(response.len() as u64));
+        if cost > gas_limit {
+            return Err(PrecompileError::OutOfGas);
+        }
+        Ok((cost, Bytes::from(response)))
+    }
+}
+
+fn host_precompiles(host: Arc<dyn HostStateReader>, limits: &ExecutionLimits) -> Vec<(Address, ContextPrecompile<InMemoryDB>)> {
+    let cache = HostQueryCache::new(host);
+    HOST_FUNCTION_NAMES.iter().enumerate().map(|(i, name)| {
+        let p = HostPrecompile {
+            function_name: name,
+            cache: cache.clone(),
+            host_call_fuel: limits.host_call_fuel,
+            fuel_per_byte: limits.fuel_per_byte,
+        };
+        (host_precompile_address(i), ContextPrecompile::Ordinary(Precompile::Stateful(Arc::new(p))))
+    }).collect()
+}
+
+/// Caller is derived from the first input address of the requesting transaction, zero when
+/// there is none (e.g. direct invocation in tests.)
+fn caller(tx: Option<&Transaction>) -> Address {
+    tx.and_then(|t| t.inputs.first())
+        .and_then(|i| i.address().ok())
+        .map(|a| Address::from_slice(&keccak256(a.proto_serialize()).0[12..]))
+        .unwrap_or(Address::ZERO)
+}
+
+fn new_evm(
+    args: &ExecutionInput,
+    limits: &ExecutionLimits,
+    db: InMemoryDB,
+    transact_to: TransactTo,
+    data: Vec<u8>,
+    host: Arc<dyn HostStateReader>,
+) -> Evm<'static, (), InMemoryDB> {
+    // Block values must only depend on the request, so every node sees the same environment.
+    let time = args.tx.as_ref().and_then(|t| t.time().ok().cloned()).unwrap_or(0);
+    let caller = caller(args.tx.as_ref());
+    let precompiles = host_precompiles(host, limits);
+    EvmBuilder::default()
+        .with_db(db)
+        .modify_cfg_env(|cfg| {
+            cfg.chain_id = REDGOLD_EVM_CHAIN_ID;
+            cfg.limit_contract_code_size = Some(limits.max_code_bytes);
+        })
+        .modify_block_env(|block| {
+            block.timestamp = U256::from((time.max(0) / 1000) as u64);
+            block.basefee = U256::ZERO;
+            block.gas_limit = U256::from(limits.fuel_limit);
+        })
+        .modify_tx_env(|tx| {
+            tx.caller = caller;
+            tx.gas_limit = limits.fuel_limit;
+            tx.gas_price = U256::ZERO;
+            tx.value = U256::ZERO;
+            tx.transact_to = transact_to;
+            tx.data = Bytes::from(data);
+        })
+        .append_handler_register_box(Box::new(move |handler| {
+            let load = handler.pre_execution.load_precompiles.clone();
+            let host = precompiles.clone();
+            handler.pre_execution.load_precompiles = Arc::new(move || {
+                let mut p = load();
+                p.extend(host.clone());
+                p
+            });
+        }))
+        .build()
+}
+
+fn load_state(state_bytes: &[u8]) -> RgResult<(EvmContractState, InMemoryDB)> {
+    let state = EvmContractState::proto_deserialize_ref(&state_bytes.to_vec())?;
+    let mut db = InMemoryDB::default();
+    let code = Bytecode::new_raw(Bytes::from(state.code.clone()));
+    let info = AccountInfo::new(U256::ZERO, 1, code.hash_slow(), code);
+    db.insert_account_info(contract_address(), info);
+    for slot in &state.storage {
+        db.insert_account_storage(contract_address(), word(&slot.key)?, word(&slot.value)?)
+            .map_err(|_| error_info("Unable to load contract storage"))?;
+    }
+    Ok((state, db))
+}
+
+fn word(bytes: &Vec<u8>) -> RgResult<U256> {
+    if bytes.len() != 32 {
+        return Err(error_message(ErrorCode::ContractStateMismatch, format!(
+            "Invalid EVM storage word length {}", bytes.len()
+        )));
+    }
+    Ok(U256::from_be_bytes::<32>(B256::from_slice(bytes).0))
+}
+
+/// Merge the storage written by the execution into the previous slots, zeroed slots are removed
+/// so equal storage always serializes to the same state bytes.
+fn next_state(
+    previous: Option<&EvmContractState>,
+    code: Vec<u8>,
+    changes: &revm::primitives::State
+) -> EvmContractState {
+    let mut storage = previous.map(|p| p.storage.iter()
+        .map(|s| (s.key.clone(), s.value.clone()))
+        .collect::<std::collections::BTreeMap<_, _>>()
+    ).unwrap_or_default();
+    if let Some(account) = changes.get(&contract_address()) {
+        for (k, v) in account.storage.iter() {
+            let key = k.to_be_bytes::<32>().to_vec();
+            if v.present_value == U256::ZERO {
+                storage.remove(&key);
+            } else {
+                storage.insert(key, v.present_value.to_be_bytes::<32>().to_vec());
+            }
+        }
+    }
+    let mut state = EvmContractState::default();
+    state.code = code;
+    state.storage = storage.into_iter().map(|(key, value)| {
+        let mut s = EvmStorageSlot::default();
+        s.key = key;
+        s.value = value;
+        s
+    }).collect();
+    state
+}
+
+fn map_result(
+    result: EvmExecutionResult,
+    state: Option<EvmContractState>,
+    limits: &ExecutionLimits
+) -> ExecutionResult {
+    let gas_used = result.gas_used();
+    let er = match result {
+        EvmExecutionResult::Success { output, .. } => {
+            let output = match output {
+                Output::Call(b) => b.to_vec(),
+                Output::Create(_, _) => vec![],
+            };
+            if output.len() > limits.max_output_bytes {
+                ExecutionResult::from_error(error_message(ErrorCode::ExecutionOutputLimitExceeded, format!(
+                    "EVM output of {} bytes exceeds limit of {}", output.len(), limits.max_output_bytes
+                )))
+            } else {
+                let mut er = ExecutionResult::default();
+                er.valid = true;
+                let mut data = StandardData::default();
+                data.data = bytes_data(output);
+                data.state = state.map(|s| s.proto_serialize()).and_then(bytes_data);
+                er.data = Some(data);
+                er
+            }
+        }
+        EvmExecutionResult::Revert { output, .. } => {
+            let mut er = ExecutionResult::from_error(error_message(
+                ErrorCode::ExecutionReverted, format!("EVM execution reverted 0x{}", hex::encode(&output))
+            ));
+            let mut data = StandardData::default();
+            data.data = bytes_data(output.to_vec());
+            er.data = Some(data);
+            er
+        }
+        EvmExecutionResult::Halt { reason: HaltReason::OutOfGas(_), .. } => {
+            ExecutionResult::from_error(error_message(ErrorCode::ExecutionFuelExhausted, format!(
+                "EVM execution ran out of gas, limit {}", limits.fuel_limit
+            )))
+        }
+        EvmExecutionResult::Halt { reason, .. } => {
+            ExecutionResult::from_error(error_info(format!("EVM execution halted {:?}", reason)))
+        }
+    };
+    er.with_fuel(gas_used, limits.fuel_limit)
+}
+
+fn evm_error<DB: std::fmt::Debug>(e: EVMError<DB>) -> ExecutionResult {
+    let code = match &e {
+        EVMError::Transaction(revm::primitives::InvalidTransaction::CallGasCostMoreThanGasLimit) =>
+            ErrorCode::ExecutionFuelExhausted,
+        _ => ErrorCode::ContractCodeInvalid
+    };
+    ExecutionResult::from_error(error_message(code, format!("EVM transaction rejected {:?}", e)))
+}
+
+/// Static checks run before a contract is accepted for deployment.
+pub fn validate_evm_contract(code: &[u8], limits: &ExecutionLimits) -> RgResult<()> {
+    if code.len() > limits.max_code_bytes {
+        return Err(error_message(ErrorCode::ContractCodeInvalid, format!(
+            "Contract code of {} bytes exceeds limit of {}", code.len(), limits.max_code_bytes
+        )));
+    }
+    if code.is_empty() {
+        return Err(error_message(ErrorCode::ContractCodeInvalid, "Empty EVM init code"));
+    }
+    Ok(())
+}
+
+/// Run the init code in `code` as a contract creation. `args.input` is appended to the init code
+/// as ABI encoded constructor arguments. The resulting runtime code and storage become the
+/// initial EvmContractState. Gas is the fuel unit, capped by `limits.fuel_limit`. Host precompiles
+/// read through `host`, which may block, so this runs on a blocking thread.
+pub fn deploy_evm_contract(
+    code: &[u8],
+    args: ExecutionInput,
+    limits: &ExecutionLimits,
+    host: Arc<dyn HostStateReader>,
+) -> RgResult<ExecutionResult> {
+    validate_evm_contract(code, limits)?;
+    let mut init = code.to_vec();
+    if let Some(i) = args.input.as_ref() {
+        init.extend(i.value.clone());
+    }
+    let mut evm = new_evm(&args, limits, InMemoryDB::default(), TransactTo::Create(CreateScheme::Create), init, host);
+    let res = match evm.transact() {
+        Ok(r) => r,
+        Err(e) => return Ok(evm_error(e)),
+    };
+    let created = match &res.result {
+        EvmExecutionResult::Success { output: Output::Create(_, Some(a)), .. } => Some(*a),
+        _ => None
+    };
+    let state = created.and_then(|a| res.state.get(&a)).map(|account| {
+        let code = account.info.code.as_ref().map(|c| c.original_bytes().to_vec()).unwrap_or_default();
+        // Storage written by the constructor lives at the created address, rebase it onto the
+        // fixed contract address used for later invocations.
+        let mut rebased = revm::primitives::State::default();
+        rebased.insert(contract_address(), account.clone());
+        next_state(None, code, &rebased)
+    });
+    let er = map_result(res.result, state, limits);
+    if er.valid && er.data.as_ref().and_then(|d| d.state.as_ref()).is_none() {
+        return Err(error_message(ErrorCode::ContractCodeInvalid, "EVM deploy produced no runtime code"));
+    }
+    Ok(er)
+}
+
+/// Call the contract held in `args.state` (a serialized EvmContractState) with `args.input` as
+/// ABI encoded calldata. Successful calls return the ABI encoded return value in `data.data`
+/// and the updated contract state in `data.state`, reverts return the revert data.
+pub fn invoke_evm_contract(
+    args: ExecutionInput,
+    limits: &ExecutionLimits,
+    host: Arc<dyn HostStateReader>,
+) -> RgResult<ExecutionResult> {
+    let state_bytes = args.state.as_ref().map(|s| s.value.clone()).unwrap_or_default();
+    let (previous, db) = load_state(&state_bytes)?;
+    let calldata = args.input.as_ref().map(|i| i.value.clone()).unwrap_or_default();
+    let mut evm = new_evm(&args, limits, db, TransactTo::Call(contract_address()), calldata, host);
+    let res = match evm.transact() {
+        Ok(r) => r,
+        Err(e) => return Ok(evm_error(e)),
+    };
+    let state = next_state(Some(&previous), previous.code.clone(), &res.state);
+    Ok(map_result(res.result, Some(state), limits))
+}
+
+#[cfg(test)]
+fn abi_string(selector: &str, s: &str) -> Vec<u8> {
+    let mut data = hex::decode(selector).unwrap();
+    let mut offset = [0u8; 32];
+    offset[31] = 0x20;
+    data.extend(offset);
+    let mut len = [0u8; 32];
+    len[31] = s.len() as u8;
+    data.extend(len);
+    let mut body = s.as_bytes().to_vec();
+    body.resize(32, 0);
+    data.extend(body);
+    data
+}
+
+#[test]
+fn evm_deploy_invoke_persists_storage() {
+    let code = hex::decode(include_str!("res/hello2.bin").trim()).unwrap();
+    let limits = ExecutionLimits::default();
+    let host: Arc<dyn HostStateReader> = Arc::new(crate::host::UnavailableHostState);
+    let deployed = deploy_evm_contract(&code, ExecutionInput::default(), &limits, host.clone()).unwrap();
+    assert!(deployed.valid);
+    assert!(deployed.fuel_consumed_or() > 0);
+    let state = deployed.data.unwrap().state.unwrap();
+
+    let mut args = ExecutionInput::default();
+    args.state = Some(state);
+    args.input = bytes_data(abi_string("c47f0027", "redgold"));
+    let set = invoke_evm_contract(args, &limits, host.clone()).unwrap();
+    assert!(set.valid);
+    let state = set.data.unwrap().state.unwrap();
+    let parsed = EvmContractState::proto_deserialize_ref(&state.value).unwrap();
+    assert_eq!(parsed.storage.len(), 1);
+
+    let mut args = ExecutionInput::default();
+    args.state = Some(state.clone());
+    args.input = bytes_data(hex::decode("17d7de7c").unwrap());
+    let get = invoke_evm_contract(args.clone(), &limits, host.clone()).unwrap();
+    assert!(get.valid);
+    let data = get.data.unwrap();
+    assert_eq!(data.state, Some(state));
+    let out = data.data.unwrap().value;
+    assert_eq!(&out[64..71], b"redgold");
+
+    // Unknown selector reverts, too little gas exhausts fuel
+    args.input = bytes_data(hex::decode("deadbeef").unwrap());
+    let revert = invoke_evm_contract(args.clone(), &limits, host.clone()).unwrap();
+    assert_eq!(revert.error_info().map(|e| e.code), Some(ErrorCode::ExecutionReverted as i32));
+    let mut small = limits.clone();
+    small.fuel_limit = 21_100;
+    args.input = bytes_data(hex::decode("17d7de7c").unwrap());
+    let oog = invoke_evm_contract(args, &small, host).unwrap();
+    assert_eq!(oog.error_info().map(|e| e.code), Some(ErrorCode::ExecutionFuelExhausted as i32));
+}
+
+#[test]
+fn evm_host_precompile_answers_queries() {
+    use redgold_schema::exec::HOST_ABI_VERSION;
+    use redgold_schema::structs::{CurrencyAmount, HostQueryRequest, HostQueryResponse};
+    // Copies calldata to memory, staticcalls the balance precompile and returns its return data
+    let code = hex::decode("36600060003760006000366000610c115afa503d600060003e3d6000f3").unwrap();
+    let mut state = EvmContractState::default();
+    state.code = code;
+    let mut request = HostQueryRequest::default();
+    request.abi_version = HOST_ABI_VERSION;
+    request.address = Some(redgold_schema::structs::Address::default());
+
+    let mut args = ExecutionInput::default();
+    args.state = bytes_data(state.proto_serialize());
+    args.input = bytes_data(request.proto_serialize());
+    let limits = ExecutionLimits::default();
+    let res = invoke_evm_contract(args, &limits, Arc::new(crate::host::FixedHostState)).unwrap();
+    assert!(res.valid);
+    let out = res.data.unwrap().data.unwrap().value;
+    let response = HostQueryResponse::proto_deserialize(out).unwrap();
+    assert!(response.error.is_none());
+    assert_eq!(response.balance, Some(CurrencyAmount::from(42)));
+    assert_eq!(host_precompile_address(1), Address::from_slice(&hex::decode("0000000000000000000000000000000000000c11").unwrap()));
+}
+
+#[test]
+fn evm_host_precompile_charges_request_before_querying() {
+    use redgold_schema::exec::{HOST_ABI_VERSION, HOST_FN_BALANCE};
+    use redgold_schema::party::central_price::CentralPricePair;
+    use redgold_schema::structs::{ContractStateMarker, CurrencyAmount, HostQueryRequest, StateSelector, SupportedCurrency, UtxoEntry, UtxoId};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    #[derive(Default)]
+    struct CountingHost(AtomicUsize);
+    impl HostStateReader for CountingHost {
+        fn snapshot_time(&self) -> i64 {
+            1000
+        }
+        fn utxo(&self, _utxo_id: &UtxoId) -> RgResult<Option<UtxoEntry>> {
+            Ok(None)
+        }
+        fn balance(&self, _address: &redgold_schema::structs::Address, _currency: SupportedCurrency) -> RgResult<CurrencyAmount> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(CurrencyAmount::from(42))
+        }
+        fn contract_state(
+            &self, _address: &redgold_schema::structs::Address, _selector: Option<&StateSelector>
+        ) -> RgResult<Option<ContractStateMarker>> {
+            Ok(None)
+        }
+        fn central_price(&self, _currency: SupportedCurrency) -> RgResult<Option<CentralPricePair>> {
+            Ok(None)
+        }
+    }
+    let host = Arc::new(CountingHost::default());
+    let limits = ExecutionLimits::default();
+    let precompile = HostPrecompile {
+        function_name: HOST_FN_BALANCE,
+        cache: HostQueryCache::new(host.clone()),
+        host_call_fuel: limits.host_call_fuel,
+        fuel_per_byte: limits.fuel_per_byte,
+    };
+    let mut request = HostQueryRequest::default();
+    request.abi_version = HOST_ABI_VERSION;
+    request.address = Some(redgold_schema::structs::Address::default());
+    let bytes = Bytes::from(request.proto_serialize());
+    let request_cost = limits.host_call_fuel + limits.fuel_per_byte * bytes.len() as u64;
+
+    let env = Env::default();
+    assert!(matches!(precompile.call(&bytes, request_cost - 1, &env), Err(PrecompileError::OutOfGas)));
+    assert_eq!(host.0.load(Ordering::SeqCst), 0);
+    // Enough for the request but not the response, the query ran but the call still fails
+    assert!(matches!(precompile.call(&bytes, request_cost, &env), Err(PrecompileError::OutOfGas)));
+    assert_eq!(host.0.load(Ordering::SeqCst), 1);
+    let (cost, response) = precompile.call(&bytes, u64::MAX, &env).expect("host call");
+    assert_eq!(cost, request_cost + limits.fuel_per_byte * response.len() as u64);
+}
@@ -1,10 +1,22 @@
-use extism::{Context, Plugin};
+use extism::manifest::MemoryOptions;
+use extism::{Context, Function, Manifest, Plugin};
 
+use redgold_schema::exec::ExecutionLimits;
 use redgold_schema::helpers::easy_json::EasyJson;
 use redgold_schema::observability::errors::EnhanceErrorInfo;
 use redgold_schema::proto_serde::ProtoSerde;
-use redgold_schema::structs::{ExecutionInput, ExecutionResult, TestContractInternalState, TestContractRequest, TestContractUpdate2};
-use redgold_schema::{bytes_data, error_info, ErrorInfoContext, RgResult};
+use redgold_schema::structs::{ErrorCode, ExecutionInput, ExecutionResult, TestContractInternalState, TestContractRequest, TestContractUpdate2};
+use redgold_schema::{bytes_data, error_info, error_message, ErrorInfoContext, RgResult};
+use std::sync::Arc;
+use std::time::Instant;
+
+use wasm_instrument::parity_wasm;
+use wasm_instrument::parity_wasm::elements::{Internal, Module};
+
+use crate::host::{host_functions, HostStateReader};
+use crate::metering::{instrument_module, FuelMeter};
+
+pub const EXTISM_ENTRYPOINT: &str = "extism_entrypoint";
 
 pub async fn invoke_wasm(
     wasm_bytes: &[u8],
@@ -34,23 +46,187 @@ pub async fn invoke_wasm(
     ExecutionResult::proto_deserialize(data.to_vec())
 }
 
+/// Metered invocation, the module is instrumented to charge fuel deterministically and run
+/// under the memory, stack, output and timeout ceilings in `limits`. Setup failures (invalid
+/// module, missing function) are returned as errors, while failures during execution produce
+/// an invalid `ExecutionResult` carrying the error code and the fuel consumed so far.
+pub async fn invoke_wasm_metered(
+    wasm_bytes: &[u8],
+    function_name: impl Into<String>,
+    args: ExecutionInput,
+    limits: &ExecutionLimits,
+) -> RgResult<ExecutionResult> {
+    let meter = FuelMeter::new(limits);
+    invoke_wasm_metered_with(wasm_bytes, function_name, args, limits, &meter, vec![]).await
+}
+
+/// Plugin execution is CPU bound and host functions block on chain state queries, so the call
+/// runs on a blocking thread where that is permitted under any runtime flavor.
+pub async fn invoke_wasm_metered_with(
+    wasm_bytes: &[u8],
+    function_name: impl Into<String>,
+    args: ExecutionInput,
+    limits: &ExecutionLimits,
+    meter: &FuelMeter,
+    host_functions: Vec<Function>,
+) -> RgResult<ExecutionResult> {
+    let instrumented = instrument_module(wasm_bytes, limits)?;
+    let fname = function_name.into();
+    let limits = limits.clone();
+    let meter = meter.clone();
+    tokio::task::spawn_blocking(move || {
+        invoke_instrumented(instrumented, fname, args, &limits, &meter, host_functions)
+    }).await.error_info("Contract execution task failed")?
+}
+
+fn invoke_instrumented(
+    instrumented: Vec<u8>,
+    fname: String,
+    args: ExecutionInput,
+    limits: &ExecutionLimits,
+    meter: &FuelMeter,
+    host_functions: Vec<Function>,
+) -> RgResult<ExecutionResult> {
+    let manifest = Manifest::new([extism::manifest::Wasm::data(instrumented)])
+        .disallow_all_hosts()
+        .with_memory_options(MemoryOptions { max_pages: Some(limits.max_memory_pages) })
+        .with_timeout(limits.timeout.clone());
+
+    let mut functions = vec![meter.host_function()];
+    functions.extend(host_functions);
+
+    let context = Context::new();
+    let mut plugin = Plugin::new_with_manifest(
+        &context,
+        &manifest,
+        functions,
+        false
+    ).map_err(|e|
+        error_info(
+            format!("Unable to build metered plugin while invoking wasm {}", e.to_string())))?;
+
+    if !plugin.has_function(fname.clone()) {
+        return Err(error_info(format!("Function not found {}", fname.clone())))?
+    }
+
+    let input = args.proto_serialize();
+    let with_fuel = |r: ExecutionResult| r.with_fuel(meter.consumed(), meter.limit());
+    if let Err(e) = meter.charge_bytes(input.len()) {
+        return Ok(with_fuel(ExecutionResult::from_error(e)));
+    }
+
+    // Extism surfaces the epoch interruption as a plain message through its C API, so a timeout
+    // is identified by the elapsed time against the configured limit rather than the error text.
+    let started = Instant::now();
+    let call_result = plugin.call(fname.clone(), input).map(|d| d.to_vec());
+    let data = match call_result {
+        Ok(d) => d,
+        Err(e) => {
+            let mut err = if meter.exhausted() {
+                FuelMeter::exhausted_error(meter.limit())
+            } else if started.elapsed() >= limits.timeout {
+                error_message(ErrorCode::ExecutionTimeout, format!(
+                    "Contract execution exceeded timeout of {:?}", limits.timeout
+                ))
+            } else {
+                error_info(format!("Error calling function {}", e.to_string()))
+            };
+            err.with_detail("function", fname);
+            return Ok(with_fuel(ExecutionResult::from_error(err)));
+        }
+    };
+
+    if data.len() > limits.max_output_bytes {
+        let err = error_message(ErrorCode::ExecutionOutputLimitExceeded, format!(
+            "Contract output of {} bytes exceeds limit of {}", data.len(), limits.max_output_bytes
+        ));
+        return Ok(with_fuel(ExecutionResult::from_error(err)));
+    }
+    if let Err(e) = meter.charge_bytes(data.len()) {
+        return Ok(with_fuel(ExecutionResult::from_error(e)));
+    }
+    let result = ExecutionResult::proto_deserialize(data)
+        .add("Unable to decode contract execution result")?;
+    Ok(with_fuel(result))
+}
+
 pub async fn invoke_extism_wasm(
     wasm_bytes: &[u8],
     args: ExecutionInput
 ) -> RgResult<ExecutionResult> {
-    invoke_wasm(wasm_bytes, "extism_entrypoint", args).await
+    invoke_wasm(wasm_bytes, EXTISM_ENTRYPOINT, args).await
+}
+
+/// Metered invocation of the standard contract entrypoint with the read-only chain state host
+/// functions linked against `host`.
+pub async fn invoke_extism_wasm_metered(
+    wasm_bytes: &[u8],
+    args: ExecutionInput,
+    limits: &ExecutionLimits,
+    host: Arc<dyn HostStateReader>,
+) -> RgResult<ExecutionResult> {
+    let meter = FuelMeter::new(limits);
+    let functions = host_functions(host, &meter);
+    invoke_wasm_metered_with(wasm_bytes, EXTISM_ENTRYPOINT, args, limits, &meter, functions).await
+}
+
+/// Static checks run before a contract is accepted for deployment, the module must fit the size
+/// limit, parse, export the standard entrypoint and survive instrumentation under `limits`.
+pub fn validate_extism_contract(wasm_bytes: &[u8], limits: &ExecutionLimits) -> RgResult<()> {
+    if wasm_bytes.len() > limits.max_code_bytes {
+        return Err(error_message(ErrorCode::ContractCodeInvalid, format!(
+            "Contract code of {} bytes exceeds limit of {}", wasm_bytes.len(), limits.max_code_bytes
+        )));
+    }
+    let module = parity_wasm::deserialize_buffer::<Module>(wasm_bytes)
+        .map_err(|e| error_message(ErrorCode::ContractCodeInvalid, format!(
+            "Unable to parse contract wasm module {}", e.to_string()
+        )))?;
+    let exports_entrypoint = module.export_section().map(|e| e.entries().iter().any(|e|
+        e.field() == EXTISM_ENTRYPOINT && matches!(e.internal(), Internal::Function(_))
+    )).unwrap_or(false);
+    if !exports_entrypoint {
+        return Err(error_message(ErrorCode::ContractCodeInvalid, format!(
+            "Contract module does not export function {}", EXTISM_ENTRYPOINT
+        )));
+    }
+    instrument_module(wasm_bytes, limits)
+        .map_err(|e| error_message(ErrorCode::ContractCodeInvalid, e.message))?;
+    Ok(())
+}
+
+/// Validate a contract and run its genesis invocation against empty state. The result must be
+/// valid and carry the initial state which becomes index 0 for the contract address.
+pub async fn deploy_extism_contract(
+    wasm_bytes: &[u8],
+    args: ExecutionInput,
+    limits: &ExecutionLimits,
+    host: Arc<dyn HostStateReader>,
+) -> RgResult<ExecutionResult> {
+    validate_extism_contract(wasm_bytes, limits)?;
+    let er = invoke_extism_wasm_metered(wasm_bytes, args, limits, host).await?;
+    if !er.valid {
+        return Err(er.error_info().cloned()
+            .unwrap_or(error_info("Contract genesis execution returned invalid result")));
+    }
+    if er.data.as_ref().and_then(|d| d.state.as_ref()).is_none() {
+        return Err(error_info("Contract genesis execution did not return an initial state"));
+    }
+    Ok(er)
 }
 
 // TODO: impl AsRef<u8>
 pub async fn invoke_extism_wasm_direct(
     wasm_bytes: impl AsRef<[u8]>,
     input: &Vec<u8>,
-    state: &Vec<u8>
+    state: &Vec<u8>,
+    limits: &ExecutionLimits,
+    host: Arc<dyn HostStateReader>,
 ) -> RgResult<ExecutionResult> {
     let mut args = ExecutionInput::default();
     args.input = bytes_data(input.clone());
     args.state = bytes_data(state.clone());
-    invoke_wasm(wasm_bytes.as_ref(), "extism_entrypoint", args).await
+    invoke_extism_wasm_metered(wasm_bytes.as_ref(), args, limits, host).await
 }
 
 
@@ -121,4 +297,48 @@ fn debug_test() {
     let data = plugin.call("count_vowels", "this is a test");
     println!("data: {:?}", data);
     // assert_eq!(data, b"{\"count\": 4}");
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn metered_infinite_loop_exhausts_fuel() {
+    // (module (memory 1) (func (export "spin") (result i32) (loop (br 0)) (i32.const 0)))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        0x07, 0x08, 0x01, 0x04, 0x73, 0x70, 0x69, 0x6e, 0x00, 0x00,
+        0x0a, 0x0b, 0x01, 0x09, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x41, 0x00, 0x0b,
+    ];
+    let mut limits = ExecutionLimits::default();
+    limits.fuel_limit = 100_000;
+    let res = invoke_wasm_metered(&wasm, "spin", ExecutionInput::default(), &limits).await.unwrap();
+    assert!(!res.valid);
+    assert_eq!(res.fuel_consumed, Some(100_000));
+    assert_eq!(res.fuel_limit, Some(100_000));
+    assert_eq!(res.error_info().map(|e| e.code), Some(ErrorCode::ExecutionFuelExhausted as i32));
+}
+
+#[test]
+fn validate_contract_requires_entrypoint_and_size() {
+    // (module (memory 1) (func (export "extism_entrypoint") (result i32) i32.const 1))
+    let mut wasm = vec![
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        0x07, 0x15, 0x01, 0x11,
+    ];
+    wasm.extend_from_slice(EXTISM_ENTRYPOINT.as_bytes());
+    wasm.extend_from_slice(&[0x00, 0x00, 0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x01, 0x0b]);
+    let mut limits = ExecutionLimits::default();
+    validate_extism_contract(&wasm, &limits).unwrap();
+
+    let mut renamed = wasm.clone();
+    renamed[28] = b'X';
+    let err = validate_extism_contract(&renamed, &limits).unwrap_err();
+    assert_eq!(err.code, ErrorCode::ContractCodeInvalid as i32);
+
+    limits.max_code_bytes = 16;
+    let err = validate_extism_contract(&wasm, &limits).unwrap_err();
+    assert_eq!(err.code, ErrorCode::ContractCodeInvalid as i32);
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use extism::{CurrentPlugin, Function, UserData, Val, ValType};
+use extism_runtime::InternalExt;
+
+use redgold_schema::exec::{HOST_ABI_VERSION, HOST_FN_BALANCE, HOST_FN_CENTRAL_PRICE, HOST_FN_CONTRACT_STATE, HOST_FN_UTXO};
+use redgold_schema::helpers::easy_json::EasyJson;
+use redgold_schema::party::central_price::CentralPricePair;
+use redgold_schema::proto_serde::ProtoSerde;
+use redgold_schema::structs::{Address, ContractStateMarker, CurrencyAmount, ErrorInfo, HostQueryRequest, HostQueryResponse, StateSelector, SupportedCurrency, UtxoEntry, UtxoId};
+use redgold_schema::{error_info, RgResult, SafeOption};
+
+use crate::metering::FuelMeter;
+
+pub const HOST_FUNCTION_NAMES: [&str; 4] = [
+    HOST_FN_UTXO, HOST_FN_BALANCE, HOST_FN_CONTRACT_STATE, HOST_FN_CENTRAL_PRICE
+];
+
+/// Read-only view of chain state exposed to contracts. Implementations must answer every
+/// query as of `snapshot_time` (the ordering point of the transaction being executed) rather
+/// than the live tip, otherwise nodes executing the same request at different moments
+/// would diverge.
+pub trait HostStateReader: Send + Sync {
+    fn snapshot_time(&self) -> i64;
+    fn utxo(&self, utxo_id: &UtxoId) -> RgResult<Option<UtxoEntry>>;
+    fn balance(&self, address: &Address, currency: SupportedCurrency) -> RgResult<CurrencyAmount>;
+    fn contract_state(
+        &self, address: &Address, selector: Option<&StateSelector>
+    ) -> RgResult<Option<ContractStateMarker>>;
+    fn central_price(&self, currency: SupportedCurrency) -> RgResult<Option<CentralPricePair>>;
+}
+
+/// Used where no chain state is available (e.g. deploy time), every query returns an error
+/// to the contract rather than failing to link.
+#[derive(Clone, Debug, Default)]
+pub struct UnavailableHostState;
+
+impl HostStateReader for UnavailableHostState {
+    fn snapshot_time(&self) -> i64 {
+        0
+    }
+    fn utxo(&self, _utxo_id: &UtxoId) -> RgResult<Option<UtxoEntry>> {
+        Err(error_info("Chain state is not available to this execution"))
+    }
+    fn balance(&self, _address: &Address, _currency: SupportedCurrency) -> RgResult<CurrencyAmount> {
+        Err(error_info("Chain state is not available to this execution"))
+    }
+    fn contract_state(
+        &self, _address: &Address, _selector: Option<&StateSelector>
+    ) -> RgResult<Option<ContractStateMarker>> {
+        Err(error_info("Chain state is not available to this execution"))
+    }
+    fn central_price(&self, _currency: SupportedCurrency) -> RgResult<Option<CentralPricePair>> {
+        Err(error_info("Chain state is not available to this execution"))
+    }
+}
+
+fn query_inner(
+    reader: &dyn HostStateReader,
+    function_name: &str,
+    request: &HostQueryRequest,
+    response: &mut HostQueryResponse
+) -> RgResult<()> {
+    if request.abi_version != HOST_ABI_VERSION {
+        return Err(error_info(format!(
+            "Unsupported host ABI version {}, expected {}", request.abi_version, HOST_ABI_VERSION
+        )));
+    }
+    match function_name {
+        HOST_FN_UTXO => {
+            let utxo_id = request.utxo_id.safe_get_msg("Missing utxo_id")?;
+            response.utxo_entry = reader.utxo(utxo_id)?;
+        }
+        HOST_FN_BALANCE => {
+            let address = request.address.safe_get_msg("Missing address")?;
+            // Balances are per currency, RDG unless the request names another
+            let currency = match request.currency {
+                None => SupportedCurrency::Redgold,
+                Some(c) => SupportedCurrency::from_i32(c)
+                    .ok_or(error_info(format!("Invalid currency {}", c)))?
+            };
+            response.balance = Some(reader.balance(address, currency)?);
+        }
+        HOST_FN_CONTRACT_STATE => {
+            let address = request.address.safe_get_msg("Missing address")?;
+            response.contract_state = reader.contract_state(address, request.selector.as_ref())?;
+        }
+        HOST_FN_CENTRAL_PRICE => {
+            let currency = request.currency.safe_get_msg("Missing currency")?;
+            let currency = SupportedCurrency::from_i32(*currency)
+                .ok_or(error_info(format!("Invalid currency {}", currency)))?;
+            response.central_price_pair_json = reader.central_price(currency)?.map(|p| p.json_or());
+        }
+        _ => return Err(error_info(format!("Unknown host function {}", function_name)))
+    }
+    Ok(())
+}
+
+/// Answer a single host query. Failures are reported inside the response so the contract can
+/// decide how to handle a missing value instead of trapping.
+pub fn query(
+    reader: &dyn HostStateReader,
+    function_name: &str,
+    request: &HostQueryRequest
+) -> HostQueryResponse {
+    let mut response = HostQueryResponse::default();
+    response.snapshot_time = reader.snapshot_time();
+    if let Err(e) = query_inner(reader, function_name, request, &mut response) {
+        response = HostQueryResponse::default();
+        response.snapshot_time = reader.snapshot_time();
+        response.error = Some(e);
+    }
+    response
+}
+
+/// Memoizes responses for the lifetime of one invocation so repeated reads of the same key
+/// always observe the same value.
+#[derive(Clone)]
+pub(crate) struct HostQueryCache {
+    reader: Arc<dyn HostStateReader>,
+    responses: Arc<Mutex<HashMap<(String, Vec<u8>), Vec<u8>>>>,
+}
+
+impl HostQueryCache {
+    pub(crate) fn new(reader: Arc<dyn HostStateReader>) -> Self {
+        Self {
+            reader,
+            responses: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub(crate) fn respond(&self, function_name: &str, request_bytes: Vec<u8>) -> RgResult<Vec<u8>> {
+        let key = (function_name.to_string(), request_bytes);
+        let mut responses = self.responses.lock()
+            .map_err(|e| error_info(format!("Host query cache lock poisoned {}", e.to_string())))?;
+        if let Some(r) = responses.get(&key) {
+            return Ok(r.clone());
+        }
+        let response = match HostQueryRequest::proto_deserialize_ref(&key.1) {
+            Ok(request) => query(self.reader.as_ref(), function_name, &request),
+            Err(e) => {
+                let mut r = HostQueryResponse::default();
+                r.error = Some(e);
+                r
+            }
+        };
+        let bytes = response.proto_serialize();
+        responses.insert(key, bytes.clone());
+        Ok(bytes)
+    }
+}
+
+fn host_error(e: ErrorInfo) -> anyhow::Error {
+    anyhow::Error::msg(e.json_or())
+}
+
+fn host_call(
+    plugin: &mut CurrentPlugin,
+    inputs: &[Val],
+    outputs: &mut [Val],
+    function_name: &str,
+    cache: &HostQueryCache,
+    meter: &FuelMeter,
+) -> Result<(), anyhow::Error> {
+    meter.charge_host_call().map_err(host_error)?;
+    let offset = inputs.get(0).and_then(|v| v.i64())
+        .ok_or(anyhow::Error::msg("Missing host query offset"))? as usize;
+    let request_bytes = plugin.memory().get(offset)?.to_vec();
+    meter.charge_bytes(request_bytes.len()).map_err(host_error)?;
+    let response = cache.respond(function_name, request_bytes).map_err(host_error)?;
+    meter.charge_bytes(response.len()).map_err(host_error)?;
+    let block = plugin.memory_mut().alloc_bytes(response)?;
+    outputs[0] = Val::I64(block.offset as i64);
+    Ok(())
+}
+
+/// Build the full versioned set of host functions backed by `reader`. Every call is charged
+/// the host call fuel plus the request and response bytes against `meter`.
+pub fn host_functions(reader: Arc<dyn HostStateReader>, meter: &FuelMeter) -> Vec<Function> {
+    let cache = HostQueryCache::new(reader);
+    HOST_FUNCTION_NAMES.iter().map(|name| {
+        let name = name.to_string();
+        let cache = cache.clone();
+        let meter = meter.clone();
+        Function::new(
+            name.clone(),
+            [ValType::I64],
+            [ValType::I64],
+            None::<UserData>,
+            move |plugin, inputs, outputs, _user_data| {
+                host_call(plugin, inputs, outputs, &name, &cache, &meter)
+            }
+        )
+    }).collect()
+}
+
+
+#[cfg(test)]
+pub(crate) struct FixedHostState;
+
+#[cfg(test)]
+impl HostStateReader for FixedHostState {
+    fn snapshot_time(&self) -> i64 {
+        1000
+    }
+    fn utxo(&self, _utxo_id: &UtxoId) -> RgResult<Option<UtxoEntry>> {
+        Ok(None)
+    }
+    fn balance(&self, _address: &Address, currency: SupportedCurrency) -> RgResult<CurrencyAmount> {
+        match currency {
+            SupportedCurrency::Redgold => Ok(CurrencyAmount::from(42)),
+            _ => Ok(CurrencyAmount::from_currency(7, currency)),
+        }
+    }
+    fn contract_state(
+        &self, _address: &Address, _selector: Option<&StateSelector>
+    ) -> RgResult<Option<ContractStateMarker>> {
+        Ok(None)
+    }
+    fn central_price(&self, _currency: SupportedCurrency) -> RgResult<Option<CentralPricePair>> {
+        Ok(None)
+    }
+}
+
+#[test]
+fn host_query_versioning_and_dispatch() {
+    let reader = FixedHostState;
+    let mut request = HostQueryRequest::default();
+    request.address = Some(Address::default());
+
+    let res = query(&reader, HOST_FN_BALANCE, &request);
+    assert!(res.error.is_some());
+    assert!(res.balance.is_none());
+
+    request.abi_version = HOST_ABI_VERSION;
+    let res = query(&reader, HOST_FN_BALANCE, &request);
+    assert!(res.error.is_none());
+    assert_eq!(res.balance, Some(CurrencyAmount::from(42)));
+    assert_eq!(res.snapshot_time, 1000);
+
+    request.currency = Some(SupportedCurrency::Ethereum as i32);
+    let res = query(&reader, HOST_FN_BALANCE, &request);
+    assert_eq!(res.balance, Some(CurrencyAmount::from_currency(7, SupportedCurrency::Ethereum)));
+    request.currency = None;
+
+    assert!(query(&reader, HOST_FN_UTXO, &request).error.is_some());
+    assert!(query(&reader, "redgold_v1_unknown", &request).error.is_some());
+    assert!(query(&UnavailableHostState, HOST_FN_BALANCE, &request).error.is_some());
+}
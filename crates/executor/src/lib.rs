@@ -1,6 +1,8 @@
 #![allow(unused_imports)]
 mod hello_world;
 pub mod extism_wrapper;
+pub mod metering;
+pub mod host;
 pub mod evm_invoke;
 
 pub fn debug() {
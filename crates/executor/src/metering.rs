@@ -0,0 +1,204 @@
+use std::sync::{Arc, Mutex};
+
+use extism::{Function, ValType};
+use wasm_instrument::gas_metering::{host_function, ConstantCostRules};
+use wasm_instrument::parity_wasm;
+use wasm_instrument::parity_wasm::elements::{External, MemoryType, Module};
+
+use redgold_schema::exec::ExecutionLimits;
+use redgold_schema::structs::ErrorCode;
+use redgold_schema::{error_info, error_message, RgResult};
+
+/// Import namespace / name of the fuel charging function injected into every contract module.
+pub const FUEL_MODULE_NAME: &str = "env";
+pub const FUEL_FUNCTION_NAME: &str = "redgold_fuel";
+
+#[derive(Clone, Debug, Default)]
+struct FuelMeterState {
+    consumed: u64,
+    limit: u64,
+    exhausted: bool,
+}
+
+/// Shared fuel counter for a single invocation, charged from the injected instruction metering
+/// import as well as from host functions and the input / output transfer.
+#[derive(Clone, Debug, Default)]
+pub struct FuelMeter {
+    state: Arc<Mutex<FuelMeterState>>,
+    host_call_fuel: u64,
+    fuel_per_byte: u64,
+}
+
+impl FuelMeter {
+    pub fn new(limits: &ExecutionLimits) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(FuelMeterState {
+                consumed: 0,
+                limit: limits.fuel_limit,
+                exhausted: false,
+            })),
+            host_call_fuel: limits.host_call_fuel,
+            fuel_per_byte: limits.fuel_per_byte,
+        }
+    }
+
+    /// Charge an amount of fuel, once the limit is crossed the meter stays exhausted and all
+    /// further charges fail. Consumption is capped at the limit so results are identical
+    /// regardless of where exactly the budget ran out.
+    pub fn charge(&self, amount: u64) -> RgResult<()> {
+        let mut state = self.state.lock()
+            .map_err(|e| error_info(format!("Fuel meter lock poisoned {}", e.to_string())))?;
+        if state.exhausted {
+            return Err(Self::exhausted_error(state.limit));
+        }
+        let next = state.consumed.saturating_add(amount);
+        if next > state.limit {
+            state.consumed = state.limit;
+            state.exhausted = true;
+            return Err(Self::exhausted_error(state.limit));
+        }
+        state.consumed = next;
+        Ok(())
+    }
+
+    pub fn charge_host_call(&self) -> RgResult<()> {
+        self.charge(self.host_call_fuel)
+    }
+
+    pub fn charge_bytes(&self, len: usize) -> RgResult<()> {
+        self.charge((len as u64).saturating_mul(self.fuel_per_byte))
+    }
+
+    pub fn consumed(&self) -> u64 {
+        self.state.lock().map(|s| s.consumed).unwrap_or(0)
+    }
+
+    pub fn limit(&self) -> u64 {
+        self.state.lock().map(|s| s.limit).unwrap_or(0)
+    }
+
+    pub fn exhausted(&self) -> bool {
+        self.state.lock().map(|s| s.exhausted).unwrap_or(true)
+    }
+
+    pub fn exhausted_error(limit: u64) -> redgold_schema::structs::ErrorInfo {
+        error_message(
+            ErrorCode::ExecutionFuelExhausted,
+            format!("Contract execution exhausted fuel limit of {}", limit)
+        )
+    }
+
+    /// Host function satisfying the injected `env.redgold_fuel(i64)` import.
+    pub fn host_function(&self) -> Function {
+        let meter = self.clone();
+        Function::new(
+            FUEL_FUNCTION_NAME,
+            [ValType::I64],
+            [],
+            None,
+            move |_internal, inputs, _outputs, _user_data| {
+                let amount = inputs.get(0).and_then(|v| v.i64()).unwrap_or(0).max(0) as u64;
+                meter.charge(amount).map_err(|_| anyhow::Error::msg("fuel exhausted"))
+            }
+        )
+    }
+}
+
+/// Rewrite a contract module so that it charges fuel for every executed block, cannot grow
+/// beyond the configured memory ceiling and cannot exceed the configured stack height.
+pub fn instrument_module(wasm_bytes: &[u8], limits: &ExecutionLimits) -> RgResult<Vec<u8>> {
+    let mut module = parity_wasm::deserialize_buffer::<Module>(wasm_bytes)
+        .map_err(|e| error_info(format!("Unable to parse contract wasm module {}", e.to_string())))?;
+
+    if module.import_section().map(|i| i.entries().iter().any(|e|
+        e.module() == FUEL_MODULE_NAME && e.field() == FUEL_FUNCTION_NAME
+    )).unwrap_or(false) {
+        return Err(error_info("Contract module must not import the reserved fuel function"));
+    }
+
+    cap_memory(&mut module, limits.max_memory_pages)?;
+
+    let rules = ConstantCostRules::new(
+        limits.instruction_fuel,
+        limits.memory_grow_fuel,
+        limits.call_per_local_fuel
+    );
+    let module = wasm_instrument::gas_metering::inject(
+        module,
+        host_function::Injector::new(FUEL_MODULE_NAME, FUEL_FUNCTION_NAME),
+        &rules
+    ).map_err(|_| error_info("Unable to inject fuel metering into contract module"))?;
+
+    let module = wasm_instrument::inject_stack_limiter(module, limits.max_stack_height)
+        .map_err(|e| error_info(format!("Unable to inject stack limiter into contract module {}", e)))?;
+
+    parity_wasm::serialize(module)
+        .map_err(|e| error_info(format!("Unable to serialize instrumented contract module {}", e.to_string())))
+}
+
+fn cap_memory(module: &mut Module, max_pages: u32) -> RgResult<()> {
+    let imports_memory = module.import_section().map(|i| i.entries().iter().any(|e|
+        matches!(e.external(), External::Memory(_))
+    )).unwrap_or(false);
+    if imports_memory {
+        return Err(error_info("Contract modules must define their own memory rather than import it"));
+    }
+    if let Some(section) = module.memory_section_mut() {
+        for entry in section.entries_mut().iter_mut() {
+            let initial = entry.limits().initial();
+            if initial > max_pages {
+                return Err(error_message(
+                    ErrorCode::ExecutionMemoryLimitExceeded,
+                    format!("Contract requests {} initial memory pages, limit is {}", initial, max_pages)
+                ));
+            }
+            let maximum = entry.limits().maximum().map(|m| m.min(max_pages)).unwrap_or(max_pages);
+            *entry = MemoryType::new(initial, Some(maximum));
+        }
+    }
+    Ok(())
+}
+
+
+#[test]
+fn fuel_meter_caps_at_limit() {
+    let mut limits = ExecutionLimits::default();
+    limits.fuel_limit = 100;
+    limits.host_call_fuel = 30;
+    let meter = FuelMeter::new(&limits);
+    meter.charge_host_call().unwrap();
+    meter.charge_host_call().unwrap();
+    meter.charge_host_call().unwrap();
+    assert_eq!(meter.consumed(), 90);
+    let err = meter.charge_host_call().unwrap_err();
+    assert_eq!(err.code, ErrorCode::ExecutionFuelExhausted as i32);
+    assert_eq!(meter.consumed(), 100);
+    assert!(meter.exhausted());
+    assert!(meter.charge(0).is_err());
+}
+
+#[test]
+fn instrument_caps_memory_and_imports_fuel() {
+    // (module (memory 1) (func (export "f") (result i32) i32.const 1))
+    let wasm = [
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f,
+        0x03, 0x02, 0x01, 0x00,
+        0x05, 0x03, 0x01, 0x00, 0x01,
+        0x07, 0x05, 0x01, 0x01, 0x66, 0x00, 0x00,
+        0x0a, 0x06, 0x01, 0x04, 0x00, 0x41, 0x01, 0x0b,
+    ];
+    let mut limits = ExecutionLimits::default();
+    limits.max_memory_pages = 2;
+    let out = instrument_module(&wasm, &limits).unwrap();
+    let module = parity_wasm::deserialize_buffer::<Module>(&out).unwrap();
+    let mem = module.memory_section().unwrap().entries()[0].limits().clone();
+    assert_eq!(mem.maximum(), Some(2));
+    let imports_fuel = module.import_section().unwrap().entries().iter()
+        .any(|e| e.module() == FUEL_MODULE_NAME && e.field() == FUEL_FUNCTION_NAME);
+    assert!(imports_fuel);
+
+    limits.max_memory_pages = 0;
+    let err = instrument_module(&wasm, &limits).unwrap_err();
+    assert_eq!(err.code, ErrorCode::ExecutionMemoryLimitExceeded as i32);
+}
@@ -180,6 +180,19 @@ pub fn get_all_tx_electrum(network: &NetworkEnvironment, address: &structs::Addr
     Ok(res)
 }
 
+/// Heights and block hashes of the most recent blocks, oldest first, ending at the chain tip.
+pub fn electrum_recent_blocks(network: &NetworkEnvironment, count: usize) -> RgResult<Vec<(u64, String)>> {
+    let backend = network_to_backends(network).get(0).cloned().ok_msg("Missing electrum backend")?;
+    let client = Client::new(&*backend)
+        .error_info("Error building bdk client")?;
+    let tip = client.block_headers_subscribe().error_info("Error subscribing to block headers")?;
+    let start = (tip.height + 1).saturating_sub(count);
+    let headers = client.block_headers(start, tip.height + 1 - start).error_info("Error getting block headers")?;
+    Ok(headers.headers.iter().enumerate()
+        .map(|(i, h)| ((start + i) as u64, h.block_hash().to_string()))
+        .collect_vec())
+}
+
 impl SingleKeyBitcoinWallet<Tree> {
 
     pub fn new_wallet_db_backed(
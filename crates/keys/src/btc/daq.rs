@@ -146,7 +146,9 @@ impl<D: BatchDatabase> SingleKeyBitcoinWallet<D> {
                 bigint_amount: None,
                 incoming,
                 currency: SupportedCurrency::Bitcoin,
-                block_number: None,
+                block_number: transaction_details.confirmation_time.as_ref()
+                    .map(|c| c.height as u64)
+                    .filter(|h| *h > 0),
                 price_usd: None,
                 fee,
                 self_address: Some(self_addr),
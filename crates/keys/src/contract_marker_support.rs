@@ -0,0 +1,40 @@
+use crate::proof_support::ProofSupport;
+use crate::KeyPair;
+use redgold_schema::structs::{ContractStateMarker, ErrorCode, Proof, PublicKey};
+use redgold_schema::{error_message, RgResult, SafeOption};
+
+pub trait ContractMarkerSupport {
+    fn sign(&mut self, key_pair: &KeyPair) -> &mut Self;
+    fn verify_proof(&self) -> RgResult<PublicKey>;
+    fn verify_follows(&self, previous: &ContractStateMarker) -> RgResult<()>;
+}
+
+impl ContractMarkerSupport for ContractStateMarker {
+
+    fn sign(&mut self, key_pair: &KeyPair) -> &mut Self {
+        let hash = self.signing_hash();
+        self.proof = Some(Proof::from_keypair_hash(&hash, key_pair));
+        self
+    }
+
+    fn verify_proof(&self) -> RgResult<PublicKey> {
+        let proof = self.proof.safe_get_msg("Missing proof on contract state marker")?;
+        proof.verify_signature_only(&self.signing_hash())?;
+        proof.public_key()
+    }
+
+    /// Recompute the aggregate state hash from `previous` and check it matches, i.e. this marker
+    /// was produced by applying its transaction on top of the same ordered history.
+    fn verify_follows(&self, previous: &ContractStateMarker) -> RgResult<()> {
+        let mut expected = self.clone();
+        expected.with_aggregate_state_hash(Some(previous))?;
+        if expected.aggregate_state_hash != self.aggregate_state_hash
+            || self.index_counter != previous.index_counter + 1 {
+            return Err(error_message(
+                ErrorCode::ContractStateMismatch,
+                "Contract state marker does not follow previous marker"
+            ));
+        }
+        Ok(())
+    }
+}
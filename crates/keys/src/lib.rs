@@ -11,6 +11,7 @@ use std::str::FromStr;
 
 pub mod proof_support;
 pub mod request_support;
+pub mod contract_marker_support;
 pub mod transaction_support;
 pub mod util;
 pub mod debug;
@@ -28,6 +29,7 @@ pub mod word_pass_support;
 pub mod eth;
 pub mod btc;
 pub mod yubikey;
+pub mod noise;
 
 pub struct TestConstants {
     pub secret: bdk::bitcoin::secp256k1::SecretKey,
@@ -0,0 +1,345 @@
+use crate::util::ToPublicKey;
+use crate::KeyPair;
+use bdk::bitcoin::secp256k1::ecdh::SharedSecret;
+use bdk::bitcoin::secp256k1::rand::rngs::OsRng;
+use bdk::bitcoin::secp256k1::Secp256k1;
+use crypto::aead::{AeadDecryptor, AeadEncryptor};
+use crypto::chacha20poly1305::ChaCha20Poly1305;
+use crypto::digest::Digest;
+use crypto::hkdf::{hkdf_expand, hkdf_extract};
+use crypto::sha2::Sha256;
+use redgold_schema::observability::errors::EnhanceErrorInfo;
+use redgold_schema::structs::{ErrorCode, NoiseFrame, NoiseHandshake, PublicKey};
+use redgold_schema::{bytes_data, error_info, error_message, RgResult, SafeOption};
+
+/// Noise KK shaped handshake, both sides know the other's static node key in advance. DH is
+/// secp256k1 ECDH so the static keys are the existing node keys from NodeMetadata.
+pub const NOISE_PROTOCOL_NAME: &[u8] = b"Noise_KK_secp256k1_ChaChaPoly_SHA256_redgold";
+/// Handshakes with a timestamp further than this from local time are rejected as replays.
+pub const NOISE_HANDSHAKE_MAX_SKEW_MILLIS: i64 = 60_000;
+/// Number of frames behind the highest seen counter still accepted, for out of order UDP delivery.
+pub const NOISE_REPLAY_WINDOW: u64 = 64;
+const TAG_BYTES: usize = 16;
+const SESSION_ID_BYTES: usize = 16;
+
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut h = Sha256::new();
+    for p in parts {
+        h.input(p);
+    }
+    let mut out = [0u8; 32];
+    h.result(&mut out);
+    out
+}
+
+fn hkdf2(ck: &[u8; 32], ikm: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut prk = [0u8; 32];
+    hkdf_extract(Sha256::new(), ck, ikm, &mut prk);
+    let mut okm = [0u8; 64];
+    hkdf_expand(Sha256::new(), &prk, &[], &mut okm);
+    let mut a = [0u8; 32];
+    let mut b = [0u8; 32];
+    a.copy_from_slice(&okm[..32]);
+    b.copy_from_slice(&okm[32..]);
+    (a, b)
+}
+
+fn aead_seal(key: &[u8; 32], counter: u64, ad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut c = ChaCha20Poly1305::new(key, &counter.to_le_bytes(), ad);
+    let mut out = vec![0u8; plaintext.len()];
+    let mut tag = [0u8; TAG_BYTES];
+    c.encrypt(plaintext, &mut out, &mut tag);
+    out.extend_from_slice(&tag);
+    out
+}
+
+fn aead_open(key: &[u8; 32], counter: u64, ad: &[u8], ciphertext: &[u8]) -> RgResult<Vec<u8>> {
+    if ciphertext.len() < TAG_BYTES {
+        return Err(error_message(ErrorCode::NoiseDecryptFailure, "Noise ciphertext too short"));
+    }
+    let (body, tag) = ciphertext.split_at(ciphertext.len() - TAG_BYTES);
+    let mut c = ChaCha20Poly1305::new(key, &counter.to_le_bytes(), ad);
+    let mut out = vec![0u8; body.len()];
+    if !c.decrypt(body, &mut out, tag) {
+        return Err(error_message(ErrorCode::NoiseDecryptFailure, "Noise authentication tag mismatch"));
+    }
+    Ok(out)
+}
+
+fn dh(public: &PublicKey, secret: &KeyPair) -> RgResult<[u8; 32]> {
+    let pk = public.to_lib_ecdsa_public_key()?;
+    Ok(SharedSecret::new(&pk, &secret.secret_key).secret_bytes())
+}
+
+fn ephemeral() -> KeyPair {
+    let (secret_key, public_key) = Secp256k1::new().generate_keypair(&mut OsRng::default());
+    KeyPair::new(&secret_key, &public_key)
+}
+
+/// Chaining key and transcript hash, shared between both handshake roles.
+#[derive(Clone)]
+struct SymmetricState {
+    ck: [u8; 32],
+    h: [u8; 32],
+    k: [u8; 32],
+}
+
+impl SymmetricState {
+    fn new(initiator: &PublicKey, responder: &PublicKey) -> RgResult<Self> {
+        let h = sha256(&[NOISE_PROTOCOL_NAME]);
+        let mut s = Self { ck: h, h, k: [0u8; 32] };
+        s.mix_hash(&initiator.raw_bytes()?);
+        s.mix_hash(&responder.raw_bytes()?);
+        Ok(s)
+    }
+
+    fn mix_hash(&mut self, data: &[u8]) {
+        self.h = sha256(&[&self.h, data]);
+    }
+
+    fn mix_key(&mut self, ikm: &[u8; 32]) {
+        let (ck, k) = hkdf2(&self.ck, ikm);
+        self.ck = ck;
+        self.k = k;
+    }
+
+    /// Empty payload AEAD over the transcript so far, proves both sides derived the same keys.
+    fn auth(&mut self) -> Vec<u8> {
+        let tag = aead_seal(&self.k, 0, &self.h, &[]);
+        self.mix_hash(&tag);
+        tag
+    }
+
+    fn verify_auth(&mut self, tag: &[u8]) -> RgResult<()> {
+        aead_open(&self.k, 0, &self.h, tag)
+            .map_err(|_| error_message(ErrorCode::NoiseHandshakeFailure, "Noise handshake authentication failed"))?;
+        self.mix_hash(tag);
+        Ok(())
+    }
+
+    fn split(&self) -> ([u8; 32], [u8; 32]) {
+        hkdf2(&self.ck, &[0u8; 32])
+    }
+}
+
+fn ephemeral_key(h: &NoiseHandshake) -> RgResult<PublicKey> {
+    let bytes = h.ephemeral.as_ref().map(|b| b.value.clone()).ok_msg("Missing noise ephemeral key")?;
+    let pk = PublicKey::from_bytes_direct_ecdsa(bytes);
+    pk.to_lib_ecdsa_public_key()?;
+    Ok(pk)
+}
+
+fn check_timestamp(h: &NoiseHandshake, time: i64) -> RgResult<()> {
+    if (h.timestamp - time).abs() > NOISE_HANDSHAKE_MAX_SKEW_MILLIS {
+        return Err(error_message(ErrorCode::NoiseHandshakeFailure, "Noise handshake timestamp outside of allowed skew"))
+            .with_detail("timestamp", h.timestamp.to_string())
+            .with_detail("time", time.to_string());
+    }
+    Ok(())
+}
+
+/// Initiator side state held between sending the first handshake message and receiving the reply.
+pub struct NoiseInitiator {
+    local: KeyPair,
+    remote: PublicKey,
+    ephemeral: KeyPair,
+    state: SymmetricState,
+}
+
+impl NoiseInitiator {
+    pub fn new(local: &KeyPair, remote: &PublicKey, time: i64) -> RgResult<(Self, NoiseHandshake)> {
+        let e = ephemeral();
+        let mut state = SymmetricState::new(&local.public_key(), remote)?;
+        let e_pub = e.public_key();
+        state.mix_hash(&e_pub.raw_bytes()?);
+        state.mix_hash(&time.to_le_bytes());
+        state.mix_key(&dh(remote, &e)?);
+        state.mix_key(&dh(remote, local)?);
+        let auth = state.auth();
+        let msg = NoiseHandshake {
+            static_key: Some(local.public_key()),
+            ephemeral: bytes_data(e_pub.raw_bytes()?),
+            timestamp: time,
+            auth: bytes_data(auth),
+            reply: false,
+        };
+        Ok((Self { local: local.clone(), remote: remote.clone(), ephemeral: e, state }, msg))
+    }
+
+    pub fn remote(&self) -> &PublicKey {
+        &self.remote
+    }
+
+    pub fn finish(mut self, reply: &NoiseHandshake, time: i64) -> RgResult<NoiseSession> {
+        if !reply.reply || reply.static_key.as_ref() != Some(&self.remote) {
+            return Err(error_message(ErrorCode::NoiseHandshakeFailure, "Noise handshake reply from unexpected key"));
+        }
+        check_timestamp(reply, time)?;
+        let re = ephemeral_key(reply)?;
+        self.state.mix_hash(&re.raw_bytes()?);
+        self.state.mix_key(&dh(&re, &self.ephemeral)?);
+        self.state.mix_key(&dh(&re, &self.local)?);
+        let tag = reply.auth.as_ref().map(|b| b.value.clone()).unwrap_or_default();
+        self.state.verify_auth(&tag)?;
+        let (send, recv) = self.state.split();
+        Ok(NoiseSession::new(&self.state.h, self.remote, send, recv, time))
+    }
+}
+
+/// Responder side of the handshake, returns the established session and the reply to send.
+pub fn noise_respond(local: &KeyPair, init: &NoiseHandshake, time: i64) -> RgResult<(NoiseSession, NoiseHandshake)> {
+    if init.reply {
+        return Err(error_message(ErrorCode::NoiseHandshakeFailure, "Expected noise handshake initiation"));
+    }
+    check_timestamp(init, time)?;
+    let remote = init.static_key.clone().ok_msg("Missing noise static key")?;
+    let ie = ephemeral_key(init)?;
+    let mut state = SymmetricState::new(&remote, &local.public_key())?;
+    state.mix_hash(&ie.raw_bytes()?);
+    state.mix_hash(&init.timestamp.to_le_bytes());
+    state.mix_key(&dh(&ie, local)?);
+    state.mix_key(&dh(&remote, local)?);
+    let tag = init.auth.as_ref().map(|b| b.value.clone()).unwrap_or_default();
+    state.verify_auth(&tag)?;
+
+    let e = ephemeral();
+    let e_pub = e.public_key();
+    state.mix_hash(&e_pub.raw_bytes()?);
+    state.mix_key(&dh(&ie, &e)?);
+    state.mix_key(&dh(&remote, &e)?);
+    let auth = state.auth();
+    let (recv, send) = state.split();
+    let reply = NoiseHandshake {
+        static_key: Some(local.public_key()),
+        ephemeral: bytes_data(e_pub.raw_bytes()?),
+        timestamp: time,
+        auth: bytes_data(auth),
+        reply: true,
+    };
+    Ok((NoiseSession::new(&state.h, remote, send, recv, time), reply))
+}
+
+/// Sliding window over received frame counters, rejects duplicates and anything too old.
+#[derive(Clone, Default)]
+pub struct ReplayWindow {
+    highest: Option<u64>,
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    pub fn check(&self, counter: u64) -> bool {
+        match self.highest {
+            None => true,
+            Some(h) if counter > h => true,
+            Some(h) => {
+                let offset = h - counter;
+                offset < NOISE_REPLAY_WINDOW && (self.bitmap & (1u64 << offset)) == 0
+            }
+        }
+    }
+
+    pub fn update(&mut self, counter: u64) {
+        match self.highest {
+            None => {
+                self.highest = Some(counter);
+                self.bitmap = 1;
+            }
+            Some(h) if counter > h => {
+                let shift = counter - h;
+                self.bitmap = if shift >= NOISE_REPLAY_WINDOW { 0 } else { self.bitmap << shift };
+                self.bitmap |= 1;
+                self.highest = Some(counter);
+            }
+            Some(h) => {
+                self.bitmap |= 1u64 << (h - counter);
+            }
+        }
+    }
+}
+
+/// Established session, reused for every message to a peer until it expires.
+#[derive(Clone)]
+pub struct NoiseSession {
+    pub session_id: Vec<u8>,
+    pub remote: PublicKey,
+    pub created: i64,
+    send_key: [u8; 32],
+    recv_key: [u8; 32],
+    send_counter: u64,
+    replay: ReplayWindow,
+}
+
+impl NoiseSession {
+    fn new(h: &[u8; 32], remote: PublicKey, send_key: [u8; 32], recv_key: [u8; 32], time: i64) -> Self {
+        Self {
+            session_id: h[..SESSION_ID_BYTES].to_vec(),
+            remote,
+            created: time,
+            send_key,
+            recv_key,
+            send_counter: 0,
+            replay: Default::default(),
+        }
+    }
+
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> RgResult<NoiseFrame> {
+        if self.send_counter == u64::MAX {
+            return Err(error_info("Noise session send counter exhausted"));
+        }
+        let counter = self.send_counter;
+        self.send_counter += 1;
+        Ok(NoiseFrame {
+            session_id: bytes_data(self.session_id.clone()),
+            counter,
+            ciphertext: bytes_data(aead_seal(&self.send_key, counter, &self.session_id, plaintext)),
+        })
+    }
+
+    pub fn decrypt(&mut self, frame: &NoiseFrame) -> RgResult<Vec<u8>> {
+        if !self.replay.check(frame.counter) {
+            return Err(error_message(ErrorCode::NoiseReplay, "Noise frame replayed or outside of window"))
+                .with_detail("counter", frame.counter.to_string());
+        }
+        let ciphertext = frame.ciphertext.as_ref().map(|b| b.value.clone()).unwrap_or_default();
+        let res = aead_open(&self.recv_key, frame.counter, &self.session_id, &ciphertext)?;
+        // Only authenticated frames may advance the window.
+        self.replay.update(frame.counter);
+        Ok(res)
+    }
+}
+
+#[test]
+fn noise_handshake_and_session() {
+    let tc = crate::TestConstants::new();
+    let a = tc.key_pair();
+    let b = KeyPair::new(&tc.secret2, &tc.public2);
+    let time = 1_000_000;
+
+    let (init, msg) = NoiseInitiator::new(&a, &b.public_key(), time).unwrap();
+    let (mut rs, reply) = noise_respond(&b, &msg, time + 10).unwrap();
+    let mut is = init.finish(&reply, time + 20).unwrap();
+    assert_eq!(is.session_id, rs.session_id);
+    assert_eq!(rs.remote, a.public_key());
+
+    let f1 = is.encrypt(b"hello").unwrap();
+    let f2 = is.encrypt(b"world").unwrap();
+    assert_eq!(rs.decrypt(&f2).unwrap(), b"world".to_vec());
+    // Out of order delivery within the window is accepted once.
+    assert_eq!(rs.decrypt(&f1).unwrap(), b"hello".to_vec());
+    assert!(rs.decrypt(&f1).is_err());
+    assert!(rs.decrypt(&f2).is_err());
+
+    let back = rs.encrypt(b"reply").unwrap();
+    assert_eq!(is.decrypt(&back).unwrap(), b"reply".to_vec());
+
+    let mut tampered = is.encrypt(b"x").unwrap();
+    tampered.ciphertext.as_mut().unwrap().value[0] ^= 1;
+    assert!(rs.decrypt(&tampered).is_err());
+
+    // Stale initiations are rejected, and a third key can't complete a handshake meant for b.
+    assert!(noise_respond(&b, &msg, time + NOISE_HANDSHAKE_MAX_SKEW_MILLIS + 1).is_err());
+    let c = crate::util::mnemonic_support::MnemonicSupport::default_kp(
+        &redgold_schema::keys::words_pass::WordsPass::from_str_hashed("noise")
+    ).unwrap();
+    assert!(noise_respond(&c, &msg, time).is_err());
+}
@@ -6,9 +6,13 @@ use itertools::Itertools;
 use redgold_schema::helpers::easy_json::EasyJson;
 use redgold_schema::observability::errors::EnhanceErrorInfo;
 use redgold_schema::proto_serde::ProtoSerde;
-use redgold_schema::structs::{Address, ErrorInfo, Hash, NetworkEnvironment, Proof, SupportedCurrency};
-use redgold_schema::{error_info, error_message, from_hex, signature_data, structs, RgResult, SafeOption};
+use redgold_schema::structs::{Address, ErrorInfo, Hash, NetworkEnvironment, Proof, PublicKeyType, SignatureType, SupportedCurrency};
+use redgold_schema::{error_info, error_message, from_hex, signature_data, structs, ErrorInfoContext, RgResult, SafeOption};
+use ed25519_dalek::Signer;
+use ethers::core::k256::ecdsa::{RecoveryId, Signature as K256Signature, SigningKey, VerifyingKey};
+use crate::solana::derive_solana::ToSolanaAddress;
 use std::collections::HashMap;
+use redgold_common::client::verify::ProofVerifier;
 use log::info;
 
 pub trait ProofSupport {
@@ -17,11 +21,44 @@ pub trait ProofSupport {
     fn from_keypair(hash: &Vec<u8>, keypair: KeyPair) -> Proof;
     fn from_keypair_hash(hash: &Hash, keypair: &KeyPair) -> Proof;
 
+    fn from_ed25519_signing_key(hash: &Hash, signing_key: &ed25519_dalek::SigningKey) -> RgResult<Proof>;
+    fn from_keypair_eth_personal_sign(hash: &Hash, keypair: &KeyPair) -> RgResult<Proof>;
+    fn from_eth_personal_sign(hash: &Hash, rsv: &Vec<u8>) -> RgResult<Proof>;
+
     fn public_key(&self) -> RgResult<structs::PublicKey>;
+    fn recover_public_key(&self, hash: &Hash) -> RgResult<structs::PublicKey>;
     fn verify_inner(&self, hash: &Hash) -> RgResult<()>;
     fn verify_single_public_key_address(&self, address: &Address) -> RgResult<()>;
 }
 
+/// Ethereum personal_sign digest of the raw hash bytes, as produced by MetaMask and other wallets.
+pub fn eth_personal_sign_hash(hash: &Hash) -> RgResult<Vec<u8>> {
+    Ok(ethers::utils::hash_message(hash.raw_bytes()?).as_bytes().to_vec())
+}
+
+fn recoverable_signature(signature: &structs::Signature) -> RgResult<(Vec<u8>, u8)> {
+    let (rs, v) = match signature.rsv.as_ref() {
+        Some(rsv) => {
+            let mut rs = rsv.r.safe_get_msg("Missing r on recoverable signature")?.value.clone();
+            rs.extend(rsv.s.safe_get_msg("Missing s on recoverable signature")?.value.clone());
+            (rs, rsv.v.safe_get_msg("Missing v on recoverable signature")?.clone())
+        }
+        None => {
+            let bytes = signature.bytes.safe_get_msg("Missing recoverable signature bytes")?.value.clone();
+            if bytes.len() != 65 {
+                return Err(error_message(structs::ErrorCode::IncorrectSignature, "Recoverable signature must be 65 bytes"));
+            }
+            (bytes[..64].to_vec(), bytes[64] as i64)
+        }
+    };
+    // Wallets commonly use the legacy 27 / 28 offset
+    let v = if v >= 27 { v - 27 } else { v };
+    if rs.len() != 64 || !(0..=1).contains(&v) {
+        return Err(error_message(structs::ErrorCode::IncorrectSignature, "Malformed recoverable signature"));
+    }
+    Ok((rs, v as u8))
+}
+
 impl ProofSupport for Proof {
     fn verify_signature_only(&self, hash: &Hash) -> RgResult<()> {
         self.verify_inner(&hash)
@@ -79,14 +116,64 @@ impl ProofSupport for Proof {
     }
 
 
+    fn from_ed25519_signing_key(hash: &Hash, signing_key: &ed25519_dalek::SigningKey) -> RgResult<Proof> {
+        let signature = signing_key.sign(&hash.raw_bytes()?);
+        Ok(Proof {
+            signature: Some(structs::Signature::ed25519(signature.to_bytes().to_vec())),
+            public_key: Some(structs::PublicKey::from_bytes_direct_ed25519(signing_key.verifying_key().to_bytes().to_vec())),
+        })
+    }
+
+    fn from_keypair_eth_personal_sign(hash: &Hash, keypair: &KeyPair) -> RgResult<Proof> {
+        let signing_key = SigningKey::from_slice(&keypair.secret_key.secret_bytes())
+            .error_msg(structs::ErrorCode::IncorrectSignature, "Invalid secret key for recoverable signature")?;
+        let (signature, recovery_id) = signing_key.sign_prehash_recoverable(&eth_personal_sign_hash(hash)?)
+            .error_msg(structs::ErrorCode::IncorrectSignature, "Recoverable signing failure")?;
+        let mut rsv = signature.to_bytes().to_vec();
+        rsv.push(recovery_id.to_byte() + 27);
+        Self::from_eth_personal_sign(hash, &rsv)
+    }
+
+    /// Build a proof from a 65 byte r || s || v personal_sign signature over the hash, the
+    /// public key is recovered so address checks work the same as for other proofs.
+    fn from_eth_personal_sign(hash: &Hash, rsv: &Vec<u8>) -> RgResult<Proof> {
+        if rsv.len() != 65 {
+            return Err(error_message(structs::ErrorCode::IncorrectSignature, "Recoverable signature must be 65 bytes"));
+        }
+        let sig = structs::Signature::recoverable(rsv[..32].to_vec(), rsv[32..64].to_vec(), rsv[64] as i64);
+        let mut proof = Proof {
+            signature: Some(sig),
+            public_key: None,
+        };
+        proof.public_key = Some(proof.recover_public_key(hash)?);
+        Ok(proof)
+    }
+
     fn public_key(&self) -> RgResult<structs::PublicKey> {
-        // TODO: RSV Signature public key reconstruction
-        // self.signature.safe_get()?.;
         self.public_key.clone().ok_msg("Missing public key")
     }
 
+    fn recover_public_key(&self, hash: &Hash) -> RgResult<structs::PublicKey> {
+        let (rs, v) = recoverable_signature(self.signature.safe_get_msg("Missing signature")?)?;
+        let signature = K256Signature::from_slice(&rs)
+            .error_msg(structs::ErrorCode::IncorrectSignature, "Invalid recoverable signature")?;
+        let recovery_id = RecoveryId::from_byte(v).ok_msg("Invalid recovery id")?;
+        let key = VerifyingKey::recover_from_prehash(&eth_personal_sign_hash(hash)?, &signature, recovery_id)
+            .error_msg(structs::ErrorCode::IncorrectSignature, "Public key recovery failure")?;
+        Ok(structs::PublicKey::from_bytes_direct_ecdsa(key.to_encoded_point(true).as_bytes().to_vec()))
+    }
+
     fn verify_inner(&self, hash: &Hash) -> RgResult<()> {
         let sig = self.signature.safe_get()?;
+        let key_type = self.public_key.safe_get_msg("Missing public key")?.key_type();
+        let expected_key_type = if sig.signature_type == SignatureType::Ed25519 as i32 {
+            PublicKeyType::Ed25519
+        } else {
+            PublicKeyType::Secp256k1
+        };
+        if key_type != expected_key_type {
+            return Err(error_message(structs::ErrorCode::IncorrectSignature, "Public key type does not match signature type"));
+        }
         let verify_hash = match sig.signature_type {
             // SignatureType::Ecdsa
             0 => {
@@ -96,6 +183,23 @@ impl ProofSupport for Proof {
             1 => {
                 btc::bitcoin_message_signer::prepare_message_sign_hash(&hash)
             }
+            // SignatureType::EcdsaRecoverable
+            2 => {
+                let recovered = self.recover_public_key(hash)?;
+                if Some(&recovered) != self.public_key.as_ref() {
+                    return Err(error_message(structs::ErrorCode::IncorrectSignature, "Recovered public key does not match proof public key"));
+                }
+                return Ok(());
+            }
+            // SignatureType::Ed25519
+            3 => {
+                let key = ed25519_dalek::VerifyingKey::try_from(self.public_key_direct_bytes()?.as_slice())
+                    .error_msg(structs::ErrorCode::IncorrectSignature, "Invalid ed25519 public key")?;
+                let signature = ed25519_dalek::Signature::from_slice(&self.signature_bytes()?)
+                    .error_msg(structs::ErrorCode::IncorrectSignature, "Invalid ed25519 signature")?;
+                return key.verify_strict(&hash.raw_bytes()?, &signature)
+                    .error_msg(structs::ErrorCode::IncorrectSignature, "Ed25519 signature verification failure");
+            }
             _ => {
                 return Err(error_info(
                     "Invalid signature type",
@@ -109,12 +213,23 @@ impl ProofSupport for Proof {
 
 
 
+/// Signature verification for light client proof checks in the common HTTP client.
+#[derive(Clone, Copy, Default)]
+pub struct ProofSignatureVerifier;
+
+impl ProofVerifier for ProofSignatureVerifier {
+    fn verify_proof(&self, proof: &Proof, hash: &Hash) -> RgResult<()> {
+        proof.verify_signature_only(hash)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use redgold_schema::signature_data;
     use redgold_schema::structs::Proof;
-    use crate::proof_support::ProofSupport;
-    use crate::TestConstants;
+    use crate::address_external::ToEthereumAddress;
+    use crate::proof_support::{ProofSupport, PublicKeySupport};
+    use crate::{KeyPair, TestConstants};
     use crate::util::public_key_ser;
 
     #[test]
@@ -141,6 +256,35 @@ fn verify_invalid_key_single_signature_proof() {
     assert!(proof.verify_signature_only(&tc.rhash_1).is_err());
 }
 
+#[test]
+fn verify_eth_personal_sign_proof() {
+    let tc = TestConstants::new();
+    let kp = KeyPair::new(&tc.secret, &tc.public);
+    let proof = Proof::from_keypair_eth_personal_sign(&tc.rhash_1, &kp).expect("sign");
+    assert_eq!(proof.public_key, Some(kp.public_key()));
+    assert!(proof.verify_signature_only(&tc.rhash_1).is_ok());
+    assert!(proof.verify_signature_only(&tc.rhash_2).is_err());
+    assert!(proof.verify_single_public_key_address(&kp.public_key().to_ethereum_address_typed().expect("eth")).is_ok());
+    let mut wrong_key = proof.clone();
+    wrong_key.public_key = public_key_ser(&tc.public2);
+    assert!(wrong_key.verify_signature_only(&tc.rhash_1).is_err());
+}
+
+#[test]
+fn verify_ed25519_proof() {
+    let tc = TestConstants::new();
+    let signing_key = ed25519_dalek::SigningKey::from_bytes(&[7u8; 32]);
+    let proof = Proof::from_ed25519_signing_key(&tc.rhash_1, &signing_key).expect("sign");
+    assert!(proof.verify_signature_only(&tc.rhash_1).is_ok());
+    assert!(proof.verify_signature_only(&tc.rhash_2).is_err());
+    let pk = proof.public_key.clone().expect("pk");
+    assert!(pk.validate().is_ok());
+    assert!(proof.verify_single_public_key_address(&pk.address().expect("addr")).is_ok());
+    let mut mismatched = proof.clone();
+    mismatched.public_key = public_key_ser(&tc.public);
+    assert!(mismatched.verify_signature_only(&tc.rhash_1).is_err());
+}
+
 }
 
 
@@ -155,6 +299,11 @@ pub trait PublicKeySupport {
 impl PublicKeySupport for structs::PublicKey {
 
     fn validate(&self) -> Result<&Self, ErrorInfo> {
+        if self.key_type() == PublicKeyType::Ed25519 {
+            ed25519_dalek::VerifyingKey::try_from(self.raw_bytes()?.as_slice())
+                .error_msg(structs::ErrorCode::IncorrectSignature, "Invalid ed25519 public key")?;
+            return Ok(self);
+        }
         let _ = self.to_lib_ecdsa_public_key()?;
         Ok(self)
     }
@@ -168,6 +317,9 @@ impl PublicKeySupport for structs::PublicKey {
 
     fn to_all_addresses(&self) -> RgResult<Vec<Address>> {
         let default = self.address()?;
+        if self.key_type() == PublicKeyType::Ed25519 {
+            return Ok(vec![default, self.to_solana_address()?]);
+        }
         let eth = self.to_ethereum_address_typed()?;
         let btc_test = self.to_bitcoin_address_typed(&NetworkEnvironment::Dev)?;
         let btc_main = self.to_bitcoin_address_typed(&NetworkEnvironment::Main)?;
@@ -176,6 +328,9 @@ impl PublicKeySupport for structs::PublicKey {
 
     fn to_all_addresses_for_network(&self, network: &NetworkEnvironment) -> RgResult<Vec<Address>> {
         let default = self.address()?;
+        if self.key_type() == PublicKeyType::Ed25519 {
+            return Ok(vec![default, self.to_solana_address()?]);
+        }
         let eth = self.to_ethereum_address_typed()?;
         let btc = self.to_bitcoin_address_typed(&network)?;
         Ok(vec![default, eth, btc])
@@ -183,9 +338,14 @@ impl PublicKeySupport for structs::PublicKey {
 
     fn to_all_addresses_for_network_by_currency(&self, network: &NetworkEnvironment) -> RgResult<HashMap<SupportedCurrency, Address>> {
         let default = self.address()?;
+        let mut hm = HashMap::new();
+        if self.key_type() == PublicKeyType::Ed25519 {
+            hm.insert(SupportedCurrency::Redgold, default);
+            hm.insert(SupportedCurrency::Solana, self.to_solana_address()?);
+            return Ok(hm);
+        }
         let eth = self.to_ethereum_address_typed()?;
         let btc = self.to_bitcoin_address_typed(&network)?;
-        let mut hm = HashMap::new();
         hm.insert(SupportedCurrency::Redgold, default);
         hm.insert(SupportedCurrency::Ethereum, eth);
         hm.insert(SupportedCurrency::Bitcoin, btc);
@@ -6,8 +6,8 @@ use itertools::Itertools;
 use log::info;
 use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
 use redgold_schema::proto_serde::ProtoSerde;
-use redgold_schema::structs::{Address, AddressDescriptor, CurrencyAmount, DebugSerChange, DebugSerChange2, ErrorInfo, Hash, Input, NetworkEnvironment, Output, Proof, PublicKey, SupportedCurrency, TimeSponsor, Transaction, TransactionOptions};
-use redgold_schema::{structs, RgResult, SafeOption};
+use redgold_schema::structs::{Address, AddressDescriptor, AddressType, ErrorCode, CurrencyAmount, DebugSerChange, DebugSerChange2, ErrorInfo, Hash, Input, NetworkEnvironment, Output, Proof, PublicKey, SupportedCurrency, TimeSponsor, Transaction, TransactionOptions};
+use redgold_schema::{error_message, structs, RgResult, SafeOption};
 use redgold_schema::errors::into_error::ToErrorInfo;
 use crate::address_external::ToBitcoinAddress;
 use redgold_schema::util::times::current_time_millis;
@@ -19,6 +19,13 @@ pub trait TransactionSupport {
     fn outputs_of_address(&self, address: &Address) -> impl Iterator<Item = &Output>;
     fn time_sponsor(&mut self, key_pair: KeyPair) -> RgResult<Transaction>;
     fn sign(&mut self, key_pair: &KeyPair) -> Result<Transaction, ErrorInfo>;
+    // Attach an externally produced proof over the signable hash (e.g. MetaMask personal_sign) to
+    // every input spending from an address of the proof public key
+    fn sign_with_proof(&mut self, proof: Proof) -> RgResult<Transaction>;
+    fn sign_ed25519(&mut self, signing_key: &ed25519_dalek::SigningKey) -> RgResult<Transaction>;
+    fn sign_eth_personal(&mut self, key_pair: &KeyPair) -> RgResult<Transaction>;
+    // Accept a transaction requiring counter-party acceptance, signing every output paid to the key
+    fn sign_counter_party(&mut self, key_pair: &KeyPair) -> RgResult<Transaction>;
     // TODO: Move all of this to TransactionBuilder
     fn inputs_match_pk_address(&self, other_address: &Address) -> bool;
     fn first_input_address_to_btc_address(&self, network: &NetworkEnvironment) -> Option<String>;
@@ -87,6 +94,61 @@ impl TransactionSupport for Transaction {
         x.struct_metadata.as_mut().expect("sm").signed_hash = Some(x.hash_or());
         Ok(x.clone())
     }
+    fn sign_with_proof(&mut self, proof: Proof) -> RgResult<Transaction> {
+        let hash = self.signable_hash();
+        proof.verify_signature_only(&hash)?;
+        let pk = proof.public_key()?;
+        let addresses = pk.to_all_addresses()?;
+        for i in self.inputs.iter_mut() {
+            if let Some(o) = i.output.as_ref() {
+                if i.proof.iter().flat_map(|p| p.public_key.as_ref()).contains(&pk) {
+                    continue;
+                }
+                let input_addr = o.address.safe_get_msg("Missing address on enriched output during signing")?;
+                if addresses.contains(input_addr) {
+                    i.proof.push(proof.clone());
+                }
+            }
+        }
+        let x = self.with_hash();
+        x.struct_metadata.as_mut().expect("sm").signed_hash = Some(x.hash_or());
+        Ok(x.clone())
+    }
+
+    fn sign_ed25519(&mut self, signing_key: &ed25519_dalek::SigningKey) -> RgResult<Transaction> {
+        let proof = Proof::from_ed25519_signing_key(&self.signable_hash(), signing_key)?;
+        self.sign_with_proof(proof)
+    }
+
+    fn sign_eth_personal(&mut self, key_pair: &KeyPair) -> RgResult<Transaction> {
+        let proof = Proof::from_keypair_eth_personal_sign(&self.signable_hash(), key_pair)?;
+        self.sign_with_proof(proof)
+    }
+
+    fn sign_counter_party(&mut self, key_pair: &KeyPair) -> RgResult<Transaction> {
+        let pk = key_pair.public_key();
+        let hash = self.signed_hash();
+        let addresses = pk.to_all_addresses()?;
+        let counter_party = self.counter_party_addresses();
+        let mut signed = false;
+        for o in self.outputs.iter_mut() {
+            let Some(addr) = o.address.as_ref() else { continue };
+            if !addresses.contains(addr) || !counter_party.contains(addr) {
+                continue;
+            }
+            if !o.counter_party_proofs.iter().flat_map(|p| p.public_key.as_ref()).contains(&pk) {
+                o.counter_party_proofs.push(Proof::from_keypair_hash(&hash, key_pair));
+            }
+            signed = true;
+        }
+        if !signed {
+            return Err(error_message(ErrorCode::AddressPublicKeyProofMismatch, "No outputs to accept for this key"));
+        }
+        let x = self.with_hash();
+        x.struct_metadata.as_mut().expect("sm").counter_party_hash = Some(x.hash_or());
+        Ok(x.clone())
+    }
+
     // Simple signing function, this won't work for multi-sig, construct separately
     fn sign_multisig(&mut self, key_pair: &KeyPair, party_address: &Address) -> RgResult<Transaction> {
         let hash = self.signable_hash();
@@ -191,30 +253,28 @@ impl InputSupport for Input {
     fn verify_assuming_enriched(&self, hash: &Hash) -> RgResult<()> {
         let o = self.output.safe_get_msg("Missing enriched output on input for transaction verification")?;
         let prev_addr = o.address.safe_get_msg("Missing address on enriched output for transaction verification")?;
-        if self.proof.len() == 1 {
+        let is_multisig = prev_addr.address_type() == AddressType::MultisigContract;
+        if !is_multisig && self.address_descriptor.is_none() {
+            if self.proof.len() != 1 {
+                return Err(error_message(ErrorCode::MissingProof, format!(
+                    "Expected exactly one proof for single key input, found {}", self.proof.len()
+                )));
+            }
             let proof = self.proof.get(0).expect("exists");
             proof.verify_signature_only(&hash)?;
             proof.verify_single_public_key_address(prev_addr)?;
         } else {
-            // if !o.is_multisig() {
-            //     "Output not declared as multisig, but multiple proofs provided".to_error()?;
-            // };
-            match self.address_descriptor.as_ref() {
-                None => {
-                    "Missing address descriptor on multisig input".to_error()?;
-                }
-                Some(d) => {
-                    let c = d.contract.as_ref().ok_msg("Missing contract")?;
-                    let t = c.threshold.as_ref().ok_msg("Missing threshold")?;
-                    let threshold = t.value;
-                    let address = d.to_address();
-                    self.verify_signatures_only(&hash)?;
-                    if address != *prev_addr {
-                        "Multisig address mismatch".to_error()?;
-                    }
-                }
+            let d = self.address_descriptor.as_ref()
+                .ok_msg("Missing address descriptor on multisig input")?;
+            let signers = self.proof.iter()
+                .map(|p| p.public_key.safe_get_msg("Missing public key on multisig proof").cloned())
+                .collect::<RgResult<Vec<PublicKey>>>()?;
+            // Validates contract, threshold and weights before deriving the address
+            d.verify_signers(&signers)?;
+            if d.to_address() != *prev_addr {
+                return Err(error_message(ErrorCode::AddressPublicKeyProofMismatch, "Multisig address descriptor does not match spent address"));
             }
-
+            self.verify_signatures_only(&hash)?;
         }
         Ok(())
     }
@@ -1,3 +1,4 @@
+use crate::proof_support::{ProofSupport, PublicKeySupport};
 use crate::transaction_support::InputSupport;
 use itertools::Itertools;
 use log::info;
@@ -29,6 +30,50 @@ fn validate_inner(tx: &Transaction) -> RgResult<()> {
     for i in &tx.inputs {
         i.verify_signatures_only(&hash)?
     }
+    validate_counter_party_proofs(tx)?;
+    validate_confirmation_proofs(tx)?;
+    Ok(())
+}
+
+// Acceptances sign the sender-signed transaction, and must come from the key owning the output
+fn validate_counter_party_proofs(tx: &Transaction) -> RgResult<()> {
+    let signed_hash = tx.signed_hash();
+    for o in &tx.outputs {
+        if o.counter_party_proofs.is_empty() {
+            continue;
+        }
+        let addr = o.address.safe_get_msg("Missing address on output with counter-party proof")?;
+        for proof in &o.counter_party_proofs {
+            proof.verify_signature_only(&signed_hash)?;
+            proof.verify_single_public_key_address(addr)?;
+        }
+    }
+    Ok(())
+}
+
+// Confirmations sign the accepted transaction, and must come from an input signer or a recipient
+fn validate_confirmation_proofs(tx: &Transaction) -> RgResult<()> {
+    let Some(hash) = tx.confirmation_signing_hash() else {
+        return Ok(());
+    };
+    let input_keys = tx.inputs.iter()
+        .flat_map(|i| i.proof.iter())
+        .filter_map(|p| p.public_key.as_ref())
+        .collect_vec();
+    let counter_parties = tx.counter_party_addresses();
+    let proofs = tx.contract().iter().flat_map(|c| c.confirmation_proofs.iter()).collect_vec();
+    for proof in proofs {
+        proof.verify_signature_only(&hash)?;
+        let public_key = proof.public_key.safe_get_msg("Missing public key on confirmation proof")?;
+        if input_keys.contains(&public_key) {
+            continue;
+        }
+        let addresses = public_key.to_all_addresses()?;
+        if !counter_parties.iter().any(|a| addresses.contains(a)) {
+            return Err(error_info("Confirmation proof is not signed by an input or counter-party key"))
+                .with_detail("public_key", public_key.json_or());
+        }
+    }
     Ok(())
 }
 
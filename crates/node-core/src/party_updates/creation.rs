@@ -71,7 +71,8 @@ pub async fn check_formations<E: ExternalNetworkResources, B: PeerBroadcast>(
     let mut event = PartyUpdateEvents::default();
     let currencies = SupportedCurrency::multisig_party_currencies();
 
-    let current_currencies = metadata.instances_proposed_by(self_public_key).iter()
+    // Draining instances don't count, their successor is formed here like any missing party.
+    let current_currencies = metadata.active_proposed_by(self_public_key).iter()
         .map(|a| a.currency())
         .collect::<HashSet<SupportedCurrency>>();
     gauge!("redgold_party_formation_current_currencies").set(current_currencies.len() as f64);
@@ -112,7 +113,7 @@ pub async fn check_formations<E: ExternalNetworkResources, B: PeerBroadcast>(
 
 
     for cur in currencies {
-        if !metadata.has_instance(cur) {
+        if !metadata.has_active_instance(cur) {
             if let Ok(Some((instance, creation_result))) = attempt_form_for_currency(
                 ext, self_hot_addresses, self_public_key, self_private_key_hex,
                 network, &words_pass, &mut all_pks, threshold, &cur,
@@ -109,6 +109,20 @@ impl EthereumWsProvider {
 
 
 
+    /// Heights and block hashes of the most recent blocks, oldest first, ending at the chain tip.
+    pub async fn recent_blocks(&self, count: u64) -> RgResult<Vec<(u64, String)>> {
+        let tip = self.provider.get_block_number().await.error_info("block number request failed")?.as_u64();
+        let start = (tip + 1).saturating_sub(count);
+        let mut res = vec![];
+        for n in start..=tip {
+            let block = self.provider.get_block(n).await.error_info("block request failed")?;
+            if let Some(h) = block.and_then(|b| b.hash) {
+                res.push((n, format!("{:?}", h)));
+            }
+        }
+        Ok(res)
+    }
+
     pub async fn subscribe_transactions(
         &self
     ) -> RgResult<impl Stream<Item=RgResult<TimestampedEthereumTransaction>> + '_> {
@@ -1,6 +1,7 @@
 use itertools::Itertools;
-use crate::structs::{Address, AddressDescriptor, AddressInfo, AddressType, ErrorInfo, Hash, OutputContract, PublicKey, SupportedCurrency, UtxoEntry, Weighting};
-use crate::{bytes_data, error_info, from_hex, ErrorInfoContext, RgResult, SafeOption};
+use crate::structs::{Address, AddressDescriptor, AddressInfo, AddressType, ErrorCode, ErrorInfo, Hash, OutputContract, PublicKey, SupportedCurrency, UtxoEntry, Weighting};
+use crate::{bytes_data, error_info, error_message, from_hex, ErrorInfoContext, RgResult, SafeOption};
+use std::collections::{HashMap, HashSet};
 use crate::structs;
 use sha3::Sha3_224;
 
@@ -34,10 +35,89 @@ impl AddressDescriptor {
         descriptor
     }
 
+    /// Weighted m-of-n descriptor, weights are aligned with the sorted public keys and the
+    /// threshold is expressed in total signing weight.
+    pub fn from_weighted_multisig_public_keys(public_key_weights: &Vec<(PublicKey, i64)>, threshold: i64) -> AddressDescriptor {
+        let pairs = public_key_weights.iter().cloned().sorted_by_key(|(k, _)| k.vec()).collect_vec();
+        let total = pairs.iter().map(|(_, w)| *w).sum::<i64>();
+        let mut descriptor = AddressDescriptor::default();
+        descriptor.public_keys = pairs.iter().map(|(k, _)| k.clone()).collect_vec();
+        let mut contract = OutputContract::default();
+        contract.threshold = Some(Weighting::from_int_basis(threshold, total));
+        contract.weights = pairs.iter().map(|(_, w)| Weighting::from_int_basis(*w, total)).collect_vec();
+        descriptor.contract = Some(contract);
+        descriptor
+    }
+
+    /// Unweighted descriptors keep the original derivation so existing multisig addresses are
+    /// unchanged, weighted descriptors commit to their weights as well.
     pub fn to_address(&self) -> Address {
-        Address::from_multisig_public_keys_and_threshold(
-            &self.public_keys, self.contract.as_ref().unwrap().threshold.as_ref().unwrap().value
-        )
+        let contract = self.contract.as_ref().unwrap();
+        let threshold = contract.threshold.as_ref().unwrap().value;
+        if contract.weights.is_empty() {
+            return Address::from_multisig_public_keys_and_threshold(&self.public_keys, threshold);
+        }
+        let pairs = self.public_keys.iter().cloned()
+            .zip(contract.weights.iter().map(|w| w.value))
+            .collect_vec();
+        let descriptor = AddressDescriptor::from_weighted_multisig_public_keys(&pairs, threshold);
+        Address {
+            address: descriptor.to_hashed().bytes,
+            address_type: AddressType::MultisigContract as i32,
+            currency: Redgold as i32,
+        }
+    }
+
+    /// Signing weight of each public key, unit weights when none are specified.
+    pub fn key_weights(&self) -> RgResult<Vec<i64>> {
+        let contract = self.contract.safe_get_msg("Missing contract on multisig address descriptor")?;
+        if contract.weights.is_empty() {
+            return Ok(vec![1; self.public_keys.len()]);
+        }
+        if contract.weights.len() != self.public_keys.len() {
+            return Err(error_message(ErrorCode::MultisigVerificationFailed, format!(
+                "Multisig descriptor has {} weights for {} public keys", contract.weights.len(), self.public_keys.len()
+            )));
+        }
+        let weights = contract.weights.iter().map(|w| w.value).collect_vec();
+        if weights.iter().any(|w| *w <= 0) {
+            return Err(error_message(ErrorCode::MultisigVerificationFailed, "Multisig weights must be positive"));
+        }
+        Ok(weights)
+    }
+
+    /// Checks that the signers are distinct members of this descriptor whose combined weight
+    /// reaches the threshold. Signatures themselves are verified separately.
+    pub fn verify_signers(&self, signers: &Vec<PublicKey>) -> RgResult<()> {
+        let contract = self.contract.safe_get_msg("Missing contract on multisig address descriptor")?;
+        let threshold = contract.threshold.safe_get_msg("Missing threshold on multisig address descriptor")?.value;
+        if threshold <= 0 {
+            return Err(error_message(ErrorCode::MultisigVerificationFailed, "Multisig threshold must be positive"));
+        }
+        if self.public_keys.iter().map(|k| k.vec()).unique().count() != self.public_keys.len() {
+            return Err(error_message(ErrorCode::MultisigVerificationFailed, "Multisig descriptor contains duplicate public keys"));
+        }
+        let weights = self.key_weights()?;
+        let mut signed = HashSet::new();
+        let mut weight = 0i64;
+        for signer in signers {
+            let index = self.public_keys.iter().position(|k| k == signer).ok_or(error_message(
+                ErrorCode::MultisigVerificationFailed,
+                format!("Signer {} is not a member of the multisig address descriptor", signer.hex())
+            ))?;
+            if !signed.insert(index) {
+                return Err(error_message(ErrorCode::MultisigVerificationFailed, format!(
+                    "Duplicate multisig signer {}", signer.hex()
+                )));
+            }
+            weight = weight.saturating_add(weights[index]);
+        }
+        if weight < threshold {
+            return Err(error_message(ErrorCode::MultisigVerificationFailed, format!(
+                "Multisig threshold not met, signed weight {} of required {}", weight, threshold
+            )));
+        }
+        Ok(())
     }
 }
 
@@ -303,12 +383,41 @@ impl AddressInfo {
                 }
             }
         }
-        AddressInfo {
+        let mut info = AddressInfo {
             address: Some(address.clone()),
             utxo_entries: entries,
             balance: bal,
             recent_transactions: vec![],
-            balances: vec![]
-        }
+            balances: vec![],
+            locked_balance: 0,
+            spendable_balance: bal,
+        };
+        info.apply_time_locks(crate::util::times::current_time_millis(), None, &HashMap::new());
+        info
     }
-}
\ No newline at end of file
+}
+#[test]
+fn weighted_multisig_signer_verification() {
+    let pk = |i: u8| PublicKey::from_bytes_direct_ecdsa(vec![2, i]);
+    let unweighted = AddressDescriptor::from_multisig_public_keys_and_threshold(&vec![pk(1), pk(2), pk(3)], 2);
+    // Unweighted derivation is unchanged
+    assert_eq!(unweighted.to_address(), Address::from_multisig_public_keys_and_threshold(&vec![pk(3), pk(1), pk(2)], 2));
+    assert!(unweighted.verify_signers(&vec![pk(1), pk(3)]).is_ok());
+    assert!(unweighted.verify_signers(&vec![pk(1)]).is_err());
+    assert!(unweighted.verify_signers(&vec![pk(1), pk(1)]).is_err());
+    assert!(unweighted.verify_signers(&vec![pk(1), pk(4)]).is_err());
+
+    let weighted = AddressDescriptor::from_weighted_multisig_public_keys(&vec![(pk(3), 1), (pk(1), 3), (pk(2), 1)], 3);
+    assert!(weighted.verify_signers(&vec![pk(1)]).is_ok());
+    assert!(weighted.verify_signers(&vec![pk(2), pk(3)]).is_err());
+    assert!(weighted.verify_signers(&vec![pk(2), pk(3), pk(2)]).is_err());
+
+    // Weights are bound to the address
+    let mut reweighted = weighted.clone();
+    reweighted.contract.as_mut().unwrap().weights.iter_mut().for_each(|w| w.value = 3);
+    assert_ne!(weighted.to_address(), reweighted.to_address());
+    assert_ne!(weighted.to_address(), unweighted.to_address());
+    let mut mismatched = weighted.clone();
+    mismatched.contract.as_mut().unwrap().weights.pop();
+    assert!(mismatched.verify_signers(&vec![pk(1)]).is_err());
+}
@@ -1,7 +1,8 @@
 use crate::conf::rg_args::RgTopLevelSubcommand;
-use crate::config_data::{ConfigData, RpcUrl};
+use crate::config_data::{BackupTargetConfig, ConfigData, RpcUrl};
 use crate::constants::{OBSERVATION_FORMATION_TIME_MILLIS, REWARD_POLL_INTERVAL, STANDARD_FINALIZATION_INTERVAL_MILLIS};
 use crate::data_folder::{DataFolder, EnvDataFolder};
+use crate::exec::ExecutionLimits;
 use crate::keys::words_pass::WordsPass;
 use crate::observability::errors::Loggable;
 use crate::proto_serde::ProtoSerde;
@@ -27,6 +28,8 @@ pub struct MempoolConfig {
     pub channel_bound: usize,
     pub max_mempool_size: usize,
     pub max_mempool_age: Duration,
+    // Maximum pending transactions per input address, protects priority from a single spammer
+    pub max_pending_per_address: usize,
     pub allow_bypass: bool,
     pub interval: Duration
 }
@@ -37,6 +40,7 @@ impl Default for MempoolConfig {
             channel_bound: 1000,
             max_mempool_size: 100000,
             max_mempool_age: Duration::from_secs(3600),
+            max_pending_per_address: 100,
             allow_bypass: true,
             interval: Duration::from_secs(1),
         }
@@ -77,17 +81,39 @@ impl Default for ContractConfig {
             contract_state_channel_bound: 1000,
             bucket_parallelism: 10,
             interval: Duration::from_secs(1),
-            ordering_delay: Duration::from_secs(1)
+            ordering_delay: Duration::from_secs(1),
+            execution_limits: ExecutionLimits::default(),
+            ordering_strategy: ContractOrderingStrategyType::PeerObservation,
+            ordering_peer_timeout: Duration::from_secs(5),
+            ordering_max_wait: Duration::from_secs(10),
         }
     }
 }
 
+/// How pending requests against the same contract contention key are ordered before execution.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ContractOrderingStrategyType {
+    // Sort by transaction time as observed locally, only safe for a single node
+    LocalTime,
+    // Wait for a quorum of seed observation proofs on each request before sorting
+    PeerObservation,
+}
+
 #[derive(Clone, Debug)]
 pub struct ContractConfig {
     pub contract_state_channel_bound: usize,
     pub bucket_parallelism: usize,
     pub interval: Duration,
+    // Allowance for observation proofs signed before a request's ordering window closed to
+    // reach this node, requests are decided only after it passes
     pub ordering_delay: Duration,
+    pub execution_limits: ExecutionLimits,
+    pub ordering_strategy: ContractOrderingStrategyType,
+    // Timeout for querying peers for their observed request set
+    pub ordering_peer_timeout: Duration,
+    // Ordering window after the request transaction time, requests without a quorum of seed
+    // observation proofs signed within it are rejected
+    pub ordering_max_wait: Duration,
 }
 
 impl Default for ContentionConfig {
@@ -258,6 +284,18 @@ impl NodeConfig {
         res
     }
 
+    pub fn confirmation_depth(&self, currency: SupportedCurrency) -> u64 {
+        let configured = self.config_data.party.as_ref()
+            .and_then(|p| p.confirmation_depths.as_ref())
+            .and_then(|d| d.iter().find(|c| c.currency == currency))
+            .map(|c| c.depth);
+        configured.unwrap_or(match currency {
+            SupportedCurrency::Bitcoin => 3,
+            SupportedCurrency::Ethereum => 12,
+            _ => 1
+        })
+    }
+
     pub fn enable_party_mode(&self) -> bool {
         self.config_data.party.as_ref().and_then(|p| p.enable).unwrap_or(false)
     }
@@ -330,6 +368,12 @@ impl NodeConfig {
             .unwrap_or(false)
     }
 
+    pub fn noise_transport_enabled(&self) -> bool {
+        !self.config_data.node.as_ref()
+            .and_then(|x| x.noise_transport_disabled)
+            .unwrap_or(false)
+    }
+
     pub fn udp_keepalive(&self) -> Duration {
         self.config_data.node.as_ref()
             .and_then(|x| x.udp_keepalive_seconds)
@@ -351,6 +395,12 @@ impl NodeConfig {
         self.config_data.external.as_ref().and_then(|e| e.s3_backup_bucket.as_ref())
     }
 
+    pub fn backup_targets(&self) -> Vec<BackupTargetConfig> {
+        self.config_data.external.as_ref()
+            .and_then(|e| e.backup_targets.clone())
+            .unwrap_or_default()
+    }
+
     pub fn server_index(&self) -> i64 {
         self.config_data.node.as_ref().and_then(|n| n.server_index).unwrap_or(0)
     }
@@ -207,6 +207,7 @@ pub enum RgTopLevelSubcommand {
     GenerateConfig(GenerateConfig),
     Swap(Swap),
     Stake(Stake),
+    Restore(RestoreCli),
     Debug(DebugCommand)
 }
 
@@ -536,6 +537,21 @@ pub struct Stake {
 
 }
 
+/// Restore the node data store from a configured encrypted backup target. The existing data
+/// store is moved aside rather than deleted.
+#[derive(Args, Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreCli {
+    /// Name of the backup target to restore from, defaults to the first configured
+    #[clap(long)]
+    pub target: Option<String>,
+    /// Snapshot time in millis to restore, defaults to the most recent
+    #[clap(long)]
+    pub snapshot: Option<i64>,
+    /// Check the restored data store for consistency before it replaces the existing one
+    #[clap(long)]
+    pub verify: bool,
+}
+
 /// Generate a mnemonic from a password (minimum 128 bits of entropy required)
 /// Recommended to use the GUI instead of the CLI for this command, for more
 /// settings.
@@ -31,6 +31,15 @@ pub struct PartyConfigData {
     pub peer_timeout_seconds: Option<i64>,
     pub gg20_peer_timeout_seconds: Option<i64>,
     pub party_config: Option<NodePartyConfig>,
+    // External chain blocks required on top of a transaction before party events act on it
+    pub confirmation_depths: Option<Vec<ConfirmationDepth>>,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, Default, Eq, PartialEq)]
+#[serde(default)]
+pub struct ConfirmationDepth {
+    pub currency: SupportedCurrency,
+    pub depth: u64,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, Default, Eq, PartialEq)]
@@ -73,6 +82,8 @@ pub struct NodeData {
     pub http_client_proxy: Option<String>,
     pub udp_serve_disabled: Option<bool>,
     pub allowed_http_proxy_origins: Option<Vec<String>>,
+    // Falls back to signed plaintext peer messages, only for debugging transport issues
+    pub noise_transport_disabled: Option<bool>,
     // pub daq: Option<DaqConfig>
 }
 
@@ -107,6 +118,24 @@ pub struct RpcUrl {
 pub struct ExternalResources {
     pub s3_backup_bucket: Option<String>,
     pub rpcs: Option<Vec<RpcUrl>>,
+    pub backup_targets: Option<Vec<BackupTargetConfig>>,
+}
+
+/// An encrypted backup destination. Either a local directory (i.e. a mounted drive) or any
+/// S3 compatible object store, for instance a MinIO endpoint.
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq, Default)]
+#[serde(default)] // This allows fields to be omitted in TOML
+pub struct BackupTargetConfig {
+    pub name: Option<String>,
+    pub directory: Option<String>,
+    pub endpoint: Option<String>,
+    pub bucket: Option<String>,
+    pub region: Option<String>,
+    pub prefix: Option<String>,
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    /// Number of snapshots to keep, oldest are deleted first.
+    pub retention: Option<usize>,
 }
 
 
@@ -225,6 +254,7 @@ impl ConfigData {
                 http_client_proxy: None,
                 udp_serve_disabled: Some(false),
                 allowed_http_proxy_origins: None,
+                noise_transport_disabled: None,
                 // daq: None,
             }),
             party: Some(PartyConfigData {
@@ -234,6 +264,7 @@ impl ConfigData {
                 peer_timeout_seconds: None,
                 gg20_peer_timeout_seconds: None,
                 party_config: None,
+                confirmation_depths: None,
             }),
             debug: None,
             local: Some(LocalStoredState {
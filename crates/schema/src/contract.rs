@@ -1,6 +1,73 @@
-use crate::structs::StateSelector;
-use crate::HashClear;
+use crate::proto_serde::ProtoHashable;
+use crate::structs::{ContractStateMarker, Hash, StateSelector};
+use crate::{HashClear, RgResult, SafeOption};
 
 impl HashClear for StateSelector {
     fn hash_clear(&mut self) {}
-}
\ No newline at end of file
+}
+
+impl HashClear for ContractStateMarker {
+    fn hash_clear(&mut self) {
+        self.proof = None;
+    }
+}
+
+impl ContractStateMarker {
+
+    /// Chain the aggregate hash forward from the previous marker, genesis state starts from the
+    /// deploy transaction hash alone.
+    pub fn next_aggregate_state_hash(
+        previous: Option<&Hash>,
+        transaction_hash: &Hash,
+        state_hash: &Hash
+    ) -> Hash {
+        let base = match previous {
+            None => transaction_hash.clone(),
+            Some(p) => p.merkle_combine(transaction_hash.clone()),
+        };
+        base.merkle_combine(state_hash.clone())
+    }
+
+    pub fn with_aggregate_state_hash(&mut self, previous: Option<&ContractStateMarker>) -> RgResult<&mut Self> {
+        let tx_hash = self.transaction_marker.safe_get_msg("Missing transaction marker")?;
+        let state_hash = self.state.safe_get_msg("Missing state")?.calculate_hash();
+        let prev = previous.and_then(|p| p.aggregate_state_hash.as_ref());
+        self.aggregate_state_hash = Some(Self::next_aggregate_state_hash(prev, tx_hash, &state_hash));
+        Ok(self)
+    }
+
+    /// Hash covered by the producing node's proof.
+    pub fn signing_hash(&self) -> Hash {
+        self.calculate_hash()
+    }
+}
+
+#[test]
+fn aggregate_state_hash_chains_ordering() {
+    use crate::bytes_data;
+    let marker = |tx: &str, state: &[u8]| {
+        let mut m = ContractStateMarker::default();
+        m.transaction_marker = Some(Hash::from_string_calculate(tx));
+        m.state = bytes_data(state.to_vec());
+        m
+    };
+    let mut genesis = marker("deploy", b"zero");
+    genesis.with_aggregate_state_hash(None).unwrap();
+
+    let mut a1 = marker("a", b"one");
+    a1.with_aggregate_state_hash(Some(&genesis)).unwrap();
+    let mut b2 = marker("b", b"two");
+    b2.with_aggregate_state_hash(Some(&a1)).unwrap();
+
+    // Same final state reached through a different ordering must not collide
+    let mut b1 = marker("b", b"one");
+    b1.with_aggregate_state_hash(Some(&genesis)).unwrap();
+    let mut a2 = marker("a", b"two");
+    a2.with_aggregate_state_hash(Some(&b1)).unwrap();
+    assert_ne!(b2.aggregate_state_hash, a2.aggregate_state_hash);
+
+    // Proof does not affect the signed hash
+    let h = b2.signing_hash();
+    b2.proof = Some(Default::default());
+    assert_eq!(h, b2.signing_hash());
+}
@@ -77,6 +77,9 @@ impl EnvDataFolder {
     pub fn parquet_self_observations(&self) -> PathBuf {
         self.parquet_exports().join("observations")
     }
+    pub fn parquet_lake(&self) -> PathBuf {
+        self.parquet_exports().join("lake")
+    }
 
     pub fn servers_path(&self) -> PathBuf {
         self.path.join("servers")
@@ -1,6 +1,16 @@
-use crate::structs::{ExecutionResult, ResponseMetadata};
+use std::time::Duration;
+use crate::structs::{ErrorInfo, ExecutionBudget, ExecutionResult, ResponseMetadata};
 use crate::message::Response;
 
+/// Version of the read-only host function ABI exposed to contracts. Function names carry the
+/// version so a future incompatible revision can be linked alongside the current one.
+pub const HOST_ABI_VERSION: i32 = 1;
+
+pub const HOST_FN_UTXO: &str = "redgold_v1_utxo";
+pub const HOST_FN_BALANCE: &str = "redgold_v1_balance";
+pub const HOST_FN_CONTRACT_STATE: &str = "redgold_v1_contract_state";
+pub const HOST_FN_CENTRAL_PRICE: &str = "redgold_v1_central_price";
+
 impl ExecutionResult {
     pub fn from_error(error: crate::structs::ErrorInfo) -> Self {
         let mut er = ExecutionResult::default();
@@ -8,4 +18,77 @@ impl ExecutionResult {
         er.result_metadata = Some(ResponseMetadata::from_error(error.clone()));
         er
     }
-}
\ No newline at end of file
+
+    pub fn with_fuel(mut self, consumed: u64, limit: u64) -> Self {
+        self.fuel_consumed = Some(consumed as i64);
+        self.fuel_limit = Some(limit as i64);
+        self
+    }
+
+    pub fn error_info(&self) -> Option<&ErrorInfo> {
+        self.result_metadata.as_ref().and_then(|r| r.error_info.as_ref())
+    }
+
+    pub fn fuel_consumed_or(&self) -> u64 {
+        self.fuel_consumed.unwrap_or(0) as u64
+    }
+}
+
+/// Resource ceilings applied to a single contract invocation. Fuel is charged deterministically
+/// by instrumenting the module, so every node running the same code against the same input
+/// agrees on both the result and the amount consumed. The timeout is only a last resort safety
+/// net against engine level hangs and is not part of consensus.
+#[derive(Clone, Debug)]
+pub struct ExecutionLimits {
+    // Total fuel budget for one invocation
+    pub fuel_limit: u64,
+    // Fuel charged per executed wasm instruction
+    pub instruction_fuel: u32,
+    // Fuel charged per 64KiB page requested through memory.grow
+    pub memory_grow_fuel: u32,
+    // Fuel charged per local variable on each function call
+    pub call_per_local_fuel: u32,
+    // Fuel charged per invocation of any host function
+    pub host_call_fuel: u64,
+    // Fuel charged per byte passed into or returned out of the contract
+    pub fuel_per_byte: u64,
+    // Maximum number of 64KiB linear memory pages the module may use
+    pub max_memory_pages: u32,
+    // Maximum size of the serialized execution result
+    pub max_output_bytes: usize,
+    // Maximum value stack height, protects the host from deep recursion
+    pub max_stack_height: u32,
+    // Maximum size of a deployed contract module
+    pub max_code_bytes: usize,
+    pub timeout: Duration,
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        Self {
+            fuel_limit: 1_000_000_000,
+            instruction_fuel: 1,
+            memory_grow_fuel: 10_000,
+            call_per_local_fuel: 1,
+            host_call_fuel: 1_000,
+            fuel_per_byte: 1,
+            // 16 MiB
+            max_memory_pages: 256,
+            max_output_bytes: 1024 * 1024,
+            max_stack_height: 65_536,
+            max_code_bytes: 4 * 1024 * 1024,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl ExecutionLimits {
+    /// Narrow the node limits to the fuel the requester paid for, never beyond the node maximum.
+    pub fn with_budget(&self, budget: Option<&ExecutionBudget>) -> Self {
+        let mut limits = self.clone();
+        if let Some(b) = budget {
+            limits.fuel_limit = limits.fuel_limit.min(b.fuel_limit.max(0) as u64);
+        }
+        limits
+    }
+}
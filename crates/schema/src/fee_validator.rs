@@ -1,20 +1,129 @@
 use crate::helpers::with_metadata_hashable::WithMetadataHashable;
-use crate::structs::{Address, SupportedCurrency, Transaction};
+use crate::proto_serde::ProtoSerde;
+use crate::structs::{Address, CurrencyAmount, ErrorCode, ExecutionBudget, Output, SupportedCurrency, Transaction};
+use crate::{error_message, RgResult, SafeOption};
 use itertools::Itertools;
 
 pub const MIN_RDG_SATS_FEE: i64 = 1000;
+// Contract execution fuel purchased per RDG sat.
+pub const FUEL_PER_RDG_SAT: i64 = 10_000;
+// Upper bound on the fuel a single request may reserve, matches the default executor limit.
+pub const MAX_REQUEST_FUEL_LIMIT: i64 = 1_000_000_000;
+// Reasonable budget for simple state updates, used where callers don't supply their own.
+pub const DEFAULT_REQUEST_FUEL_LIMIT: i64 = 10_000_000;
+// Fee rates are expressed in milli-sats per serialized byte so small transactions don't round to zero.
+pub const FEE_RATE_SCALE: i64 = 1000;
+
+/// Deterministic fee schedule for contract execution, cost is linear in the requested fuel
+/// budget and rounded up to the nearest sat.
+pub fn estimate_execution_fee(fuel_limit: i64) -> CurrencyAmount {
+    let fuel = fuel_limit.max(0);
+    let sats = (fuel + FUEL_PER_RDG_SAT - 1) / FUEL_PER_RDG_SAT;
+    CurrencyAmount::from_rdg(sats)
+}
+
+impl ExecutionBudget {
+    pub fn new(fuel_limit: i64) -> Self {
+        Self {
+            fuel_limit,
+            fee: Some(estimate_execution_fee(fuel_limit)),
+        }
+    }
+
+    pub fn fee_amount(&self) -> i64 {
+        self.fee.as_ref().map(|f| f.amount).unwrap_or(0)
+    }
+
+    pub fn validate(&self) -> RgResult<()> {
+        if self.fuel_limit <= 0 || self.fuel_limit > MAX_REQUEST_FUEL_LIMIT {
+            return Err(error_message(ErrorCode::InsufficientFee, format!(
+                "Execution budget fuel limit {} must be between 1 and {}", self.fuel_limit, MAX_REQUEST_FUEL_LIMIT
+            )));
+        }
+        let fee = self.fee.safe_get_msg("Missing execution budget fee")?;
+        if fee.currency_or() != SupportedCurrency::Redgold {
+            return Err(error_message(ErrorCode::InsufficientFee, "Execution fees must be paid in RDG"));
+        }
+        let estimate = estimate_execution_fee(self.fuel_limit);
+        if fee.amount < estimate.amount {
+            return Err(error_message(ErrorCode::InsufficientFee, format!(
+                "Execution budget fee {} is below estimated cost {} for fuel limit {}",
+                fee.amount, estimate.amount, self.fuel_limit
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Output {
+    pub fn execution_budget(&self) -> Option<&ExecutionBudget> {
+        self.data.as_ref().and_then(|d| d.execution_budget.as_ref())
+    }
+
+    /// Requests and deploys both run contract code, so both pay for the fuel they reserve.
+    pub fn charges_execution(&self) -> bool {
+        self.is_request() || self.is_deploy()
+    }
+}
 
 pub trait TransactionFeeValidator {
     fn validate_fee(&self, addresses: &Vec<Address>) -> bool;
     fn validate_fee_only(&self, addresses: &Vec<Address>) -> bool;
+    fn fee_paid(&self, addresses: &Vec<Address>) -> i64;
+    fn fee_rate(&self, addresses: &Vec<Address>) -> i64;
 }
 
 pub trait ResolvedTransactionFeeValidator {
     fn validate_resolved_fee(&self, addresses: &Vec<Address>, max_parent_time: i64) -> bool;
 }
 
+pub trait ContractFeeValidator {
+    fn charges_execution(&self) -> bool;
+    fn execution_fee_total(&self) -> i64;
+    fn required_rdg_fee(&self) -> i64;
+    fn validate_contract_fees(&self, addresses: &Vec<Address>) -> RgResult<()>;
+}
+
+impl ContractFeeValidator for Transaction {
+    fn charges_execution(&self) -> bool {
+        self.outputs.iter().any(|o| o.charges_execution())
+    }
+
+    fn execution_fee_total(&self) -> i64 {
+        self.outputs.iter()
+            .filter(|o| o.charges_execution())
+            .flat_map(|o| o.execution_budget())
+            .map(|b| b.fee_amount())
+            .sum::<i64>()
+    }
+
+    fn required_rdg_fee(&self) -> i64 {
+        MIN_RDG_SATS_FEE + self.execution_fee_total()
+    }
+
+    /// Every contract request or deploy must carry a valid execution budget, and the fee outputs
+    /// must pay for the base transaction fee plus all committed execution fees.
+    fn validate_contract_fees(&self, addresses: &Vec<Address>) -> RgResult<()> {
+        for o in self.outputs.iter().filter(|o| o.charges_execution()) {
+            let budget = o.execution_budget().ok_or(error_message(
+                ErrorCode::InsufficientFee, "Contract output missing execution budget"
+            ))?;
+            budget.validate()?;
+        }
+        if self.charges_execution() && !self.validate_fee_only(addresses) {
+            return Err(error_message(ErrorCode::InsufficientFee, format!(
+                "Contract fee outputs below required fee {}", self.required_rdg_fee()
+            )));
+        }
+        Ok(())
+    }
+}
+
 impl ResolvedTransactionFeeValidator for Transaction {
     fn validate_resolved_fee(&self, addresses: &Vec<Address>, max_parent_time: i64) -> bool {
+        if self.charges_execution() {
+            return self.validate_fee_only(addresses);
+        }
         let small_num_outputs = self.outputs.len() < 5;
         let total_amount = self.output_amount_total().to_fractional();
         let min_stake = total_amount >= 1.0;
@@ -42,18 +151,84 @@ impl ResolvedTransactionFeeValidator for Transaction {
 
 impl TransactionFeeValidator for Transaction {
     fn validate_fee(&self, addresses: &Vec<Address>) -> bool {
-
+        // Contract requests and deploys always pay for execution, no zero fee exemption applies.
+        if self.charges_execution() {
+            return self.validate_fee_only(addresses);
+        }
         let small_num_outputs = self.outputs.len() < 5;
         let matches_zero_fee_condition = self.output_amount_total().to_fractional() >= 1.0 && small_num_outputs;
         matches_zero_fee_condition || self.validate_fee_only(addresses)
     }
 
     fn validate_fee_only(&self, addresses: &Vec<Address>) -> bool {
-        let value = self.output_address_amounts_opt()
+        let fee_condition = self.fee_paid(addresses) >= self.required_rdg_fee();
+        fee_condition
+    }
+
+    /// Total RDG paid to the fee addresses.
+    fn fee_paid(&self, addresses: &Vec<Address>) -> i64 {
+        self.output_address_amounts_opt()
             .filter(|(address, amount)| {
                 addresses.contains(address) && amount.currency_or() == SupportedCurrency::Redgold
-            }).map(|(_, amount)| amount.amount).sum::<i64>();
-        let fee_condition = value >= MIN_RDG_SATS_FEE;
-        fee_condition
+            }).map(|(_, amount)| amount.amount).sum::<i64>()
     }
-}
\ No newline at end of file
+
+    /// Fee paid per serialized byte, scaled by FEE_RATE_SCALE.
+    fn fee_rate(&self, addresses: &Vec<Address>) -> i64 {
+        let size = self.proto_serialize().len().max(1) as i64;
+        self.fee_paid(addresses).saturating_mul(FEE_RATE_SCALE) / size
+    }
+}
+
+#[test]
+fn execution_fee_schedule() {
+    assert_eq!(estimate_execution_fee(0).amount, 0);
+    assert_eq!(estimate_execution_fee(1).amount, 1);
+    assert_eq!(estimate_execution_fee(FUEL_PER_RDG_SAT).amount, 1);
+    assert_eq!(estimate_execution_fee(FUEL_PER_RDG_SAT + 1).amount, 2);
+    assert!(ExecutionBudget::new(1_000_000).validate().is_ok());
+    let mut underpaid = ExecutionBudget::new(1_000_000);
+    underpaid.fee = Some(CurrencyAmount::from_rdg(1));
+    assert_eq!(underpaid.validate().unwrap_err().code, ErrorCode::InsufficientFee as i32);
+    assert!(ExecutionBudget::new(0).validate().is_err());
+    assert!(ExecutionBudget::new(MAX_REQUEST_FUEL_LIMIT + 1).validate().is_err());
+}
+
+#[test]
+fn fee_rate_scales_with_size() {
+    let fee_address = Address::script_hash(&vec![1]).unwrap();
+    let other = Address::script_hash(&vec![2]).unwrap();
+    let addresses = vec![fee_address.clone()];
+    let mut small = Transaction::default();
+    small.outputs.push(Output::new(&fee_address, 2000));
+    small.outputs.push(Output::new(&other, 5000));
+    assert_eq!(small.fee_paid(&addresses), 2000);
+
+    let mut large = small.clone();
+    for _ in 0..10 {
+        large.outputs.push(Output::new(&other, 1));
+    }
+    assert_eq!(large.fee_paid(&addresses), 2000);
+    assert!(small.fee_rate(&addresses) > large.fee_rate(&addresses));
+    assert_eq!(small.fee_rate(&vec![]), 0);
+}
+
+#[test]
+fn deploy_pays_for_execution() {
+    let fee_address = Address::script_hash(&vec![1]).unwrap();
+    let addresses = vec![fee_address.clone()];
+    let mut deploy = Output::new(&Address::script_hash(&vec![2]).unwrap(), 5000);
+    deploy.output_type = Some(crate::structs::OutputType::Deploy as i32);
+    let mut tx = Transaction::default();
+    tx.outputs.push(deploy);
+    tx.outputs.push(Output::new(&fee_address, MIN_RDG_SATS_FEE));
+    assert!(tx.validate_contract_fees(&addresses).is_err());
+
+    let budget = ExecutionBudget::new(1_000_000);
+    tx.outputs[0].data.as_mut().unwrap().execution_budget = Some(budget.clone());
+    assert_eq!(tx.required_rdg_fee(), MIN_RDG_SATS_FEE + budget.fee_amount());
+    assert!(tx.validate_contract_fees(&addresses).is_err());
+    assert!(!tx.validate_fee(&addresses));
+    tx.outputs[1] = Output::new(&fee_address, tx.required_rdg_fee());
+    assert!(tx.validate_contract_fees(&addresses).is_ok());
+}
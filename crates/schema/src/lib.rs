@@ -69,6 +69,7 @@ pub mod transaction_info;
 pub mod exec;
 pub mod contract;
 pub mod weighting;
+pub mod time_lock;
 pub mod pow;
 pub mod tx_schema_validate;
 pub mod fee_validator;
@@ -1,4 +1,4 @@
-use crate::structs::{ContentionKey, CurrencyAmount, ErrorInfo, Hash, Observation, Output, OutputType, StakeRequest, StandardContractType, StandardData, StandardRequest, StandardResponse, StateSelector, SwapFulfillment, SwapRequest, UtxoEntry};
+use crate::structs::{ContentionKey, CurrencyAmount, ErrorInfo, ExecutorBackend, Hash, Observation, Output, OutputType, StakeRequest, StandardContractType, StandardData, StandardRequest, StandardResponse, StateSelector, SwapFulfillment, SwapRequest, UtxoEntry};
 use crate::transaction::amount_data;
 use crate::{Address, HashClear, RgResult, SafeOption};
 
@@ -40,6 +40,16 @@ impl Output {
             .map(|d| d.value.clone())
     }
 
+    /// Executor the contract code runs under, Extism when not specified.
+    pub fn executor_backend(&self) -> Option<ExecutorBackend> {
+        let contract = self.contract.as_ref()
+            .and_then(|d| d.code_execution_contract.as_ref())?;
+        match contract.executor {
+            None => Some(ExecutorBackend::Extism),
+            Some(e) => ExecutorBackend::from_i32(e)
+        }
+    }
+
     pub fn validate_deploy_code(&self) -> RgResult<Vec<u8>> {
         // Validate deploy
         if self.is_deploy() {
@@ -71,6 +81,9 @@ impl Output {
 
     pub fn request_contention_key(&self) -> RgResult<ContentionKey> {
         let option = self.address.safe_get_msg("Missing address")?;
+        if self.is_deploy() {
+            return Ok(ContentionKey::contract_request(option, None));
+        }
         let sel = self.request_selector()?;
         Ok(ContentionKey::contract_request(option, sel))
     }
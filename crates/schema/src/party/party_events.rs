@@ -53,11 +53,28 @@ pub struct PartyEvents where {
     pub portfolio_request_events: PortfolioRequestEvents,
     pub default_fee_addrs: Vec<Address>,
     pub seeds: Vec<PublicKey>,
+    // Latest observed external chain heights and the depth required before acting on an event
+    #[serde(default)]
+    pub external_tips: HashMap<SupportedCurrency, u64>,
+    #[serde(default)]
+    pub confirmation_depths: HashMap<SupportedCurrency, u64>,
     // pub party_pk_all_address: Vec<Address>,
 }
 
 impl PartyEvents {
 
+    /// Internal events are final once observed, external events must be buried under the
+    /// configured number of blocks for their chain.
+    pub fn has_confirmation_depth(&self, e: &AddressEvent) -> bool {
+        match e {
+            External(t) => {
+                let required = self.confirmation_depths.get(&t.currency).cloned().unwrap_or(0);
+                t.has_confirmation_depth(self.external_tips.get(&t.currency).cloned(), required)
+            }
+            AddressEvent::Internal(_) => true
+        }
+    }
+
     pub fn event_counts(&self) -> HashMap<SupportedCurrency, i64> {
         let mut map = HashMap::new();
         for e in self.events.iter() {
@@ -114,6 +131,29 @@ impl PartyEvents {
         }).cloned()
     }
 
+    /// Transfers between the party's own addresses, such as sweeps from a rotated instance into
+    /// its successor, are neither orders nor changes to the party balance.
+    pub fn is_party_migration(&self, e: &AddressEvent) -> bool {
+        let party = self.all_party_address();
+        match e {
+            External(t) => {
+                party.iter()
+                    .flat_map(|a| a.render_string().ok())
+                    .any(|a| a.to_lowercase() == t.other_address.to_lowercase())
+            }
+            AddressEvent::Internal(t) => {
+                let inputs = t.tx.input_address_descriptor_address_or_public_key();
+                let outputs = t.tx.outputs.iter()
+                    .filter(|o| !o.is_fee())
+                    .filter_map(|o| o.address.as_ref())
+                    .collect_vec();
+                !inputs.is_empty() && !outputs.is_empty()
+                    && inputs.iter().all(|a| party.contains(a))
+                    && outputs.iter().all(|a| party.contains(a))
+            }
+        }
+    }
+
     pub fn all_party_address(&self) -> Vec<Address> {
         self.party_addresses.clone().into_iter().flat_map(|(a,v)| {
             v
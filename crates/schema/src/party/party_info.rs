@@ -61,30 +61,58 @@ impl PartyInfo {
 
 impl PartyMetadata {
 
-    pub fn combine(&mut self, other: &PartyMetadata) {
+    /// Merge metadata received from `sender`. Only an instance's proposer may introduce it or
+    /// its memberships, and only its proposer or members may change its state, so a single seed
+    /// can't rewrite instances it has no part in.
+    pub fn combine(&mut self, other: &PartyMetadata, sender: &PublicKey) {
+        let mut introduced = HashSet::new();
         for inst in other.instances.iter() {
-            if let Some(a) = inst.address.as_ref() {
-                if self.instance_of_address(a).is_none() {
-                    self.instances.push(inst.clone());
+            let Some(a) = inst.address.as_ref() else { continue };
+            let members = self.members_of(a);
+            if let Some(existing) = self.instances.iter_mut().find(|i| i.address.as_ref() == Some(a)) {
+                let allowed = existing.proposer.as_ref() == Some(sender) || members.contains(sender);
+                // State changes such as rotation are carried by the newer copy.
+                if allowed && inst.proposer == existing.proposer
+                    && inst.last_update_time > existing.last_update_time {
+                    *existing = inst.clone();
                 }
+            } else if inst.proposer.as_ref() == Some(sender) {
+                self.instances.push(inst.clone());
+                introduced.insert(a.clone());
             }
         }
         for member in other.memberships.iter() {
-            if let Some(pk) = member.public_key.as_ref() {
-                if let Some(existing) = self.memberships.iter_mut()
-                    .find(|m| m.public_key.as_ref() == Some(pk)) {
-                    for part in member.participate.iter() {
-                        if existing.participate.iter().find(|p| p.address == part.address).is_none() {
-                            existing.participate.push(part.clone());
-                        }
+            let Some(pk) = member.public_key.as_ref() else { continue };
+            let participate = member.participate.iter()
+                .filter(|p| p.address.as_ref().map(|a| introduced.contains(a)).unwrap_or(false))
+                .cloned()
+                .collect::<Vec<PartyParticipation>>();
+            if participate.is_empty() {
+                continue;
+            }
+            if let Some(existing) = self.memberships.iter_mut()
+                .find(|m| m.public_key.as_ref() == Some(pk)) {
+                for part in participate {
+                    if existing.participate.iter().find(|p| p.address == part.address).is_none() {
+                        existing.participate.push(part);
                     }
-                } else {
-                    self.memberships.push(member.clone());
                 }
+            } else {
+                self.memberships.push(PartyMembership {
+                    public_key: Some(pk.clone()),
+                    participate,
+                });
             }
         }
     }
 
+    /// Whether `pk` holds a key share of the active instance at `address`, according to the
+    /// memberships its proposer published.
+    pub fn holds_share(&self, address: &Address, pk: &PublicKey) -> bool {
+        self.instance_of_address(address).map(|i| i.is_active()).unwrap_or(false)
+            && self.members_of(address).contains(pk)
+    }
+
     pub fn members_of(&self, address: &Address) -> HashSet<PublicKey> {
         self.memberships.iter()
             .filter(|m| m.participate.iter().any(|p| p.address.as_ref() == Some(address)))
@@ -93,10 +121,10 @@ impl PartyMetadata {
     }
     pub fn address_by_currency(&self) -> HashMap<SupportedCurrency, Vec<Address>> {
         self.instances.iter()
-            .group_by(|a| a.address.as_ref().map(|a| a.currency()))
+            .filter(|a| a.address.is_some())
+            .into_group_map_by(|a| a.currency())
             .into_iter()
-            .filter(|(k, _)| k.is_some())
-            .map(|(k, v)| (k.clone().unwrap(), v
+            .map(|(k, v)| (k, v.into_iter()
                 .sorted_by(|a, b| a.creation_time.cmp(&b.creation_time))
                 .map(|a| a.address.as_ref().unwrap()).cloned().collect()))
             .collect()
@@ -164,6 +192,69 @@ impl PartyMetadata {
             .any(|a| a.currency() == cur)
     }
 
+    pub fn has_active_instance(&self, cur: SupportedCurrency) -> bool {
+        self.instances.iter()
+            .filter(|i| i.is_active())
+            .any(|i| i.currency() == cur)
+    }
+
+    /// Instances being retired in favor of a successor party.
+    pub fn draining(&self) -> Vec<PartyInstance> {
+        self.instances.iter()
+            .filter(|i| i.state() == PartyState::PendingRemoval)
+            .cloned()
+            .collect()
+    }
+
+    /// Mark an instance as draining so a successor is formed for its currency.
+    pub fn mark_draining(&mut self, addr: &Address, time: i64) -> bool {
+        if let Some(i) = self.instances.iter_mut().find(|i| i.address.as_ref() == Some(addr)) {
+            if i.is_active() {
+                i.set_state(PartyState::PendingRemoval);
+                i.last_update_time = Some(time);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Point each draining instance without a successor at the newest active instance of the same
+    /// currency and proposer, returns whether anything changed.
+    pub fn link_successors(&mut self, time: i64) -> bool {
+        let mut links = vec![];
+        for i in self.instances.iter().filter(|i| i.state() == PartyState::PendingRemoval && i.successor.is_none()) {
+            let successor = self.instances.iter()
+                .filter(|s| s.is_active() && s.currency() == i.currency() && s.proposer == i.proposer)
+                .filter(|s| s.creation_time >= i.creation_time)
+                .max_by_key(|s| s.creation_time)
+                .and_then(|s| s.address.clone());
+            if let (Some(pred), Some(succ)) = (i.address.clone(), successor) {
+                links.push((pred, succ));
+            }
+        }
+        for (pred, succ) in links.iter() {
+            for i in self.instances.iter_mut() {
+                if i.address.as_ref() == Some(pred) {
+                    i.successor = Some(succ.clone());
+                    i.last_update_time = Some(time);
+                } else if i.address.as_ref() == Some(succ) && !i.priors.contains(pred) {
+                    i.priors.push(pred.clone());
+                    i.last_update_time = Some(time);
+                }
+            }
+        }
+        !links.is_empty()
+    }
+
+    /// Retire a drained instance once nothing is left to forward from it.
+    pub fn retire(&mut self, addr: &Address, time: i64) {
+        if let Some(i) = self.instances.iter_mut().find(|i| i.address.as_ref() == Some(addr)) {
+            i.set_state(PartyState::Inactive);
+            i.expired_time = Some(time);
+            i.last_update_time = Some(time);
+        }
+    }
+
     pub fn instances_of(&self, cur: &SupportedCurrency) -> impl Iterator<Item=&PartyInstance> {
         let cur = cur.clone() as i32;
         self.instances.iter()
@@ -218,3 +309,50 @@ impl PartyInstance {
         self.address.as_ref().map(|a| a.currency()).unwrap_or(SupportedCurrency::Redgold)
     }
 }
+
+#[test]
+fn rotation_links_successor_and_propagates() {
+    let proposer = PublicKey::from_bytes_direct_ecdsa(vec![2; 33]);
+    let addr = |i: u8| {
+        let mut a = Address::from_byte_calculate(&vec![i; 32]).expect("address");
+        a.set_currency(SupportedCurrency::Bitcoin);
+        a
+    };
+    let instance = |i: u8, time: i64| {
+        let mut inst = PartyInstance::default();
+        inst.address = Some(addr(i));
+        inst.proposer = Some(proposer.clone());
+        inst.set_state(PartyState::Active);
+        inst.creation_time = Some(time);
+        inst.last_update_time = Some(time);
+        inst
+    };
+    let mut md = PartyMetadata::default();
+    md.instances.push(instance(1, 10));
+    let stale = md.clone();
+
+    assert!(md.mark_draining(&addr(1), 20));
+    assert!(!md.has_active_instance(SupportedCurrency::Bitcoin));
+    assert!(!md.link_successors(20));
+    md.instances.push(instance(2, 30));
+    assert!(md.link_successors(40));
+    assert_eq!(md.instance_of_address(&addr(1)).unwrap().successor, Some(addr(2)));
+    assert_eq!(md.instance_of_address(&addr(2)).unwrap().priors, vec![addr(1)]);
+    assert_eq!(md.address(&SupportedCurrency::Bitcoin), Some(addr(2)));
+
+    md.add_members(&vec![proposer.clone()], Some(addr(2)));
+
+    // Updates from a peer with no part in the instances are ignored
+    let outsider = PublicKey::from_bytes_direct_ecdsa(vec![3; 33]);
+    let mut other = stale.clone();
+    other.combine(&md, &outsider);
+    assert_eq!(other, stale);
+
+    let mut other = stale;
+    other.combine(&md, &proposer);
+    assert_eq!(other.draining().len(), 1);
+    assert_eq!(other.instances.len(), 2);
+    assert!(other.holds_share(&addr(2), &proposer));
+    assert!(!other.holds_share(&addr(2), &outsider));
+    assert!(!other.holds_share(&addr(1), &proposer));
+}
@@ -2,7 +2,7 @@ use crate::helpers::easy_json::EasyJson;
 use crate::party::address_event::AddressEvent;
 use crate::party::external_data::{ExternalNetworkData, PriceDataPointUsdQuery};
 use crate::party::party_events::{OrderFulfillment, PartyEvents};
-use crate::structs::{PartyData, PublicKey, SupportedCurrency, Transaction};
+use crate::structs::{Address, ExternalTransactionId, PartyData, PublicKey, SupportedCurrency, Transaction};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use crate::parties::{PartyState, PartyInfo, PartyMetadata, PartyInstance};
@@ -22,7 +22,21 @@ pub struct PartyInternalData {
     pub address_events: Vec<AddressEvent>,
     pub price_data: PriceDataPointUsdQuery,
     pub party_events: Option<PartyEvents>,
-    pub locally_fulfilled_orders: Option<Vec<OrderFulfillment>>
+    pub locally_fulfilled_orders: Option<Vec<OrderFulfillment>>,
+    // Rotated instances whose funds are still being moved to their successor.
+    #[serde(default)]
+    pub draining: Vec<DrainingInstance>,
+}
+
+// A predecessor party address and the successor its balance and late deposits are swept into.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct DrainingInstance {
+    pub currency: SupportedCurrency,
+    pub predecessor: Address,
+    pub successor: Address,
+    pub started_time: i64,
+    pub last_sweep_time: Option<i64>,
+    pub sweeps: Vec<ExternalTransactionId>,
 }
 
 impl PartyInternalData {
@@ -44,5 +58,9 @@ impl PartyInternalData {
         true
     }
 
+    pub fn draining_of(&self, predecessor: &Address) -> Option<&DrainingInstance> {
+        self.draining.iter().find(|d| &d.predecessor == predecessor)
+    }
+
 
 }
\ No newline at end of file
@@ -1,4 +1,4 @@
-use crate::structs::{Signature, SignatureType};
+use crate::structs::{RsvSignature, Signature, SignatureType};
 use crate::{bytes_data, RgResult, SafeOption};
 
 impl Signature {
@@ -16,6 +16,24 @@ impl Signature {
             rsv: None
         }
     }
+    pub fn ed25519(bytes: Vec<u8>) -> Self {
+        Self {
+            bytes: bytes_data(bytes),
+            signature_type: SignatureType::Ed25519 as i32,
+            rsv: None
+        }
+    }
+    pub fn recoverable(r: Vec<u8>, s: Vec<u8>, v: i64) -> Self {
+        Self {
+            bytes: None,
+            signature_type: SignatureType::EcdsaRecoverable as i32,
+            rsv: Some(RsvSignature {
+                r: bytes_data(r),
+                s: bytes_data(s),
+                v: Some(v),
+            })
+        }
+    }
     pub fn raw_bytes(&self) -> RgResult<Vec<u8>> {
         Ok(self.bytes.safe_get()?.value.clone())
     }
@@ -0,0 +1,211 @@
+use crate::helpers::with_metadata_hashable::WithMetadataHashable;
+use crate::structs::{AddressInfo, ErrorCode, Hash, Output, OutputTimeLock, TimeLockWindow, Transaction};
+use crate::{error_message, RgResult};
+use crate::observability::errors::EnhanceErrorInfo;
+use std::collections::HashMap;
+
+/// Block height a time falls into, matching the windows used for block formation.
+pub fn block_height_at(time: i64, genesis_time: i64, interval_millis: i64) -> i64 {
+    if interval_millis <= 0 || time < genesis_time {
+        return 0;
+    }
+    (time - genesis_time) / interval_millis
+}
+
+impl TimeLockWindow {
+    pub fn new(offset: i64, delay: i64, window_size: i64) -> Self {
+        Self {
+            delay: delay.max(0) as u64,
+            offset: offset.max(0) as u64,
+            window_size: window_size.max(0) as u64,
+        }
+    }
+
+    pub fn validate(&self) -> RgResult<()> {
+        if self.window_size == 0 {
+            return Err(error_message(ErrorCode::OutputTimeLocked, "Time lock window size must be positive"));
+        }
+        if self.delay > 0 && self.window_size > self.delay {
+            return Err(error_message(ErrorCode::OutputTimeLocked, "Time lock window size cannot exceed its period"));
+        }
+        Ok(())
+    }
+
+    pub fn is_open(&self, time: i64) -> bool {
+        if time < 0 || (time as u64) < self.offset {
+            return false;
+        }
+        let elapsed = time as u64 - self.offset;
+        if self.delay == 0 {
+            elapsed < self.window_size
+        } else {
+            elapsed % self.delay < self.window_size
+        }
+    }
+}
+
+/// Every condition gating a spend of an output, gathered from the output's own lock and the
+/// `lock_period` and `time_lock_window` of the transaction which created it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpendLock {
+    pub unlock_time: Option<i64>,
+    pub unlock_height: Option<i64>,
+    pub window: Option<TimeLockWindow>,
+}
+
+impl SpendLock {
+    /// An unknown height never satisfies a height lock.
+    pub fn is_unlocked(&self, time: i64, height: Option<i64>) -> bool {
+        let time_ok = self.unlock_time.map(|t| time >= t).unwrap_or(true);
+        let height_ok = self.unlock_height
+            .map(|h| height.map(|current| current >= h).unwrap_or(false))
+            .unwrap_or(true);
+        let window_ok = self.window.as_ref().map(|w| w.is_open(time)).unwrap_or(true);
+        time_ok && height_ok && window_ok
+    }
+
+    /// Reject a spend from a transaction at `time` landing in block `height`.
+    pub fn validate_spendable(&self, time: i64, height: Option<i64>) -> RgResult<()> {
+        if !self.is_unlocked(time, height) {
+            return Err(error_message(ErrorCode::OutputTimeLocked, "Output is time locked"))
+                .with_detail("time", time.to_string())
+                .with_detail("height", format!("{:?}", height))
+                .with_detail("time_lock", format!("{:?}", self));
+        }
+        Ok(())
+    }
+}
+
+impl OutputTimeLock {
+    pub fn until_time(unlock_time: i64) -> Self {
+        Self {
+            unlock_time: Some(unlock_time),
+            ..Default::default()
+        }
+    }
+
+    pub fn until_height(unlock_height: i64) -> Self {
+        Self {
+            unlock_height: Some(unlock_height),
+            ..Default::default()
+        }
+    }
+}
+
+impl Output {
+    pub fn time_lock(&self) -> Option<&OutputTimeLock> {
+        self.contract.as_ref().and_then(|c| c.time_lock.as_ref())
+    }
+
+    /// Lock from the output alone, for when the creating transaction isn't at hand.
+    pub fn spend_lock(&self) -> SpendLock {
+        SpendLock {
+            unlock_time: self.time_lock().and_then(|l| l.unlock_time),
+            unlock_height: self.time_lock().and_then(|l| l.unlock_height),
+            window: None,
+        }
+    }
+
+    pub fn is_time_locked(&self, time: i64, height: Option<i64>) -> bool {
+        !self.spend_lock().is_unlocked(time, height)
+    }
+}
+
+impl Transaction {
+    /// Lock on spending output `index`, its own lock along with the `lock_period` after this
+    /// transaction's time and the `time_lock_window` of its contract.
+    pub fn output_spend_lock(&self, index: usize) -> RgResult<SpendLock> {
+        let output = self.outputs.get(index)
+            .ok_or(error_message(ErrorCode::OutputTimeLocked, "Output index out of bounds"))?;
+        let mut lock = output.spend_lock();
+        if let Some(c) = self.contract() {
+            if let Some(period) = c.lock_period {
+                let period_end = self.time()?.saturating_add(period as i64);
+                lock.unlock_time = Some(lock.unlock_time.map(|t| t.max(period_end)).unwrap_or(period_end));
+            }
+            lock.window = c.time_lock_window.clone();
+        }
+        Ok(lock)
+    }
+
+    pub fn validate_time_lock_contract(&self) -> RgResult<()> {
+        if let Some(w) = self.contract().and_then(|c| c.time_lock_window.as_ref()) {
+            w.validate()?;
+        }
+        Ok(())
+    }
+}
+
+impl AddressInfo {
+    /// Split the balance into amounts spendable at `time` and amounts still held by time locks.
+    /// Transaction wide locks are taken from `parents` where the creating transaction is known.
+    pub fn apply_time_locks(&mut self, time: i64, height: Option<i64>, parents: &HashMap<Hash, Transaction>) {
+        let mut locked = 0;
+        for u in &self.utxo_entries {
+            let Some(o) = u.output.as_ref() else { continue };
+            let lock = u.utxo_id.as_ref()
+                .and_then(|id| id.transaction_hash.as_ref().and_then(|h| parents.get(h)).map(|p| (id, p)))
+                .and_then(|(id, p)| p.output_spend_lock(id.output_index as usize).ok())
+                .unwrap_or(o.spend_lock());
+            if !lock.is_unlocked(time, height) {
+                locked += o.opt_amount().unwrap_or(0);
+            }
+        }
+        self.locked_balance = locked;
+        self.spendable_balance = self.balance - locked;
+    }
+}
+
+#[test]
+fn time_lock_conditions() {
+    use crate::structs::{Address, OutputContract};
+    let mut o = Output::new(&Address::script_hash(&vec![1]).unwrap(), 100);
+    assert!(o.spend_lock().validate_spendable(0, Some(0)).is_ok());
+    let mut c = OutputContract::default();
+    c.time_lock = Some(OutputTimeLock {
+        unlock_time: Some(1000),
+        unlock_height: Some(5),
+    });
+    o.contract = Some(c);
+    assert!(o.spend_lock().validate_spendable(999, Some(10)).is_err());
+    assert!(o.spend_lock().validate_spendable(1000, Some(4)).is_err());
+    assert!(o.spend_lock().validate_spendable(1000, Some(5)).is_ok());
+    assert!(o.is_time_locked(2000, None));
+
+    // Open for 10ms every 100ms starting at 1000
+    let w = TimeLockWindow::new(1000, 100, 10);
+    assert!(w.validate().is_ok());
+    assert!(!w.is_open(999));
+    assert!(w.is_open(1005));
+    assert!(!w.is_open(1050));
+    assert!(w.is_open(1309));
+    assert!(TimeLockWindow::new(0, 10, 20).validate().is_err());
+
+    assert_eq!(block_height_at(2500, 1000, 500), 3);
+    assert_eq!(block_height_at(500, 1000, 500), 0);
+}
+
+#[test]
+fn transaction_contract_locks_outputs() {
+    use crate::structs::{Address, TransactionContract, TransactionOptions};
+    let mut tx = Transaction::default();
+    tx.outputs.push(Output::new(&Address::script_hash(&vec![1]).unwrap(), 100));
+    tx.struct_metadata = crate::struct_metadata(1000);
+    let mut contract = TransactionContract::default();
+    contract.lock_period = Some(500);
+    // Open for the first 100ms of every second
+    contract.time_lock_window = Some(TimeLockWindow::new(0, 1000, 100));
+    let mut options = TransactionOptions::default();
+    options.contract = Some(contract);
+    tx.options = Some(options);
+    assert!(tx.validate_time_lock_contract().is_ok());
+
+    let lock = tx.output_spend_lock(0).expect("lock");
+    assert_eq!(lock.unlock_time, Some(1500));
+    assert!(!lock.is_unlocked(1050, None));
+    assert!(!lock.is_unlocked(1550, None));
+    assert!(lock.is_unlocked(2050, None));
+    // The output alone carries no lock
+    assert!(!tx.outputs[0].is_time_locked(1050, None));
+    assert!(tx.output_spend_lock(1).is_err());
+}
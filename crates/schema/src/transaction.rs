@@ -667,6 +667,20 @@ impl Transaction {
     }
 
 
+    /// Hash counter-parties sign, None when the transaction has no counter-party proofs.
+    pub fn counter_party_hash(&self) -> Option<Hash> {
+        let has_proofs = self.outputs.iter().any(|o| !o.counter_party_proofs.is_empty());
+        Some(self.signed_hash()).filter(|_| has_proofs)
+    }
+
+    /// Hash confirmations sign, None when the transaction has no confirmation proofs.
+    pub fn confirmation_signing_hash(&self) -> Option<Hash> {
+        let has_proofs = self.options.iter()
+            .flat_map(|o| o.contract.iter())
+            .any(|c| !c.confirmation_proofs.is_empty());
+        Some(self.confirmation_hash()).filter(|_| has_proofs)
+    }
+
     #[allow(dead_code)]
     fn pre_counter_party_hash(&self) -> Hash {
         self.signed_hash()
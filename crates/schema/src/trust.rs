@@ -35,6 +35,9 @@ impl TrustData {
     pub fn maybe_label(&self) -> Option<f64> {
         self.label_rating.clone().map(|l| l.to_float())
     }
+    pub fn maybe_confidence(&self) -> Option<f64> {
+        self.confidence.clone().map(|c| c.to_float())
+    }
 
     pub fn from_label(label: f64) -> Self {
         let mut t = TrustData::default();
@@ -0,0 +1,134 @@
+use crate::helpers::with_metadata_hashable::WithMetadataHashable;
+use crate::observability::errors::EnhanceErrorInfo;
+use crate::structs::{Address, ErrorCode, Output, Transaction, TransactionContract};
+use crate::{error_message, RgResult};
+
+/// Acceptance window used when the contract does not specify a finalize_window.
+pub const DEFAULT_ACCEPTANCE_WINDOW_MILLIS: i64 = 1000 * 60 * 60 * 24;
+
+impl TransactionContract {
+    pub fn counter_party_acceptance(window_millis: Option<i64>) -> Self {
+        Self {
+            confirmation: Some(true),
+            finalize_window: window_millis.map(|w| w.max(0) as u64),
+            ..Default::default()
+        }
+    }
+}
+
+impl Transaction {
+    pub fn contract(&self) -> Option<&TransactionContract> {
+        self.options.as_ref().and_then(|o| o.contract.as_ref())
+    }
+
+    pub fn requires_acceptance(&self) -> bool {
+        self.contract().and_then(|c| c.confirmation).unwrap_or(false)
+    }
+
+    pub fn acceptance_expiry(&self) -> RgResult<i64> {
+        let window = self.contract()
+            .and_then(|c| c.finalize_window)
+            .map(|w| w as i64)
+            .unwrap_or(DEFAULT_ACCEPTANCE_WINDOW_MILLIS);
+        Ok(self.time()?.saturating_add(window))
+    }
+
+    /// Outputs paying someone other than the sender, which the recipient must accept.
+    /// Change and fee outputs never require acceptance.
+    pub fn counter_party_outputs(&self) -> impl Iterator<Item = &Output> {
+        let inputs = self.input_address_descriptor_address_or_public_key();
+        self.outputs.iter()
+            .filter(|o| !o.is_fee())
+            .filter(move |o| o.address.as_ref().map(|a| !inputs.contains(a)).unwrap_or(false))
+    }
+
+    pub fn counter_party_addresses(&self) -> Vec<Address> {
+        self.counter_party_outputs().filter_map(|o| o.address.clone()).collect()
+    }
+
+    pub fn is_accepted(&self) -> bool {
+        self.counter_party_outputs().all(|o| !o.counter_party_proofs.is_empty())
+    }
+
+    pub fn is_acceptance_expired(&self, time: i64) -> bool {
+        self.acceptance_expiry().map(|e| time > e).unwrap_or(true)
+    }
+
+    /// Held transactions reserve their inputs until the acceptance window closes. The conflicting
+    /// transaction's own time decides whether the reservation still applies, so every node agrees.
+    pub fn reserves_inputs_against(&self, other: &Transaction) -> bool {
+        if self.signed_hash() == other.signed_hash() {
+            return false;
+        }
+        let expired = other.time().map(|t| self.is_acceptance_expired(*t)).unwrap_or(false);
+        !expired && other.input_utxo_ids().any(|u| self.input_utxo_ids().any(|r| r == u))
+    }
+
+    /// Counter-party proof signatures are verified with the other proofs, this only checks that
+    /// every recipient has accepted and that the acceptance arrived before expiry.
+    pub fn validate_acceptance(&self, time: i64) -> RgResult<()> {
+        if !self.requires_acceptance() {
+            return Ok(());
+        }
+        let expiry = self.acceptance_expiry()?;
+        if time > expiry {
+            return Err(error_message(ErrorCode::AcceptanceExpired, "Counter-party acceptance window has closed"))
+                .with_detail("expiry", expiry.to_string())
+                .with_detail("time", time.to_string());
+        }
+        if !self.is_accepted() {
+            return Err(error_message(ErrorCode::AcceptancePending, "Transaction is missing counter-party acceptance"))
+                .with_detail("expiry", expiry.to_string());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn counter_party_acceptance_conditions() {
+    use crate::structs::{Input, OutputType, Proof, StructMetadata, TransactionOptions};
+    let sender = Address::script_hash(&vec![1]).unwrap();
+    let recipient = Address::script_hash(&vec![2]).unwrap();
+    let mut tx = Transaction::default();
+    let mut input = Input::default();
+    let d = crate::structs::AddressDescriptor::from_multisig_public_keys_and_threshold(&vec![], 1);
+    input.address_descriptor = Some(d.clone());
+    tx.inputs.push(input);
+    let change_address = d.to_address();
+    tx.outputs.push(Output::new(&recipient, 100));
+    tx.outputs.push(Output::new(&change_address, 50));
+    let mut fee = Output::new(&sender, 10);
+    fee.output_type = Some(OutputType::Fee as i32);
+    tx.outputs.push(fee);
+    let mut sm = StructMetadata::default();
+    sm.time = Some(1000);
+    tx.struct_metadata = Some(sm);
+    let mut options = TransactionOptions::default();
+    options.contract = Some(TransactionContract::counter_party_acceptance(Some(500)));
+    tx.options = Some(options);
+
+    assert!(tx.requires_acceptance());
+    assert_eq!(tx.counter_party_addresses(), vec![recipient.clone()]);
+    assert_eq!(tx.acceptance_expiry().unwrap(), 1500);
+    assert_eq!(tx.validate_acceptance(1200).unwrap_err().code, ErrorCode::AcceptancePending as i32);
+
+    tx.outputs.get_mut(0).unwrap().counter_party_proofs.push(Proof::default());
+    assert!(tx.is_accepted());
+    assert!(tx.validate_acceptance(1500).is_ok());
+    assert_eq!(tx.validate_acceptance(1501).unwrap_err().code, ErrorCode::AcceptanceExpired as i32);
+
+    let mut conflicting = Transaction::default();
+    conflicting.inputs = tx.inputs.clone();
+    conflicting.struct_metadata = Some(StructMetadata { time: Some(1400), ..Default::default() });
+    assert!(!tx.reserves_inputs_against(&conflicting));
+    let utxo_id = crate::structs::UtxoId::new(&crate::structs::Hash::from_string_calculate("parent"), 0);
+    tx.inputs.get_mut(0).unwrap().utxo_id = Some(utxo_id.clone());
+    conflicting.inputs.get_mut(0).unwrap().utxo_id = Some(utxo_id);
+    assert!(tx.reserves_inputs_against(&conflicting));
+    assert!(!tx.reserves_inputs_against(&tx.clone()));
+    conflicting.struct_metadata.as_mut().unwrap().time = Some(1501);
+    assert!(!tx.reserves_inputs_against(&conflicting));
+
+    tx.options.as_mut().unwrap().contract = None;
+    assert!(tx.validate_acceptance(5000).is_ok());
+}
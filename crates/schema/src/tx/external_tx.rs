@@ -65,6 +65,23 @@ impl ExternalTimedTransaction {
         self.timestamp.is_some()
     }
 
+    /// Blocks including and built on top of the transaction's block, given the chain tip.
+    pub fn confirmations(&self, tip: u64) -> Option<u64> {
+        self.block_number.filter(|b| *b <= tip).map(|b| tip - b + 1)
+    }
+
+    /// Whether the transaction is buried deeply enough to act on. When either the block or the
+    /// tip is unknown depth can't be measured, so only a zero depth requirement passes.
+    pub fn has_confirmation_depth(&self, tip: Option<u64>, required: u64) -> bool {
+        if !self.confirmed() {
+            return false;
+        }
+        match (self.block_number, tip) {
+            (Some(_), Some(tip)) => self.confirmations(tip).map(|c| c >= required).unwrap_or(false),
+            _ => required == 0
+        }
+    }
+
     pub fn to_brief(&self) -> BriefTransaction {
         BriefTransaction {
             hash: self.tx_id.clone(),
@@ -84,4 +101,20 @@ impl ExternalTimedTransaction {
     }
 
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn external_confirmation_depth() {
+    let mut tx = ExternalTimedTransaction::default();
+    assert!(!tx.has_confirmation_depth(Some(100), 1));
+    tx.timestamp = Some(1);
+    assert!(!tx.has_confirmation_depth(Some(100), 6));
+    assert!(tx.has_confirmation_depth(Some(100), 0));
+    tx.block_number = Some(95);
+    assert_eq!(tx.confirmations(100), Some(6));
+    assert!(tx.has_confirmation_depth(Some(100), 6));
+    assert!(!tx.has_confirmation_depth(Some(99), 6));
+    assert!(!tx.has_confirmation_depth(None, 6));
+    // Tip behind the transaction block, e.g. after a reorg
+    assert!(!tx.has_confirmation_depth(Some(90), 1));
+}
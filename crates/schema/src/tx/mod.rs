@@ -2,4 +2,7 @@ pub mod currency_amount;
 pub mod external_tx;
 pub mod tx_builder;
 pub mod builder_portfolio;
-pub mod currency_id;
\ No newline at end of file
+pub mod currency_id;
+pub mod replace_by_fee;
+pub mod acceptance;
+pub mod reward;
@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+
+use crate::fee_validator::TransactionFeeValidator;
+use crate::structs::{Address, ErrorCode, Transaction, TransactionType, UtxoId};
+use crate::{error_message, RgResult};
+
+impl Transaction {
+    pub fn is_cancel(&self) -> bool {
+        self.transaction_type().map(|t| t == TransactionType::Cancel).unwrap_or(false)
+    }
+
+    /// A cancel may only return value to its own input addresses, plus fee outputs.
+    pub fn validate_cancel(&self) -> RgResult<()> {
+        let inputs = self.input_address_descriptor_address_or_public_key();
+        for o in self.outputs.iter().filter(|o| !o.is_fee()) {
+            if o.is_deploy() || o.is_request() || o.contract.is_some() {
+                return Err(error_message(ErrorCode::ReplacementRejected, "Cancel transaction cannot contain contract outputs"));
+            }
+            let returned = o.address.as_ref().map(|a| inputs.contains(a)).unwrap_or(false);
+            if !returned {
+                return Err(error_message(ErrorCode::ReplacementRejected, "Cancel transaction outputs must return to input addresses"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fixed UTXO inputs spent by both transactions.
+    pub fn conflicting_inputs(&self, other: &Transaction) -> Vec<UtxoId> {
+        let other_inputs = other.utxo_inputs().collect::<HashSet<_>>();
+        self.utxo_inputs().filter(|u| other_inputs.contains(u)).cloned().collect()
+    }
+
+    /// A replacement must spend every input of the pending transaction it supersedes and pay
+    /// strictly more in fees, so a replacement can never free up an input for a third spend
+    /// or be used to churn the mempool for free.
+    pub fn validate_replacement(&self, replaced: &Transaction, fee_addresses: &Vec<Address>) -> RgResult<()> {
+        let inputs = self.utxo_inputs().collect::<HashSet<_>>();
+        if !replaced.utxo_inputs().all(|u| inputs.contains(u)) {
+            return Err(error_message(ErrorCode::ReplacementRejected, "Replacement must spend all inputs of the pending transaction"));
+        }
+        let fee = self.fee_paid(fee_addresses);
+        let replaced_fee = replaced.fee_paid(fee_addresses);
+        if fee <= replaced_fee {
+            return Err(error_message(ErrorCode::ReplacementRejected, format!(
+                "Replacement fee {} must be strictly greater than pending fee {}", fee, replaced_fee
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn replacement_requires_all_inputs_and_higher_fee() {
+    use crate::structs::{Hash, Input, Output, OutputType, TransactionOptions};
+    let fee_address = Address::script_hash(&vec![1]).unwrap();
+    let fee_addresses = vec![fee_address.clone()];
+    let utxo = |i: i64| UtxoId::new(&Hash::from_string_calculate("parent"), i);
+    let tx = |inputs: Vec<i64>, fee: i64| {
+        let mut t = Transaction::default();
+        for i in inputs {
+            let mut input = Input::default();
+            input.utxo_id = Some(utxo(i));
+            t.inputs.push(input);
+        }
+        let mut fee_output = Output::new(&fee_address, fee);
+        fee_output.output_type = Some(OutputType::Fee as i32);
+        t.outputs.push(fee_output);
+        t.options = Some(TransactionOptions::default());
+        t
+    };
+    let pending = tx(vec![0, 1], 1000);
+    assert_eq!(tx(vec![1, 2], 5000).conflicting_inputs(&pending), vec![utxo(1)]);
+    assert!(tx(vec![0, 1], 2000).validate_replacement(&pending, &fee_addresses).is_ok());
+    assert!(tx(vec![0, 1, 2], 2000).validate_replacement(&pending, &fee_addresses).is_ok());
+    assert!(tx(vec![0, 1], 1000).validate_replacement(&pending, &fee_addresses).is_err());
+    assert!(tx(vec![1], 2000).validate_replacement(&pending, &fee_addresses).is_err());
+
+    let mut cancel = tx(vec![0, 1], 2000);
+    cancel.options.as_mut().unwrap().transaction_type = TransactionType::Cancel as i32;
+    assert!(cancel.is_cancel());
+    // Fee outputs only
+    assert!(cancel.validate_cancel().is_ok());
+    cancel.outputs.push(Output::new(&Address::script_hash(&vec![2]).unwrap(), 5000));
+    assert!(cancel.validate_cancel().is_err());
+}
@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use crate::helpers::with_metadata_hashable::WithMetadataHashable;
+use crate::proto_serde::ProtoSerde;
+use crate::structs::{Address, ErrorCode, Input, NetworkEnvironment, Output, RewardDistribution, SupportedCurrency, Transaction, TransactionOptions, TransactionType, UtxoId};
+use crate::tx_schema_validate::DUST_LIMIT;
+use crate::{error_message, struct_metadata, RgResult};
+
+impl Transaction {
+    pub fn is_reward(&self) -> bool {
+        self.transaction_type().map(|t| t == TransactionType::Reward).unwrap_or(false)
+    }
+
+    pub fn reward_distribution(&self) -> Option<&RewardDistribution> {
+        self.options.as_ref().and_then(|o| o.reward_distribution.as_ref())
+    }
+
+    /// Fee outputs this transaction paid to the given addresses, along with their amounts. These
+    /// are what an epoch's reward transaction spends.
+    pub fn fee_utxos(&self, fee_addresses: &Vec<Address>) -> Vec<(UtxoId, i64)> {
+        let hash = self.hash_or();
+        self.outputs.iter().enumerate()
+            .filter(|(_, o)| o.address.as_ref().map(|a| fee_addresses.contains(a)).unwrap_or(false))
+            .filter_map(|(i, o)| o.opt_amount_typed()
+                .filter(|a| a.currency_or() == SupportedCurrency::Redgold)
+                .map(|a| (UtxoId::new(&hash, i as i64), a.amount)))
+            .collect()
+    }
+
+    /// Structural checks only, whether the inputs and payouts match the epoch is verified by
+    /// rebuilding the transaction from accepted data, and input amounts by the resolver.
+    pub fn validate_reward(&self) -> RgResult<()> {
+        let d = self.reward_distribution().ok_or(error_message(
+            ErrorCode::InvalidReward, "Reward transaction missing distribution"
+        ))?;
+        if self.inputs.is_empty() {
+            return Err(error_message(ErrorCode::InvalidReward, "Reward transaction must spend the epoch's fees"));
+        }
+        // Fee outputs are released by the epoch rebuild rather than by their owner's signature
+        if self.inputs.iter().any(|i| !i.proof.is_empty() || i.floating_utxo_id.is_some()) {
+            return Err(error_message(ErrorCode::InvalidReward, "Reward inputs must be unsigned fee outputs"));
+        }
+        if self.time()? != &d.end_time {
+            return Err(error_message(ErrorCode::InvalidReward, "Reward transaction time must be the epoch end"));
+        }
+        for o in &self.outputs {
+            if o.is_fee() || o.contract.is_some() || o.address.is_none() || o.opt_amount().is_none() {
+                return Err(error_message(ErrorCode::InvalidReward, "Reward outputs must be plain payments"));
+            }
+        }
+        if self.total_output_amount() != d.fees_collected {
+            return Err(error_message(ErrorCode::InvalidReward, format!(
+                "Reward outputs total {} does not match fees collected {}",
+                self.total_output_amount(), d.fees_collected
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Split `total` proportionally to the integer weights. Recipients whose share would fall below
+/// the dust limit are dropped and the remainder re-split, rounding remainder goes to the heaviest
+/// recipient. Output order is by weight descending then address bytes, so the result only
+/// depends on the inputs and never on iteration order.
+pub fn reward_payouts(total: i64, weights: &Vec<(Address, i64)>) -> Vec<(Address, i64)> {
+    let mut merged: HashMap<Address, i64> = HashMap::new();
+    for (a, w) in weights.iter().filter(|(_, w)| *w > 0) {
+        *merged.entry(a.clone()).or_insert(0) += *w;
+    }
+    let mut recipients = merged.into_iter().collect::<Vec<(Address, i64)>>();
+    recipients.sort_by(|(a1, w1), (a2, w2)| {
+        w2.cmp(w1).then(a1.proto_serialize().cmp(&a2.proto_serialize()))
+    });
+
+    loop {
+        let weight_total = recipients.iter().map(|(_, w)| *w as i128).sum::<i128>();
+        if total <= 0 || weight_total == 0 {
+            return vec![];
+        }
+        let mut payouts = recipients.iter()
+            .map(|(a, w)| (a.clone(), ((total as i128 * *w as i128) / weight_total) as i64))
+            .collect::<Vec<(Address, i64)>>();
+        let len = recipients.len();
+        recipients.retain(|(a, _)| {
+            payouts.iter().find(|(p, _)| p == a).map(|(_, amt)| *amt >= DUST_LIMIT).unwrap_or(false)
+        });
+        if recipients.len() == len {
+            let remainder = total - payouts.iter().map(|(_, amt)| *amt).sum::<i64>();
+            if let Some(first) = payouts.first_mut() {
+                first.1 += remainder;
+            }
+            return payouts;
+        }
+    }
+}
+
+/// Build the reward transaction for an epoch. It spends the epoch's fee outputs without
+/// signatures, every field is derived from the distribution, fee outputs and payout weights so
+/// each node produces the same hash.
+pub fn reward_transaction(
+    distribution: &RewardDistribution,
+    fee_inputs: &Vec<UtxoId>,
+    weights: &Vec<(Address, i64)>,
+    network: &NetworkEnvironment,
+) -> Option<Transaction> {
+    let payouts = reward_payouts(distribution.fees_collected, weights);
+    if payouts.is_empty() || fee_inputs.is_empty() {
+        return None;
+    }
+    let mut fee_inputs = fee_inputs.clone();
+    fee_inputs.sort_by(|a, b| a.proto_serialize().cmp(&b.proto_serialize()));
+    let mut tx = Transaction {
+        inputs: fee_inputs.into_iter().map(|u| Input {
+            utxo_id: Some(u),
+            ..Default::default()
+        }).collect(),
+        outputs: payouts.iter().map(|(a, amt)| Output::new(a, *amt)).collect(),
+        struct_metadata: struct_metadata(distribution.end_time),
+        options: Some(TransactionOptions {
+            salt: Some(distribution.epoch),
+            network_type: Some(*network as i32),
+            transaction_type: TransactionType::Reward as i32,
+            reward_distribution: Some(distribution.clone()),
+            ..Default::default()
+        }),
+    };
+    tx.with_hash();
+    Some(tx)
+}
+
+#[test]
+fn reward_payouts_are_deterministic_and_exact() {
+    let addr = |i: u8| Address::from_byte_calculate(&vec![i; 32]).expect("address");
+    let weights = vec![(addr(1), 10), (addr(2), 30), (addr(3), 30), (addr(4), 1), (addr(5), 0)];
+    let total = 50_000;
+    let payouts = reward_payouts(total, &weights);
+    // The weight 1 recipient would receive below the dust limit and is dropped.
+    assert_eq!(payouts.len(), 3);
+    assert_eq!(payouts.iter().map(|(_, a)| *a).sum::<i64>(), total);
+    assert_eq!(payouts[2].0, addr(1));
+
+    let d = RewardDistribution { epoch: 3, start_time: 0, end_time: 100, fees_collected: total };
+    let reversed = weights.iter().rev().cloned().collect::<Vec<_>>();
+    let fee_tx = Transaction {
+        outputs: vec![Output::new(&addr(9), total - 2000), Output::new(&addr(8), 2000)],
+        struct_metadata: struct_metadata(50),
+        ..Default::default()
+    };
+    let fee_utxos = fee_tx.fee_utxos(&vec![addr(9)]);
+    assert_eq!(fee_utxos.len(), 1);
+    let inputs = vec![fee_utxos[0].0.clone(), UtxoId::new(&fee_tx.hash_or(), 5)];
+    let inputs_reversed = inputs.iter().rev().cloned().collect::<Vec<_>>();
+    let a = reward_transaction(&d, &inputs, &weights, &NetworkEnvironment::Dev).expect("tx");
+    let b = reward_transaction(&d, &inputs_reversed, &reversed, &NetworkEnvironment::Dev).expect("tx");
+    assert_eq!(a.hash_or(), b.hash_or());
+    assert!(a.is_reward());
+    a.validate_reward().expect("valid");
+    assert!(reward_transaction(&RewardDistribution { fees_collected: 0, ..d.clone() }, &inputs, &weights, &NetworkEnvironment::Dev).is_none());
+    assert!(reward_transaction(&d, &vec![], &weights, &NetworkEnvironment::Dev).is_none());
+}
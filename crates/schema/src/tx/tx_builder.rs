@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 use crate::conf::node_config::NodeConfig;
-use crate::fee_validator::{TransactionFeeValidator, MIN_RDG_SATS_FEE};
+use crate::fee_validator::{ContractFeeValidator, TransactionFeeValidator};
 use crate::helpers::easy_json::EasyJson;
 use crate::helpers::with_metadata_hashable::WithMetadataHashable;
 use crate::observability::errors::EnhanceErrorInfo;
-use crate::structs::{Address, AddressDescriptor, AddressInfo, CodeExecutionContract, CurrencyAmount, DepositRequest, ErrorInfo, ExecutorBackend, ExternalTransactionId, Input, LiquidityRange, NetworkEnvironment, NodeMetadata, Observation, Output, OutputContract, OutputType, PeerMetadata, PoWProof, StakeDeposit, StakeRequest, StakeWithdrawal, StandardContractType, StandardData, StandardRequest, StandardResponse, SupportedCurrency, Transaction, TransactionData, TransactionOptions, UtxoEntry, UtxoId};
+use crate::structs::{Address, AddressDescriptor, AddressInfo, CodeExecutionContract, CurrencyAmount, DepositRequest, ErrorInfo, ExecutionBudget, ExecutorBackend, ExternalTransactionId, Input, LiquidityRange, NetworkEnvironment, NodeMetadata, Observation, Output, OutputContract, OutputTimeLock, OutputType, PeerMetadata, PoWProof, StakeDeposit, StakeRequest, StakeWithdrawal, StandardContractType, StandardData, StandardRequest, StandardResponse, SupportedCurrency, TimeLockWindow, Transaction, TransactionContract, TransactionData, TransactionOptions, UtxoEntry, UtxoId};
 use crate::transaction::amount_data;
 use crate::tx_schema_validate::SchemaValidationSupport;
+use crate::util::times::current_time_millis;
 use crate::{bytes_data, error_info, structs, RgResult, SafeOption};
 use itertools::Itertools;
 use log::info;
@@ -78,6 +79,15 @@ impl TransactionBuilder {
         self.transaction.options = Some(options);
         self
     }
+
+    // Hold the transaction until every recipient signs an acceptance, expiring after window_millis
+    pub fn with_counter_party_acceptance(&mut self, window_millis: Option<i64>) -> &mut Self {
+        let acceptance = TransactionContract::counter_party_acceptance(window_millis);
+        let contract = self.contract_mut();
+        contract.confirmation = acceptance.confirmation;
+        contract.finalize_window = acceptance.finalize_window;
+        self
+    }
     // pub fn with_input_utxo_id(&mut self, input_utxo_id: &UtxoId) -> &mut Self {
     //     let mut input = Input::default();
     //     input.utxo_id = Some(input_utxo_id.clone());
@@ -197,10 +207,86 @@ impl TransactionBuilder {
         self
     }
 
+    /// Output that cannot be spent until the given time and / or block height.
+    pub fn with_time_locked_output(
+        &mut self,
+        destination: &Address,
+        amount: &CurrencyAmount,
+        unlock_time: Option<i64>,
+        unlock_height: Option<i64>
+    ) -> RgResult<&mut Self> {
+        if unlock_time.is_none() && unlock_height.is_none() {
+            return Err(error_info("Time locked output requires an unlock time or height"));
+        }
+        let lock = OutputTimeLock {
+            unlock_time,
+            unlock_height,
+        };
+        Ok(self.with_locked_output(destination, amount.amount, lock))
+    }
 
+    /// Every output of this transaction only spendable within recurring windows.
+    pub fn with_time_lock_window(&mut self, window: TimeLockWindow) -> RgResult<&mut Self> {
+        window.validate()?;
+        self.contract_mut().time_lock_window = Some(window);
+        Ok(self)
+    }
+
+    /// Every output of this transaction locked until `period_millis` after the transaction time.
+    pub fn with_lock_period(&mut self, period_millis: i64) -> &mut Self {
+        self.contract_mut().lock_period = Some(period_millis.max(0) as u64);
+        self
+    }
+
+    fn contract_mut(&mut self) -> &mut TransactionContract {
+        self.transaction.options.get_or_insert_with(TransactionOptions::default)
+            .contract.get_or_insert_with(TransactionContract::default)
+    }
+
+    /// Vesting schedule as `tranches` equal outputs unlocking every `period_millis` after `start_time`,
+    /// with any rounding remainder released in the final tranche.
+    pub fn with_vesting_outputs(
+        &mut self,
+        destination: &Address,
+        amount: &CurrencyAmount,
+        start_time: i64,
+        period_millis: i64,
+        tranches: i64
+    ) -> RgResult<&mut Self> {
+        if tranches <= 0 || period_millis <= 0 {
+            return Err(error_info("Vesting requires a positive number of tranches and period"));
+        }
+        let per_tranche = amount.amount / tranches;
+        if per_tranche <= 0 {
+            return Err(error_info("Vesting amount too small for number of tranches"));
+        }
+        for i in 0..tranches {
+            let tranche_amount = if i == tranches - 1 {
+                amount.amount - per_tranche * (tranches - 1)
+            } else {
+                per_tranche
+            };
+            let unlock_time = start_time + period_millis * (i + 1);
+            self.with_locked_output(destination, tranche_amount, OutputTimeLock::until_time(unlock_time));
+        }
+        Ok(self)
+    }
+
+    fn with_locked_output(&mut self, destination: &Address, amount: i64, lock: OutputTimeLock) -> &mut Self {
+        let mut output = Output::new(destination, amount);
+        let mut contract = OutputContract::default();
+        contract.time_lock = Some(lock);
+        output.contract = Some(contract);
+        self.transaction.outputs.push(output);
+        self
+    }
+
+    /// Attach a contract request along with an execution budget for up to `fuel_limit` fuel, the
+    /// estimated execution fee is added on top of the base fee when the transaction is built.
     pub fn with_contract_request_output(&mut self,
                                         destination: &Address,
-                                        serialized_request: &Vec<u8>
+                                        serialized_request: &Vec<u8>,
+                                        fuel_limit: i64
     ) -> RgResult<&mut Self> {
         let mut o = Output::default();
         o.address = Some(destination.clone());
@@ -209,6 +295,7 @@ impl TransactionBuilder {
         o.contract = Some(c);
         let mut d = StandardData::default();
         d.request = bytes_data(serialized_request.clone());
+        d.execution_budget = Some(ExecutionBudget::new(fuel_limit));
         o.data = Some(d);
         o.output_type = Some(OutputType::RequestCall as i32);
         self.transaction.outputs.push(o);
@@ -217,8 +304,10 @@ impl TransactionBuilder {
     }
 
     // TODO: Do we need to deal with contract state here?
+    /// The genesis invocation of a deploy is charged like a request, up to `fuel_limit` fuel.
     pub fn with_contract_deploy_output_and_predicate_input(
-        &mut self, code: impl AsRef<[u8]>, c_amount: CurrencyAmount, use_predicate_input: bool) -> RgResult<&mut Self> {
+        &mut self, code: impl AsRef<[u8]>, c_amount: CurrencyAmount, use_predicate_input: bool, fuel_limit: i64
+    ) -> RgResult<&mut Self> {
         let destination = Address::script_hash(code.as_ref())?;
         let mut o = Output::default();
         o.address = Some(destination.clone());
@@ -230,6 +319,9 @@ impl TransactionBuilder {
         contract.code_execution_contract = Some(code_exec);
         o.contract = Some(contract);
         o.data = amount_data(c_amount.amount as u64);
+        if let Some(d) = o.data.as_mut() {
+            d.execution_budget = Some(ExecutionBudget::new(fuel_limit));
+        }
         self.transaction.outputs.push(o);
         if use_predicate_input {
             let input = Input::predicate_filter(&destination);
@@ -483,11 +575,18 @@ impl TransactionBuilder {
             }
         }
 
+        // Outputs still time locked at the transaction time can't be selected, height locked ones
+        // included as the builder doesn't know the block height. Locks from the creating
+        // transaction's contract aren't known here and are left to validation.
+        let time = self.transaction.time().ok().cloned().unwrap_or(current_time_millis());
         for u in self.utxos.clone() {
 
             if self.balance() > 0 {
                 break
             }
+            if u.output.as_ref().map(|o| o.is_time_locked(time, None)).unwrap_or(false) {
+                continue;
+            }
             if u.opt_amount().is_some() {
                 if let Some(v) = u.address().ok().and_then(|a| address_descriptors.get(&a)) {
                     self.with_unsigned_input_address_descriptor(u.clone(), v)?;
@@ -505,12 +604,14 @@ impl TransactionBuilder {
             self.with_remainder();
         }
 
-        if !self.transaction.validate_fee_only(&self.fee_addrs) && !self.zero_fee_requested {
+        let zero_fee_allowed = self.zero_fee_requested && !self.transaction.charges_execution();
+        if !self.transaction.validate_fee_only(&self.fee_addrs) && !zero_fee_allowed {
+            let required_fee = self.transaction.required_rdg_fee();
             let mut found_fee = false;
             for o in self.transaction.outputs.iter_mut().rev() {
                 if let Some(a) = o.data.as_mut().and_then(|data| data.amount.as_mut()) {
-                    if a.currency_or() == SupportedCurrency::Redgold && a.amount > MIN_RDG_SATS_FEE {
-                        a.amount -= MIN_RDG_SATS_FEE;
+                    if a.currency_or() == SupportedCurrency::Redgold && a.amount > required_fee {
+                        a.amount -= required_fee;
                         found_fee = true;
                         // info!("builder Found fee deduction");
                         break;
@@ -518,7 +619,8 @@ impl TransactionBuilder {
                 }
             }
             if found_fee {
-                self.with_default_fee()?;
+                let first_fee_addr = (*self.fee_addrs.get(0).safe_get_msg("Missing fee address")?).clone();
+                self.with_fee(&first_fee_addr, &CurrencyAmount::from(required_fee))?;
             }
             if !self.transaction.validate_fee_only(&self.fee_addrs) && !self.allow_bypass_fee {
                 return Err(ErrorInfo::error_info("Insufficient fee")).add(self.transaction.json_or())
@@ -15,7 +15,7 @@ pub trait SchemaValidationSupport {
     fn validate_current_time(&self, max_delta: Option<i64>) -> RgResult<()>;
 }
 
-const DUST_LIMIT : i64 = 1000;
+pub const DUST_LIMIT : i64 = 1000;
 const MAX_TX_BYTE_SIZE: usize = 100_000;
 impl SchemaValidationSupport for Transaction  {
     fn validate_schema(&self, network_opt: Option<&NetworkEnvironment>, expect_signed: bool) -> RgResult<()> {
@@ -64,7 +64,7 @@ impl SchemaValidationSupport for Transaction  {
         // TODO: Deal with this later for genesis / nmd
         if self.inputs.is_empty() {
             // if all nmd or
-            if !self.is_metadata_or_obs() {
+            if !self.is_metadata_or_obs() && !self.is_reward() {
                 Err(error_code(structs::ErrorCode::MissingInputs))?;
             }
         }
@@ -72,6 +72,16 @@ impl SchemaValidationSupport for Transaction  {
             Err(error_code(structs::ErrorCode::MissingOutputs))?;
         }
 
+        if self.is_cancel() {
+            self.validate_cancel()?;
+        }
+
+        self.validate_time_lock_contract()?;
+
+        if self.is_reward() {
+            self.validate_reward()?;
+        }
+
         if let Some(o) = &self.options {
             if let Some(d) = &o.data {
                 if let Some(m) = &d.message {
@@ -19,7 +19,9 @@ export CARGO_TARGET_DIR="./target"
 #	cp $CARGO_TARGET_DIR/wasm32-wasi/release/$NAME ./test_contract_guest.wasi.wasm
 #echo "Compiled using module memory"
 
-cargo build --package redgold-sdk --release --target wasm32-unknown-unknown
+# Contracts are instrumented for fuel metering before execution, which only supports MVP wasm
+# (plus sign extension), so newer default target features must be disabled.
+RUSTFLAGS="-C target-cpu=mvp" cargo build --package redgold-sdk --release --target wasm32-unknown-unknown
 #cargo build --package redgold-sdk --release --target wasm32-wasi
 cp $CARGO_TARGET_DIR/wasm32-unknown-unknown/release/$NAME ./test_contract_guest.wasm
 #cp $CARGO_TARGET_DIR/wasm32-wasi/release/$NAME ./test_contract_guest.wasm
@@ -0,0 +1,68 @@
+use extism_pdk::Memory;
+
+use redgold_schema::exec::HOST_ABI_VERSION;
+use redgold_schema::helpers::easy_json::json_from;
+use redgold_schema::party::central_price::CentralPricePair;
+use redgold_schema::proto_serde::ProtoSerde;
+use redgold_schema::structs::{Address, ContractStateMarker, CurrencyAmount, HostQueryRequest, HostQueryResponse, StateSelector, SupportedCurrency, UtxoEntry, UtxoId};
+use redgold_schema::{error_info, RgResult, SafeOption};
+
+// Read-only chain state host functions, see redgold_schema::exec for the ABI version.
+// Each takes the offset of a serialized HostQueryRequest and returns the offset of a
+// serialized HostQueryResponse.
+#[link(wasm_import_module = "env")]
+extern "C" {
+    fn redgold_v1_utxo(offset: u64) -> u64;
+    fn redgold_v1_balance(offset: u64) -> u64;
+    fn redgold_v1_contract_state(offset: u64) -> u64;
+    fn redgold_v1_central_price(offset: u64) -> u64;
+}
+
+fn new_request() -> HostQueryRequest {
+    let mut request = HostQueryRequest::default();
+    request.abi_version = HOST_ABI_VERSION;
+    request
+}
+
+fn call(host_fn: unsafe extern "C" fn(u64) -> u64, request: HostQueryRequest) -> RgResult<HostQueryResponse> {
+    let input = Memory::from_bytes(request.proto_serialize());
+    let offset = unsafe { host_fn(input.offset) };
+    let output = Memory::find(offset).ok_or(error_info("Empty host query response"))?;
+    let response = HostQueryResponse::proto_deserialize(output.to_vec())?;
+    if let Some(e) = response.error {
+        return Err(e);
+    }
+    Ok(response)
+}
+
+/// Look up an output created at or before the ordering point of the executing transaction.
+pub fn utxo(utxo_id: &UtxoId) -> RgResult<Option<UtxoEntry>> {
+    let mut request = new_request();
+    request.utxo_id = Some(utxo_id.clone());
+    Ok(call(redgold_v1_utxo, request)?.utxo_entry)
+}
+
+pub fn balance(address: &Address) -> RgResult<CurrencyAmount> {
+    let mut request = new_request();
+    request.address = Some(address.clone());
+    call(redgold_v1_balance, request)?.balance.ok_msg("Missing balance in host response")
+}
+
+/// Latest state of another contract as of the ordering point.
+pub fn contract_state(
+    address: &Address,
+    selector: Option<&StateSelector>
+) -> RgResult<Option<ContractStateMarker>> {
+    let mut request = new_request();
+    request.address = Some(address.clone());
+    request.selector = selector.cloned();
+    Ok(call(redgold_v1_contract_state, request)?.contract_state)
+}
+
+pub fn central_price(currency: SupportedCurrency) -> RgResult<Option<CentralPricePair>> {
+    let mut request = new_request();
+    request.currency = Some(currency as i32);
+    call(redgold_v1_central_price, request)?.central_price_pair_json
+        .map(|j| json_from::<CentralPricePair>(&j))
+        .transpose()
+}
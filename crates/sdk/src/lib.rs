@@ -1,6 +1,7 @@
 #![allow(unused_imports, dead_code)]
 pub mod example;
 pub mod entry;
+pub mod host;
 
 use crate::entry::with_entry_decoder;
 use crate::example::example_contract_main;
@@ -11,6 +11,8 @@ use redgold_schema::{empty_public_request, SafeOption};
 use std::time::Duration;
 use tracing::{debug, info};
 use redgold_common::client::http::{self, RequestResponseAuth};
+use redgold_common::client::verify::{ConfirmationScore, SignerDirectory};
+use redgold_keys::proof_support::ProofSignatureVerifier;
 
 #[derive(Clone)]
 pub struct PublicClient {
@@ -106,6 +108,17 @@ impl PublicClient {
     }
 
 
+    /// Submit synchronously and verify the returned observation proofs against known signers.
+    pub async fn send_transaction_verified(
+        &self,
+        t: &Transaction,
+        directory: &SignerDirectory,
+    ) -> Result<(SubmitTransactionResponse, ConfirmationScore), ErrorInfo> {
+        let mut c = self.client_wrapper();
+        c.timeout = Duration::from_secs(180);
+        c.send_transaction_verified(t, directory, &ProofSignatureVerifier).await
+    }
+
     pub async fn faucet(
         &self,
         t: &Address
@@ -1,9 +1,12 @@
 use crate::core::relay::Relay;
+use crate::util;
 use redgold_keys::address_external::{ToBitcoinAddress, ToEthereumAddress};
 use redgold_keys::address_support::AddressSupport;
 use redgold_keys::proof_support::PublicKeySupport;
 use redgold_schema::proto_serde::ProtoSerde;
 use redgold_schema::structs::{Address, AddressInfo, ErrorInfo, Hash, HashSearchResponse, PeerId, PublicKey};
+use redgold_schema::RgResult;
+use std::collections::{HashMap, HashSet};
 
 pub async fn hash_query(relay: Relay, hash_input: String, limit: Option<i64>, offset: Option<i64>) -> Result<HashSearchResponse, ErrorInfo> {
     let mut response = HashSearchResponse::default();
@@ -76,6 +79,7 @@ pub async fn get_address_info(relay: &Relay, limit: Option<i64>, offset: Option<
     let limit = limit.unwrap_or(10);
     let offset = offset.unwrap_or(0);
     info.recent_transactions = relay.ds.transaction_store.get_all_tx_for_address(&a, limit, offset).await?;
+    apply_time_locks(relay, &mut info).await?;
     Ok(info)
 }
 
@@ -95,6 +99,24 @@ pub async fn get_address_info_public_key(relay: &Relay, pk: &PublicKey, limit: O
     info.recent_transactions.extend(btc_info.recent_transactions);
     info.utxo_entries.extend(eth_info.utxo_entries);
     info.utxo_entries.extend(btc_info.utxo_entries);
+    apply_time_locks(relay, &mut info).await?;
 
     Ok(info)
 }
+
+/// Creating transactions are loaded so their contract lock period and windows count as well.
+async fn apply_time_locks(relay: &Relay, info: &mut AddressInfo) -> RgResult<()> {
+    let now = util::current_time_millis_i64();
+    let height = relay.block_height_at(now).await?;
+    let mut parents = HashMap::new();
+    let hashes = info.utxo_entries.iter()
+        .filter_map(|u| u.utxo_id.as_ref().and_then(|id| id.transaction_hash.clone()))
+        .collect::<HashSet<_>>();
+    for h in hashes {
+        if let Some(tx) = relay.ds.transaction_store.query_accepted_tx(&h).await? {
+            parents.insert(h, tx);
+        }
+    }
+    info.apply_time_locks(now, height, &parents);
+    Ok(())
+}
@@ -23,5 +23,6 @@ pub mod v1;
 pub mod udp_keepalive;
 pub mod client;
 pub mod warp_helpers;
+pub mod stream_api;
 
 
@@ -7,6 +7,7 @@ use std::time::Duration;
 
 use itertools::Itertools;
 use metrics::counter;
+use redgold_keys::request_support::RequestSupport;
 use rand::rngs::OsRng;
 use rand::RngCore;
 use redgold_common::flume_send_help::{new_channel, RecvAsyncErrorInfo, SendErrorInfo};
@@ -167,6 +168,14 @@ pub async fn handle_proto_post(reqb: Bytes, _address: Option<SocketAddr>, relay:
     counter!("redgold.api.handle_proto_post").increment(1);
     let vec_b = reqb.to_vec();
     let mut request = Request::proto_deserialize(vec_b)?;
+    if let Some(res) = relay.noise.handle_rest(&relay, &request, origin.clone()).await {
+        return res;
+    }
+    if relay.node_config.noise_transport_enabled() {
+        if let Ok(signer) = request.verify_auth() {
+            relay.noise.check_plaintext(&request, &signer).await?;
+        }
+    }
     request.origin = origin;
     relay.receive_request_send_internal(request, None).await
 }
@@ -318,6 +327,17 @@ pub async fn run_server(relay: Relay) -> Result<(), ErrorInfo>{
             }
         });
 
+    let tmp_relay = relay.clone();
+    let trust_scores = warp::get()
+        .and(warp::path("trust-scores"))
+        .and_then(move || {
+            let relay3 = tmp_relay.clone();
+            async move {
+                let ps = relay3.eigen_trust_result().await;
+                as_warp_json_response(ps)
+            }
+        });
+
     let bin_relay = relay.clone();
 
     let request_normal = warp::post()
@@ -369,6 +389,7 @@ pub async fn run_server(relay: Relay) -> Result<(), ErrorInfo>{
     let relay_arc = Arc::new(relay2.clone());
 
     let routes = hello
+        .or(trust_scores)
         .or(trust)
         .or(peer_tx)
         .or(node_tx)
@@ -385,6 +406,7 @@ pub async fn run_server(relay: Relay) -> Result<(), ErrorInfo>{
         // .or(explorer_hash)
         // .or(explorer_recent)
         .or(explorer::server::explorer_specific_routes(relay2.clone()))
+        .or(crate::api::stream_api::stream_routes(relay2.clone()))
         .or(v1_api_routes(relay_arc))
         .or(home);
 
@@ -6,6 +6,7 @@ use warp::Rejection;
 
 use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
 use redgold_schema::{error_info, struct_metadata_new, SafeOption};
+use redgold_schema::time_lock::block_height_at;
 
 use crate::schema::error_message;
 use crate::schema::structs;
@@ -120,28 +121,19 @@ pub async fn account_coins(
 }
 
 pub async fn block(r: Rosetta, request: BlockRequest) -> Result<BlockResponse, ErrorInfo> {
-    // r.validate_network(request.network_identifier).await?;
-    // let mut maybe_hash = None;
-    // let partial_block_identifier = request.block_identifier;
-    // if let Some(h) = partial_block_identifier.hash {
-    //     maybe_hash = Some(schema::decode_hex(h)?);
-    // };
-    // // TODO: Shouldn't this whole thing really check to verify the active height / active chain thing works?
-    // // revisit this on block removed events.
-    // let maybe_block = r
-    //     .relay
-    //     .ds
-    //     .address_block_store
-    //     .query_block_hash_height(maybe_hash, partial_block_identifier.index)
-    //     .await?;
-    // Ok(BlockResponse {
-    //     block: match maybe_block {
-    //         Some(b) => r.translate_block(b, State::Accepted).await?.into(),
-    //         None => None,
-    //     },
-    //     other_transactions: None,
-    // })
-    Err(error_info("Not implemented"))
+    r.validate_network(request.network_identifier).await?;
+    let partial_block_identifier = request.block_identifier;
+    let maybe_block = r.query_block(
+        partial_block_identifier.hash,
+        partial_block_identifier.index
+    ).await?;
+    Ok(BlockResponse {
+        block: match maybe_block {
+            Some(b) => r.translate_block(b, State::Accepted).await?.into(),
+            None => None,
+        },
+        other_transactions: None,
+    })
 }
 
 pub async fn block_transaction(
@@ -149,28 +141,27 @@ pub async fn block_transaction(
     request: BlockTransactionRequest,
 ) -> Result<BlockTransactionResponse, ErrorInfo> {
     r.validate_network(request.network_identifier).await?;
-    Err(error_info("Not implemented"))
-    // let block = r
-    //     .query_block(
-    //         Some(request.block_identifier.hash),
-    //         Some(request.block_identifier.index),
-    //     )
-    //     .await?
-    //     .ok_or(error_message(
-    //         RGError::UnknownBlock,
-    //         "Block not found in data store",
-    //     ))?;
-    //
-    // for t in block.transactions {
-    //     if t.hash_hex()? == request.transaction_identifier.hash {
-    //         let transaction = r.translate_transaction(t.clone(), State::Accepted).await?;
-    //         return Ok(BlockTransactionResponse { transaction });
-    //     }
-    // }
-    // Err(error_message(
-    //     RGError::UnknownTransaction,
-    //     "Transaction not found in block",
-    // ))
+    let block = r
+        .query_block(
+            Some(request.block_identifier.hash),
+            Some(request.block_identifier.index),
+        )
+        .await?
+        .ok_or(error_message(
+            RGError::UnknownBlock,
+            "Block not found in data store",
+        ))?;
+
+    for t in block.transactions {
+        if t.hash_hex() == request.transaction_identifier.hash {
+            let transaction = r.translate_transaction(t.clone(), State::Accepted).await?;
+            return Ok(BlockTransactionResponse { transaction });
+        }
+    }
+    Err(error_message(
+        RGError::UnknownTransaction,
+        "Transaction not found in block",
+    ))
 }
 
 pub async fn call(r: Rosetta, request: CallRequest) -> Result<CallResponse, ErrorInfo> {
@@ -397,17 +388,29 @@ pub async fn construction_submit(
     Rosetta::transaction_identifier(&tx)
 }
 
-// TODO: How to deal with this?
-// Build a new table called block event or something?
-// update block index table to include both old and new ones?? how to to handle gc?
+// Upper bound on events or transactions returned per request.
+const MAX_RESULTS: i64 = 1000;
+
 pub async fn events_blocks(
     r: Rosetta,
     request: EventsBlocksRequest,
 ) -> Result<EventsBlocksResponse, ErrorInfo> {
     r.validate_network(request.network_identifier).await?;
-    Ok(EventsBlocksResponse{
-        max_sequence: 0,
-        events: vec![]
+    let limit = request.limit.unwrap_or(100).clamp(0, MAX_RESULTS);
+    let max_sequence = r.relay.ds.block.query_max_block_event_sequence().await?.unwrap_or(0);
+    // Without an offset, return the most recent events up to the tip.
+    let offset = request.offset.unwrap_or(std::cmp::max(max_sequence - limit + 1, 0));
+    let mut events = vec![];
+    for (sequence, height, hash, removed) in r.relay.ds.block.query_block_events(offset, limit).await? {
+        events.push(BlockEvent {
+            sequence,
+            block_identifier: BlockIdentifier { index: height, hash: hash.hex() },
+            _type: if removed { BlockEventType::REMOVED } else { BlockEventType::ADDED },
+        });
+    }
+    Ok(EventsBlocksResponse {
+        max_sequence,
+        events
     })
 }
 
@@ -487,6 +490,10 @@ pub async fn network_options(
                 OperationStatus{
                     status: format!("{:?}", State::Accepted),
                     successful: true
+                },
+                OperationStatus{
+                    status: format!("{:?}", State::Reverted),
+                    successful: false
                 }],
             operation_types: vec![Rosetta::operation_type()],
             // TODO: Errors list
@@ -518,7 +525,9 @@ pub async fn network_status(
     })
 }
 
-#[allow(unused_variables, unused_assignments, unused_mut, dead_code)]
+/// Searches accepted and rejected transactions, rejected ones reported with a `Reverted`
+/// status. Only transactions falling in windows of already formed blocks are returned, each
+/// identified by the block whose window contains its time.
 pub async fn search_transactions(
     r: Rosetta,
     request: SearchTransactionsRequest,
@@ -530,55 +539,310 @@ pub async fn search_transactions(
     if let Some(s) = request._type {
         r.validate_type(s).await?;
     }
-    // if let Some(s) = request.coin_identifier {
-    //     // todo validate
-    //     //r.validate_type(s)?;
-    // }
-    // if let Some(s) = request.status {
-    //     // todo validate
-    //     //r.validate_type(s)?;
-    // }
 
-    let mut _transaction_hash: Option<Hash> = None;
-    let mut _addr: Option<Address> = None;
-    let mut _success: bool = false;
-    let mut _is_and: bool = false;
-    let mut _is_or: bool = false;
-    // let limit = request.limit;
-    // let offset = request.offset;
-    // let max_block = request.max_block;
-
-    for ti in request.transaction_identifier {
-        let b = from_hex(ti.hash)?;
-        // _transaction_hash = Some(Hash::new_from_proto(b));
-    }
+    let mut transaction_hash: Option<Hash> = None;
+    let mut addr: Option<Address> = None;
+    let is_or = matches!(request.operator, Some(Operator::OR));
+    let limit = request.limit.unwrap_or(100).clamp(0, MAX_RESULTS);
+    let offset = std::cmp::max(request.offset.unwrap_or(0), 0);
 
-    for a in request.address {
-        _addr = Some(a.parse_address()?);
+    if let Some(ti) = request.transaction_identifier {
+        transaction_hash = Some(Hash::from_hex(ti.hash)?);
+    }
+    if let Some(a) = request.address {
+        addr = Some(a.parse_address()?);
     }
     if let Some(s) = request.account_identifier {
         // TODO: Abstract this, validate no subaccount present or throw error.
-        _addr = Some(s.address.parse_address()?);
+        addr = Some(s.address.parse_address()?);
     }
 
-    if let Some(s) = request.success {
-        _success = s;
+    let empty = SearchTransactionsResponse {
+        transactions: vec![],
+        total_count: 0,
+        next_offset: None
+    };
+    let Some(last_block) = r.relay.ds.block.query_last_block().await? else {
+        return Ok(empty);
+    };
+    let max_block = request.max_block.map(|m| m.min(last_block.height)).unwrap_or(last_block.height);
+    if max_block < 0 {
+        return Ok(empty);
     }
+    let genesis_time = r.relay.ds.config_store.get_genesis().await?
+        .safe_get_msg("Missing genesis transaction")?
+        .time()?.clone();
+    let interval = r.relay.node_config.block_formation_interval.as_millis() as i64;
+    let max_time = genesis_time + (max_block + 1) * interval;
+    let match_any = is_or && (transaction_hash.is_some() || addr.is_some() || request.success.is_some());
+
+    let (results, total_count) = r.relay.ds.transaction_store.search_transactions(
+        transaction_hash.as_ref(), addr.as_ref(), request.success, match_any, max_time, limit, offset
+    ).await?;
+
+    let mut blocks: HashMap<i64, structs::Block> = HashMap::new();
+    let mut transactions = vec![];
+    for (tx, rejection) in results {
+        let height = block_height_at(tx.time()?.clone(), genesis_time, interval);
+        if !blocks.contains_key(&height) {
+            let block = r.relay.ds.block.query_block_height(height).await?
+                .ok_or(error_message(RGError::UnknownBlock, format!("Missing block at height {}", height)))?;
+            blocks.insert(height, block);
+        }
+        let block = blocks.get(&height).safe_get_msg("Missing cached block")?;
+        let state = if rejection.is_some() { State::Reverted } else { State::Accepted };
+        let transaction = r.translate_transaction(tx, state).await?;
+        transactions.push(BlockTransaction {
+            block_identifier: Rosetta::block_identifier(block),
+            transaction,
+        });
+    }
+    let end = offset + transactions.len() as i64;
+    Ok(SearchTransactionsResponse {
+        transactions,
+        total_count,
+        next_offset: if end < total_count { Some(end) } else { None }
+    })
+}
 
-    if let Some(s) = request.operator {
-        match s {
-            Operator::OR => {
-                _is_or = true;
+#[cfg(test)]
+fn conforms_to_spec(spec: &serde_json::Value, schema: &serde_json::Value, value: &serde_json::Value, path: &str) {
+    use serde_json::Value;
+    if let Some(r) = schema.get("$ref").and_then(|r| r.as_str()) {
+        let name = r.trim_start_matches("#/components/schemas/");
+        let resolved = &spec["components"]["schemas"][name];
+        assert!(!resolved.is_null(), "Unknown schema reference {} at {}", r, path);
+        return conforms_to_spec(spec, resolved, value, path);
+    }
+    if let Some(variants) = schema.get("enum").and_then(|e| e.as_array()) {
+        assert!(variants.contains(value), "Value {} at {} not in enum {:?}", value, path, variants);
+    }
+    match schema.get("type").and_then(|t| t.as_str()) {
+        Some("object") => {
+            let obj = value.as_object().unwrap_or_else(|| panic!("Expected object at {}", path));
+            for req in schema.get("required").and_then(|r| r.as_array()).cloned().unwrap_or_default() {
+                let req = req.as_str().expect("required field name");
+                assert!(obj.contains_key(req), "Missing required field {}.{}", path, req);
             }
-            Operator::AND => {
-                _is_and = true;
+            for (k, v) in obj {
+                if let Some(prop) = schema.get("properties").and_then(|p| p.get(k)) {
+                    conforms_to_spec(spec, prop, v, &format!("{}.{}", path, k));
+                }
+            }
+        }
+        Some("array") => {
+            let arr = value.as_array().unwrap_or_else(|| panic!("Expected array at {}", path));
+            for (i, v) in arr.iter().enumerate() {
+                conforms_to_spec(spec, &schema["items"], v, &format!("{}[{}]", path, i));
             }
         }
+        Some("integer") => {
+            let i = value.as_i64().unwrap_or_else(|| panic!("Expected integer at {}", path));
+            if let Some(min) = schema.get("minimum").and_then(Value::as_i64) {
+                assert!(i >= min, "Value {} at {} below minimum {}", i, path, min);
+            }
+        }
+        Some("string") => assert!(value.is_string(), "Expected string at {}", path),
+        Some("boolean") => assert!(value.is_boolean(), "Expected boolean at {}", path),
+        _ => {}
     }
+}
 
-    Ok(SearchTransactionsResponse{
-        transactions: vec![],
-        total_count: 0,
-        next_offset: None
-    })
+#[test]
+fn rosetta_search_and_events_conform_to_spec() {
+    let spec: serde_json::Value = serde_json::from_str(include_str!("api.json")).expect("spec");
+    let schema = |name: &str| spec["components"]["schemas"][name].clone();
+    for p in ["/events/blocks", "/search/transactions"] {
+        assert!(spec["paths"].get(p).is_some(), "Spec missing endpoint {}", p);
+    }
+
+    let request = serde_json::json!({
+        "network_identifier": {"blockchain": Rosetta::redgold_blockchain(), "network": "debug"},
+        "operator": "or",
+        "max_block": 5,
+        "offset": 0,
+        "limit": 10,
+        "transaction_identifier": {"hash": "00"},
+        "address": "addr",
+        "success": true
+    });
+    conforms_to_spec(&spec, &schema("SearchTransactionsRequest"), &request, "SearchTransactionsRequest");
+    let parsed: SearchTransactionsRequest = serde_json::from_value(request).expect("deser");
+    assert_eq!(parsed.operator, Some(Operator::OR));
+
+    let block_identifier = BlockIdentifier { index: 3, hash: "ab".to_string() };
+    let events = EventsBlocksResponse {
+        max_sequence: 2,
+        events: vec![
+            BlockEvent { sequence: 1, block_identifier: block_identifier.clone(), _type: BlockEventType::REMOVED },
+            BlockEvent { sequence: 2, block_identifier: block_identifier.clone(), _type: BlockEventType::ADDED },
+        ],
+    };
+    let events = serde_json::to_value(&events).expect("ser");
+    conforms_to_spec(&spec, &schema("EventsBlocksResponse"), &events, "EventsBlocksResponse");
+
+    let address = Address::script_hash(&vec![1]).expect("address");
+    let operation = Rosetta::operation(0, State::Accepted, address, 100, CoinAction::CREATED).expect("op");
+    let search = SearchTransactionsResponse {
+        transactions: vec![BlockTransaction {
+            block_identifier,
+            transaction: Transaction::new(TransactionIdentifier { hash: "cd".to_string() }, vec![operation]),
+        }],
+        total_count: 2,
+        next_offset: Some(1),
+    };
+    let search = serde_json::to_value(&search).expect("ser");
+    conforms_to_spec(&spec, &schema("SearchTransactionsResponse"), &search, "SearchTransactionsResponse");
+}
+
+#[tokio::test]
+async fn rosetta_search_and_events_from_data_store() {
+    use crate::core::relay::Relay;
+    use redgold_keys::word_pass_support::WordsPassNodeConfig;
+    use redgold_schema::conf::node_config::NodeConfig;
+    use redgold_schema::structs::{Block, StructMetadata, TransactionOptions};
+
+    fn tx_at(time: i64, address: &Address) -> structs::Transaction {
+        let mut tx = structs::Transaction::default();
+        tx.outputs.push(Output::new(address, 1000));
+        tx.options = Some(TransactionOptions::default());
+        tx.struct_metadata = Some(StructMetadata { time: Some(time), ..Default::default() });
+        tx.with_hash();
+        tx
+    }
+    fn block_at(height: i64, time: i64) -> Block {
+        let mut block = Block::default();
+        block.height = height;
+        block.struct_metadata = Some(StructMetadata { time: Some(time), ..Default::default() });
+        block.with_hash();
+        block
+    }
+    async fn search(r: &Rosetta, spec: &serde_json::Value, request: serde_json::Value) -> SearchTransactionsResponse {
+        conforms_to_spec(spec, &spec["components"]["schemas"]["SearchTransactionsRequest"], &request, "SearchTransactionsRequest");
+        let response = search_transactions(r.clone(), serde_json::from_value(request).expect("deser")).await.expect("search");
+        let value = serde_json::to_value(&response).expect("ser");
+        conforms_to_spec(spec, &spec["components"]["schemas"]["SearchTransactionsResponse"], &value, "SearchTransactionsResponse");
+        response
+    }
+    async fn events(r: &Rosetta, spec: &serde_json::Value, request: serde_json::Value) -> EventsBlocksResponse {
+        conforms_to_spec(spec, &spec["components"]["schemas"]["EventsBlocksRequest"], &request, "EventsBlocksRequest");
+        let response = events_blocks(r.clone(), serde_json::from_value(request).expect("deser")).await.expect("events");
+        let value = serde_json::to_value(&response).expect("ser");
+        conforms_to_spec(spec, &spec["components"]["schemas"]["EventsBlocksResponse"], &value, "EventsBlocksResponse");
+        response
+    }
+    fn hashes(response: &SearchTransactionsResponse) -> Vec<String> {
+        response.transactions.iter().map(|t| t.transaction.transaction_identifier.hash.clone()).collect()
+    }
+
+    let spec: serde_json::Value = serde_json::from_str(include_str!("api.json")).expect("spec");
+    let relay = Relay::new(NodeConfig::from_test_id(&(20 as u16))).await;
+    relay.ds.run_migrations().await.expect("migrations");
+    let r = Rosetta { relay: relay.clone() };
+    let network = serde_json::json!({
+        "blockchain": Rosetta::redgold_blockchain(),
+        "network": relay.node_config.network.to_std_string()
+    });
+
+    // Blocks at heights 0 to 2 with height 1 replaced, and transactions in their windows plus
+    // one past the last formed block.
+    let interval = relay.node_config.block_formation_interval.as_millis() as i64;
+    let genesis_time = 1_700_000_000_000;
+    let a = Address::script_hash(&vec![1]).expect("address");
+    let b = Address::script_hash(&vec![2]).expect("address");
+    let genesis = tx_at(genesis_time, &a);
+    let accepted = tx_at(genesis_time + interval + 1, &b);
+    let rejected = tx_at(genesis_time + interval + 2, &b);
+    let latest = tx_at(genesis_time + 2 * interval + 1, &a);
+    let unformed = tx_at(genesis_time + 3 * interval + 1, &b);
+    relay.ds.config_store.store_genesis(&genesis).await.expect("genesis");
+    for tx in [&genesis, &accepted, &latest, &unformed] {
+        relay.ds.accept_transaction(tx, tx.time().expect("time").clone(), None, false).await.expect("accept");
+    }
+    relay.ds.accept_transaction(
+        &rejected, genesis_time + interval + 2, Some(error_info("Double spend")), false
+    ).await.expect("reject");
+    let replaced = block_at(1, genesis_time + interval);
+    let blocks = [
+        block_at(0, genesis_time),
+        replaced.clone(),
+        block_at(1, genesis_time + interval + 5),
+        block_at(2, genesis_time + 2 * interval),
+    ];
+    for block in &blocks {
+        relay.ds.block.insert_block(block).await.expect("block");
+    }
+    let [genesis, accepted, rejected, latest] = [&genesis, &accepted, &rejected, &latest].map(|t| t.hash_or().hex());
+
+    // Newest first, paged by limit and offset, excluding the transaction past the last block.
+    let page = search(&r, &spec, serde_json::json!({"network_identifier": network, "limit": 2})).await;
+    assert_eq!(hashes(&page), vec![latest.clone(), rejected.clone()]);
+    assert_eq!(page.total_count, 4);
+    assert_eq!(page.next_offset, Some(2));
+    assert_eq!(page.transactions[0].block_identifier, Rosetta::block_identifier(&blocks[3]));
+    assert_eq!(page.transactions[1].block_identifier, Rosetta::block_identifier(&blocks[2]));
+    assert_eq!(page.transactions[1].transaction.operations[0].status, Some(format!("{:?}", State::Reverted)));
+    assert_eq!(page.transactions[0].transaction.operations[0].status, Some(format!("{:?}", State::Accepted)));
+    let page = search(&r, &spec, serde_json::json!({"network_identifier": network, "limit": 2, "offset": 2})).await;
+    assert_eq!(hashes(&page), vec![accepted.clone(), genesis.clone()]);
+    assert_eq!(page.total_count, 4);
+    assert_eq!(page.next_offset, None);
+
+    // max_block bounds the search window and is clamped to the last formed block.
+    let bounded = search(&r, &spec, serde_json::json!({"network_identifier": network, "max_block": 1})).await;
+    assert_eq!(hashes(&bounded), vec![rejected.clone(), accepted.clone(), genesis.clone()]);
+    let clamped = search(&r, &spec, serde_json::json!({"network_identifier": network, "max_block": 99})).await;
+    assert_eq!(clamped.total_count, 4);
+
+    // success filters on acceptance, combined with other filters by AND unless OR is requested.
+    let failed = search(&r, &spec, serde_json::json!({"network_identifier": network, "success": false})).await;
+    assert_eq!(hashes(&failed), vec![rejected.clone()]);
+    let succeeded = search(&r, &spec, serde_json::json!({"network_identifier": network, "success": true})).await;
+    assert_eq!(hashes(&succeeded), vec![latest.clone(), accepted.clone(), genesis.clone()]);
+    let failed_b = search(&r, &spec, serde_json::json!({
+        "network_identifier": network, "operator": "and", "address": b.render_string().expect("render"), "success": false
+    })).await;
+    assert_eq!(hashes(&failed_b), vec![rejected.clone()]);
+    let either = serde_json::json!({
+        "network_identifier": network, "operator": "or",
+        "address": a.render_string().expect("render"), "transaction_identifier": {"hash": accepted}
+    });
+    assert_eq!(hashes(&search(&r, &spec, either.clone()).await), vec![latest.clone(), accepted.clone(), genesis.clone()]);
+    let mut both = either;
+    both["operator"] = serde_json::json!("and");
+    let both = search(&r, &spec, both).await;
+    assert!(both.transactions.is_empty());
+    assert_eq!(both.total_count, 0);
+
+    // The store search backing the handler, with an exclusive time bound.
+    let (found, count) = relay.ds.transaction_store.search_transactions(
+        None, Some(&b), None, false, genesis_time + 3 * interval, 10, 0
+    ).await.expect("store search");
+    assert_eq!(count, 2);
+    assert_eq!(found.iter().map(|(t, _)| t.hash_or().hex()).collect::<Vec<_>>(), vec![rejected.clone(), accepted.clone()]);
+    assert!(found[0].1.is_some() && found[1].1.is_none());
+    let (_, count) = relay.ds.transaction_store.search_transactions(
+        None, Some(&b), None, false, genesis_time + 4 * interval, 10, 0
+    ).await.expect("store search");
+    assert_eq!(count, 3);
+
+    // Every block change is in the event log, the replacement recorded as a removal then an add.
+    let all = events(&r, &spec, serde_json::json!({"network_identifier": network})).await;
+    assert_eq!(all.max_sequence, 5);
+    assert_eq!(all.events.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+    assert_eq!(
+        all.events.iter().map(|e| e._type).collect::<Vec<_>>(),
+        vec![BlockEventType::ADDED, BlockEventType::ADDED, BlockEventType::REMOVED, BlockEventType::ADDED, BlockEventType::ADDED]
+    );
+    assert_eq!(all.events[2].block_identifier, Rosetta::block_identifier(&replaced));
+    assert_eq!(all.events[3].block_identifier, Rosetta::block_identifier(&blocks[2]));
+    let tip = events(&r, &spec, serde_json::json!({"network_identifier": network, "limit": 2})).await;
+    assert_eq!(tip.events.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![4, 5]);
+    let from = events(&r, &spec, serde_json::json!({"network_identifier": network, "offset": 2, "limit": 2})).await;
+    assert_eq!(from.max_sequence, 5);
+    assert_eq!(from.events.iter().map(|e| e.sequence).collect::<Vec<_>>(), vec![2, 3]);
+    assert_eq!(
+        relay.ds.block.query_block_events(3, 1).await.expect("block events"),
+        vec![(3, 1, replaced.hash_or(), true)]
+    );
 }
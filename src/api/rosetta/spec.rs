@@ -1,7 +1,7 @@
 use crate::api::rosetta::models::{AccountIdentifier, Amount, Block, BlockIdentifier, CoinAction, CoinChange, CoinIdentifier, Currency, NetworkIdentifier, Operation, OperationIdentifier, PublicKey, Signature, Transaction, TransactionIdentifier};
 use crate::core::relay::Relay;
 use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
-use redgold_schema::structs::{Address, ErrorCode as RGError, ErrorInfo, Proof, State};
+use redgold_schema::structs::{Address, ErrorCode as RGError, ErrorInfo, Hash, Proof, State};
 use redgold_schema::{bytes_data, constants, error_info, error_message, from_hex, structs, SafeOption};
 
 #[derive(Clone)]
@@ -87,20 +87,18 @@ impl Rosetta {
         hash: Option<String>,
         height: Option<i64>,
     ) -> Result<Option<structs::Block>, ErrorInfo> {
-        // let mut maybe_hash = None;
-        // if let Some(h) = hash {
-        //     maybe_hash = Some(schema::decode_hex(h)?);
-        // };
-        // // TODO: Shouldn't this whole thing really check to verify the active height / active chain thing works?
-        // // revisit this on block removed events.
-        // let maybe_block = self
-        //     .relay
-        //     .ds
-        //     .address_block_store
-        //     .query_block_hash_height(maybe_hash, height)
-        //     .await?;
-        // Ok(maybe_block)
-        Err(error_info("Not implemented"))
+        let block = match (hash, height) {
+            (Some(h), _) => self.relay.ds.block.query_block_hash(&Hash::from_hex(h)?).await?,
+            (None, Some(i)) => self.relay.ds.block.query_block_height(i).await?,
+            (None, None) => Some(self.latest_block().await?),
+        };
+        // Both identifiers supplied must refer to the same block
+        if let (Some(b), Some(i)) = (&block, height) {
+            if b.height != i {
+                return Ok(None);
+            }
+        }
+        Ok(block)
     }
 
     pub fn block_identifier(block: &structs::Block) -> BlockIdentifier {
@@ -123,11 +121,10 @@ impl Rosetta {
     }
 
     pub async fn latest_block(&self) -> Result<crate::schema::structs::Block, ErrorInfo> {
-        // self.relay.ds.address_block_store.query_last_block().await?.ok_or(error_message(
-        //     RGError::DataStoreInternalCorruption,
-        //     "Missing latest block",
-        // ))
-        Err(error_info("Not implemented"))
+        self.relay.ds.block.query_last_block().await?.ok_or(error_message(
+            RGError::DataStoreInternalCorruption,
+            "Missing latest block",
+        ))
     }
 
     pub fn transaction_identifier(
@@ -188,15 +185,11 @@ impl Rosetta {
                 let hash = utxo.transaction_hash.safe_get()?;
                 let result = self.relay.ds.transaction_store
                     .query_maybe_transaction(&hash).await?;
-                // TODO: Translate result to error here
-                let out = result
+                result
                     .as_ref()
-                    .expect("a")
-                    .0
-                    .outputs
-                    .get(utxo.output_index as usize)
-                    .expect("a");
-                out.clone()
+                    .and_then(|(tx, _)| tx.outputs.get(utxo.output_index as usize))
+                    .cloned()
+                    .ok_or(error_info("Input references an unknown transaction output"))?
             }
             Some(o) => o,
         };
@@ -222,7 +215,12 @@ impl Rosetta {
         }
         for input in &transaction.inputs {
             // TODO: Enrich input's output if missing
-            let output = self.input_output(input.clone()).await?;
+            let output = match self.input_output(input.clone()).await {
+                Ok(o) => o,
+                // Rejected transactions may spend outputs which never existed
+                Err(_) if state == State::Reverted => continue,
+                Err(e) => return Err(e),
+            };
             operations.push(Self::operation(
                 index,
                 state,
@@ -249,7 +247,7 @@ impl Rosetta {
     }
 
     pub async fn translate_block(&self, block: structs::Block, state: State) -> Result<Block, ErrorInfo> {
-        let parent_height = std::cmp::max(block.height - 1, block.height);
+        let parent_height = std::cmp::max(block.height - 1, 0);
         let parent_hash = block
             .clone()
             .previous_block_hash
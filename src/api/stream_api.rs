@@ -0,0 +1,262 @@
+use crate::core::relay::Relay;
+use crate::core::stream_events::{Replay, ResumeToken, StreamEnvelope, StreamEvent};
+use futures::{stream, Stream, StreamExt};
+use metrics::{counter, gauge};
+use redgold_keys::address_support::AddressSupport;
+use redgold_schema::structs::ErrorCode;
+use redgold_schema::{error_info, error_message, RgResult};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::error;
+use warp::sse::Event;
+use warp::{Filter, Rejection};
+use crate::api::warp_helpers::as_warp_json_response;
+use crate::util;
+
+// Transactions backfilled per connection, a client further behind resumes again for the next page.
+const MAX_BACKFILL_EVENTS: i64 = 1000;
+const MAX_STREAM_SUBSCRIBERS: usize = 1000;
+
+#[derive(serde::Deserialize, Default, Clone)]
+pub struct StreamQuery {
+    /// Comma separated, any of `transactions`, `party`. Address and hash filters imply their topics.
+    pub topics: Option<String>,
+    /// Comma separated addresses, streams transactions involving any of them.
+    pub addresses: Option<String>,
+    /// Comma separated hashes, streams observation proofs for any of them.
+    pub hashes: Option<String>,
+    /// Token from the last event received, same as the SSE `Last-Event-ID` header.
+    pub resume: Option<String>,
+}
+
+/// Which events a subscriber receives.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StreamFilter {
+    pub all_transactions: bool,
+    pub addresses: HashSet<String>,
+    pub hashes: HashSet<String>,
+    pub party: bool,
+}
+
+fn split_csv(s: &Option<String>) -> Vec<String> {
+    s.as_ref().map(|s| s.split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+    ).unwrap_or_default()
+}
+
+impl StreamFilter {
+
+    pub fn from_query(q: &StreamQuery) -> RgResult<Self> {
+        let mut f = StreamFilter::default();
+        for t in split_csv(&q.topics) {
+            match t.as_str() {
+                "transactions" => f.all_transactions = true,
+                "party" => f.party = true,
+                _ => return Err(error_info(format!("Unknown stream topic {}", t))),
+            }
+        }
+        for a in split_csv(&q.addresses) {
+            f.addresses.insert(a.parse_address()?.render_string()?);
+        }
+        for h in split_csv(&q.hashes) {
+            f.hashes.insert(h.to_lowercase());
+        }
+        if f == StreamFilter::default() {
+            return Err(error_info("Stream subscription requires at least one topic, address or hash"));
+        }
+        Ok(f)
+    }
+
+    fn wants_transactions(&self) -> bool {
+        self.all_transactions || !self.addresses.is_empty()
+    }
+
+    pub fn matches(&self, e: &StreamEvent) -> bool {
+        match e {
+            StreamEvent::Transaction { addresses, .. } => {
+                self.all_transactions || addresses.iter().any(|a| self.addresses.contains(a))
+            }
+            StreamEvent::ObservationProof { hash, .. } => self.hashes.contains(hash),
+            StreamEvent::PartyFulfillment { .. } => self.party,
+            StreamEvent::Gap { .. } => true,
+        }
+    }
+}
+
+fn event_name(e: &StreamEvent) -> &'static str {
+    match e {
+        StreamEvent::Transaction { .. } => "transaction",
+        StreamEvent::ObservationProof { .. } => "observation_proof",
+        StreamEvent::PartyFulfillment { .. } => "party_fulfillment",
+        StreamEvent::Gap { .. } => "gap",
+    }
+}
+
+fn to_sse(e: StreamEnvelope) -> Result<Event, Infallible> {
+    let event = Event::default().id(e.token.clone()).event(event_name(&e.event));
+    Ok(event.json_data(&e).unwrap_or_else(|err| {
+        error!("Failed to serialize stream event: {}", err);
+        Event::default().comment("serialization failure")
+    }))
+}
+
+/// Events a resuming client missed, and whether the backfill was truncated.
+struct CatchUp {
+    events: Vec<StreamEnvelope>,
+    /// Acceptance position covered by the backfill, live transactions at or before it are skipped.
+    position: i64,
+    truncated: bool,
+}
+
+/// Transactions accepted after the token's position are backfilled from the database one page
+/// at a time, other buffered events replay by sequence. When the token has left the buffer the
+/// other topics get a gap marker instead.
+async fn catch_up(relay: &Relay, filter: &StreamFilter, token: ResumeToken, subscribed_seq: u64) -> RgResult<CatchUp> {
+    let se = &relay.stream_events;
+    let now = util::current_time_millis_i64();
+    let mut events = vec![];
+    let (seq, buffered) = match se.replay_after(&token) {
+        Replay::Buffered(buffered) => (token.seq, buffered),
+        Replay::Expired => {
+            if filter.party || !filter.hashes.is_empty() {
+                events.push(StreamEnvelope {
+                    token: se.token(subscribed_seq, token.position),
+                    seq: subscribed_seq,
+                    position: token.position,
+                    time: now,
+                    event: StreamEvent::Gap {
+                        reason: "Resume token expired, only accepted transactions were backfilled".to_string(),
+                    },
+                });
+            }
+            (subscribed_seq, vec![])
+        }
+    };
+    let mut position = token.position;
+    let mut truncated = false;
+    if filter.wants_transactions() {
+        let until = relay.ds.transaction_store.max_accepted_position().await?;
+        let page = relay.ds.transaction_store
+            .query_accepted_after_position(token.position, until, MAX_BACKFILL_EVENTS + 1).await?;
+        truncated = page.len() as i64 > MAX_BACKFILL_EVENTS;
+        for (p, tx) in page.into_iter().take(MAX_BACKFILL_EVENTS as usize) {
+            position = p;
+            events.push(StreamEnvelope {
+                token: se.token(seq, p),
+                seq,
+                position: p,
+                time: now,
+                event: StreamEvent::transaction(tx),
+            });
+        }
+        if truncated {
+            counter!("redgold_stream_backfill_truncated").increment(1);
+            events.push(StreamEnvelope {
+                token: se.token(seq, position),
+                seq,
+                position,
+                time: now,
+                event: StreamEvent::Gap {
+                    reason: "Backfill truncated, resume from this token for the next page".to_string(),
+                },
+            });
+            return Ok(CatchUp { events, position, truncated });
+        }
+        position = position.max(until);
+    }
+    // Buffered transactions were covered by the backfill, or aren't wanted.
+    for e in buffered.into_iter().filter(|e| e.seq <= subscribed_seq) {
+        if matches!(e.event, StreamEvent::Transaction { .. }) {
+            continue;
+        }
+        events.push(StreamEnvelope { token: se.token(e.seq, position), position, ..e });
+    }
+    Ok(CatchUp { events, position, truncated })
+}
+
+/// Live events after `last_seq` and transactions after `position`, which skips anything already
+/// backfilled. A subscriber that falls behind the channel gets a gap marker and can resume from
+/// its last token.
+fn live(relay: Relay, rx: broadcast::Receiver<StreamEnvelope>, last_seq: u64, position: i64) -> impl Stream<Item = StreamEnvelope> {
+    stream::unfold((relay, rx, last_seq, position), |(relay, mut rx, last_seq, position)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(e) if e.seq <= last_seq => continue,
+                Ok(e) if matches!(e.event, StreamEvent::Transaction { .. }) && e.position <= position => continue,
+                Ok(e) => {
+                    let seq = e.seq;
+                    let position = e.position.max(position);
+                    let e = StreamEnvelope { token: relay.stream_events.token(seq, position), position, ..e };
+                    return Some((e, (relay, rx, seq, position)));
+                }
+                Err(RecvError::Lagged(n)) => {
+                    counter!("redgold_stream_subscriber_lagged").increment(1);
+                    let gap = StreamEnvelope {
+                        token: relay.stream_events.token(last_seq, position),
+                        seq: last_seq,
+                        position,
+                        time: util::current_time_millis_i64(),
+                        event: StreamEvent::Gap { reason: format!("Subscriber lagged by {} events", n) },
+                    };
+                    return Some((gap, (relay, rx, last_seq, position)));
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
+pub async fn subscribe(relay: Relay, query: StreamQuery, last_event_id: Option<String>) -> RgResult<impl warp::Reply> {
+    let filter = StreamFilter::from_query(&query)?;
+    let resume = query.resume.or(last_event_id)
+        .map(|t| ResumeToken::parse(&t).ok_or(error_info("Invalid resume token")))
+        .transpose()?;
+    if relay.stream_events.subscriber_count() >= MAX_STREAM_SUBSCRIBERS {
+        counter!("redgold_stream_subscriptions_rejected").increment(1);
+        return Err(error_message(ErrorCode::RateLimited, "Too many stream subscribers"));
+    }
+    // Subscribe before catching up so nothing published in between is lost, anything both
+    // replayed and received live is skipped by sequence or acceptance position.
+    let (rx, subscribed_seq) = relay.stream_events.subscribe();
+    let caught_up = match resume {
+        Some(token) => catch_up(&relay, &filter, token, subscribed_seq).await?,
+        None => CatchUp { events: vec![], position: 0, truncated: false },
+    };
+    counter!("redgold_stream_subscriptions").increment(1);
+    gauge!("redgold_stream_subscribers").set(relay.stream_events.subscriber_count() as f64);
+
+    let CatchUp { events, position, truncated } = caught_up;
+    // A truncated backfill ends the connection, the client resumes for the next page.
+    let live = if truncated {
+        None
+    } else {
+        Some(live(relay, rx, subscribed_seq, position))
+    };
+    let events = stream::iter(events)
+        .chain(stream::iter(live).flatten())
+        .filter(move |e| futures::future::ready(filter.matches(&e.event)))
+        .map(to_sse);
+    Ok(warp::sse::reply(warp::sse::keep_alive().interval(Duration::from_secs(15)).stream(events)))
+}
+
+pub fn stream_routes(relay: Relay) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path("stream"))
+        .and(warp::path::end())
+        .and(warp::query::<StreamQuery>())
+        .and(warp::header::optional::<String>("last-event-id"))
+        .and_then(move |query: StreamQuery, last_event_id: Option<String>| {
+            let relay = relay.clone();
+            async move {
+                match subscribe(relay, query, last_event_id).await {
+                    Ok(r) => Ok::<_, Rejection>(warp::reply::Reply::into_response(r)),
+                    Err(e) => as_warp_json_response::<(), _>(Err(e)).map(warp::reply::Reply::into_response),
+                }
+            }
+        })
+}
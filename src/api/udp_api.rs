@@ -8,6 +8,7 @@ use tokio_util::udp::UdpFramed;
 
 use crate::api::udp_api::UdpOperation::Outgoing;
 use crate::core::internal_message::{MessageOrigin, PeerMessage};
+use crate::core::transport::noise_transport::{NoiseSessions, NOISE_UDP_HANDSHAKE_ATTEMPTS};
 use crate::util;
 use bytes::{BufMut, BytesMut};
 use futures::future::try_join;
@@ -16,13 +17,14 @@ use futures::sink::SinkExt;
 use futures::stream::{SplitSink, SplitStream};
 use futures::TryStreamExt;
 use itertools::Itertools;
+use metrics::counter;
 use redgold_common::flume_send_help::{Channel, SendErrorInfo};
 use redgold_keys::request_support::RequestSupport;
 use redgold_schema::proto_serde::ProtoSerde;
-use redgold_schema::structs::{ErrorInfo, UdpMessage};
+use redgold_schema::structs::{ErrorInfo, PublicKey, UdpMessage};
 use redgold_schema::message::Response;
 use redgold_schema::message::Request;
-use redgold_schema::{bytes_data, ErrorInfoContext, RgResult};
+use redgold_schema::{bytes_data, ErrorInfoContext, RgResult, SafeOption};
 use std::io;
 use std::net::SocketAddr;
 use std::sync::Arc;
@@ -212,7 +214,9 @@ pub struct UdpServer {
     sink: SplitSink<UdpFramed<UdpMessageCodec>, (UdpMessage, SocketAddr)>,
     peer_message_rx: Channel<PeerMessage>,
     udp_outgoing_messages: Channel<PeerMessage>,
-    response_channels: HashMap<String, UdpResponseHandler>
+    response_channels: HashMap<String, UdpResponseHandler>,
+    // Encrypted transport sessions, plaintext signed requests only when disabled
+    noise: Option<NoiseSessions>,
 }
 
 
@@ -223,7 +227,8 @@ impl UdpServer {
     pub async fn new(
         incoming_peer_messages: Channel<PeerMessage>,
         outgoing_udp_messages: Channel<PeerMessage>,
-        port: Option<u16>
+        port: Option<u16>,
+        noise: Option<NoiseSessions>,
     ) -> RgResult<()> {
         let port = port.unwrap_or(0);
         let addr = format!("0.0.0.0:{}", port.to_string());
@@ -240,6 +245,7 @@ impl UdpServer {
             udp_outgoing_messages: outgoing_udp_messages.clone(),
             messages: Default::default(),
             response_channels: Default::default(),
+            noise,
         };
         server.run(stream).await
     }
@@ -249,7 +255,7 @@ impl UdpServer {
 
         // Change to stream
 
-        let interval = tokio::time::interval(std::time::Duration::from_secs(100));
+        let interval = tokio::time::interval(std::time::Duration::from_secs(10));
         let interval_stream = IntervalStream::new(interval).map(|_| UdpOperation::Interval);
         let incoming_stream = stream
             .map(|m| UdpOperation::Incoming(m.error_info("Failed to receive UDP message")));
@@ -289,7 +295,10 @@ impl UdpServer {
 
     async fn send_rx_incoming_log(&mut self, data: Vec<u8>, addr: SocketAddr) -> Result<(), ErrorInfo> {
         let req = Request::proto_deserialize(data)?;
-        let node_pk = req.verify_auth()?;
+        let (req, node_pk) = match self.open_noise(req, addr).await? {
+            Some(r) => r,
+            None => return Ok(())
+        };
 
         if !self.check_is_response(&req).await {
             let mut pm = PeerMessage::empty();
@@ -302,6 +311,105 @@ impl UdpServer {
         Ok(())
     }
 
+    /// Handle handshakes and decrypt frames. Returns the signed request to process and its
+    /// signer, or None if the message was consumed by the handshake.
+    async fn open_noise(&mut self, req: Request, addr: SocketAddr) -> RgResult<Option<(Request, PublicKey)>> {
+        let Some(noise) = self.noise.clone() else {
+            let pk = req.verify_auth()?;
+            return Ok(Some((req, pk)));
+        };
+        if let Some(h) = req.noise_handshake.as_ref() {
+            if h.reply {
+                let (remote, session) = noise.finish_pending(h)?;
+                for pm in noise.take_queued(&remote) {
+                    if let Some(a) = pm.socket_addr {
+                        let mut outer = Request::default();
+                        outer.noise_frame = Some(NoiseSessions::encrypt(&session, &pm.request.proto_serialize())?);
+                        self.send_request(&outer, a).await?;
+                    }
+                }
+            } else {
+                let mut reply = Request::default();
+                reply.noise_handshake = Some(noise.respond_known(h).await?);
+                self.send_request(&reply, addr).await?;
+            }
+            return Ok(None);
+        }
+        if let Some(frame) = req.noise_frame.as_ref() {
+            let (session, inner) = noise.open_request(frame)?;
+            let remote = session.lock().ok().map(|s| s.remote.clone()).ok_msg("Noise session lock poisoned")?;
+            return Ok(Some((inner, remote)));
+        }
+        let pk = req.verify_auth()?;
+        noise.check_plaintext(&req, &pk).await?;
+        Ok(Some((req, pk)))
+    }
+
+    async fn send_request(&mut self, request: &Request, b_addr: SocketAddr) -> RgResult<()> {
+        let ser = request.proto_serialize();
+        let chunks = ser.chunks(UDP_CHUNK_SIZE);
+        let parts = chunks.len();
+        for (i, chunk) in chunks.enumerate() {
+            let msg = UdpMessage::new(chunk.to_vec(), i as i64, parts as i64);
+            // log::debug!("Sending UDP message to {}", b_addr);
+            self.sink.send((msg, b_addr)).await.error_info("Failed to send UDP message")?;
+        }
+        Ok(())
+    }
+
+    /// Encrypt to the peer's session when one exists. Otherwise the message waits on a
+    /// handshake, only peers not in the peer store which never answer one are sent the plaintext
+    /// signed request.
+    async fn send_outgoing(&mut self, pm: PeerMessage, b_addr: SocketAddr) -> RgResult<()> {
+        let (Some(noise), Some(remote)) = (self.noise.clone(), pm.public_key.clone()) else {
+            return self.send_request(&pm.request, b_addr).await;
+        };
+        if let Some(session) = noise.outgoing_session(&remote) {
+            let mut outer = Request::default();
+            outer.noise_frame = Some(NoiseSessions::encrypt(&session, &pm.request.proto_serialize())?);
+            return self.send_request(&outer, b_addr).await;
+        }
+        self.retry_expired_handshakes(&noise).await?;
+        if noise.is_unsupported(&remote) && !noise.is_known(&remote).await? {
+            return self.send_request(&pm.request, b_addr).await;
+        }
+        noise.queue(&remote, pm);
+        if !noise.has_pending(&remote) {
+            self.send_handshake(&noise, &remote, b_addr, 1).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_handshake(&mut self, noise: &NoiseSessions, remote: &PublicKey, addr: SocketAddr, attempts: u32) -> RgResult<()> {
+        let (init, req) = noise.initiate(remote)?;
+        noise.set_pending(remote, init, addr, attempts);
+        self.send_request(&req, addr).await
+    }
+
+    /// Handshake again for any reply lost in transit. Once attempts run out, queued messages to
+    /// a known peer are dropped rather than sent in plaintext.
+    async fn retry_expired_handshakes(&mut self, noise: &NoiseSessions) -> RgResult<()> {
+        for (remote, pending) in noise.expire_pending() {
+            if pending.attempts < NOISE_UDP_HANDSHAKE_ATTEMPTS {
+                counter!("redgold_noise_udp_handshake_retry").increment(1);
+                self.send_handshake(noise, &remote, pending.addr, pending.attempts + 1).await?;
+                continue;
+            }
+            let queued = noise.take_queued(&remote);
+            if noise.is_known(&remote).await? {
+                counter!("redgold_noise_queued_dropped").increment(queued.len() as u64);
+                continue;
+            }
+            noise.mark_unsupported(&remote);
+            for q in queued {
+                if let Some(a) = q.socket_addr {
+                    self.send_request(&q.request, a).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
     async fn process_incoming_udp_message(&mut self, typed: Result<(UdpMessage, SocketAddr), ErrorInfo>) -> Result<(), ErrorInfo> {
         match typed {
             Err(e) => {
@@ -351,14 +459,8 @@ impl UdpServer {
         match udp_operation {
             Outgoing(pm) => {
                 if let Some(b_addr) = pm.socket_addr {
-                    let ser = pm.request.proto_serialize();
-                    let chunks = ser.chunks(UDP_CHUNK_SIZE);
-                    let parts = chunks.len();
-                    for (i, chunk) in chunks.enumerate() {
-                        let msg = UdpMessage::new(chunk.to_vec(), i as i64, parts as i64);
-                        // log::debug!("Sending UDP message to {}", b_addr);
-                        self.sink.send((msg, b_addr)).await.error_info("Failed to send UDP message")?;
-                    }
+                    // A failed handshake for one peer shouldn't stop the server
+                    self.send_outgoing(pm, b_addr).await.ok();
                 }
                 Ok(())
             },
@@ -380,6 +482,10 @@ impl UdpServer {
                 for i in stale_messages {
                     self.messages.remove(&i);
                 }
+                if let Some(n) = self.noise.clone() {
+                    self.retry_expired_handshakes(&n).await.ok();
+                    n.prune();
+                }
                 Ok(())
             }
         }
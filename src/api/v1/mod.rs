@@ -13,7 +13,7 @@ use redgold_keys::public_key_parse_support::PublicKeyParseSupport;
 use redgold_schema::explorer::DetailedAddress;
 use redgold_schema::party::search_events::PartyEventSearch;
 use redgold_schema::proto_serde::ProtoSerde;
-use redgold_schema::structs::{CurrencyAmount, SupportedCurrency};
+use redgold_schema::structs::{CurrencyAmount, SupportedCurrency, Transaction};
 use redgold_schema::message::Request;
 use redgold_schema::{RgResult, SafeOption};
 use serde::{Deserialize, Serialize};
@@ -247,6 +247,19 @@ pub fn v1_api_routes(r: Arc<Relay>) -> impl Filter<Extract = (impl warp::Reply +
             public_swap_lookup(api_data).await
         });
 
+    let pending_acceptance = warp::get()
+        .with_v1()
+        .and(warp::path("acceptance"))
+        .with_relay_and_ip(r.clone())
+        .and(warp::path::param())
+        .map(|mut api_data: ApiData, address: String| {
+            api_data.param = Some(address);
+            api_data
+        })
+        .and_then_as(move |api_data: ApiData| async move {
+            pending_acceptance_lookup(api_data.relay, api_data.param.unwrap().clone()).await
+        });
+
     // TODO: Waterfall function, from address / raw address / public key proto / compact public key /
     let explorer_public_address = warp::get()
         .with_v1()
@@ -291,6 +304,7 @@ pub fn v1_api_routes(r: Arc<Relay>) -> impl Filter<Extract = (impl warp::Reply +
         .or(explorer_public_address)
         .or(public_swap)
         .or(gui_init)
+        .or(pending_acceptance)
 
 }
 
@@ -342,6 +356,13 @@ async fn explorer_public_address(relay: Arc<Relay>, hash: String) -> RgResult<Ve
 }
 
 
+// Transactions waiting on a counter-party acceptance which pay to or spend from the address,
+// recipients accept by resubmitting them with their counter-party proofs attached.
+async fn pending_acceptance_lookup(relay: Arc<Relay>, address: String) -> RgResult<Vec<Transaction>> {
+    let address = address.parse_address_incl_raw()?;
+    relay.pending_acceptances_for(&address).await
+}
+
 async fn balance_lookup(relay: Arc<Relay>, hash: String) -> RgResult<CurrencyAmount> {
     let net = relay.node_config.network.clone();
     let pk_parse = hash.clone().parse_public_key().and_then(|pk| pk.to_all_addresses_for_network(&net));
@@ -0,0 +1,290 @@
+use crate::core::backup::aws_backup::S3Constructor;
+use async_trait::async_trait;
+use aws_sdk_s3 as s3;
+use aws_sdk_s3::config::{Credentials, Region};
+use aws_sdk_s3::primitives::ByteStream;
+use redgold_schema::conf::node_config::NodeConfig;
+use redgold_schema::config_data::BackupTargetConfig;
+use redgold_schema::observability::errors::EnhanceErrorInfo;
+use redgold_schema::{error_info, ErrorInfoContext, RgResult, SafeOption};
+use std::path::PathBuf;
+
+/// Storage backend for encrypted backups. Keys are '/' separated paths, all data passed in is
+/// already encrypted so implementations only need to store raw bytes.
+#[async_trait]
+pub trait BackupTarget: Send + Sync {
+    fn name(&self) -> String;
+    async fn put(&self, key: &str, data: Vec<u8>) -> RgResult<()>;
+    /// Store a file's contents, implementations may stream it rather than read it into memory.
+    async fn put_file(&self, key: &str, path: &PathBuf) -> RgResult<()> {
+        let data = tokio::fs::read(path).await
+            .error_info("Failed to read backup file")
+            .with_detail("path", path.to_string_lossy().to_string())?;
+        self.put(key, data).await
+    }
+    async fn get(&self, key: &str) -> RgResult<Vec<u8>>;
+    async fn list(&self, prefix: &str) -> RgResult<Vec<String>>;
+    async fn delete(&self, key: &str) -> RgResult<()>;
+}
+
+pub struct LocalDirectoryTarget {
+    pub root: PathBuf,
+}
+
+impl LocalDirectoryTarget {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> RgResult<PathBuf> {
+        if key.split('/').any(|p| p == "..") {
+            return Err(error_info("Backup key cannot contain parent directory references"))
+                .with_detail("key", key.to_string());
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BackupTarget for LocalDirectoryTarget {
+    fn name(&self) -> String {
+        self.root.to_string_lossy().to_string()
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> RgResult<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.error_info("Failed to create backup directory")?;
+        }
+        // Write to a temporary file first so a partial write never looks like a complete object.
+        let tmp = path.with_extension("partial");
+        tokio::fs::write(&tmp, data).await
+            .error_info("Failed to write backup object")
+            .with_detail("key", key.to_string())?;
+        tokio::fs::rename(&tmp, &path).await.error_info("Failed to move backup object into place")
+    }
+
+    async fn get(&self, key: &str) -> RgResult<Vec<u8>> {
+        tokio::fs::read(self.path(key)?).await
+            .error_info("Failed to read backup object")
+            .with_detail("key", key.to_string())
+    }
+
+    async fn list(&self, prefix: &str) -> RgResult<Vec<String>> {
+        let mut keys = vec![];
+        let mut dirs = vec![self.root.clone()];
+        while let Some(dir) = dirs.pop() {
+            if !dir.exists() {
+                continue;
+            }
+            let mut entries = tokio::fs::read_dir(&dir).await.error_info("Failed to read backup directory")?;
+            while let Some(entry) = entries.next_entry().await.error_info("Bad read")? {
+                let path = entry.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().map(|e| e != "partial").unwrap_or(true) {
+                    let key = path.strip_prefix(&self.root).error_info("Bad backup path")?
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy().to_string())
+                        .collect::<Vec<String>>()
+                        .join("/");
+                    if key.starts_with(prefix) {
+                        keys.push(key);
+                    }
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn delete(&self, key: &str) -> RgResult<()> {
+        let path = self.path(key)?;
+        if path.exists() {
+            tokio::fs::remove_file(path).await.error_info("Failed to delete backup object")?;
+        }
+        Ok(())
+    }
+}
+
+/// Any S3 API compatible object store, AWS itself when no endpoint is configured or i.e. a
+/// MinIO endpoint otherwise.
+pub struct S3CompatibleTarget {
+    pub client: s3::Client,
+    pub bucket: String,
+}
+
+impl S3CompatibleTarget {
+    /// Bucket accessed with the node's own AWS credentials.
+    pub async fn from_node_credentials(bucket: &String, node_config: &NodeConfig) -> Self {
+        Self { client: node_config.config_data.s3_client().await, bucket: bucket.clone() }
+    }
+
+    pub async fn new(config: &BackupTargetConfig, node_config: &NodeConfig) -> RgResult<Self> {
+        let bucket = config.bucket.clone().ok_msg("S3 backup target missing bucket")?;
+        let client = match (&config.endpoint, &config.access_key, &config.secret_key) {
+            (None, None, None) => node_config.config_data.s3_client().await,
+            _ => {
+                let region = config.region.clone().unwrap_or("us-east-1".to_string());
+                let access = config.access_key.clone()
+                    .or(node_config.aws_access())
+                    .ok_msg("S3 backup target missing access key")?;
+                let secret = config.secret_key.clone()
+                    .or(node_config.config_data.keys.as_ref().and_then(|k| k.aws_secret.clone()))
+                    .ok_msg("S3 backup target missing secret key")?;
+                let shared = aws_config::from_env()
+                    .region(Region::new(region))
+                    .credentials_provider(Credentials::new(access, secret, None, None, "static"))
+                    .load()
+                    .await;
+                let mut builder = s3::config::Builder::from(&shared);
+                if let Some(e) = config.endpoint.as_ref() {
+                    // Self-hosted stores generally don't support virtual hosted bucket names.
+                    builder = builder.endpoint_url(e).force_path_style(true);
+                }
+                s3::Client::from_conf(builder.build())
+            }
+        };
+        Ok(Self { client, bucket })
+    }
+}
+
+#[async_trait]
+impl BackupTarget for S3CompatibleTarget {
+    fn name(&self) -> String {
+        format!("s3://{}", self.bucket)
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> RgResult<()> {
+        self.client.put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .error_info("S3 put object failure")
+            .with_detail("key", key.to_string())
+            .with_detail("bucket", self.bucket.clone())?;
+        Ok(())
+    }
+
+    async fn put_file(&self, key: &str, path: &PathBuf) -> RgResult<()> {
+        let body = ByteStream::from_path(path).await
+            .error_info("Failed to read backup file")
+            .with_detail("path", path.to_string_lossy().to_string())?;
+        self.client.put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(body)
+            .send()
+            .await
+            .error_info("S3 put object failure")
+            .with_detail("key", key.to_string())
+            .with_detail("bucket", self.bucket.clone())
+            .with_detail("path", path.to_string_lossy().to_string())?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> RgResult<Vec<u8>> {
+        let res = self.client.get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .error_info("S3 get object failure")
+            .with_detail("key", key.to_string())
+            .with_detail("bucket", self.bucket.clone())?;
+        let bytes = res.body.collect().await.error_info("S3 get object body failure")?;
+        Ok(bytes.into_bytes().to_vec())
+    }
+
+    async fn list(&self, prefix: &str) -> RgResult<Vec<String>> {
+        let mut keys = vec![];
+        let mut token: Option<String> = None;
+        loop {
+            let res = self.client.list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_continuation_token(token.clone())
+                .send()
+                .await
+                .error_info("S3 list failure")
+                .with_detail("prefix", prefix.to_string())
+                .with_detail("bucket", self.bucket.clone())?;
+            for o in res.contents().unwrap_or_default() {
+                if let Some(k) = o.key() {
+                    keys.push(k.to_string());
+                }
+            }
+            token = res.next_continuation_token().map(|t| t.to_string());
+            if !res.is_truncated() || token.is_none() {
+                break;
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    async fn delete(&self, key: &str) -> RgResult<()> {
+        self.client.delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .error_info("S3 delete object failure")
+            .with_detail("key", key.to_string())?;
+        Ok(())
+    }
+}
+
+/// Upload every file below a directory, keyed by its path relative to the directory.
+pub async fn upload_directory(target: &dyn BackupTarget, dir: &PathBuf, prefix: &str) -> RgResult<()> {
+    let mut dirs = vec![dir.clone()];
+    while let Some(d) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&d).await
+            .error_info("Failed to read directory")
+            .with_detail("path", d.to_string_lossy().to_string())?;
+        while let Some(entry) = entries.next_entry().await.error_info("Bad read")? {
+            let path = entry.path();
+            if path.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let relative = path.strip_prefix(dir).error_info("Bad upload path")?
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<String>>()
+                .join("/");
+            let key = format!("{}/{}", prefix.trim_end_matches('/'), relative);
+            target.put_file(&key, &path).await.with_detail("target", target.name())?;
+        }
+    }
+    Ok(())
+}
+
+/// Construct the configured target, a directory takes precedence over a bucket.
+pub async fn backup_target(config: &BackupTargetConfig, node_config: &NodeConfig) -> RgResult<Box<dyn BackupTarget>> {
+    if let Some(d) = config.directory.as_ref() {
+        return Ok(Box::new(LocalDirectoryTarget::new(d)));
+    }
+    if config.bucket.is_some() {
+        return Ok(Box::new(S3CompatibleTarget::new(config, node_config).await?));
+    }
+    Err(error_info("Backup target requires either a directory or a bucket"))
+        .with_detail("name", config.name.clone().unwrap_or_default())
+}
+
+#[tokio::test]
+async fn local_directory_target_round_trip() {
+    let root = std::env::temp_dir().join(format!("rg_backup_target_{}", std::process::id()));
+    let t = LocalDirectoryTarget::new(root.clone());
+    t.put("a/objects/1", vec![1, 2, 3]).await.unwrap();
+    t.put("a/manifests/2", vec![4]).await.unwrap();
+    t.put("b/objects/3", vec![5]).await.unwrap();
+    assert_eq!(t.get("a/objects/1").await.unwrap(), vec![1, 2, 3]);
+    assert_eq!(t.list("a/").await.unwrap(), vec!["a/manifests/2".to_string(), "a/objects/1".to_string()]);
+    t.delete("a/objects/1").await.unwrap();
+    assert_eq!(t.list("a/objects").await.unwrap().len(), 0);
+    assert!(t.put("../escape", vec![]).await.is_err());
+    std::fs::remove_dir_all(root).ok();
+}
@@ -0,0 +1,486 @@
+use crate::core::backup::backup_target::{backup_target, BackupTarget};
+use crate::core::relay::Relay;
+use crate::util;
+use crate::util::sym_crypt;
+use async_trait::async_trait;
+use crypto::hmac::Hmac;
+use crypto::mac::{Mac, MacResult};
+use crypto::sha2::Sha256;
+use itertools::Itertools;
+use redgold_common_no_wasm::stream_handlers::IntervalFold;
+use redgold_data::data_store::DataStore;
+use redgold_keys::util::mnemonic_support::MnemonicSupport;
+use redgold_keys::word_pass_support::WordsPassNodeConfig;
+use redgold_schema::conf::node_config::NodeConfig;
+use redgold_schema::config_data::BackupTargetConfig;
+use redgold_schema::helpers::easy_json::{EasyJson, EasyJsonDeser};
+use redgold_schema::keys::words_pass::WordsPass;
+use redgold_schema::observability::errors::{EnhanceErrorInfo, Loggable};
+use redgold_schema::{error_info, ErrorInfoContext, RgResult, SafeOption};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tracing::info;
+
+/// Derivation paths reserved for backup encryption and authentication keys, never used for
+/// signing so the backup keys can't be linked to any address.
+pub const BACKUP_ENCRYPTION_DERIVATION_PATH: &str = "m/44'/16180'/100'/0'/0'";
+pub const BACKUP_MAC_DERIVATION_PATH: &str = "m/44'/16180'/100'/0'/1'";
+const BACKUP_MAGIC: &[u8] = b"RGBK1";
+const IV_BYTES: usize = 16;
+const MAC_BYTES: usize = 32;
+/// Files are split into content-defined chunks so unchanged regions aren't uploaded again, even
+/// when data is inserted before them and shifts their offsets.
+pub const BACKUP_MIN_CHUNK_BYTES: usize = 1024 * 1024;
+pub const BACKUP_MAX_CHUNK_BYTES: usize = 16 * 1024 * 1024;
+// Boundary when the low bits of the rolling hash are zero, roughly every 4 MiB past the minimum.
+const BACKUP_CHUNK_MASK: u64 = (1 << 22) - 1;
+pub const DEFAULT_BACKUP_RETENTION: usize = 7;
+pub const DATA_STORE_BACKUP_NAME: &str = "data_store.sqlite";
+
+/// AES-256-CBC with an HMAC-SHA256 over the iv and ciphertext, both keys derived from the
+/// node's words so a backup can be restored anywhere the words are available.
+pub struct BackupCipher {
+    key: [u8; 32],
+    mac_key: [u8; 32],
+}
+
+impl BackupCipher {
+    pub fn from_words(words: &WordsPass) -> RgResult<Self> {
+        Ok(Self {
+            key: words.derive_seed_at_path(BACKUP_ENCRYPTION_DERIVATION_PATH)?,
+            mac_key: words.derive_seed_at_path(BACKUP_MAC_DERIVATION_PATH)?,
+        })
+    }
+
+    fn mac(&self, data: &[u8]) -> MacResult {
+        let mut h = Hmac::new(Sha256::new(), &self.mac_key);
+        h.input(data);
+        h.result()
+    }
+
+    /// Keyed content identifier, used as the object name so identical chunks are only stored
+    /// once without revealing a plain hash of the contents.
+    pub fn content_id(&self, data: &[u8]) -> String {
+        hex::encode(self.mac(data).code())
+    }
+
+    pub fn encrypt(&self, data: &[u8]) -> RgResult<Vec<u8>> {
+        let iv = sym_crypt::get_iv();
+        let ciphertext = sym_crypt::encrypt(data, &self.key, &iv)
+            .map_err(|e| error_info(format!("Backup encryption failure {:?}", e)))?;
+        let mut res = BACKUP_MAGIC.to_vec();
+        res.extend_from_slice(&iv);
+        res.extend_from_slice(&ciphertext);
+        let mac = self.mac(&res[BACKUP_MAGIC.len()..]);
+        res.extend_from_slice(mac.code());
+        Ok(res)
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> RgResult<Vec<u8>> {
+        if data.len() < BACKUP_MAGIC.len() + IV_BYTES + MAC_BYTES || !data.starts_with(BACKUP_MAGIC) {
+            return Err(error_info("Not an encrypted backup object"));
+        }
+        let (body, tag) = data[BACKUP_MAGIC.len()..].split_at(data.len() - BACKUP_MAGIC.len() - MAC_BYTES);
+        if self.mac(body) != MacResult::new(tag) {
+            return Err(error_info("Backup object failed authentication, wrong words or corrupted data"));
+        }
+        let (iv, ciphertext) = body.split_at(IV_BYTES);
+        sym_crypt::decrypt(ciphertext, &self.key, iv)
+            .map_err(|e| error_info(format!("Backup decryption failure {:?}", e)))
+    }
+}
+
+// Fixed pseudo-random table for the gear rolling hash, generated with splitmix64 so chunk
+// boundaries are identical across nodes and versions.
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x5247_4b42_4745_4152;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+static GEAR: [u64; 256] = gear_table();
+
+/// Length of the chunk at the start of data. The boundary follows the last bytes before it, so
+/// it moves with the content, and is forced at max when no boundary is found. Returns None when
+/// more data is needed, the caller passes at least max bytes unless the input has ended.
+fn chunk_boundary(data: &[u8], eof: bool, min: usize, max: usize, mask: u64) -> Option<usize> {
+    if data.is_empty() {
+        return None;
+    }
+    let limit = data.len().min(max);
+    let mut hash: u64 = 0;
+    for (i, b) in data.iter().enumerate().take(limit).skip(min) {
+        hash = (hash << 1).wrapping_add(GEAR[*b as usize]);
+        if hash & mask == 0 {
+            return Some(i + 1);
+        }
+    }
+    if limit == max || eof {
+        Some(limit)
+    } else {
+        None
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BackupFileEntry {
+    pub name: String,
+    pub size: u64,
+    pub chunks: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BackupManifest {
+    pub time: i64,
+    pub network: String,
+    pub files: Vec<BackupFileEntry>,
+}
+
+impl BackupManifest {
+    pub fn chunk_ids(&self) -> HashSet<String> {
+        self.files.iter().flat_map(|f| f.chunks.iter().cloned()).collect()
+    }
+}
+
+/// Keys for a single node on a target, shared between every snapshot of that node.
+pub struct BackupLayout {
+    pub root: String,
+}
+
+impl BackupLayout {
+    pub fn new(config: &BackupTargetConfig, node_config: &NodeConfig) -> Self {
+        let prefix = config.prefix.clone().unwrap_or("redgold".to_string());
+        Self {
+            root: format!(
+                "{}/{}/{}",
+                prefix.trim_end_matches('/'),
+                node_config.network.to_std_string(),
+                node_config.server_index()
+            )
+        }
+    }
+
+    pub fn objects(&self) -> String {
+        format!("{}/objects/", self.root)
+    }
+
+    pub fn object(&self, id: &String) -> String {
+        format!("{}{}", self.objects(), id)
+    }
+
+    pub fn manifests(&self) -> String {
+        format!("{}/manifests/", self.root)
+    }
+
+    pub fn manifest(&self, time: i64) -> String {
+        format!("{}{}", self.manifests(), time)
+    }
+
+    pub fn manifest_time(&self, key: &String) -> Option<i64> {
+        key.strip_prefix(&self.manifests()).and_then(|t| t.parse::<i64>().ok())
+    }
+}
+
+pub struct EncryptedBackup {
+    pub target: Box<dyn BackupTarget>,
+    pub cipher: BackupCipher,
+    pub layout: BackupLayout,
+    pub retention: usize,
+}
+
+impl EncryptedBackup {
+    pub async fn new(config: &BackupTargetConfig, node_config: &NodeConfig) -> RgResult<Self> {
+        Ok(Self {
+            target: backup_target(config, node_config).await?,
+            cipher: BackupCipher::from_words(&node_config.words())?,
+            layout: BackupLayout::new(config, node_config),
+            retention: config.retention.unwrap_or(DEFAULT_BACKUP_RETENTION).max(1),
+        })
+    }
+
+    /// All configured targets, or the one matching a name.
+    pub async fn from_config(node_config: &NodeConfig, name: Option<&String>) -> RgResult<Vec<Self>> {
+        let mut res = vec![];
+        for c in node_config.backup_targets().iter()
+            .filter(|c| name.map(|n| c.name.as_ref() == Some(n)).unwrap_or(true)) {
+            res.push(Self::new(c, node_config).await?);
+        }
+        Ok(res)
+    }
+
+    /// Snapshot times available on the target, oldest first.
+    pub async fn snapshots(&self) -> RgResult<Vec<i64>> {
+        let keys = self.target.list(&self.layout.manifests()).await?;
+        Ok(keys.iter().flat_map(|k| self.layout.manifest_time(k)).sorted().collect())
+    }
+
+    pub async fn manifest(&self, time: i64) -> RgResult<BackupManifest> {
+        let data = self.target.get(&self.layout.manifest(time)).await?;
+        let json = String::from_utf8(self.cipher.decrypt(&data)?).error_info("Manifest is not utf8")?;
+        json.json_from()
+    }
+
+    /// Upload only the chunks of a file not already present on the target.
+    async fn upload_file(&self, path: &PathBuf, name: &str, existing: &mut HashSet<String>) -> RgResult<BackupFileEntry> {
+        let mut file = tokio::fs::File::open(path).await.error_info("Failed to open backup file")?;
+        let mut entry = BackupFileEntry { name: name.to_string(), ..Default::default() };
+        let mut uploaded = 0;
+        let mut buffer: Vec<u8> = Vec::with_capacity(BACKUP_MAX_CHUNK_BYTES);
+        let mut eof = false;
+        loop {
+            while !eof && buffer.len() < BACKUP_MAX_CHUNK_BYTES {
+                let wanted = (BACKUP_MAX_CHUNK_BYTES - buffer.len()) as u64;
+                let read = (&mut file).take(wanted).read_to_end(&mut buffer).await
+                    .error_info("Failed to read backup file")?;
+                eof = read == 0;
+            }
+            let Some(len) = chunk_boundary(
+                &buffer, eof, BACKUP_MIN_CHUNK_BYTES, BACKUP_MAX_CHUNK_BYTES, BACKUP_CHUNK_MASK
+            ) else {
+                break;
+            };
+            let chunk = buffer.drain(..len).collect::<Vec<u8>>();
+            entry.size += len as u64;
+            let id = self.cipher.content_id(&chunk);
+            if !existing.contains(&id) {
+                self.target.put(&self.layout.object(&id), self.cipher.encrypt(&chunk)?).await?;
+                existing.insert(id.clone());
+                uploaded += 1;
+            }
+            entry.chunks.push(id);
+        }
+        info!("Backup of {} to {} uploaded {} of {} chunks", name, self.target.name(), uploaded, entry.chunks.len());
+        Ok(entry)
+    }
+
+    pub async fn backup(&self, files: &Vec<(String, PathBuf)>, network: String, time: i64) -> RgResult<BackupManifest> {
+        let mut existing = self.target.list(&self.layout.objects()).await?
+            .iter()
+            .flat_map(|k| k.strip_prefix(&self.layout.objects()).map(|s| s.to_string()))
+            .collect::<HashSet<String>>();
+        let mut manifest = BackupManifest { time, network, files: vec![] };
+        for (name, path) in files {
+            manifest.files.push(self.upload_file(path, name, &mut existing).await?);
+        }
+        // Manifest goes last so a snapshot is never visible before all its chunks are.
+        let data = self.cipher.encrypt(manifest.json_or().as_bytes())?;
+        self.target.put(&self.layout.manifest(time), data).await?;
+        self.apply_retention().await?;
+        Ok(manifest)
+    }
+
+    /// Drop the oldest snapshots beyond the retention count, then any chunk no longer
+    /// referenced by a remaining snapshot.
+    pub async fn apply_retention(&self) -> RgResult<()> {
+        let snapshots = self.snapshots().await?;
+        let expired = snapshots.len().saturating_sub(self.retention);
+        for t in snapshots.iter().take(expired) {
+            self.target.delete(&self.layout.manifest(*t)).await?;
+        }
+        let mut referenced = HashSet::new();
+        for t in snapshots.iter().skip(expired) {
+            referenced.extend(self.manifest(*t).await?.chunk_ids());
+        }
+        for key in self.target.list(&self.layout.objects()).await? {
+            let id = key.strip_prefix(&self.layout.objects()).unwrap_or("").to_string();
+            if !referenced.contains(&id) {
+                self.target.delete(&key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Download and decrypt a file from a snapshot, checking every chunk against its id. Chunks
+    /// are written as they arrive to a partial file which is only moved into place once complete.
+    pub async fn restore_file(&self, entry: &BackupFileEntry, path: &PathBuf) -> RgResult<()> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.error_info("Failed to create restore directory")?;
+        }
+        let partial = path.with_extension("partial");
+        let res = self.restore_chunks(entry, &partial).await;
+        if res.is_err() {
+            tokio::fs::remove_file(&partial).await.ok();
+        }
+        res?;
+        tokio::fs::rename(&partial, path).await.error_info("Failed to move restored file into place")
+    }
+
+    async fn restore_chunks(&self, entry: &BackupFileEntry, partial: &PathBuf) -> RgResult<()> {
+        let mut file = tokio::fs::File::create(partial).await.error_info("Failed to create restored file")?;
+        let mut written = 0u64;
+        for id in &entry.chunks {
+            let chunk = self.cipher.decrypt(&self.target.get(&self.layout.object(id)).await?)?;
+            if &self.cipher.content_id(&chunk) != id {
+                return Err(error_info("Backup chunk does not match its content id"))
+                    .with_detail("chunk", id.clone());
+            }
+            file.write_all(&chunk).await.error_info("Failed to write restored file")?;
+            written += chunk.len() as u64;
+        }
+        if written != entry.size {
+            return Err(error_info("Restored file size does not match manifest"))
+                .with_detail("file", entry.name.clone());
+        }
+        file.sync_all().await.error_info("Failed to sync restored file")
+    }
+}
+
+pub struct EncryptedBackupService {
+    pub relay: Relay,
+}
+
+impl EncryptedBackupService {
+    pub fn new(relay: &Relay) -> Self {
+        Self { relay: relay.clone() }
+    }
+
+    async fn do_backup(&self) -> RgResult<()> {
+        let nc = &self.relay.node_config;
+        let backups = EncryptedBackup::from_config(nc, None).await?;
+        if backups.is_empty() {
+            return Ok(());
+        }
+        let ct = util::current_time_millis_i64();
+        let staging = nc.env_data_folder().backups().join("staging");
+        tokio::fs::create_dir_all(&staging).await.error_info("Couldn't create backup staging dir")?;
+        let snapshot = staging.join(DATA_STORE_BACKUP_NAME);
+        tokio::fs::remove_file(&snapshot).await.ok();
+        self.relay.ds.snapshot_to(&snapshot).await?;
+        let files = vec![(DATA_STORE_BACKUP_NAME.to_string(), snapshot.clone())];
+        for b in backups {
+            if let Some(last) = b.snapshots().await.log_error().ok().and_then(|s| s.last().cloned()) {
+                if ct - last < 1000 * 86400 / 2 {
+                    info!("Skipping backup to {}, not enough time has passed since last backup", b.target.name());
+                    continue;
+                }
+            }
+            b.backup(&files, nc.network.to_std_string(), ct).await
+                .with_detail("target", b.target.name())
+                .log_error()
+                .ok();
+        }
+        tokio::fs::remove_file(&snapshot).await.ok();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IntervalFold for EncryptedBackupService {
+    async fn interval_fold(&mut self) -> RgResult<()> {
+        self.do_backup().await.log_error().ok();
+        Ok(())
+    }
+}
+
+/// Restore the data store from a snapshot, the latest by default. With verify the restored
+/// store must pass a consistency check before it replaces the existing one, which is kept
+/// alongside it rather than deleted.
+pub async fn restore_data_store(
+    node_config: &NodeConfig,
+    target: Option<&String>,
+    snapshot: Option<i64>,
+    verify: bool
+) -> RgResult<PathBuf> {
+    let backup = EncryptedBackup::from_config(node_config, target).await?
+        .into_iter().next()
+        .ok_msg("No matching backup target configured")?;
+    let time = match snapshot {
+        Some(t) => t,
+        None => backup.snapshots().await?.last().cloned().ok_msg("No backup snapshots found")?
+    };
+    let manifest = backup.manifest(time).await?;
+    let entry = manifest.files.iter()
+        .find(|f| f.name == DATA_STORE_BACKUP_NAME)
+        .ok_msg("Snapshot does not contain a data store")?;
+    let folder = node_config.env_data_folder();
+    let restored = folder.backups().join("restore").join(time.to_string()).join(DATA_STORE_BACKUP_NAME);
+    backup.restore_file(entry, &restored).await?;
+    info!("Restored snapshot {} from {} to {:?}", time, backup.target.name(), restored);
+
+    if verify {
+        let ds = DataStore::from_config_path(&restored).await;
+        let problems = ds.verify_consistency().await;
+        ds.ctx.pool.close().await;
+        let problems = problems?;
+        if !problems.is_empty() {
+            return Err(error_info("Restored data store failed verification"))
+                .with_detail("problems", problems.join(", "))
+                .with_detail("path", restored.to_string_lossy().to_string());
+        }
+        info!("Restored data store passed verification");
+    }
+
+    let dest = folder.data_store_path();
+    if dest.exists() {
+        let ct = util::current_time_millis_i64();
+        let kept = dest.with_extension(format!("sqlite.pre-restore-{}", ct));
+        tokio::fs::rename(&dest, &kept).await.error_info("Failed to move existing data store aside")?;
+        for suffix in ["-wal", "-shm"] {
+            let p = PathBuf::from(format!("{}{}", dest.to_string_lossy(), suffix));
+            tokio::fs::remove_file(p).await.ok();
+        }
+        info!("Existing data store kept at {:?}", kept);
+    }
+    tokio::fs::rename(&restored, &dest).await.error_info("Failed to move restored data store into place")?;
+    Ok(dest)
+}
+
+#[test]
+fn backup_cipher_round_trip() {
+    let words = WordsPass::test_words();
+    let cipher = BackupCipher::from_words(&words).unwrap();
+    let data = b"some data store contents".to_vec();
+    let enc = cipher.encrypt(&data).unwrap();
+    assert_ne!(enc, data);
+    assert_eq!(cipher.decrypt(&enc).unwrap(), data);
+    assert_eq!(cipher.content_id(&data), cipher.content_id(&data));
+
+    let mut tampered = enc.clone();
+    let last = tampered.len() - MAC_BYTES - 1;
+    tampered[last] ^= 1;
+    assert!(cipher.decrypt(&tampered).is_err());
+
+    let other = BackupCipher::from_words(&WordsPass::from_str_hashed("other")).unwrap();
+    assert!(other.decrypt(&enc).is_err());
+}
+
+#[test]
+fn content_defined_chunks_survive_insertions() {
+    let mut state: u64 = 7;
+    let data = (0..200_000).map(|_| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as u8
+    }).collect::<Vec<u8>>();
+    let chunks = |d: &[u8]| {
+        let mut res = vec![];
+        let mut offset = 0;
+        while let Some(len) = chunk_boundary(&d[offset..], true, 256, 8192, (1 << 10) - 1) {
+            res.push(d[offset..offset + len].to_vec());
+            offset += len;
+        }
+        res
+    };
+    let original = chunks(&data);
+    assert_eq!(original.concat(), data);
+    assert!(original.iter().all(|c| c.len() <= 8192));
+    assert!(original.len() > 50);
+
+    let mut edited = data.clone();
+    edited.splice(1000..1000, vec![1, 2, 3, 4, 5]);
+    let after = chunks(&edited);
+    assert_eq!(after.concat(), edited);
+    let shared = after.iter().filter(|c| original.contains(c)).count();
+    assert!(shared >= original.len() - 2, "{} of {} chunks shared", shared, original.len());
+    assert_eq!(chunk_boundary(&data[..100], false, 256, 8192, 1023), None);
+}
@@ -1 +1,3 @@
-pub mod aws_backup;
\ No newline at end of file
+pub mod aws_backup;
+pub mod backup_target;
+pub mod encrypted_backup;
@@ -1,101 +1,137 @@
-// use crate::core::relay::Relay;
-// use redgold_data::data_store::DataStore;
-// // use crate::genesis::create_genesis_block;
-// use crate::schema::error_message;
-// use crate::schema::structs::{Address, AddressBlock, Block, Error, ErrorInfo, Output};
-// use crate::schema::WithMetadataHashable;
-// use crate::schema::{struct_metadata, SafeBytesAccess};
-// use crate::util;
-// use crate::util::rg_merkle;
-// use itertools::Itertools;
-// use tracing::{debug, error};
-// use std::collections::HashMap;
-// use std::sync::Arc;
-// use tokio::runtime::Runtime;
-//
-// /// This is only used for backwards compatibility purposes and should not be considered
-// /// the source of truth or irreversibility.
-// pub struct BlockFormationProcess {
-//     relay: Relay,
-//     last_block: Block,
-// }
-//
-// /// This is only used for backwards compatibility purposes and should not be considered
-// /// the source of truth or irreversibility.
-// impl BlockFormationProcess {
-//     pub async fn run(mut self) -> Result<(), ErrorInfo> {
-//         let mut interval = tokio::time::interval(self.relay.node_config.block_formation_interval);
-//         // TODO: everything in here is basically a placeholder for later
-//         loop {
-//             interval.tick().await;
-//             let i = self.last_block.time().expect("Last block time missing");
-//             let txs = self
-//                 .relay
-//                 .ds
-//                 // TODO: No
-//                 .transaction_store
-//                 .query_time_transaction(i as i64, util::current_time_millis_i64()).await
-//                 // .map_err(|e| error!("Error query_time_transaction {}", e.to_string()))
-//                 .ok();
-//             if let Some(txa) = txs {
-//                 // TODO: re-query observation edge here.
-//                 let last_time = txa.iter().map(|t| t.time.clone()).max().expect("max");
-//                 let vec = txa
-//                     .clone()
-//                     .iter()
-//                     .map(|e| e.transaction.as_ref().expect("").clone())
-//                     .collect_vec();
-//                 let leafs = vec
-//                     .clone()
-//                     .iter()
-//                     .map(|e| e.hash_bytes().expect("vec").clone())
-//                     .collect_vec();
-//                 let height = self.last_block.height + 1;
-//                 let block = Block {
-//                     // TODO: use a real merkle root here
-//                     merkle_root: Some(rg_merkle::build_root_simple(&leafs)),
-//                     transactions: vec.clone(), // TODO: can leave this blank and enforce it properly
-//                     // to remove the clone on hash calculation? That's clever do it as part
-//                     // of a constructor function.
-//                     struct_metadata: struct_metadata(last_time.clone() as i64),
-//                     previous_block_hash: Some(self.last_block.hash_or()),
-//                     metadata: None,
-//                     height,
-//                 }
-//                 .with_hash()
-//                 .clone();
-//
-//                 // todo: based on node config
-//                 // TODO: Re-enable
-//                 // self.relay.ds.insert_block_update_historicals(&block).await?;
-//                 self.last_block = block.clone();
-//                 metrics::counter!("redgold.blocks.created").increment(1);
-//                 debug!("Formed block with hash {}", block.hash_hex_or_missing())
-//             }
-//         }
-//     }
-//
-//     pub async fn default(relay: Relay) -> Result<Self, ErrorInfo> {
-//         let last_block_opt = relay
-//             .ds
-//             .address_block_store
-//             .query_last_block()
-//             .await?;
-//             // .expect("Datastore failure on last block query")
-//             // .ok_or(error_message(Error::UnknownError, "Missing last block"))
-//         //
-//         let last_block = match last_block_opt {
-//             None => {
-//                 // let block = create_genesis_block();
-//                 // TODO: only if historical balance tracking enabled
-//                 // relay.ds.insert_block_update_historicals(&block).await?;
-//                 panic!("Genesis block created")
-//             }
-//             Some(b) => {b}
-//         };
-//         Ok(Self { relay, last_block })
-//     }
-// }
-//
-// #[test]
-// fn test_block_formation() {}
+use async_trait::async_trait;
+use itertools::Itertools;
+use metrics::counter;
+use redgold_common_no_wasm::stream_handlers::IntervalFold;
+use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+use redgold_schema::structs::{Block, Transaction};
+use redgold_schema::{struct_metadata, RgResult};
+use tracing::debug;
+
+use crate::core::relay::Relay;
+use crate::util;
+use crate::util::rg_merkle;
+
+// Upper bound on blocks formed per tick while catching up after downtime.
+const MAX_BLOCKS_PER_TICK: i64 = 100;
+
+/// Groups accepted transactions into blocks for consumers expecting a chain (Rosetta, indexers.)
+/// This is derived data, not the source of truth or irreversibility.
+///
+/// Block `h` covers transaction times in `[genesis + h * interval, genesis + (h + 1) * interval)`
+/// with transactions ordered by (time, hash), so every node with the same accepted set forms
+/// identical blocks regardless of when it received them. Empty windows still produce a block so
+/// heights stay contiguous. A window is only sealed once the transaction finalization time has
+/// passed since its end, giving late acceptances time to arrive.
+pub struct BlockFormationProcess {
+    relay: Relay,
+    last_block: Option<Block>,
+}
+
+/// Deterministically build the block at `height` from the transactions in its window.
+pub fn form_block(
+    previous: Option<&Block>,
+    height: i64,
+    window_start: i64,
+    transactions: Vec<Transaction>
+) -> Block {
+    let transactions = transactions.into_iter()
+        .sorted_by(|a, b| {
+            a.time().cloned().unwrap_or(0).cmp(&b.time().cloned().unwrap_or(0))
+                .then(a.hash_or().vec().cmp(&b.hash_or().vec()))
+        })
+        .collect_vec();
+    let merkle_root = if transactions.is_empty() {
+        None
+    } else {
+        let leafs = transactions.iter().map(|t| t.hash_or().vec()).collect_vec();
+        Some(rg_merkle::build_root_simple(&leafs))
+    };
+    let mut block = Block {
+        merkle_root,
+        transactions,
+        struct_metadata: struct_metadata(window_start),
+        previous_block_hash: previous.map(|p| p.hash_or()),
+        metadata: None,
+        height,
+    };
+    block.with_hash();
+    block
+}
+
+impl BlockFormationProcess {
+    pub fn new(relay: &Relay) -> Self {
+        Self {
+            relay: relay.clone(),
+            last_block: None,
+        }
+    }
+
+    fn interval_millis(&self) -> i64 {
+        self.relay.node_config.block_formation_interval.as_millis() as i64
+    }
+
+    async fn genesis_time(&self) -> RgResult<Option<i64>> {
+        let genesis = self.relay.ds.config_store.get_genesis().await?;
+        genesis.map(|g| g.time().cloned()).transpose()
+    }
+
+    async fn form_next(&mut self, genesis_time: i64, now: i64) -> RgResult<bool> {
+        let interval = self.interval_millis();
+        let height = self.last_block.as_ref().map(|b| b.height + 1).unwrap_or(0);
+        let start = genesis_time + height * interval;
+        let end = start + interval;
+        let settle = self.relay.node_config.transaction_finalization_time.as_millis() as i64;
+        if end + settle > now {
+            return Ok(false);
+        }
+        let transactions = self.relay.ds.transaction_store
+            .query_time_transaction_accepted_ordered(start, end).await?;
+        let block = form_block(self.last_block.as_ref(), height, start, transactions);
+        self.relay.ds.block.insert_block(&block).await?;
+        counter!("redgold_blocks_created").increment(1);
+        debug!("Formed block {} with hash {} and {} transactions", height, block.hash_or().hex(), block.transactions.len());
+        self.last_block = Some(block);
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl IntervalFold for BlockFormationProcess {
+    async fn interval_fold(&mut self) -> RgResult<()> {
+        let Some(genesis_time) = self.genesis_time().await? else {
+            return Ok(());
+        };
+        if self.last_block.is_none() {
+            self.last_block = self.relay.ds.block.query_last_block().await?;
+        }
+        let now = util::current_time_millis_i64();
+        for _ in 0..MAX_BLOCKS_PER_TICK {
+            if !self.form_next(genesis_time, now).await? {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn block_formation_is_order_independent() {
+    let tx = |salt: i64, time: i64| {
+        let mut t = Transaction::default();
+        t.struct_metadata = struct_metadata(time);
+        t.options = Some(redgold_schema::structs::TransactionOptions {
+            salt: Some(salt),
+            ..Default::default()
+        });
+        t.with_hash();
+        t
+    };
+    let txs = vec![tx(1, 10), tx(2, 5), tx(3, 5)];
+    let genesis = form_block(None, 0, 0, vec![]);
+    assert!(genesis.merkle_root.is_none());
+    let a = form_block(Some(&genesis), 1, 0, txs.clone());
+    let b = form_block(Some(&genesis), 1, 0, txs.into_iter().rev().collect_vec());
+    assert_eq!(a.hash_or(), b.hash_or());
+    assert_eq!(a.previous_block_hash, Some(genesis.hash_or()));
+    assert_eq!(a.transactions.last().and_then(|t| t.time().ok().cloned()), Some(10));
+}
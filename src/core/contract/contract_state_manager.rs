@@ -1,15 +1,50 @@
+use crate::core::contract::host_state::DataStoreHostState;
+use crate::core::contract::ordering::{ordering_strategy, ContractOrderingStrategy, PendingContractRequest};
 use crate::core::relay::Relay;
 use crate::util;
 // use async_std::prelude::FutureExt;
 use async_trait::async_trait;
 use flume::Sender;
+use metrics::counter;
 use futures::future::Either;
+use itertools::Itertools;
 use redgold_common::flume_send_help::SendErrorInfo;
 use redgold_common_no_wasm::stream_handlers::IntervalFoldOrReceive;
+use redgold_keys::contract_marker_support::ContractMarkerSupport;
+use redgold_keys::util::mnemonic_support::MnemonicSupport;
+use redgold_keys::word_pass_support::WordsPassNodeConfig;
 use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
-use redgold_schema::structs::{ContentionKey, ContractStateMarker, Output, Transaction};
-use redgold_schema::{error_info, ErrorInfoContext, RgResult, SafeOption};
+use redgold_schema::structs::{Address, ContentionKey, ContractStateMarker, ErrorCode, ExecutionInput, ExecutorBackend, Output, Transaction};
+use redgold_executor::evm_invoke::revm_executor;
+use redgold_schema::{bytes_data, error_info, error_message, ErrorInfoContext, RgResult, SafeOption};
 use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Checks a deploy output must pass before the transaction carrying it is accepted: the code
+/// hashes to the deploy address, passes static validation for its backend and the address has no
+/// existing state. Returns the backend and code for execution.
+pub async fn validate_deploy_output(relay: &Relay, output: &Output) -> RgResult<(ExecutorBackend, Vec<u8>)> {
+    let address = output.address.safe_get_msg("Missing deploy address")?;
+    let contract = output.contract.as_ref()
+        .and_then(|c| c.code_execution_contract.as_ref())
+        .safe_get_msg("Missing code execution contract on deploy output")?;
+    let backend = output.executor_backend()
+        .ok_or(error_message(ErrorCode::ContractCodeInvalid, "Unsupported executor backend for deploy"))?;
+    let code = contract.code.as_ref().safe_get_msg("Missing contract code")?;
+    if &Address::script_hash(&code.value)? != address {
+        return Err(error_message(ErrorCode::ContractCodeInvalid, "Deploy address does not match code hash"));
+    }
+    let limits = &relay.node_config.contract.execution_limits;
+    match backend {
+        ExecutorBackend::Extism => redgold_executor::extism_wrapper::validate_extism_contract(&code.value, limits)?,
+        ExecutorBackend::Evm => revm_executor::validate_evm_contract(&code.value, limits)?,
+    }
+    let existing = relay.ds.state.query_recent_state(address, None, Some(1)).await?;
+    if !existing.is_empty() {
+        return Err(error_message(ErrorCode::ContractAlreadyDeployed, "Contract address already has state"));
+    }
+    Ok((backend, code.value.clone()))
+}
 
 //
 // #[derive(Clone)]
@@ -67,50 +102,6 @@ use std::collections::HashMap;
 // pub struct ContractStateManager {
 //     pub contract_state_channels: Arc<DashMap<Address, ContractStateManagerEntry>>
 // }
-//
-// impl ContractStateManager {
-//     pub fn new() -> Self {
-//         Self {
-//             contract_state_channels: Arc::new(Default::default()),
-//         }
-//     }
-//
-//     // pub async fn with_jh_check(csm: &ContractStateManagerEntry) -> RgResult<()> {
-//     //     let guard = csm.join_handle.lock()
-//     //         .map_err(|e| error_info(format!("Lock failure: {}", e.to_string())))?;
-//     //     let jh = std::mem::take(&mut *guard);
-//     //     if jh.is_finished() {
-//     //         jh.await.error_info("join error")?
-//     //     } else {
-//     //         Ok(())
-//     //     }
-//     // }
-//
-//     pub fn channel(&self, address: &Address, relay: Relay) -> RgResult<ContractStateManagerEntry> {
-//         // TODO: Max channels exceeded, need to rebalance to drop partition distance
-//
-//         let entry = self
-//             .contract_state_channels
-//             .entry(address.clone());
-//         let res = match entry {
-//             Entry::Occupied(v) => {
-//                 let res = v.get().clone();
-//                 // Self::with_jh_check(&res)?;
-//                 Ok(res)
-//             },
-//             Entry::Vacant(entry) => {
-//                 let csme = ContractStateManagerEntry::new(
-//                     relay.node_config.contract.contract_state_channel_bound.clone(),
-//                     &address,
-//                     &relay
-//                 );
-//                 // Self::with_jh_check(&csme)?;
-//                 Ok(csme)
-//             }
-//         };
-//         res
-//     }
-// }
 
 
 #[derive(Clone)]
@@ -125,23 +116,17 @@ pub enum ContractStateMessage {
 
 pub struct ContractStateManager {
     relay: Relay,
-    unordered: HashMap<ContentionKey, Vec<(Transaction, i64)>>,
-    subscribers: HashMap<ContentionKey, Vec<flume::Sender<RgResult<ContractStateMarker>>>>
-}
-
-#[derive(Clone)]
-struct UnorderedContractUpdate {
-    transaction: Transaction,
-    output: Output,
-    receipt_time: i64
+    unordered: HashMap<ContentionKey, Vec<PendingContractRequest>>,
+    strategy: Box<dyn ContractOrderingStrategy>,
 }
 
 impl ContractStateManager {
     pub fn new(relay: Relay) -> Self {
+        let strategy = ordering_strategy(&relay);
         Self {
             relay,
             unordered: Default::default(),
-            subscribers: Default::default(),
+            strategy,
         }
     }
     pub async fn process_tx(&mut self,
@@ -151,85 +136,218 @@ impl ContractStateManager {
     ) -> RgResult<()> {
 
         if output.is_deploy() {
-            Err(error_info("Deploy transactions not supported"))?;
+            let result = self.deploy(transaction, output).await;
+            if result.is_ok() {
+                counter!("redgold_contract_deploy").increment(1);
+            }
+            response.send_rg_err(result)?;
+            return Ok(());
         }
         if !output.is_request() {
             Err(error_info("Non-request transaction outputs not supported"))?;
         }
         let contention_key = output.request_contention_key()?;
-        let tuple = (transaction.clone(), util::current_time_millis_i64());
-        let result = self.unordered.get_mut(&contention_key);
-        if let Some(vec) = result {
-            vec.push(tuple);
-        } else {
-            self.unordered.insert(contention_key.clone(), vec![tuple]);
+        let pending = PendingContractRequest {
+            transaction: transaction.clone(),
+            output: output.clone(),
+            response: response.clone(),
+        };
+        let hash = pending.hash();
+        self.unordered.entry(contention_key.clone()).or_default().push(pending);
+        self.relay.contract_pending_requests.entry(contention_key).or_default().push(hash);
+        Ok(())
+    }
+
+    /// Validate deployed code, run the genesis invocation against empty state and store the
+    /// result as index 0 for the contract address. A deploy creates its own address so it has
+    /// nothing to contend with and is processed immediately rather than on the interval.
+    pub async fn deploy(&self, transaction: &Transaction, output: &Output) -> RgResult<ContractStateMarker> {
+        let (backend, code) = validate_deploy_output(&self.relay, output).await?;
+        let address = output.address.safe_get_msg("Missing deploy address")?;
+        // Genesis execution is paid for like any request, never at the node maximum for free.
+        let budget = output.execution_budget()
+            .ok_or(error_message(ErrorCode::InsufficientFee, "Deploy output missing execution budget"))?;
+        let time = transaction.time()?.clone();
+        let mut input = ExecutionInput::default();
+        input.tx = Some(transaction.clone());
+        let limits = self.relay.node_config.contract.execution_limits
+            .with_budget(Some(budget));
+        let er = match backend {
+            ExecutorBackend::Extism => redgold_executor::extism_wrapper::deploy_extism_contract(
+                &code,
+                input,
+                &limits,
+                Arc::new(DataStoreHostState::pinned(&self.relay, time).await)
+            ).await?,
+            ExecutorBackend::Evm => {
+                let host = Arc::new(DataStoreHostState::pinned(&self.relay, time).await);
+                let er = tokio::task::spawn_blocking(move || {
+                    revm_executor::deploy_evm_contract(&code, input, &limits, host)
+                }).await.error_info("EVM deploy task failed")??;
+                if !er.valid {
+                    return Err(er.error_info().cloned()
+                        .unwrap_or(error_info("EVM contract deploy returned invalid result")));
+                }
+                er
+            }
+        };
+        counter!("redgold_contract_execution_fuel").increment(er.fuel_consumed_or());
+
+        let mut csm = ContractStateMarker::default();
+        csm.state = er.data.as_ref().and_then(|d| d.state.clone());
+        csm.address = Some(address.clone());
+        csm.time = time;
+        csm.transaction_marker = Some(transaction.hash_or());
+        csm.index_counter = 0;
+        csm.fuel_consumed = er.fuel_consumed.clone();
+        self.finalize_marker(&mut csm, None)?;
+        self.relay.ds.state.insert_state(csm.clone()).await?;
+        Ok(csm)
+    }
+
+    fn finalize_marker(&self, csm: &mut ContractStateMarker, previous: Option<&ContractStateMarker>) -> RgResult<()> {
+        csm.with_aggregate_state_hash(previous)?;
+        csm.sign(&self.relay.node_config.words().default_kp()?);
+        Ok(())
+    }
+
+    async fn execute_request(
+        &self,
+        backend: ExecutorBackend,
+        code: &Vec<u8>,
+        previous: &ContractStateMarker,
+        request: &PendingContractRequest,
+    ) -> RgResult<ContractStateMarker> {
+        let tx = &request.transaction;
+        let output = &request.output;
+        let time = tx.time()?.clone();
+        if time < previous.time {
+            // Admitted requests execute only once every earlier window has settled, so this one
+            // reached the node after a later request had already been ordered past it.
+            counter!("redgold_contract_ordering_late_admitted").increment(1);
+            return Err(error_message(
+                ErrorCode::ContractOrderingRejected,
+                "Contract request was admitted after a later request had already executed"
+            ));
         }
-        if let Some(vec) = self.subscribers.get_mut(&contention_key) {
-            vec.push(response.clone());
-        } else {
-            self.subscribers.insert(contention_key, vec![response.clone()]);
+        let input = output.request_data()?;
+        let limits = self.relay.node_config.contract.execution_limits
+            .with_budget(output.execution_budget());
+        let state = &previous.state.safe_get()?.value;
+        let er = match backend {
+            ExecutorBackend::Extism => redgold_executor::extism_wrapper::invoke_extism_wasm_direct(
+                code,
+                input,
+                state,
+                &limits,
+                Arc::new(DataStoreHostState::pinned(&self.relay, time).await)
+            ).await?,
+            // Runtime code of an EVM contract is carried in its state rather than the deploy output
+            ExecutorBackend::Evm => {
+                let mut args = ExecutionInput::default();
+                args.tx = Some(tx.clone());
+                args.input = bytes_data(input.clone());
+                args.state = bytes_data(state.clone());
+                let host = Arc::new(DataStoreHostState::pinned(&self.relay, time).await);
+                let limits = limits.clone();
+                tokio::task::spawn_blocking(move || revm_executor::invoke_evm_contract(args, &limits, host))
+                    .await.error_info("EVM execution task failed")??
+            }
+        };
+        if !er.valid {
+            counter!("redgold_contract_execution_invalid").increment(1);
+            return Err(er.error_info().cloned()
+                .unwrap_or(error_info("Contract execution returned invalid result")));
         }
-        Ok(())
+        counter!("redgold_contract_execution_fuel").increment(er.fuel_consumed_or());
+        let d = er.data.safe_get_msg("data")?;
+        let updated_state = d.state.safe_get_msg("state")?;
+        let mut csm = ContractStateMarker::default();
+        csm.state = Some(updated_state.clone());
+        csm.response = d.data.clone();
+        csm.address = previous.address.clone();
+        csm.selector = previous.selector.clone();
+        csm.time = time;
+        csm.transaction_marker = Some(tx.hash_or());
+        csm.index_counter = previous.index_counter + 1;
+        csm.fuel_consumed = er.fuel_consumed.clone();
+        self.finalize_marker(&mut csm, Some(previous))?;
+        self.relay.ds.state.insert_state(csm.clone()).await?;
+        Ok(csm)
     }
 
-    // TODO: Introduce time ordering strategy selector, additional proofs, etc.
-    pub async fn interval(&mut self) -> RgResult<()> {
-        let _ct = util::current_time_millis_i64();
-        let mut finished_contention_keys = vec![];
-        for (k, v) in self.unordered.iter_mut() {
-            // TODO: Earlier validation should find something in the recent state -- at least
-            // something close-ish since none of these transactions can be guaranteed to be issuing a
-            // recent reference due to contention time.
-            let address = k.address.safe_get_msg("Missing address on contention key")?;
-            let sel = k.selector.as_ref();
-            let most_recent_query_result = self.relay.ds.state.query_recent_state(
-                address,
-                sel,
-                Some(1)
-            ).await?;
-            let head = most_recent_query_result.get(0);
-            let most_recent = head.safe_get_msg("Missing most recent contract state")?;
-            let resolve = self.relay.ds.resolve_code(address).await?;
+    /// Execute requests in the agreed order, each on top of the state produced by the last.
+    async fn execute_ordered(&self, key: &ContentionKey, ready: Vec<PendingContractRequest>) -> RgResult<()> {
+        let address = key.address.safe_get_msg("Missing address on contention key")?;
+        let most_recent = self.relay.ds.state.query_recent_state(
+            address,
+            key.selector.as_ref(),
+            Some(1)
+        ).await?.get(0).cloned();
+        let code = self.relay.ds.resolve_code(address).await.and_then(|resolve| {
             let u = resolve.utxo_entry.safe_get_msg("Code Utxo")?;
             let o = u.output.safe_get_msg("Output")?;
-            let code_opt = o.code();
-            let code = code_opt.safe_get_msg("Code")?;
-
-            // TODO: This is only one strategy, and missing proof steps etc.
-            let mut v2 = v.clone();
-            v2.sort_by(|a, b| a.0.time().expect("t").cmp(&b.0.time().expect("t")));
-            for (idx, (tx, _t)) in v2.iter().enumerate() {
-                // TODO: Send to executor channel rather than processing here locally.
-                let output_col = tx.output_of(&address);
-                let output_of = output_col.get(0);
-                let output = output_of.safe_get_msg("Missing output")?;
-                let input = output.request_data()?;
-                let er = redgold_executor::extism_wrapper::invoke_extism_wasm_direct(
-                    code,
-                    input,
-                    most_recent.state.safe_get()?.value.as_ref(),
-                ).await?;
-                let d = er.data.safe_get_msg("data")?;
-                let updated_state = d.state.safe_get_msg("state")?;
-                let mut csm = ContractStateMarker::default();
-                csm.state = Some(updated_state.clone());
-                csm.address = Some(address.clone());
-                csm.selector = sel.cloned();
-                csm.time = tx.time()?.clone();
-                csm.transaction_marker = Some(tx.hash_or());
-                csm.index_counter = most_recent.index_counter + 1;
-                self.relay.ds.state.insert_state(csm.clone()).await?;
-                if let Some(subs) = self.subscribers.remove(k) {
-                    for sub in &subs {
-                        sub.send_rg_err(Ok(csm.clone()))?;
-                    }
+            let backend = o.executor_backend().ok_or(error_info("Unsupported executor backend"))?;
+            Ok((backend, o.code().safe_get_msg("Code")?.clone()))
+        });
+        let (mut previous, (backend, code)) = match (most_recent, code) {
+            (Some(m), Ok(c)) => (m, c),
+            (None, _) => {
+                // Requests against an address with no deployed state can never be satisfied
+                let e = error_info("Contract has not been deployed, missing most recent contract state");
+                for r in &ready {
+                    r.response.send_rg_err(Err(e.clone())).ok();
+                }
+                return Ok(());
+            }
+            (_, Err(e)) => {
+                for r in &ready {
+                    r.response.send_rg_err(Err(e.clone())).ok();
                 }
-                v.remove(idx);
+                return Ok(());
             }
-            finished_contention_keys.push(k.clone())
+        };
+        for r in &ready {
+            let result = self.execute_request(backend, &code, &previous, r).await;
+            if let Ok(csm) = &result {
+                previous = csm.clone();
+            }
+            r.response.send_rg_err(result).ok();
         }
-        for k in &finished_contention_keys {
-            self.unordered.remove(k);
+        Ok(())
+    }
+
+    pub async fn interval(&mut self) -> RgResult<()> {
+        let now = util::current_time_millis_i64();
+        let keys = self.unordered.keys().cloned().collect_vec();
+        for k in keys {
+            let pending = self.unordered.get(&k).cloned().unwrap_or_default();
+            let decision = self.strategy.order(&k, &pending, now).await?;
+            let ready = decision.ready.iter()
+                .filter_map(|h| pending.iter().find(|p| &p.hash() == h))
+                .cloned()
+                .collect_vec();
+            if !ready.is_empty() {
+                self.execute_ordered(&k, ready).await?;
+            }
+            // Requests which never reached agreement are rejected rather than ordered locally
+            let (rejected, remaining): (Vec<_>, Vec<_>) = pending.into_iter()
+                .filter(|p| !decision.ready.contains(&p.hash()))
+                .partition(|p| decision.rejected.contains(&p.hash()));
+            for p in rejected {
+                counter!("redgold_contract_ordering_rejected").increment(1);
+                p.response.send_rg_err(Err(error_message(
+                    ErrorCode::ContractOrderingRejected,
+                    "Contract request did not reach an observation quorum within its ordering window"
+                ))).ok();
+            }
+            if remaining.is_empty() {
+                self.unordered.remove(&k);
+                self.relay.contract_pending_requests.remove(&k);
+            } else {
+                self.relay.contract_pending_requests.insert(k.clone(), remaining.iter().map(|p| p.hash()).collect_vec());
+                self.unordered.insert(k, remaining);
+            }
         }
         Ok(())
     }
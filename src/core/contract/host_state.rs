@@ -0,0 +1,143 @@
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+
+use itertools::Itertools;
+use redgold_executor::host::HostStateReader;
+use redgold_schema::party::central_price::CentralPricePair;
+use redgold_schema::structs::{Address, ContractStateMarker, CurrencyAmount, StateSelector, SupportedCurrency, UtxoEntry, UtxoId};
+use redgold_schema::{error_info, RgResult, SafeOption};
+use tokio::runtime::Handle;
+
+use crate::core::relay::Relay;
+
+// Page size used while walking an address history to rebuild its balance at the snapshot.
+const ADDRESS_HISTORY_PAGE: i64 = 1000;
+
+/// Chain state as of the ordering point of a contract request, backed by the node datastore.
+/// Everything is derived from accepted transactions and stored markers with a time at or before
+/// `snapshot_time` rather than the live UTXO set, so later spends seen by some nodes but not
+/// others cannot change the answer a contract observes.
+///
+/// Central prices are captured once when the snapshot is pinned, from the party price history at
+/// or before the snapshot, and only once the party has processed events beyond it. A node which
+/// hasn't caught up answers with an error instead of a value other nodes won't see.
+///
+/// Host functions are invoked synchronously from the blocking thread the plugin runs on, so
+/// queries are driven through the runtime handle captured when the snapshot was pinned.
+#[derive(Clone)]
+pub struct DataStoreHostState {
+    relay: Relay,
+    snapshot_time: i64,
+    handle: Handle,
+    central_prices: Option<HashMap<SupportedCurrency, CentralPricePair>>,
+}
+
+impl DataStoreHostState {
+    pub async fn pinned(relay: &Relay, snapshot_time: i64) -> Self {
+        let central_prices = Self::central_prices_at(relay, snapshot_time).await;
+        Self {
+            relay: relay.clone(),
+            snapshot_time,
+            handle: Handle::current(),
+            central_prices,
+        }
+    }
+
+    async fn central_prices_at(relay: &Relay, snapshot_time: i64) -> Option<HashMap<SupportedCurrency, CentralPricePair>> {
+        let data = relay.external_network_shared_data.clone_read().await;
+        data.iter()
+            .sorted_by_key(|(k, _)| k.hex())
+            .filter_map(|(_, v)| v.party_events.as_ref())
+            .find(|pe| pe.events.iter().filter_map(|e| e.time(&pe.seeds)).any(|t| t > snapshot_time))
+            .map(|pe| pe.central_price_history.clone().unwrap_or_default().into_iter()
+                .filter(|(t, _)| *t <= snapshot_time)
+                .last()
+                .map(|(_, prices)| prices)
+                .unwrap_or_default())
+    }
+
+    fn block<T>(&self, f: impl Future<Output = RgResult<T>>) -> RgResult<T> {
+        self.handle.block_on(f)
+    }
+
+    async fn utxo_async(&self, utxo_id: &UtxoId) -> RgResult<Option<UtxoEntry>> {
+        let hash = utxo_id.transaction_hash.safe_get_msg("Missing transaction hash")?;
+        let tx = self.relay.ds.transaction_store.query_accepted_tx(hash).await?;
+        let Some(tx) = tx else { return Ok(None) };
+        let time = tx.time()?.clone();
+        if time > self.snapshot_time {
+            return Ok(None);
+        }
+        Ok(tx.outputs.get(utxo_id.output_index as usize)
+            .map(|o| o.utxo_entry(hash, utxo_id.output_index, time)))
+    }
+
+    async fn balance_async(&self, address: &Address, currency: SupportedCurrency) -> RgResult<CurrencyAmount> {
+        let mut created: HashMap<UtxoId, i64> = HashMap::new();
+        let mut spent: HashSet<UtxoId> = HashSet::new();
+        let mut offset = 0;
+        loop {
+            let txs = self.relay.ds.transaction_store
+                .get_all_tx_for_address(address, ADDRESS_HISTORY_PAGE, offset).await?;
+            let page_len = txs.len() as i64;
+            for tx in txs {
+                if tx.time()?.clone() > self.snapshot_time {
+                    continue;
+                }
+                for u in tx.to_utxo_address(address) {
+                    if let (Some(id), Some(amount)) = (u.utxo_id.clone(), u.opt_amount()) {
+                        if amount.currency_or() == currency {
+                            created.insert(id, amount.amount);
+                        }
+                    }
+                }
+                for i in &tx.inputs {
+                    if let Some(id) = &i.utxo_id {
+                        spent.insert(id.clone());
+                    }
+                }
+            }
+            if page_len < ADDRESS_HISTORY_PAGE {
+                break;
+            }
+            offset += page_len;
+        }
+        let total = created.iter()
+            .filter(|(id, _)| !spent.contains(id))
+            .map(|(_, a)| *a)
+            .sum::<i64>();
+        Ok(CurrencyAmount::from_currency(total, currency))
+    }
+
+    async fn contract_state_async(
+        &self, address: &Address, selector: Option<&StateSelector>
+    ) -> RgResult<Option<ContractStateMarker>> {
+        self.relay.ds.state.query_state_at(address, selector, self.snapshot_time).await
+    }
+}
+
+impl HostStateReader for DataStoreHostState {
+    fn snapshot_time(&self) -> i64 {
+        self.snapshot_time
+    }
+
+    fn utxo(&self, utxo_id: &UtxoId) -> RgResult<Option<UtxoEntry>> {
+        self.block(self.utxo_async(utxo_id))
+    }
+
+    fn balance(&self, address: &Address, currency: SupportedCurrency) -> RgResult<CurrencyAmount> {
+        self.block(self.balance_async(address, currency))
+    }
+
+    fn contract_state(
+        &self, address: &Address, selector: Option<&StateSelector>
+    ) -> RgResult<Option<ContractStateMarker>> {
+        self.block(self.contract_state_async(address, selector))
+    }
+
+    fn central_price(&self, currency: SupportedCurrency) -> RgResult<Option<CentralPricePair>> {
+        let prices = self.central_prices.as_ref()
+            .ok_or(error_info("Central prices have not settled past the snapshot time"))?;
+        Ok(prices.get(&currency).cloned())
+    }
+}
@@ -1 +1,3 @@
-pub mod contract_state_manager;
\ No newline at end of file
+pub mod contract_state_manager;
+pub mod host_state;
+pub mod ordering;
\ No newline at end of file
@@ -0,0 +1,258 @@
+use async_trait::async_trait;
+use itertools::Itertools;
+use redgold_schema::conf::node_config::ContractOrderingStrategyType;
+use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+use redgold_schema::message::Request;
+use redgold_schema::structs::{ContentionKey, ContractOrderingObservationRequest, ContractStateMarker, Hash, Output, PublicKey, State, Transaction};
+use redgold_schema::RgResult;
+use tracing::debug;
+
+use crate::core::relay::Relay;
+
+#[derive(Clone)]
+pub struct PendingContractRequest {
+    pub transaction: Transaction,
+    pub output: Output,
+    pub response: flume::Sender<RgResult<ContractStateMarker>>,
+}
+
+impl PendingContractRequest {
+    pub fn hash(&self) -> Hash {
+        self.transaction.hash_or()
+    }
+}
+
+/// Where a request stands in its ordering window. Windows close `ordering_max_wait` after the
+/// signed transaction time and only proofs whose signed observation time falls in the window
+/// count, so every node holding the same proofs reaches the same decision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OrderingStatus {
+    /// A quorum of seeds observed the request as accepted within its window.
+    Admitted,
+    /// The window closed without a quorum, the request is never ordered.
+    Rejected,
+    /// The window is open, or closed too recently for every proof signed in it to have arrived.
+    Undecided,
+}
+
+/// Decide a request from the seeds' accepted proofs, each with the signed time it was observed.
+/// `settle` allows proofs signed before the window closed to reach this node.
+pub fn ordering_status(
+    accepted: &Vec<(PublicKey, i64)>,
+    seeds: &Vec<PublicKey>,
+    window_close: i64,
+    settle: i64,
+    now: i64
+) -> OrderingStatus {
+    if now < window_close + settle {
+        return OrderingStatus::Undecided;
+    }
+    let signers = accepted.iter()
+        .filter(|(pk, time)| *time <= window_close && seeds.contains(pk))
+        .map(|(pk, _)| pk)
+        .unique()
+        .count();
+    if signers * 2 > seeds.len().max(1) {
+        OrderingStatus::Admitted
+    } else {
+        OrderingStatus::Rejected
+    }
+}
+
+/// Requests to execute and to reject now.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OrderingDecision {
+    /// In canonical order, each executes on top of the state produced by the last.
+    pub ready: Vec<Hash>,
+    pub rejected: Vec<Hash>,
+}
+
+impl OrderingDecision {
+    /// Walk requests in canonical order, admitted ones execute only until the first undecided
+    /// request so nothing runs ahead of an earlier request that may still be admitted.
+    pub fn from_statuses(statuses: Vec<(Hash, OrderingStatus)>) -> Self {
+        let mut decision = Self::default();
+        let mut blocked = false;
+        for (h, status) in statuses {
+            match status {
+                OrderingStatus::Admitted if !blocked => decision.ready.push(h),
+                OrderingStatus::Admitted => {}
+                OrderingStatus::Rejected => decision.rejected.push(h),
+                OrderingStatus::Undecided => blocked = true,
+            }
+        }
+        decision
+    }
+}
+
+/// Decides which pending requests for a contention key are ready to execute and in what order.
+/// Every node must arrive at the same order for the same request set, otherwise the resulting
+/// states and aggregate hashes diverge.
+#[async_trait]
+pub trait ContractOrderingStrategy: Send + Sync {
+    /// Requests to execute now in canonical order and requests to reject, neither to keep waiting.
+    async fn order(
+        &self,
+        key: &ContentionKey,
+        pending: &Vec<PendingContractRequest>,
+        now: i64
+    ) -> RgResult<OrderingDecision>;
+}
+pub fn ordering_strategy(relay: &Relay) -> Box<dyn ContractOrderingStrategy> {
+    match relay.node_config.contract.ordering_strategy {
+        ContractOrderingStrategyType::LocalTime => Box::new(LocalTimeOrdering),
+        ContractOrderingStrategyType::PeerObservation => Box::new(PeerObservationOrdering {
+            relay: relay.clone()
+        }),
+    }
+}
+
+/// Canonical order shared by all strategies, transaction time with the hash as a tie breaker.
+pub fn canonical_order(transactions: Vec<&Transaction>) -> Vec<Hash> {
+    transactions.iter()
+        .map(|t| (t.time().cloned().unwrap_or(0), t.hash_or()))
+        .sorted_by(|(t1, h1), (t2, h2)| t1.cmp(t2).then(h1.vec().cmp(&h2.vec())))
+        .map(|(_, h)| h)
+        .collect_vec()
+}
+
+/// Orders everything seen locally immediately. Only consistent when a single node executes the
+/// contract, kept for local testing.
+pub struct LocalTimeOrdering;
+
+#[async_trait]
+impl ContractOrderingStrategy for LocalTimeOrdering {
+    async fn order(
+        &self,
+        _key: &ContentionKey,
+        pending: &Vec<PendingContractRequest>,
+        _now: i64
+    ) -> RgResult<OrderingDecision> {
+        Ok(OrderingDecision {
+            ready: canonical_order(pending.iter().map(|p| &p.transaction).collect_vec()),
+            rejected: vec![],
+        })
+    }
+}
+
+/// Orders requests by whether a quorum of seeds signed accepted observation proofs for them
+/// within their ordering window, judged by the transaction and observation times rather than
+/// when proofs arrived locally. Only the canonical prefix of admitted requests executes, so a
+/// request can't be ordered ahead of an earlier one whose window is still open. Trusted peers
+/// are asked which requests they hold for the key; if one of those has a quorum but hasn't
+/// arrived locally the key waits for it.
+pub struct PeerObservationOrdering {
+    relay: Relay,
+}
+
+impl PeerObservationOrdering {
+
+    /// Seed signers of accepted proofs for the hash with the signed time of each observation.
+    async fn accepted_proofs(&self, hash: &Hash) -> RgResult<Vec<(PublicKey, i64)>> {
+        let proofs = self.relay.ds.observation.select_observation_edge(hash).await?;
+        Ok(proofs.iter()
+            .filter_map(|p| {
+                let m = p.metadata.as_ref().filter(|m| m.state() == State::Accepted)?;
+                let time = m.struct_metadata.as_ref().and_then(|s| s.time)?;
+                let pk = p.proof.as_ref().and_then(|p| p.public_key.clone())?;
+                Some((pk, time))
+            })
+            .collect_vec())
+    }
+
+    async fn status(&self, request: &PendingContractRequest, now: i64) -> RgResult<OrderingStatus> {
+        let contract = &self.relay.node_config.contract;
+        let time = request.transaction.time()?.clone();
+        let seeds = self.relay.node_config.seeds_at_pk(time);
+        Ok(ordering_status(
+            &self.accepted_proofs(&request.hash()).await?,
+            &seeds,
+            time + contract.ordering_max_wait.as_millis() as i64,
+            contract.ordering_delay.as_millis() as i64,
+            now
+        ))
+    }
+
+    /// Whether a request seen only by peers has a quorum of seed proofs, whatever their time.
+    async fn has_quorum(&self, hash: &Hash) -> RgResult<bool> {
+        let seeds = self.relay.node_config.seeds_now_pk();
+        let accepted = self.accepted_proofs(hash).await?;
+        Ok(ordering_status(&accepted, &seeds, i64::MAX, 0, i64::MAX) == OrderingStatus::Admitted)
+    }
+
+    async fn peer_held(&self, key: &ContentionKey) -> RgResult<Vec<Hash>> {
+        let peers = self.relay.trusted_nodes().await?;
+        if peers.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut request = Request::default();
+        let mut obs = ContractOrderingObservationRequest::default();
+        obs.contention_key = Some(key.clone());
+        request.contract_ordering_observation_request = Some(obs);
+        let timeout = self.relay.node_config.contract.ordering_peer_timeout.clone();
+        let results = Relay::broadcast(self.relay.clone(), peers, request, Some(timeout)).await;
+        let mut held = vec![];
+        for (pk, r) in results {
+            match r.map(|r| r.contract_ordering_observation_response) {
+                Ok(Some(o)) => held.extend(o.transaction_hashes),
+                Ok(None) => {}
+                Err(e) => {
+                    debug!("Contract ordering observation failed for peer {}: {}", pk.short_id(), e.message);
+                }
+            }
+        }
+        Ok(held.into_iter().unique().collect_vec())
+    }
+}
+
+#[async_trait]
+impl ContractOrderingStrategy for PeerObservationOrdering {
+    async fn order(
+        &self,
+        key: &ContentionKey,
+        pending: &Vec<PendingContractRequest>,
+        now: i64
+    ) -> RgResult<OrderingDecision> {
+        let mut statuses = vec![];
+        for h in canonical_order(pending.iter().map(|p| &p.transaction).collect_vec()) {
+            let Some(request) = pending.iter().find(|p| p.hash() == h) else { continue };
+            statuses.push((h, self.status(request, now).await?));
+        }
+        let mut decision = OrderingDecision::from_statuses(statuses);
+        if decision.ready.is_empty() {
+            return Ok(decision);
+        }
+        let local = pending.iter().map(|p| p.hash()).collect_vec();
+        for h in self.peer_held(key).await? {
+            if !local.contains(&h) && self.has_quorum(&h).await? {
+                decision.ready.clear();
+                break;
+            }
+        }
+        Ok(decision)
+    }
+}
+
+#[test]
+fn ordering_decided_from_signed_times() {
+    let seeds = (1..=3u8).map(|i| PublicKey::from_bytes_direct_ecdsa(vec![i; 33])).collect_vec();
+    let close = 10_000;
+    let settle = 1_000;
+    let proofs = vec![(seeds[0].clone(), 9_000), (seeds[1].clone(), 9_500), (seeds[1].clone(), 9_600)];
+    assert_eq!(ordering_status(&proofs, &seeds, close, settle, close), OrderingStatus::Undecided);
+    assert_eq!(ordering_status(&proofs, &seeds, close, settle, close + settle), OrderingStatus::Admitted);
+    // Proofs signed after the window closed don't count, however early they arrived
+    let late = vec![(seeds[0].clone(), 9_000), (seeds[1].clone(), 10_001)];
+    assert_eq!(ordering_status(&late, &seeds, close, settle, close + settle), OrderingStatus::Rejected);
+
+    let h = |s: &str| Hash::from_string_calculate(s);
+    let decision = OrderingDecision::from_statuses(vec![
+        (h("a"), OrderingStatus::Admitted),
+        (h("b"), OrderingStatus::Rejected),
+        (h("c"), OrderingStatus::Undecided),
+        (h("d"), OrderingStatus::Admitted),
+        (h("e"), OrderingStatus::Rejected),
+    ]);
+    assert_eq!(decision.ready, vec![h("a")]);
+    assert_eq!(decision.rejected, vec![h("b"), h("e")]);
+}
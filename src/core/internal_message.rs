@@ -95,7 +95,9 @@ pub struct TransactionMessage {
     pub transaction: Transaction,
     pub response_channel: Option<flume::Sender<Response>>,
     pub origin: Option<structs::PublicKey>,
-    pub origin_ip: Option<String>
+    pub origin_ip: Option<String>,
+    // Pending transactions this one superseded in the mempool through replace-by-fee
+    pub replaced_transactions: Vec<structs::Hash>,
 }
 use redgold_schema::structs::{DynamicNodeMetadata, NodeMetadata, TransportBackend};
 use redgold_schema::{structs, ErrorInfoContext};
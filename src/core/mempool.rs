@@ -1,4 +1,5 @@
 use crate::core::internal_message::TransactionMessage;
+use crate::core::process_transaction::RequestProcessor;
 use crate::core::relay::Relay;
 use crate::core::transact::tx_validate::TransactionValidator;
 use crate::util;
@@ -18,36 +19,226 @@ use redgold_schema::observability::errors::EnhanceErrorInfo;
 use redgold_schema::observability::errors::Loggable;
 use redgold_schema::pow::TransactionPowValidate;
 use redgold_schema::proto_serde::ProtoSerde;
-use redgold_schema::structs::{Address, QueryTransactionResponse, SubmitTransactionResponse, Transaction};
+use redgold_schema::conf::node_config::MempoolConfig;
+use redgold_schema::structs::{Address, ErrorCode, Hash, QueryTransactionResponse, SubmitTransactionResponse, Transaction, UtxoId};
 use redgold_schema::message::Response;
 use redgold_schema::{error_info, error_message, RgResult};
 use std::cmp::Ordering;
-use std::collections::BinaryHeap;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use tracing::Level;
 
 pub struct Mempool {
     relay: Relay,
-    heap: BinaryHeap<MempoolEntry>
+    entries: MempoolEntries,
 }
 
+/// Pending entries ordered by priority, indexed by input address and by the inputs they spend.
+/// Holds no relay state so the capacity rules can be checked on their own.
+#[derive(Default)]
+pub struct MempoolEntries {
+    entries: BTreeSet<MempoolEntry>,
+    pending_per_address: HashMap<Address, usize>,
+    // Pending entry spending each fixed input, used to detect replacements
+    utxo_spenders: HashMap<UtxoId, MempoolEntry>,
+}
+
+/// Entries removed to admit a new one.
+#[derive(Debug, Default, PartialEq)]
+pub struct Admitted {
+    pub replaced: Vec<MempoolEntry>,
+    pub evicted: Option<MempoolEntry>,
+}
+
+impl MempoolEntries {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Pending entries spending any of the same inputs as the given entry.
+    pub fn conflicts(&self, mempool_entry: &MempoolEntry) -> Vec<MempoolEntry> {
+        mempool_entry.transaction.transaction.utxo_inputs()
+            .filter_map(|u| self.utxo_spenders.get(u))
+            .unique_by(|e| e.hash.clone())
+            .cloned()
+            .collect_vec()
+    }
+
+    /// Insert an entry, replacing pending entries which spend the same inputs and evicting the
+    /// lowest priority entry when at capacity. A replacement must spend all inputs of each entry it
+    /// replaces and pay strictly more. Entries which would themselves be the lowest priority in a
+    /// full mempool are rejected. Limits are checked as they'll stand once the replaced entries
+    /// are gone, and nothing is removed until every check has passed.
+    pub fn insert(
+        &mut self, mut mempool_entry: MempoolEntry, config: &MempoolConfig, fee_addresses: &Vec<Address>
+    ) -> RgResult<Admitted> {
+        let replaced = self.conflicts(&mempool_entry);
+        for c in &replaced {
+            mempool_entry.transaction.transaction.validate_replacement(&c.transaction.transaction, fee_addresses)?;
+        }
+        let over_limit = mempool_entry.input_addresses.iter().any(|a| {
+            let released = replaced.iter().filter(|c| c.input_addresses.contains(a)).count();
+            let pending = self.pending_per_address.get(a).cloned().unwrap_or(0).saturating_sub(released);
+            pending >= config.max_pending_per_address
+        });
+        if over_limit {
+            counter!("redgold_mempool_address_limit").increment(1);
+            return Err(error_message(ErrorCode::MempoolAddressLimit, format!(
+                "Input address has {} or more pending mempool transactions", config.max_pending_per_address
+            )));
+        }
+        let remaining = self.entries.len().saturating_sub(replaced.len());
+        let full = remaining >= config.max_mempool_size;
+        if full {
+            let lowest_outranked = self.entries.iter()
+                .find(|e| !replaced.contains(e))
+                .map(|l| l < &mempool_entry)
+                .unwrap_or(true);
+            if !lowest_outranked {
+                counter!("redgold_mempool_full_rejected").increment(1);
+                return Err(error_message(ErrorCode::MempoolFull, format!(
+                    "Mempool full at {} entries, fee rate {} too low", self.entries.len(), mempool_entry.fee_rate
+                )));
+            }
+        }
+        for c in &replaced {
+            self.remove(c);
+            mempool_entry.transaction.replaced_transactions.push(c.hash.clone());
+        }
+        let evicted = if full { self.pop_lowest() } else { None };
+        for a in &mempool_entry.input_addresses {
+            *self.pending_per_address.entry(a.clone()).or_default() += 1;
+        }
+        for u in mempool_entry.transaction.transaction.utxo_inputs() {
+            self.utxo_spenders.insert(u.clone(), mempool_entry.clone());
+        }
+        self.entries.insert(mempool_entry);
+        Ok(Admitted { replaced, evicted })
+    }
+
+    /// Highest priority entry.
+    pub fn pop_highest(&mut self) -> Option<MempoolEntry> {
+        let o = self.entries.pop_last();
+        if let Some(me) = &o {
+            self.unindex(me);
+        }
+        o
+    }
+
+    fn pop_lowest(&mut self) -> Option<MempoolEntry> {
+        let o = self.entries.pop_first();
+        if let Some(me) = &o {
+            self.unindex(me);
+        }
+        o
+    }
+
+    pub fn remove(&mut self, entry: &MempoolEntry) -> bool {
+        let removed = self.entries.remove(entry);
+        if removed {
+            self.unindex(entry);
+        }
+        removed
+    }
+
+    fn unindex(&mut self, entry: &MempoolEntry) {
+        for u in entry.transaction.transaction.utxo_inputs() {
+            if self.utxo_spenders.get(u).map(|e| e.hash == entry.hash).unwrap_or(false) {
+                self.utxo_spenders.remove(u);
+            }
+        }
+        for a in &entry.input_addresses {
+            if let Some(c) = self.pending_per_address.get_mut(a) {
+                *c = c.saturating_sub(1);
+                if *c == 0 {
+                    self.pending_per_address.remove(a);
+                }
+            }
+        }
+    }
+}
 
 impl Mempool {
     pub fn new(relay: &Relay) -> Self {
         Self {
             relay: relay.clone(),
-            heap: BinaryHeap::new()
+            entries: MempoolEntries::default(),
         }
     }
-    pub fn push(&mut self, mempool_entry: MempoolEntry) {
+
+    /// Transactions already handed off for processing which spend any of the same inputs.
+    fn in_process_conflicts(&self, mempool_entry: &MempoolEntry) -> Vec<RequestProcessor> {
+        let inputs = mempool_entry.transaction.transaction.utxo_inputs().collect::<HashSet<_>>();
+        self.relay.transaction_channels.iter()
+            .filter(|p| p.transaction_hash != mempool_entry.hash)
+            .filter(|p| p.transaction.utxo_inputs().any(|u| inputs.contains(u)))
+            .map(|p| p.value().clone())
+            .collect_vec()
+    }
+
+    /// Insert an entry, notifying the senders of any entries it replaced or evicted. A valid
+    /// replacement also supersedes conflicting transactions which have already left the mempool
+    /// but are still in process, those are aborted once the replacement has been admitted and the
+    /// replacement is held back until they've released their inputs.
+    pub fn push(&mut self, mut mempool_entry: MempoolEntry) -> RgResult<()> {
+        let fee_addresses = self.relay.default_fee_addrs();
+        // Already aborted when this entry was first admitted
+        let in_process = self.in_process_conflicts(&mempool_entry).into_iter()
+            .filter(|p| !mempool_entry.transaction.replaced_transactions.contains(&p.transaction_hash))
+            .collect_vec();
+        for p in &in_process {
+            mempool_entry.transaction.transaction.validate_replacement(&p.transaction, &fee_addresses)?;
+            mempool_entry.transaction.replaced_transactions.push(p.transaction_hash.clone());
+        }
+        let hash = mempool_entry.hash.clone();
         let transaction = mempool_entry.transaction.transaction.clone();
-        self.heap.push(mempool_entry);
+        let admitted = self.entries.insert(mempool_entry, &self.relay.node_config.mempool, &fee_addresses)?;
+        for p in in_process {
+            counter!("redgold_mempool_replaced_in_process").increment(1);
+            p.abort_replaced(&hash).log_error().ok();
+        }
+        for c in admitted.replaced {
+            self.relay.mempool_entries.remove(&c.hash);
+            counter!("redgold_mempool_replaced").increment(1);
+            if let Some(r) = c.transaction.response_channel.as_ref() {
+                let e = error_message(ErrorCode::TransactionReplaced, format!("Replaced by transaction {}", hash.hex()));
+                r.send_rg_err(Response::from_error_info(e)).ok();
+            }
+        }
+        if let Some(evicted) = admitted.evicted {
+            self.relay.mempool_entries.remove(&evicted.hash);
+            counter!("redgold_mempool_evicted").increment(1);
+            if let Some(r) = evicted.transaction.response_channel.as_ref() {
+                let e = error_message(ErrorCode::MempoolFull, "Evicted from mempool by higher fee rate transaction");
+                r.send_rg_err(Response::from_error_info(e)).ok();
+            }
+        }
         self.relay.mempool_entries.insert(transaction.hash_or(), transaction);
+        Ok(())
+    }
+
+    /// Put a popped entry back, telling the sender if it no longer fits.
+    fn requeue(&mut self, entry: MempoolEntry) {
+        let hash = entry.hash.clone();
+        let response_channel = entry.transaction.response_channel.clone();
+        if let Err(e) = self.push(entry)
+            .with_detail("transaction_hash", hash.hex())
+            .log_error() {
+            counter!("redgold_mempool_requeue_failed").increment(1);
+            if let Some(r) = response_channel {
+                r.send_rg_err(Response::from_error_info(e)).ok();
+            }
+        }
     }
 
+    /// Highest priority entry.
     pub fn pop(&mut self) -> Option<MempoolEntry> {
-        let o = self.heap.pop();
+        let o = self.entries.pop_highest();
         if let Some(me) = &o {
-            self.relay.mempool_entries.remove(&me.transaction.transaction.hash_or());
+            self.relay.mempool_entries.remove(&me.hash);
         }
         o
     }
@@ -62,11 +253,21 @@ impl Mempool {
             // Notify subscribers for transaction channel rather than just dropping and returning error
         }
         message.transaction.validate(Some(addrs), Some(&self.relay.node_config.network))?;
+        Ok(MempoolEntry::new(message.clone(), addrs, util::current_time_millis_i64()))
+    }
+}
 
-        let entry = MempoolEntry {
-            transaction: message.clone()
-        };
-        Ok(entry)
+impl MempoolEntry {
+    pub fn new(transaction: TransactionMessage, fee_addresses: &Vec<Address>, received_time: i64) -> Self {
+        let tx = &transaction.transaction;
+        Self {
+            fee_rate: tx.fee_rate(fee_addresses),
+            pow_valid: tx.pow_validate().unwrap_or(false),
+            received_time,
+            hash: tx.hash_or(),
+            input_addresses: tx.input_address_descriptor_address_or_public_key().into_iter().unique().collect_vec(),
+            transaction,
+        }
     }
 }
 
@@ -74,27 +275,36 @@ impl Eq for MempoolEntry {}
 
 impl PartialEq<Self> for MempoolEntry {
     fn eq(&self, other: &Self) -> bool {
-        other.transaction.transaction.hash_or().eq(&self.transaction.transaction.hash_or())
+        self.hash.eq(&other.hash)
     }
 }
 
 impl PartialOrd<Self> for MempoolEntry {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        // TODO: Compare by seed fee acceptable ordering
-        other.transaction.transaction.total_output_amount().partial_cmp(
-            &self.transaction.transaction.total_output_amount())
+        Some(self.cmp(other))
     }
 }
 
+/// Greater is higher priority, fee rate first, then a valid PoW proof, then earliest received,
+/// with the hash as a final tie breaker so distinct entries never compare equal.
 impl Ord for MempoolEntry {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.cmp(other)
+        self.fee_rate.cmp(&other.fee_rate)
+            .then(self.pow_valid.cmp(&other.pow_valid))
+            .then(other.received_time.cmp(&self.received_time))
+            .then(self.hash.vec().cmp(&other.hash.vec()))
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct MempoolEntry {
-    pub transaction: TransactionMessage
+    pub transaction: TransactionMessage,
+    // Fee paid to seed addresses per serialized byte, see TransactionFeeValidator::fee_rate
+    pub fee_rate: i64,
+    pub pow_valid: bool,
+    pub received_time: i64,
+    pub hash: Hash,
+    pub input_addresses: Vec<Address>,
 }
 
 
@@ -107,7 +317,7 @@ impl IntervalFold for Mempool {
         let genesis_hash = self.relay.ds.config_store.get_genesis().await?.map(|g| g.hash_or());
 
         let messages = self.relay.mempool.recv_while()?;
-        gauge!("redgold_mempool_messages_recv").set(self.heap.len() as f64);
+        gauge!("redgold_mempool_messages_recv").set(self.entries.len() as f64);
 
         let addrs = self.relay.node_config.seed_peer_addresses();
         for message in messages {
@@ -137,16 +347,32 @@ impl IntervalFold for Mempool {
                     }
                 }
                 Ok(entry) => {
-                    counter!("redgold_mempool_added").increment(1);
-                    self.push(entry);
+                    let response_channel = entry.transaction.response_channel.clone();
+                    match self.push(entry) {
+                        Ok(_) => {
+                            counter!("redgold_mempool_added").increment(1);
+                        }
+                        Err(e) => {
+                            if let Some(r) = response_channel {
+                                r.send_rg_err(Response::from_error_info(e))?;
+                            }
+                        }
+                    }
                 }
             }
         }
-        gauge!("redgold_mempool_size").set(self.heap.len() as f64);
+        gauge!("redgold_mempool_size").set(self.entries.len() as f64);
 
+        let mut awaiting_release = vec![];
         loop {
             let option = self.pop();
             if let Some(entry) = option {
+                let replaced_in_process = entry.transaction.replaced_transactions.iter()
+                    .any(|h| self.relay.transaction_channels.contains_key(h));
+                if replaced_in_process {
+                    awaiting_release.push(entry);
+                    continue;
+                }
                 counter!("redgold_mempool_pop").increment(1);
                 match self.relay.transaction_process.sender.try_send(entry.transaction.clone()) {
                     Ok(_) => {
@@ -155,7 +381,8 @@ impl IntervalFold for Mempool {
                     Err(e) => {
                         match e {
                             TrySendError::Full(_) => {
-                                self.push(entry);
+                                // Was just removed so there should be room to put it back
+                                self.requeue(entry);
                                 break;
                             }
                             TrySendError::Disconnected(_) => {
@@ -168,7 +395,123 @@ impl IntervalFold for Mempool {
                 break;
             }
         }
+        for entry in awaiting_release {
+            self.requeue(entry);
+        }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+fn test_entry(fee_address: &Address, nonce: u8, fee: i64, received_time: i64) -> MempoolEntry {
+    test_spend(fee_address, nonce, fee, received_time, vec![])
+}
+
+#[cfg(test)]
+fn test_spend(fee_address: &Address, nonce: u8, fee: i64, received_time: i64, inputs: Vec<i64>) -> MempoolEntry {
+    use redgold_schema::structs::{Input, Output};
+    let mut tx = Transaction::default();
+    for i in inputs {
+        let mut input = Input::default();
+        input.utxo_id = Some(UtxoId::new(&Hash::from_string_calculate("parent"), i));
+        tx.inputs.push(input);
+    }
+    tx.outputs.push(Output::new(fee_address, fee));
+    // Same serialized size for every nonce, so equal fees give equal fee rates
+    tx.outputs.push(Output::new(&Address::script_hash(&vec![nonce]).expect("address"), 1));
+    let message = TransactionMessage {
+        transaction: tx,
+        response_channel: None,
+        origin: None,
+        origin_ip: None,
+        replaced_transactions: vec![],
+    };
+    MempoolEntry::new(message, &vec![fee_address.clone()], received_time)
+}
+
+#[test]
+fn mempool_entry_priority_ordering() {
+    let fee_address = Address::script_hash(&vec![1]).expect("address");
+    let low = test_entry(&fee_address, 2, 1000, 0);
+    let high = test_entry(&fee_address, 3, 5000, 10);
+    let high_later = test_entry(&fee_address, 4, 5000, 20);
+    let higher = test_entry(&fee_address, 5, 6000, 30);
+    assert_eq!(high.fee_rate, high_later.fee_rate);
+    assert!(high > low);
+    // Equal fee rate, earlier received wins
+    assert!(high > high_later);
+    assert!(higher > high);
+
+    let mut set = BTreeSet::new();
+    set.insert(high_later.clone());
+    set.insert(low.clone());
+    set.insert(higher.clone());
+    set.insert(high.clone());
+    assert_eq!(set.pop_last(), Some(higher));
+    assert_eq!(set.pop_last(), Some(high));
+    assert_eq!(set.pop_last(), Some(high_later));
+    assert_eq!(set.pop_first(), Some(low));
+}
+
+#[test]
+fn mempool_full_evicts_lowest_priority() {
+    let fee_address = Address::script_hash(&vec![1]).expect("address");
+    let fee_addresses = vec![fee_address.clone()];
+    let config = MempoolConfig { max_mempool_size: 2, ..Default::default() };
+    let mut entries = MempoolEntries::default();
+    let low = test_entry(&fee_address, 2, 1000, 0);
+    let mid = test_entry(&fee_address, 3, 2000, 0);
+    assert_eq!(entries.insert(low.clone(), &config, &fee_addresses).expect("insert"), Admitted::default());
+    assert_eq!(entries.insert(mid.clone(), &config, &fee_addresses).expect("insert"), Admitted::default());
+
+    // Lower than everything pending, rejected rather than evicting
+    let lowest = test_entry(&fee_address, 4, 500, 0);
+    let err = entries.insert(lowest, &config, &fee_addresses).expect_err("full");
+    assert_eq!(err.code, ErrorCode::MempoolFull as i32);
+    assert_eq!(entries.len(), 2);
+
+    let high = test_entry(&fee_address, 5, 3000, 0);
+    let admitted = entries.insert(high.clone(), &config, &fee_addresses).expect("insert");
+    assert_eq!(admitted.evicted, Some(low));
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries.pop_highest(), Some(high));
+    assert_eq!(entries.pop_highest(), Some(mid));
+    assert!(entries.is_empty());
+}
+
+#[test]
+fn replacement_keeps_pending_entry_until_admitted() {
+    let fee_address = Address::script_hash(&vec![1]).expect("address");
+    let fee_addresses = vec![fee_address.clone()];
+    let sender = Address::script_hash(&vec![10]).expect("address");
+    let other_sender = Address::script_hash(&vec![11]).expect("address");
+    let config = MempoolConfig { max_mempool_size: 2, max_pending_per_address: 1, ..Default::default() };
+    let from = |mut e: MempoolEntry, addresses: Vec<Address>| {
+        e.input_addresses = addresses;
+        e
+    };
+    let mut entries = MempoolEntries::default();
+    let pending = from(test_spend(&fee_address, 2, 1000, 0, vec![0]), vec![sender.clone()]);
+    let other = from(test_spend(&fee_address, 3, 5000, 0, vec![5]), vec![other_sender.clone()]);
+    entries.insert(pending.clone(), &config, &fee_addresses).expect("insert");
+    entries.insert(other.clone(), &config, &fee_addresses).expect("insert");
+
+    // Full and at the address limit, both freed by the entry being replaced
+    let replacement = from(test_spend(&fee_address, 4, 2000, 0, vec![0]), vec![sender.clone()]);
+    let admitted = entries.insert(replacement.clone(), &config, &fee_addresses).expect("replace");
+    assert_eq!(admitted.replaced, vec![pending.clone()]);
+    assert_eq!(admitted.evicted, None);
+    assert_eq!(entries.len(), 2);
+
+    // Fails the address limit on another input address, the pending entry must survive
+    let rejected = from(test_spend(&fee_address, 5, 3000, 0, vec![0]), vec![sender, other_sender]);
+    let err = entries.insert(rejected.clone(), &config, &fee_addresses).expect_err("limit");
+    assert_eq!(err.code, ErrorCode::MempoolAddressLimit as i32);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries.conflicts(&rejected), vec![replacement]);
+
+    // Underpaying replacement
+    let cheap = test_spend(&fee_address, 6, 1500, 0, vec![0]);
+    let err = entries.insert(cheap, &config, &fee_addresses).expect_err("fee");
+    assert_eq!(err.code, ErrorCode::ReplacementRejected as i32);
+}
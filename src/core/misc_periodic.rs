@@ -20,6 +20,7 @@ impl IntervalFold for MiscPeriodic {
     async fn interval_fold(&mut self) -> RgResult<()> {
         self.relay.ds.transaction_store.delete_old_rejected_transaction(None, None).await?;
         self.relay.ds.count_gauges().await?;
+        self.relay.peer_rate_limiter.evict_idle();
         Ok(())
     }
 }
\ No newline at end of file
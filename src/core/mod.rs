@@ -21,3 +21,4 @@ pub mod transport;
 pub mod misc_periodic;
 pub(crate) mod backup;
 pub mod services;
+pub mod stream_events;
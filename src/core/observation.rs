@@ -224,6 +224,7 @@ impl ObservationBuffer {
 
 
         self.relay.ds.observation.insert_observation_and_edges(&signed_tx).await?;
+        self.relay.stream_events.publish_observation(&signed_tx);
         // Verify stored.
         assert!(self.relay.ds.observation.query_observation(&signed_tx.hash_or()).await?.is_some());
         // TODO: Test to see if one of the transactions was stored correctly.
@@ -76,6 +76,7 @@ impl ObservationHandler {
             counter!("redgold.observation.peer.added").increment(1);
             self.notify_subscribers(&o).await;
             self.relay.ds.observation.insert_observation_and_edges(&o).await?;
+            self.relay.stream_events.publish_observation(&o);
         } else {
             counter!("redgold.observation.peer.rejected").increment(1);
             // info!("Rejected peer observation: {}", o.json_or());
@@ -7,7 +7,7 @@ use flume::{Sender, TryRecvError};
 use futures::{TryFutureExt, TryStreamExt};
 use itertools::Itertools;
 use metrics::{counter, histogram};
-use redgold_schema::structs::{ContentionKey, ContractStateMarker, ExecutionInput, ExecutorBackend, GossipTransactionRequest, Hash, PublicResponse, QueryObservationProofRequest, UtxoId, ValidationType};
+use redgold_schema::structs::{ContentionKey, GossipTransactionRequest, Hash, PublicResponse, QueryObservationProofRequest, UtxoId, ValidationType};
 use redgold_schema::message::Response;
 use redgold_schema::message::Request;
 use redgold_schema::{message, struct_metadata_new, structs, RgResult, SafeOption};
@@ -17,6 +17,7 @@ use tokio::task::{JoinError, JoinHandle};
 use tracing::{debug, error, info};
 use uuid::Uuid;
 
+use crate::core::contract::contract_state_manager::validate_deploy_output;
 use crate::core::internal_message::{PeerMessage, TransactionMessage};
 use crate::core::relay::Relay;
 use crate::core::resolver::resolve_transaction;
@@ -38,7 +39,6 @@ use crate::util::runtimes::build_runtime;
 use futures::{stream::FuturesUnordered, StreamExt};
 use redgold_common::flume_send_help::{new_bounded_channel, Channel, RecvAsyncErrorInfo, SendErrorInfo};
 use redgold_data::data_store::DataStore;
-use redgold_executor::extism_wrapper;
 use redgold_keys::proof_support::ProofSupport;
 use redgold_keys::transaction_support::TransactionSupport;
 use redgold_keys::tx_proof_validate::TransactionProofValidator;
@@ -56,6 +56,8 @@ pub struct Conflict {
     //  Not really necessary but put other info here.
     transaction_hash: Hash,
     abort: bool,
+    // Abort because a higher fee replacement was admitted to the mempool
+    replacement: bool,
     processing_start_time: i64,
     request_processer: RequestProcessor,
 }
@@ -93,6 +95,17 @@ impl RequestProcessor {
             internal_channel: new_bounded_channel(200),
         };
     }
+
+    /// Abort processing in favor of a replacement admitted to the mempool.
+    pub fn abort_replaced(&self, replacement: &Hash) -> RgResult<()> {
+        self.sender.send_rg_err(Conflict {
+            transaction_hash: replacement.clone(),
+            abort: true,
+            replacement: true,
+            processing_start_time: current_time_millis_i64(),
+            request_processer: self.clone(),
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -279,7 +292,8 @@ impl TransactionProcessContext {
         // Change these to raw Response instead of public response
         let mut pr = message::Response::default();
         match result_or_error.log_error() {
-            Ok(o) => {
+            Ok(mut o) => {
+                o.replaced_transaction_hashes = transaction_message.replaced_transactions.clone();
                 metadata.success = true;
                 counter!("redgold_process_transaction_success", &self.relay.node_config.gauge_id()).increment(1);
                 pr.submit_transaction_response = Some(o);
@@ -336,6 +350,20 @@ impl TransactionProcessContext {
 
         // Validate obvious schema related errors / local errors requiring no other context information
         transaction.validate(Some(&self.relay.node_config.seed_peer_addresses()), Some(&self.relay.node_config.network))?;
+        // Transactions requiring counter-party acceptance are held by relays until every
+        // recipient signs, and are never accepted once the acceptance window closes.
+        if transaction.requires_acceptance() {
+            let observed = self.relay.acceptance_observation_time(&transaction.hash_or()).await?;
+            transaction.validate_acceptance(observed)?;
+        }
+        if transaction.is_reward() {
+            crate::trust::rewards::validate_reward_transaction(&self.relay, transaction).await?;
+        }
+        // Contract code must be valid before the deploy is accepted on any network, execution
+        // afterwards only produces the initial state.
+        for o in transaction.outputs.iter().filter(|o| o.is_deploy()) {
+            validate_deploy_output(&self.relay, o).await?;
+        }
         Ok(())
 
     }
@@ -363,6 +391,8 @@ impl TransactionProcessContext {
         ).await?;
         resolver_data.validate_input_output_amounts_match()?;
         resolver_data.validate_resolved_fees(&self.relay.default_fee_addrs())?;
+        let height = self.relay.block_height_at(*transaction.time()?).await?;
+        resolver_data.validate_time_locks(height)?;
         transaction = resolver_data.with_enriched_inputs()?;
 
         let fixed_utxo_ids = transaction.fixed_utxo_ids_of_inputs()?;
@@ -386,6 +416,7 @@ impl TransactionProcessContext {
         let self_conflict = Conflict {
             transaction_hash: hash.clone(),
             abort: false,
+            replacement: false,
             processing_start_time: processing_time_start.clone(),
             request_processer: request_processor.clone(),
         };
@@ -495,6 +526,11 @@ impl TransactionProcessContext {
                 .await.ok();
             if let Some(o) = res {
                 let conflict = o?;
+                if conflict.replacement {
+                    return Err(error_message(ErrorCode::TransactionReplaced, format!(
+                        "Replaced by transaction {}", conflict.transaction_hash.hex()
+                    )));
+                }
                 if conflict.abort {
                     // translate error codes for db access / etc. into internal server error.
                     // TODO: This error code just indicates a conflict, not necessarily a deliberate double spend
@@ -531,6 +567,7 @@ impl TransactionProcessContext {
             let this_as_conflict = Conflict {
                 transaction_hash: hash.clone(),
                 abort: true,
+                replacement: false,
                 processing_start_time: processing_time_start.clone(),
                 request_processer: request_processor.clone(),
             };
@@ -650,37 +687,13 @@ impl TransactionProcessContext {
         submit_response.transaction = Some(transaction.clone());
         submit_response.transaction_hash = Some(hash.clone());
 
-        // Here now we need to send this transaction to a contract state manager if it's appropriate
-
-        // For now this is the 'deploy' operation -- but it's not correct / validated yet.
+        // Contract deploys and requests are handed to the contract state manager, which validates
+        // and executes them in order and answers with the resulting state marker.
         if !self.relay.node_config.network.is_main() {
         for o in &transaction.outputs {
-            if let Some(c) = o.contract
-                .as_ref().and_then(|c| c.code_execution_contract.as_ref()) {
-                if let Some(b) = c.executor {
-                    if b == (ExecutorBackend::Extism as i32) {
-                        if let Some(code) = &c.code {
-                            let mut input = ExecutionInput::default();
-                            input.tx = Some(transaction.clone());
-                            debug!("Invoking deploy contract call");
-                            let er = extism_wrapper::invoke_wasm(
-                                &*code.value,
-                                "extism_entrypoint",
-                                input
-                            ).await?;
-                            let mut csm = ContractStateMarker::default();
-                            csm.state = er.data.as_ref()
-                                .and_then(|d| d.state.clone());
-                            csm.address = o.address.clone();
-                            csm.time = transaction.time()?.clone();
-                            csm.index_counter = 0;
-                            csm.transaction_marker = Some(transaction.hash_or());
-                            self.relay.ds.state.insert_state(csm).await?;
-                            // TODO: ^ save the error above and return it to the user for processing this?
-                            // we should know about this error well before we accept the transaction.
-                        }
-                    }
-                }
+            if o.is_deploy() {
+                let csm = self.relay.send_contract_ordering_message(&transaction, &o).await?;
+                info!("Deployed contract initial CSM: {}", csm.json_or())
             }
             if o.is_request() {
                 let csm = self.relay.send_contract_ordering_message(&transaction, &o).await?;
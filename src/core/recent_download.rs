@@ -77,6 +77,7 @@ impl RecentDownload {
                                 // Not needed here
                                 update.parent_transaction.signable_hash(),
                                 false,
+                                !update.parent_transaction.is_reward(),
                                 update.parent_transaction.time()?.clone(),
                             ).await
                                 .with_detail("resolve_invocation", "recent_download");
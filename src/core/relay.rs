@@ -12,6 +12,7 @@ use std::time::{Duration, Instant};
 
 use crate::core::discover::peer_discovery::DiscoveryMessage;
 use crate::core::internal_message;
+use crate::core::transact::tx_validate::TransactionValidator;
 use crate::schema::structs::{
     ErrorCode, ErrorInfo, NodeState, PeerMetadata, SubmitTransactionRequest, SubmitTransactionResponse,
 };
@@ -25,12 +26,13 @@ use metrics::counter;
 use redgold_common::flume_send_help;
 use redgold_common::flume_send_help::{new_channel, Channel};
 use redgold_common_no_wasm::tx_new::TransactionBuilderSupport;
-use redgold_schema::observability::errors::EnhanceErrorInfo;
+use redgold_schema::observability::errors::{EnhanceErrorInfo, Loggable};
 use redgold_schema::structs::{AboutNodeRequest, Address, ContentionKey, ContractStateMarker, CurrencyAmount, DynamicNodeMetadata, GossipTransactionRequest, Hash, HashType, HealthRequest, InitiateMultipartyKeygenRequest, InitiateMultipartySigningRequest, MultipartyIdentifier, NetworkEnvironment, NodeMetadata, ObservationProof, Output, PartitionInfo, PartyId, PeerId, PeerIdInfo, PeerNodeInfo, PublicKey, ResolveHashRequest, RoomId, State, SupportedCurrency, SupportedCurrencyIter, Transaction, TransportBackend, TrustData, UtxoEntry, UtxoId, ValidationType};
 use redgold_schema::message::Response;
 use redgold_schema::message::Request;
 use redgold_schema::tx::tx_builder::TransactionBuilder;
-use redgold_schema::{error_info, struct_metadata_new, structs, ErrorInfoContext, RgResult};
+use redgold_schema::{error_info, error_message, struct_metadata_new, structs, ErrorInfoContext, RgResult};
+use redgold_schema::time_lock::block_height_at;
 use strum::IntoEnumIterator;
 use tokio::runtime::Runtime;
 use tokio::sync::MutexGuard;
@@ -46,6 +48,10 @@ use crate::schema::structs::{Observation, ObservationMetadata};
 use crate::schema::SafeOption;
 use crate::util;
 use crate::util::keys::ToPublicKey;
+use crate::core::transport::noise_transport::NoiseSessions;
+use crate::core::transport::rate_limit::PeerRateLimiter;
+use crate::core::stream_events::StreamEvents;
+use crate::trust::eigentrust::EigenTrustResult;
 use redgold_common::flume_send_help::RecvAsyncErrorInfo;
 use redgold_data::data_store::DataStore;
 use redgold_data::peer::PeerTrustQueryResult;
@@ -63,6 +69,9 @@ use redgold_schema::transaction::{amount_to_raw_amount, TransactionMaybeError};
 use redgold_schema::util::lang_util::WithMaxLengthString;
 use redgold_schema::util::xor_distance::{xorf_conv_distance, xorfc_hash};
 
+// Held transactions are kept this long past their acceptance expiry before being pruned.
+const ACCEPTANCE_PRUNE_GRACE_MILLIS: i64 = 1000 * 60 * 10;
+
 #[derive(Clone)]
 pub struct TransactionErrorCache {
     pub process_time: u64,
@@ -197,11 +206,29 @@ pub struct Relay {
     /// Authorization channel for multiparty keygen to determine room_id and participating keys
     pub mp_keygen_authorizations: Arc<Mutex<HashMap<RoomId, InitiateMultipartyKeygenRequest>>>,
     pub mp_signing_authorizations: Arc<Mutex<HashMap<RoomId, InitiateMultipartySigningRequest>>>,
+    /// Party orders claimed by an outstanding co-signing request, keyed by order event
+    /// identifier with the claim time
+    pub party_orders_in_flight: Arc<DashMap<String, i64>>,
     pub contract_state_manager_channels: Vec<Channel<ContractStateMessage>>,
+    /// Contract request transaction hashes currently awaiting ordering, shared with peers so
+    /// they can agree on the request set for a contention key
+    pub contract_pending_requests: Arc<DashMap<ContentionKey, Vec<Hash>>>,
     pub contention: Vec<Channel<ContentionMessage>>,
     pub predicted_trust_overall_rating_score: Arc<Mutex<HashMap<PeerId, f64>>>,
+    // Latest EigenTrust run and its inputs, label scale ratings from it fill the map above
+    pub eigen_trust: Arc<Mutex<EigenTrustResult>>,
     pub unknown_resolved_inputs: Channel<ResolvedInput>,
     pub mempool_entries: Arc<DashMap<Hash, Transaction>>,
+    // Genesis transaction time, cached once known as it never changes
+    pub genesis_time: Arc<AtomicCell<Option<i64>>>,
+    // Transactions awaiting counter-party acceptance, keyed by signed hash
+    pub pending_acceptances: Arc<DashMap<Hash, Transaction>>,
+    // Encrypted peer transport sessions
+    pub noise: NoiseSessions,
+    // Per peer token buckets for expensive request types
+    pub peer_rate_limiter: PeerRateLimiter,
+    // Accepted transactions, observation proofs and party fulfillments for streaming subscribers
+    pub stream_events: StreamEvents,
     pub faucet_rate_limiter: Arc<Mutex<HashMap<String, (Instant, i32)>>>,
     pub tx_writer: Channel<TxWriterMessage>,
     pub peer_send_failures: Arc<tokio::sync::Mutex<HashMap<PublicKey, (ErrorInfo, i64)>>>,
@@ -211,6 +238,7 @@ pub struct Relay {
     pub btc_multisig_wallets: Arc<tokio::sync::Mutex<HashMap<Address, Arc<tokio::sync::Mutex<SingleKeyBitcoinWallet<Tree>>>>>>,
     pub peer_info: PeerInfo,
     pub eth_daq: EthDaq,
+    pub btc_daq: BtcDaq,
     pub monero_wallet_messages: Channel<MoneroSyncInteraction>,
     pub coinbase_ticker: WriteOneReadAll<CoinbaseWsTicker>,
     // pub latest_prices: Arc<Mutex<HashMap<SupportedCurrency, f64>>>,
@@ -349,6 +377,24 @@ impl Relay {
         self.node_config.seed_peer_addresses()
     }
 
+    /// Block height a transaction at `time` falls into, used to evaluate height time locks. None
+    /// until the genesis transaction is known, which never satisfies a height lock.
+    pub async fn block_height_at(&self, time: i64) -> RgResult<Option<i64>> {
+        let genesis_time = match self.genesis_time.load() {
+            Some(t) => t,
+            None => {
+                let Some(genesis) = self.ds.config_store.get_genesis().await? else {
+                    return Ok(None);
+                };
+                let t = *genesis.time()?;
+                self.genesis_time.store(Some(t));
+                t
+            }
+        };
+        let interval = self.node_config.block_formation_interval.as_millis() as i64;
+        Ok(Some(block_height_at(time, genesis_time, interval)))
+    }
+
     pub async fn discover_peer(&self, nmd: &NodeMetadata) -> RgResult<()> {
         self.discovery.send(DiscoveryMessage::new(nmd.clone(), None)).await
     }
@@ -492,7 +538,9 @@ use redgold_common::flume_send_help::SendErrorInfo;
 use redgold_common_no_wasm::arc_swap_wrapper::WriteOneReadAll;
 use redgold_crawler::coinbase::ticker_schema::TickerMessage;
 use redgold_crawler_native::coinbase_ws::CoinbaseWsTicker;
+use redgold_daq::btc_daq::BtcDaq;
 use redgold_daq::eth::EthDaq;
+use redgold_daq::external_net_daq::ExternalDaq;
 use redgold_keys::address_external::ToEthereumAddress;
 use redgold_node_core::services::monero_wallet_messages::{MoneroSyncInteraction, MoneroWalletMessage};
 use redgold_rpc_integ::eth::eth_wallet::EthWalletWrapper;
@@ -555,6 +603,12 @@ impl Relay {
         // Err(error_info("test"))
     }
 
+    pub async fn eigen_trust_result(&self) -> RgResult<EigenTrustResult> {
+        Ok(self.eigen_trust.lock().map_err(|e| error_info(format!(
+            "Failed to lock eigen_trust {}", e.to_string()))
+        )?.clone())
+    }
+
     pub async fn is_seed(&self, pk: &PublicKey) -> bool {
         self.node_config.seeds_now().iter()
             .filter(|s| s.public_key.as_ref().filter(|&p| p == pk).is_some())
@@ -1199,6 +1253,15 @@ impl Relay {
             .safe_get_msg("Missing transaction field on submit request")?
             .clone();
         tx.with_hash();
+        self.validate_acceptance_reservations(&tx)?;
+        if tx.requires_acceptance() {
+            self.prune_expired_acceptances().await?;
+            if !tx.is_accepted() {
+                return self.hold_for_acceptance(tx).await;
+            }
+            self.pending_acceptances.remove(&tx.signed_hash());
+            self.ds.transaction_store.delete_pending_acceptance(&tx.signed_hash()).await?;
+        }
         // info!("Relay submitting transaction");
         self.mempool
             .send(TransactionMessage {
@@ -1206,6 +1269,7 @@ impl Relay {
                 response_channel,
                 origin,
                 origin_ip,
+                replaced_transactions: vec![],
             })
             .await?;
 
@@ -1213,6 +1277,8 @@ impl Relay {
             transaction_hash: tx.clone().hash_or().into(),
             query_transaction_response: None,
             transaction: Some(tx.clone()),
+            replaced_transaction_hashes: vec![],
+            pending_acceptance: false,
         };
         if tx_req.sync_query_response {
             let response1 = r.recv_async_err().await?;
@@ -1223,6 +1289,82 @@ impl Relay {
         Ok(response)
     }
 
+    // Held transactions are persisted and gossiped so any relay can serve them to the recipient,
+    // and their inputs stay reserved until the acceptance window closes.
+    async fn hold_for_acceptance(&self, tx: Transaction) -> RgResult<SubmitTransactionResponse> {
+        tx.validate_from(&self.node_config)?;
+        let observed = self.acceptance_observation_time(&tx.hash_or()).await?;
+        if tx.is_acceptance_expired(observed) {
+            return Err(error_message(ErrorCode::AcceptanceExpired, "Counter-party acceptance window has closed"));
+        }
+        let signed_hash = tx.signed_hash();
+        if !self.pending_acceptances.contains_key(&signed_hash) {
+            self.ds.transaction_store.insert_pending_acceptance(&tx, tx.acceptance_expiry()?).await?;
+            self.pending_acceptances.insert(signed_hash, tx.clone());
+            counter!("redgold_pending_acceptance_held").increment(1);
+            self.gossip(&tx).await.log_error().ok();
+        }
+        Ok(SubmitTransactionResponse {
+            transaction_hash: tx.hash_or().into(),
+            query_transaction_response: None,
+            transaction: Some(tx),
+            replaced_transaction_hashes: vec![],
+            pending_acceptance: true,
+        })
+    }
+
+    fn validate_acceptance_reservations(&self, tx: &Transaction) -> RgResult<()> {
+        let reserved = self.pending_acceptances.iter()
+            .find(|p| p.value().reserves_inputs_against(tx))
+            .map(|p| p.key().clone());
+        if let Some(signed_hash) = reserved {
+            return Err(error_message(
+                ErrorCode::TransactionRejectedDoubleSpend,
+                "Inputs are reserved by a transaction awaiting counter-party acceptance"
+            )).with_detail("pending_signed_hash", signed_hash.hex());
+        }
+        Ok(())
+    }
+
+    /// Time an accepted transaction is judged against for expiry. The earliest signed observation
+    /// of it is used when one exists, so nodes agree regardless of their own clocks; the first
+    /// node to see it falls back to the time it is observing it.
+    pub async fn acceptance_observation_time(&self, hash: &Hash) -> RgResult<i64> {
+        let observed = self.ds.observation.select_observation_edge(hash).await?
+            .iter()
+            .filter_map(|p| p.metadata.as_ref())
+            .filter_map(|m| m.struct_metadata.as_ref())
+            .filter_map(|s| s.time)
+            .min();
+        Ok(observed.unwrap_or_else(util::current_time_millis_i64))
+    }
+
+    pub async fn load_pending_acceptances(&self) -> RgResult<()> {
+        for tx in self.ds.transaction_store.query_pending_acceptances().await? {
+            self.pending_acceptances.insert(tx.signed_hash(), tx);
+        }
+        self.prune_expired_acceptances().await
+    }
+
+    // Pruning only drops held transactions from storage, whether they are still reserved is
+    // decided by the conflicting transaction's time, so the local clock is used with a grace period.
+    pub async fn prune_expired_acceptances(&self) -> RgResult<()> {
+        let cutoff = util::current_time_millis_i64() - ACCEPTANCE_PRUNE_GRACE_MILLIS;
+        self.pending_acceptances.retain(|_, tx| !tx.is_acceptance_expired(cutoff));
+        self.ds.transaction_store.delete_pending_acceptances_expired_before(cutoff).await?;
+        Ok(())
+    }
+
+    /// Transactions awaiting acceptance which either pay to or spend from the address.
+    pub async fn pending_acceptances_for(&self, address: &Address) -> RgResult<Vec<Transaction>> {
+        self.prune_expired_acceptances().await?;
+        Ok(self.pending_acceptances.iter()
+            .filter(|t| t.counter_party_addresses().contains(address)
+                || t.input_address_descriptor_address_or_public_key().contains(address))
+            .map(|t| t.value().clone())
+            .collect_vec())
+    }
+
     pub async fn default() -> Self {
         Self::new(NodeConfig::default_debug()).await
     }
@@ -1256,7 +1398,7 @@ impl Relay {
             observation_metadata: flume_send_help::new_channel::<ObservationMetadataInternalSigning>(),
             peer_message_tx: new_channel::<PeerMessage>(),
             peer_message_rx: flume_send_help::new_channel::<PeerMessage>(),
-            ds,
+            ds: ds.clone(),
             transaction_channels: Arc::new(DashMap::new()),
             utxo_channels: Arc::new(DashMap::new()),
             trust: flume_send_help::new_channel::<TrustUpdate>(),
@@ -1265,11 +1407,19 @@ impl Relay {
             discovery: flume_send_help::new_bounded_channel(100),
             mp_keygen_authorizations: Arc::new(Mutex::new(Default::default())),
             mp_signing_authorizations: Arc::new(Mutex::new(Default::default())),
+            party_orders_in_flight: Arc::new(DashMap::new()),
             contract_state_manager_channels,
+            contract_pending_requests: Arc::new(DashMap::new()),
             contention,
             predicted_trust_overall_rating_score: Arc::new(Mutex::new(Default::default())),
+            eigen_trust: Arc::new(Mutex::new(Default::default())),
             unknown_resolved_inputs: flume_send_help::new_channel(),
             mempool_entries: Arc::new(Default::default()),
+            genesis_time: Arc::new(AtomicCell::new(None)),
+            pending_acceptances: Arc::new(Default::default()),
+            noise: NoiseSessions::new(&node_config.keypair()).with_peer_store(ds.peer_store.clone()),
+            peer_rate_limiter: PeerRateLimiter::default(),
+            stream_events: StreamEvents::new(),
             faucet_rate_limiter: Arc::new(Mutex::new(Default::default())),
             tx_writer: new_channel(),
             peer_send_failures: Arc::new(Default::default()),
@@ -1278,8 +1428,13 @@ impl Relay {
             btc_wallets: Arc::new(Default::default()),
             btc_multisig_wallets: Arc::new(Default::default()),
             peer_info: Default::default(),
-            // eth_daq: Default::default(),
-            eth_daq: Default::default(),
+            eth_daq: EthDaq {
+                daq: ExternalDaq::with_store(SupportedCurrency::Ethereum, ds.external_event.clone()),
+                ..Default::default()
+            },
+            btc_daq: BtcDaq {
+                daq: ExternalDaq::with_store(SupportedCurrency::Bitcoin, ds.external_event.clone()),
+            },
             monero_wallet_messages: Default::default(),
             coinbase_ticker: Default::default(),
         }
@@ -171,6 +171,7 @@ pub fn validate_single_result(
 pub async fn resolve_input(
     input: Input, relay: Relay, _peers: Vec<PublicKey>, signable_hash: Hash,
     check_liveness: bool,
+    verify_proof: bool,
     time: i64
 )
                            -> Result<ResolvedInput, ErrorInfo> {
@@ -272,7 +273,9 @@ pub async fn resolve_input(
         signable_hash,
         parent_output,
     };
-    resolved.verify_proof()?;
+    if verify_proof {
+        resolved.verify_proof()?;
+    }
     Ok(resolved)
 }
 // }
@@ -347,8 +350,23 @@ impl ResolvedTransaction {
         Ok(())
     }
 
+    /// Reject spending outputs whose time locks, including the lock period and windows of the
+    /// transaction which created them, aren't satisfied at this transaction's time and the block
+    /// height it falls into.
+    pub fn validate_time_locks(&self, height: Option<i64>) -> RgResult<()> {
+        let time = self.transaction.time()?.clone();
+        for r in &self.fixed_resolutions {
+            let index = r.input.utxo_id.safe_get_msg("missing utxoid")?.output_index as usize;
+            r.parent_transaction.output_spend_lock(index)?.validate_spendable(time, height)?;
+        }
+        Ok(())
+    }
+
     pub fn validate_resolved_fees(&self, fee_addrs: &Vec<Address>) -> RgResult<()> {
         let max_parent_time = self.max_parent_time();
+        if self.transaction.is_reward() {
+            return Ok(());
+        }
         if !self.transaction.validate_resolved_fee(fee_addrs, max_parent_time) {
             "Transaction fee is too low or to unsupported fee address"
                 .to_error()
@@ -385,7 +403,8 @@ pub async fn resolve_transaction(tx: &Transaction, relay: Relay
         .map(|input|
         async{tokio::spawn(resolve_input(input.clone(), relay.clone(),
                                         // runtime.clone(),
-                                         peers.clone(), tx.signable_hash().clone(), true, time))
+                                         // Reward inputs are unsigned fee outputs, checked by rebuilding the epoch
+                                         peers.clone(), tx.signable_hash().clone(), true, !tx.is_reward(), time))
             .await.map_err(|e| error_info(e.to_string()))}
             .map_err(|mut e| {
                 e.with_detail("invocation", "resolve_transaction_async_input");
@@ -65,7 +65,8 @@ impl Node {
         let udp = UdpServer::new(
             relay.peer_message_rx.clone(),
             relay.udp_outgoing_messages.clone(),
-            Some(node_config.udp_port()));
+            Some(node_config.udp_port()),
+            node_config.noise_transport_enabled().then(|| relay.noise.clone()));
         sjh.add("UdpServer", tokio::spawn(udp));
 
         if node_config.nat_traversal_required() ||
@@ -147,6 +148,12 @@ impl Node {
             Shuffle::new(&relay), relay.node_config.shuffle_interval, false
         ));
 
+        sjh.add("BlockFormation", run_interval_fold(
+            crate::core::block_formation::BlockFormationProcess::new(&relay),
+            relay.node_config.block_formation_interval.clone(),
+            false
+        ));
+
         sjh.add("Mempool", run_interval_fold(
             crate::core::mempool::Mempool::new(&relay), relay.node_config.mempool.interval.clone(), false
         ));
@@ -185,12 +192,28 @@ impl Node {
         sjh.add("AwsBackup", run_interval_fold(
             crate::core::backup::aws_backup::AwsBackup::new(&relay), Duration::from_secs(86400), false
         ));
+        if !node_config.backup_targets().is_empty() {
+            sjh.add("EncryptedBackup", run_interval_fold(
+                crate::core::backup::encrypted_backup::EncryptedBackupService::new(&relay), Duration::from_secs(86400), false
+            ));
+        }
+        sjh.add("Trust", run_interval_fold(
+            crate::trust::Trust::new(&relay), Duration::from_secs(300), false
+        ));
+        sjh.add("Rewards", run_interval_fold(
+            crate::trust::rewards::Rewards::new(&relay),
+            Duration::from_secs(relay.node_config.reward_poll_interval_secs),
+            false
+        ));
         sjh.add("RecentParityCheck", run_interval_fold(
             RecentParityCheck::new(&relay), Duration::from_secs(3600), false
         ));
 
-        if node_config.network.is_main_stage_network() {
+        // Party confirmation depth is measured against the tips these record, so they also run
+        // whenever party mode is enabled.
+        if node_config.network.is_main_stage_network() || node_config.enable_party_mode() {
             sjh.add("EthDaq", relay.eth_daq.start(&relay.node_config).await);
+            sjh.add("BtcDaq", relay.btc_daq.start(&relay.node_config).await);
         }
 
         sjh.handles
@@ -0,0 +1,230 @@
+use metrics::counter;
+use rand::RngCore;
+use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+use redgold_schema::party::party_events::OrderFulfillment;
+use redgold_schema::proto_serde::ProtoSerde;
+use redgold_schema::structs::{ObservationProof, PublicKey, Transaction};
+use redgold_schema::util::times::current_time_millis;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// Events kept in memory for clients resuming with a token, older gaps are backfilled from the
+/// database where possible.
+const REPLAY_BUFFER_SIZE: usize = 10_000;
+const LIVE_CHANNEL_SIZE: usize = 4096;
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// A transaction was accepted, addresses are every rendered input and output address.
+    Transaction {
+        hash: String,
+        addresses: Vec<String>,
+        transaction: Transaction,
+    },
+    /// An observation proof was received for the hash.
+    ObservationProof {
+        hash: String,
+        proof: ObservationProof,
+    },
+    /// A party order was fulfilled.
+    PartyFulfillment {
+        party_key: String,
+        fulfillment: OrderFulfillment,
+        fulfillment_event: String,
+    },
+    /// Events between the resume token and this one may have been missed, the client should
+    /// resynchronize the affected topics by polling once.
+    Gap {
+        reason: String,
+    },
+}
+
+impl StreamEvent {
+    pub fn transaction(tx: Transaction) -> Self {
+        let mut addresses = tx.input_address_descriptor_address_or_public_key();
+        addresses.extend(tx.outputs.iter().filter_map(|o| o.address.clone()));
+        let mut addresses = addresses.iter().flat_map(|a| a.render_string().ok()).collect::<Vec<String>>();
+        addresses.sort();
+        addresses.dedup();
+        StreamEvent::Transaction {
+            hash: tx.hash_or().hex(),
+            addresses,
+            transaction: tx,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct StreamEnvelope {
+    /// Pass back as `resume` or the SSE `Last-Event-ID` header to continue after this event.
+    pub token: String,
+    pub seq: u64,
+    /// Acceptance position of the latest transaction covered by this event.
+    pub position: i64,
+    pub time: i64,
+    pub event: StreamEvent,
+}
+
+/// Parsed resume token, `<boot id>-<sequence>-<position>`. The boot id identifies the in memory
+/// sequence, which restarts with the node. The position is the acceptance position of the last
+/// transaction delivered, transactions after it are backfilled from the database.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResumeToken {
+    pub boot_id: String,
+    pub seq: u64,
+    pub position: i64,
+}
+
+impl ResumeToken {
+    pub fn render(&self) -> String {
+        format!("{}-{}-{}", self.boot_id, self.seq, self.position)
+    }
+
+    pub fn parse(s: &str) -> Option<ResumeToken> {
+        let mut split = s.trim().split('-');
+        let boot_id = split.next()?.to_string();
+        let seq = split.next()?.parse::<u64>().ok()?;
+        let position = split.next()?.parse::<i64>().ok()?;
+        if split.next().is_some() {
+            return None;
+        }
+        Some(ResumeToken { boot_id, seq, position })
+    }
+}
+
+/// Where a resuming client picks up.
+pub enum Replay {
+    /// Every event after the token is still buffered.
+    Buffered(Vec<StreamEnvelope>),
+    /// The token is from a previous boot or has fallen out of the buffer.
+    Expired,
+}
+
+/// Fan out of node events to streaming API subscribers.
+#[derive(Clone)]
+pub struct StreamEvents {
+    boot_id: String,
+    next_seq: Arc<AtomicU64>,
+    position: Arc<AtomicI64>,
+    buffer: Arc<Mutex<VecDeque<StreamEnvelope>>>,
+    sender: broadcast::Sender<StreamEnvelope>,
+}
+
+impl StreamEvents {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(LIVE_CHANNEL_SIZE);
+        Self {
+            boot_id: hex::encode(rand::rngs::OsRng.next_u64().to_be_bytes()),
+            next_seq: Arc::new(AtomicU64::new(1)),
+            position: Arc::new(AtomicI64::new(0)),
+            buffer: Arc::new(Mutex::new(VecDeque::with_capacity(REPLAY_BUFFER_SIZE))),
+            sender,
+        }
+    }
+
+    /// Live receiver along with the last sequence published before it, every later event is
+    /// delivered to the receiver.
+    pub fn subscribe(&self) -> (broadcast::Receiver<StreamEnvelope>, u64) {
+        let _guard = self.buffer.lock();
+        (self.sender.subscribe(), self.next_seq.load(Ordering::SeqCst) - 1)
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    pub fn token(&self, seq: u64, position: i64) -> String {
+        ResumeToken { boot_id: self.boot_id.clone(), seq, position }.render()
+    }
+
+    pub fn publish(&self, event: StreamEvent) {
+        self.publish_at(event, None)
+    }
+
+    fn publish_at(&self, event: StreamEvent, accepted_position: Option<i64>) {
+        let time = current_time_millis();
+        // Sequence assignment, buffering and sending happen under the lock so the buffer stays
+        // ordered and subscribers get a consistent starting point.
+        let Ok(mut buffer) = self.buffer.lock() else { return };
+        if let Some(p) = accepted_position {
+            self.position.fetch_max(p, Ordering::SeqCst);
+        }
+        let position = self.position.load(Ordering::SeqCst);
+        let seq = self.next_seq.fetch_add(1, Ordering::SeqCst);
+        let envelope = StreamEnvelope {
+            token: self.token(seq, position),
+            seq,
+            position,
+            time,
+            event,
+        };
+        if buffer.len() >= REPLAY_BUFFER_SIZE {
+            buffer.pop_front();
+        }
+        buffer.push_back(envelope.clone());
+        // No receivers is not an error, nobody is subscribed.
+        let _ = self.sender.send(envelope);
+        counter!("redgold_stream_events_published").increment(1);
+    }
+
+    /// Publish an accepted transaction along with its acceptance position.
+    pub fn publish_transaction(&self, tx: &Transaction, position: i64) {
+        self.publish_at(StreamEvent::transaction(tx.clone()), Some(position));
+    }
+
+    /// Publish a proof for each hash covered by an observation transaction.
+    pub fn publish_observation(&self, observation: &Transaction) {
+        let Ok(proofs) = observation.build_observation_proofs() else { return };
+        for proof in proofs {
+            let hash = proof.metadata.as_ref()
+                .and_then(|m| m.observed_hash.as_ref())
+                .map(|h| h.hex());
+            if let Some(hash) = hash {
+                self.publish(StreamEvent::ObservationProof { hash, proof });
+            }
+        }
+    }
+
+    pub fn publish_party_fulfillment(&self, party_key: &PublicKey, fulfillment: &OrderFulfillment, fulfillment_event: String) {
+        self.publish(StreamEvent::PartyFulfillment {
+            party_key: party_key.hex(),
+            fulfillment: fulfillment.clone(),
+            fulfillment_event,
+        });
+    }
+
+    /// Buffered events after the token, if they're all still buffered.
+    pub fn replay_after(&self, token: &ResumeToken) -> Replay {
+        if token.boot_id != self.boot_id {
+            return Replay::Expired;
+        }
+        let Ok(buffer) = self.buffer.lock() else { return Replay::Expired };
+        let oldest = buffer.front().map(|e| e.seq).unwrap_or(self.next_seq.load(Ordering::SeqCst));
+        if token.seq + 1 < oldest {
+            return Replay::Expired;
+        }
+        Replay::Buffered(buffer.iter().filter(|e| e.seq > token.seq).cloned().collect())
+    }
+}
+
+#[test]
+fn resume_replays_only_missed_events() {
+    let events = StreamEvents::new();
+    for _ in 0..3 {
+        events.publish(StreamEvent::Gap { reason: "test".to_string() });
+    }
+    let first = events.buffer.lock().unwrap().front().cloned().unwrap();
+    let token = ResumeToken::parse(&first.token).expect("token");
+    assert_eq!(token.seq, first.seq);
+    assert_eq!(token.position, first.position);
+    match events.replay_after(&token) {
+        Replay::Buffered(missed) => assert_eq!(missed.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![2, 3]),
+        Replay::Expired { .. } => panic!("expected buffered replay"),
+    }
+    let other_boot = ResumeToken { boot_id: "other".to_string(), ..token };
+    assert!(matches!(events.replay_after(&other_boot), Replay::Expired));
+}
@@ -1,6 +1,6 @@
 use redgold_keys::tx_proof_validate::TransactionProofValidator;
 use redgold_schema::conf::node_config::NodeConfig;
-use redgold_schema::fee_validator::TransactionFeeValidator;
+use redgold_schema::fee_validator::{ContractFeeValidator, TransactionFeeValidator};
 use redgold_schema::helpers::easy_json::EasyJson;
 use redgold_schema::observability::errors::EnhanceErrorInfo;
 use redgold_schema::structs::{Address, NetworkEnvironment, Transaction};
@@ -16,7 +16,8 @@ impl TransactionValidator for Transaction {
         self.validate_keys(network)?;
         if let Some(addrs) = fee_addrs {
             // Temporary bypass for node config updates, to be removed later
-            let allow_bypass = self.is_metadata_or_obs();
+            // Reward transactions pay no fee, their outputs are checked against the epoch instead
+            let allow_bypass = self.is_metadata_or_obs() || self.is_reward();
             if !self.validate_fee(addrs) && !allow_bypass {
                 let result = Err(error_info("Transaction fee is too low or to unsupported fee address"))
                     .with_detail("transaction", self.json_or())
@@ -24,6 +25,8 @@ impl TransactionValidator for Transaction {
                 return result
 
             };
+            self.validate_contract_fees(addrs)
+                .with_detail("transaction", self.json_or())?;
         }
         Ok(())
     }
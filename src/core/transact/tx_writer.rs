@@ -69,7 +69,7 @@ impl TxWriter {
         } else {
             message.time
         };
-        self.relay
+        let position = self.relay
             .ds
             .accept_transaction(
                 &transaction, time, message.rejection_reason.clone(), message.update_utxo
@@ -89,6 +89,9 @@ impl TxWriter {
 
         }
 
+        if message.rejection_reason.is_none() {
+            self.relay.stream_events.publish_transaction(transaction, position);
+        }
         // info!("Wrote transaction: {}", transaction.hash_or());
 
 
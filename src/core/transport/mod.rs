@@ -1,2 +1,4 @@
 pub mod peer_event_handler;
 pub mod peer_rx_event_handler;
+pub mod noise_transport;
+pub mod rate_limit;
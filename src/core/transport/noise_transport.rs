@@ -0,0 +1,391 @@
+use crate::core::internal_message::PeerMessage;
+use crate::core::relay::Relay;
+use crate::util;
+use dashmap::DashMap;
+use metrics::counter;
+use redgold_common::client::http::{RequestResponseAuth, RgHttpClient};
+use redgold_keys::noise::{noise_respond, NoiseInitiator, NoiseSession, NOISE_HANDSHAKE_MAX_SKEW_MILLIS};
+use redgold_keys::request_support::RequestSupport;
+use redgold_keys::KeyPair;
+use redgold_schema::message::{Request, Response};
+use redgold_schema::observability::errors::EnhanceErrorInfo;
+use redgold_schema::proto_serde::ProtoSerde;
+use redgold_schema::structs::{ErrorCode, NoiseFrame, NoiseHandshake, PublicKey};
+use redgold_schema::{error_message, RgResult, SafeOption};
+use redgold_data::peer::PeerStore;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// Sessions are re-keyed with a fresh handshake after this long.
+pub const NOISE_SESSION_TTL_MILLIS: i64 = 1000 * 60 * 60;
+/// A UDP handshake without a reply in this time is sent again.
+pub const NOISE_UDP_HANDSHAKE_TIMEOUT_MILLIS: i64 = 10_000;
+/// UDP handshakes sent before giving up on a peer. Queued messages to a known peer are then
+/// dropped, an unknown peer is assumed not to support noise.
+pub const NOISE_UDP_HANDSHAKE_ATTEMPTS: u32 = 3;
+/// Unknown peers without noise support are sent plaintext, and retried after this long.
+pub const NOISE_UNSUPPORTED_RETRY_MILLIS: i64 = 1000 * 60 * 60;
+
+type SharedSession = Arc<Mutex<NoiseSession>>;
+
+/// Outgoing UDP handshake awaiting its reply.
+pub struct PendingHandshake {
+    init: NoiseInitiator,
+    started: i64,
+    pub addr: SocketAddr,
+    pub attempts: u32,
+}
+
+/// Session table for encrypted peer transport, shared by the REST and UDP paths. Each side
+/// initiates its own session for outgoing messages and answers on whichever session a message
+/// arrived on.
+///
+/// Nodes in the peer store are known peers: only they may open sessions, and traffic with them
+/// never falls back to plaintext other than for discovery, so a lost or stripped handshake
+/// can't downgrade the connection.
+#[derive(Clone)]
+pub struct NoiseSessions {
+    keypair: KeyPair,
+    peers: Option<PeerStore>,
+    outgoing: Arc<DashMap<PublicKey, SharedSession>>,
+    incoming: Arc<DashMap<Vec<u8>, SharedSession>>,
+    pending: Arc<DashMap<PublicKey, PendingHandshake>>,
+    queued: Arc<DashMap<PublicKey, Vec<PeerMessage>>>,
+    // Initiator ephemeral keys already answered, a replayed initiation is dropped.
+    seen_initiations: Arc<DashMap<Vec<u8>, i64>>,
+    unsupported: Arc<DashMap<PublicKey, i64>>,
+}
+
+impl NoiseSessions {
+    pub fn new(keypair: &KeyPair) -> Self {
+        Self {
+            keypair: keypair.clone(),
+            peers: None,
+            outgoing: Arc::new(Default::default()),
+            incoming: Arc::new(Default::default()),
+            pending: Arc::new(Default::default()),
+            queued: Arc::new(Default::default()),
+            seen_initiations: Arc::new(Default::default()),
+            unsupported: Arc::new(Default::default()),
+        }
+    }
+
+    pub fn with_peer_store(mut self, peers: PeerStore) -> Self {
+        self.peers = Some(peers);
+        self
+    }
+
+    pub async fn is_known(&self, remote: &PublicKey) -> RgResult<bool> {
+        match self.peers.as_ref() {
+            Some(p) => Ok(p.peer_id_for_node_pk(remote).await?.is_some()),
+            None => Ok(false)
+        }
+    }
+
+    /// Plaintext signed requests from known peers are only accepted for discovery, which is
+    /// how a peer learns about this node before it can handshake with it.
+    pub async fn check_plaintext(&self, request: &Request, signer: &PublicKey) -> RgResult<()> {
+        if is_discovery_only(request) || !self.is_known(signer).await? {
+            return Ok(());
+        }
+        counter!("redgold_noise_plaintext_rejected").increment(1);
+        Err(error_message(ErrorCode::NoiseHandshakeFailure, "Known peers must send requests over an encrypted session"))
+    }
+
+    fn lock(session: &SharedSession) -> RgResult<std::sync::MutexGuard<NoiseSession>> {
+        session.lock().map_err(|_| error_message(ErrorCode::NoiseHandshakeFailure, "Noise session lock poisoned"))
+    }
+
+    fn insert(&self, session: NoiseSession, outgoing: bool) -> SharedSession {
+        let remote = session.remote.clone();
+        let id = session.session_id.clone();
+        let shared = Arc::new(Mutex::new(session));
+        if outgoing {
+            self.outgoing.insert(remote, shared.clone());
+        }
+        self.incoming.insert(id, shared.clone());
+        shared
+    }
+
+    pub fn outgoing_session(&self, remote: &PublicKey) -> Option<SharedSession> {
+        let ct = util::current_time_millis_i64();
+        self.outgoing.get(remote)
+            .map(|s| s.value().clone())
+            .filter(|s| Self::lock(s).map(|s| ct - s.created < NOISE_SESSION_TTL_MILLIS).unwrap_or(false))
+    }
+
+    pub fn drop_outgoing(&self, remote: &PublicKey) {
+        if let Some((_, s)) = self.outgoing.remove(remote) {
+            if let Ok(s) = Self::lock(&s) {
+                self.incoming.remove(&s.session_id);
+            }
+        }
+    }
+
+    pub fn is_unsupported(&self, remote: &PublicKey) -> bool {
+        self.unsupported.get(remote)
+            .map(|t| util::current_time_millis_i64() - *t.value() < NOISE_UNSUPPORTED_RETRY_MILLIS)
+            .unwrap_or(false)
+    }
+
+    pub fn mark_unsupported(&self, remote: &PublicKey) {
+        counter!("redgold_noise_unsupported_peer").increment(1);
+        self.unsupported.insert(remote.clone(), util::current_time_millis_i64());
+    }
+
+    pub fn initiate(&self, remote: &PublicKey) -> RgResult<(NoiseInitiator, Request)> {
+        let (init, msg) = NoiseInitiator::new(&self.keypair, remote, util::current_time_millis_i64())?;
+        let mut req = Request::default();
+        req.noise_handshake = Some(msg);
+        Ok((init, req))
+    }
+
+    pub fn finish(&self, init: NoiseInitiator, reply: &NoiseHandshake) -> RgResult<SharedSession> {
+        let session = init.finish(reply, util::current_time_millis_i64())?;
+        counter!("redgold_noise_session_initiated").increment(1);
+        Ok(self.insert(session, true))
+    }
+
+    /// Answer a handshake initiation from a known peer.
+    pub async fn respond_known(&self, init: &NoiseHandshake) -> RgResult<NoiseHandshake> {
+        let initiator = init.static_key.as_ref().ok_msg("Missing noise static key")?;
+        if !self.is_known(initiator).await? {
+            counter!("redgold_noise_unknown_initiator").increment(1);
+            return Err(error_message(ErrorCode::NoiseHandshakeFailure, "Noise handshake initiator is not a known peer"));
+        }
+        self.respond(init)
+    }
+
+    /// Answer a handshake initiation, registering the session for incoming frames.
+    pub fn respond(&self, init: &NoiseHandshake) -> RgResult<NoiseHandshake> {
+        let ct = util::current_time_millis_i64();
+        let e = init.ephemeral.as_ref().map(|b| b.value.clone()).ok_msg("Missing noise ephemeral key")?;
+        if self.seen_initiations.contains_key(&e) {
+            return Err(error_message(ErrorCode::NoiseReplay, "Noise handshake initiation replayed"));
+        }
+        let (session, reply) = noise_respond(&self.keypair, init, ct)?;
+        self.seen_initiations.insert(e, ct);
+        self.insert(session, false);
+        counter!("redgold_noise_session_accepted").increment(1);
+        Ok(reply)
+    }
+
+    pub fn encrypt(session: &SharedSession, data: &[u8]) -> RgResult<NoiseFrame> {
+        Self::lock(session)?.encrypt(data)
+    }
+
+    /// Decrypt an incoming frame into the signed request it carries. The request signature must
+    /// belong to the same node that authenticated the session.
+    pub fn open_request(&self, frame: &NoiseFrame) -> RgResult<(SharedSession, Request)> {
+        let id = frame.session_id.as_ref().map(|b| b.value.clone()).unwrap_or_default();
+        let session = self.incoming.get(&id)
+            .map(|s| s.value().clone())
+            .ok_or(error_message(ErrorCode::NoiseHandshakeFailure, "Unknown noise session"))?;
+        let (data, remote) = {
+            let mut s = Self::lock(&session)?;
+            (s.decrypt(frame)?, s.remote.clone())
+        };
+        let request = Request::proto_deserialize(data)?;
+        let signer = request.verify_auth()?;
+        if signer != remote {
+            return Err(error_message(ErrorCode::NoiseHandshakeFailure, "Request signer does not match noise session key"));
+        }
+        Ok((session, request))
+    }
+
+    pub fn seal_response(session: &SharedSession, response: &Response) -> RgResult<Response> {
+        let mut outer = Response::default();
+        outer.noise_frame = Some(Self::encrypt(session, &response.proto_serialize())?);
+        Ok(outer)
+    }
+
+    pub fn open_response(session: &SharedSession, response: &Response) -> RgResult<Response> {
+        let frame = response.noise_frame.as_ref().ok_msg("Missing noise frame on response")?;
+        let data = Self::lock(session)?.decrypt(frame)?;
+        Response::proto_deserialize(data)
+    }
+
+    /// Handshake or encrypted frame arriving over REST, anything else is left to the plaintext path.
+    pub async fn handle_rest(&self, relay: &Relay, request: &Request, origin: Option<String>) -> Option<RgResult<Response>> {
+        if let Some(h) = request.noise_handshake.as_ref() {
+            return Some(self.respond_known(h).await.map(|reply| {
+                let mut r = Response::default();
+                r.noise_handshake = Some(reply);
+                r
+            }));
+        }
+        let frame = request.noise_frame.as_ref()?;
+        Some(self.handle_rest_frame(relay, frame, origin).await)
+    }
+
+    async fn handle_rest_frame(&self, relay: &Relay, frame: &NoiseFrame, origin: Option<String>) -> RgResult<Response> {
+        let (session, mut inner) = self.open_request(frame)?;
+        inner.origin = origin;
+        let response = relay.receive_request_send_internal(inner, None).await
+            .unwrap_or_else(|e| Response::from_error_info(e));
+        Self::seal_response(&session, &response)
+    }
+
+    async fn rest_handshake(&self, client: &RgHttpClient, remote: &PublicKey, known: bool) -> RgResult<Option<SharedSession>> {
+        let (init, req) = self.initiate(remote)?;
+        let res = client.proto_post(&req, "request_proto".to_string()).await?;
+        match res.noise_handshake.as_ref() {
+            Some(reply) => Ok(Some(self.finish(init, reply)?)),
+            None if known => {
+                counter!("redgold_noise_known_peer_handshake_failed").increment(1);
+                res.as_error_info()?;
+                Err(error_message(ErrorCode::NoiseHandshakeFailure, "Known peer did not answer the noise handshake"))
+            }
+            None => {
+                // Older peers process the envelope as an empty request, and peers which don't
+                // know this node yet refuse the handshake until discovery completes.
+                self.mark_unsupported(remote);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Send a signed request over an encrypted session, handshaking first if required. Only
+    /// peers not in the peer store fall back to the signed plaintext request.
+    pub async fn rest_request(&self, relay: &Relay, client: &RgHttpClient, request: Request, remote: &PublicKey) -> RgResult<Response> {
+        let mut signed = request.clone();
+        if signed.trace_id.is_none() {
+            signed.trace_id = Some(Uuid::new_v4().to_string());
+        }
+        let ser = relay.sign_request(signed).await?.proto_serialize();
+        let known = self.is_known(remote).await?;
+        for attempt in 0..2 {
+            let session = match self.outgoing_session(remote) {
+                Some(s) => s,
+                None if !known && self.is_unsupported(remote) => break,
+                None => match self.rest_handshake(client, remote, known).await? {
+                    Some(s) => s,
+                    None => break
+                }
+            };
+            let mut outer = Request::default();
+            outer.noise_frame = Some(Self::encrypt(&session, &ser)?);
+            let res = client.proto_post(&outer, "request_proto".to_string()).await?;
+            if res.noise_frame.is_none() {
+                // The peer lost the session, i.e. after a restart, so handshake again once.
+                self.drop_outgoing(remote);
+                if attempt == 0 {
+                    continue;
+                }
+                res.as_error_info()?;
+                return Err(error_message(ErrorCode::NoiseHandshakeFailure, "Peer did not return an encrypted response"));
+            }
+            let inner = Self::open_response(&session, &res)?;
+            inner.as_error_info().add("Response metadata found as errorInfo")?;
+            return relay.verify(inner, Some(remote)).add("Response authentication verification failure");
+        }
+        client.proto_post_request(request, Some(relay.node_metadata().await?), Some(remote)).await
+    }
+
+    pub fn queue(&self, remote: &PublicKey, message: PeerMessage) {
+        self.queued.entry(remote.clone()).or_default().push(message);
+    }
+
+    pub fn take_queued(&self, remote: &PublicKey) -> Vec<PeerMessage> {
+        self.queued.remove(remote).map(|(_, v)| v).unwrap_or_default()
+    }
+
+    /// Outgoing UDP handshakes are asynchronous, the initiator waits here for the reply.
+    pub fn set_pending(&self, remote: &PublicKey, init: NoiseInitiator, addr: SocketAddr, attempts: u32) {
+        let started = util::current_time_millis_i64();
+        self.pending.insert(remote.clone(), PendingHandshake { init, started, addr, attempts });
+    }
+
+    pub fn has_pending(&self, remote: &PublicKey) -> bool {
+        self.pending.contains_key(remote)
+    }
+
+    /// Remove every handshake that was never answered within the timeout.
+    pub fn expire_pending(&self) -> Vec<(PublicKey, PendingHandshake)> {
+        let ct = util::current_time_millis_i64();
+        let expired = self.pending.iter()
+            .filter(|p| ct - p.value().started > NOISE_UDP_HANDSHAKE_TIMEOUT_MILLIS)
+            .map(|p| p.key().clone())
+            .collect::<Vec<PublicKey>>();
+        expired.iter()
+            .flat_map(|k| self.pending.remove_if(k, |_, p| ct - p.started > NOISE_UDP_HANDSHAKE_TIMEOUT_MILLIS))
+            .collect()
+    }
+
+    pub fn finish_pending(&self, reply: &NoiseHandshake) -> RgResult<(PublicKey, SharedSession)> {
+        let remote = reply.static_key.clone().ok_msg("Missing noise static key")?;
+        let (_, pending) = self.pending.remove(&remote)
+            .ok_or(error_message(ErrorCode::NoiseHandshakeFailure, "No pending noise handshake for reply"))?;
+        Ok((remote, self.finish(pending.init, reply)?))
+    }
+
+    pub fn prune(&self) {
+        let ct = util::current_time_millis_i64();
+        self.seen_initiations.retain(|_, t| ct - *t < 2 * NOISE_HANDSHAKE_MAX_SKEW_MILLIS);
+        self.incoming.retain(|_, s| Self::lock(s).map(|s| ct - s.created < 2 * NOISE_SESSION_TTL_MILLIS).unwrap_or(false));
+        self.outgoing.retain(|_, s| Self::lock(s).map(|s| ct - s.created < NOISE_SESSION_TTL_MILLIS).unwrap_or(false));
+        self.unsupported.retain(|_, t| ct - *t < NOISE_UNSUPPORTED_RETRY_MILLIS);
+        // Messages only wait while a handshake is in flight, expired handshakes flush or drop
+        // their queue so anything left here has nothing to wait on.
+        let dropped = self.queued.iter()
+            .filter(|q| !self.pending.contains_key(q.key()))
+            .map(|q| q.key().clone())
+            .collect::<Vec<PublicKey>>();
+        for k in dropped {
+            if let Some((_, q)) = self.queued.remove(&k) {
+                counter!("redgold_noise_queued_dropped").increment(q.len() as u64);
+            }
+        }
+    }
+}
+
+// Discovery requests only carry public node information, so known peers may still send them in
+// plaintext to a node which doesn't know them yet.
+fn is_discovery_only(request: &Request) -> bool {
+    if request.about_node_request.is_none() && request.get_peers_info_request.is_none() {
+        return false;
+    }
+    let mut discovery = Request::default();
+    discovery.about_node_request = request.about_node_request.clone();
+    discovery.get_peers_info_request = request.get_peers_info_request.clone();
+    discovery.proof = request.proof.clone();
+    discovery.node_metadata = request.node_metadata.clone();
+    discovery.abridged_node_metadata = request.abridged_node_metadata.clone();
+    discovery.trace_id = request.trace_id.clone();
+    discovery.trace = request.trace.clone();
+    discovery.origin = request.origin.clone();
+    discovery == *request
+}
+
+#[test]
+fn noise_sessions_round_trip() {
+    let tc = redgold_keys::TestConstants::new();
+    let a = NoiseSessions::new(&tc.key_pair());
+    let b = NoiseSessions::new(&KeyPair::new(&tc.secret2, &tc.public2));
+    let b_pk = KeyPair::new(&tc.secret2, &tc.public2).public_key();
+
+    let (init, req) = a.initiate(&b_pk).unwrap();
+    let reply = b.respond(req.noise_handshake.as_ref().unwrap()).unwrap();
+    assert!(b.respond(req.noise_handshake.as_ref().unwrap()).is_err());
+    let session = a.finish(init, &reply).unwrap();
+    assert!(a.outgoing_session(&b_pk).is_some());
+
+    let signed = Request::default()
+        .with_metadata(redgold_schema::structs::NodeMetadata {
+            public_key: Some(tc.key_pair().public_key()),
+            ..Default::default()
+        })
+        .with_auth(&tc.key_pair());
+    let frame = NoiseSessions::encrypt(&session, &signed.proto_serialize()).unwrap();
+    let (_, opened) = b.open_request(&frame).unwrap();
+    assert_eq!(opened, signed);
+    assert!(b.open_request(&frame).is_err());
+
+    let mut discovery = Request::default();
+    discovery.about_node_request = Some(Default::default());
+    assert!(is_discovery_only(&discovery.clone().with_auth(&tc.key_pair())));
+    discovery.submit_transaction_request = Some(Default::default());
+    assert!(!is_discovery_only(&discovery));
+    assert!(!is_discovery_only(&Request::default()));
+}
@@ -90,6 +90,7 @@ impl PeerOutgoingEventHandler {
                     let ip = Ipv4Addr::from_str(&*ip).error_info("ip parse")?;
                     let socket_addr = SocketAddr::new(IpAddr::V4(ip), udp_port);
                     message.socket_addr = Some(socket_addr);
+                    message.public_key = Some(pk.clone());
                     relay.udp_outgoing_messages.send(message).await?
                 }
             }
@@ -207,5 +208,8 @@ impl PeerOutgoingEventHandler {
 
 pub async fn rest_peer(relay: &Relay, ip: String, port: i64, request: Request, intended_pk: &structs::PublicKey) -> Result<Response, ErrorInfo> {
     let client = redgold_common::client::http::RgHttpClient::new(ip, port as u16, Some(Box::new(relay.clone())));
+    if relay.node_config.noise_transport_enabled() {
+        return relay.noise.rest_request(relay, &client, request, intended_pk).await;
+    }
     client.proto_post_request(request, Some(relay.node_metadata().await?), Some(intended_pk)).await
 }
@@ -9,6 +9,7 @@ use crate::api::hash_query::get_address_info_public_key;
 use crate::core::discover::peer_discovery::DiscoveryMessage;
 use crate::core::internal_message::{PeerMessage, TransactionMessage};
 use crate::core::relay::Relay;
+use crate::core::transport::rate_limit::RequestClass;
 use crate::data::download::process_download_request;
 use redgold_schema::errors::helpers::WithMetrics;
 use crate::party::order_fulfillment::handle_multisig_request;
@@ -36,11 +37,11 @@ use redgold_schema::observability::errors::EnhanceErrorInfo;
 // use crate::multiparty_gg20::watcher::DepositWatcher;
 use redgold_schema::observability::errors::Loggable;
 use redgold_schema::proto_serde::ProtoSerde;
-use redgold_schema::structs::BatchTransactionResolveResponse;
-use redgold_schema::structs::{AboutNodeRequest, AboutNodeResponse, ErrorInfo, GetPartiesInfoResponse, GetPeersInfoRequest, GetPeersInfoResponse, Hash, PublicKey, QueryObservationProofResponse, RecentDiscoveryTransactionsResponse, ResolveCodeResponse, SubmitTransactionRequest, TransactionEntry, UtxoId, UtxoValidResponse};
+use redgold_schema::structs::{BatchTransactionResolveResponse, ErrorCode};
+use redgold_schema::structs::{AboutNodeRequest, AboutNodeResponse, ContractOrderingObservationResponse, ErrorInfo, GetPartiesInfoResponse, GetPeersInfoRequest, GetPeersInfoResponse, Hash, PublicKey, QueryObservationProofResponse, RecentDiscoveryTransactionsResponse, ResolveCodeResponse, SubmitTransactionRequest, TransactionEntry, UtxoId, UtxoValidResponse};
 use redgold_schema::util::lang_util::{SameResult, WithMaxLengthString};
 use redgold_schema::util::timers::PerfTimer;
-use redgold_schema::{error_info, structs, RgResult, SafeOption};
+use redgold_schema::{error_info, error_message, structs, RgResult, SafeOption};
 // use svg::Node;
 use tokio::runtime::Runtime;
 use tokio::task::JoinHandle;
@@ -130,7 +131,32 @@ impl<E> PeerRxEventHandler<E> where E: ExternalNetworkResources + Send + 'static
             counter!("redgold_request_response_ip", &labels).increment(1)
         }
 
-        // TODO: Rate limiting here
+        let classes = RequestClass::of(&request);
+        if !classes.is_empty() {
+            let limiter = &relay.peer_rate_limiter;
+            match verified.as_ref() {
+                Ok(pk) => {
+                    let peer = pk.hex();
+                    let trust = match limiter.cached_trust(&peer) {
+                        Some(t) => t,
+                        None => {
+                            let t = relay.get_trust_of_node_as_query(pk).await.ok().flatten().map(|t| t.trust);
+                            limiter.cache_trust(&peer, t);
+                            t
+                        }
+                    };
+                    limiter.check(&peer, &classes, trust, true)?;
+                }
+                Err(_) => {
+                    // Without a verified key or a source address there's nothing to attribute
+                    // the quota to, so these are refused rather than sharing one bucket.
+                    let ip = origin_ip.map(|a| a.ip().to_string()).ok_or(
+                        error_message(ErrorCode::RateLimited, "Unattributable request to rate limited endpoint")
+                    )?;
+                    limiter.check(&ip, &classes, None, false)?;
+                }
+            }
+        }
 
         // TODO: add a uuid here
 
@@ -305,7 +331,15 @@ impl<E> PeerRxEventHandler<E> where E: ExternalNetworkResources + Send + 'static
             }
         }
 
-        // TODO: Check for auth info and use for rate limiting
+        if let Some(r) = &request.contract_ordering_observation_request {
+            let key = r.contention_key.safe_get_msg("Missing contention key")?;
+            let mut res = ContractOrderingObservationResponse::default();
+            res.transaction_hashes = relay.contract_pending_requests.get(key)
+                .map(|v| v.value().clone())
+                .unwrap_or_default();
+            response.contract_ordering_observation_response = Some(res);
+        }
+
         // oooh need a request id, 2 of them
         // No auth required requests first
         if let Some(r) = request.hash_search_request {
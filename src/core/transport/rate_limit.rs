@@ -0,0 +1,215 @@
+use dashmap::DashMap;
+use metrics::{counter, gauge};
+use redgold_schema::message::Request;
+use redgold_schema::observability::errors::EnhanceErrorInfo;
+use redgold_schema::structs::ErrorCode;
+use redgold_schema::{error_message, RgResult};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Past this many buckets unauthenticated addresses without an existing bucket are refused
+/// until the next eviction pass, verified peers are always admitted.
+const MAX_RATE_LIMIT_BUCKETS: usize = 100_000;
+
+/// How long a peer's trust score is reused before it's looked up again.
+const RATE_LIMIT_TRUST_TTL: Duration = Duration::from_secs(60);
+
+/// Request types which are expensive enough to serve that each peer gets a separate quota.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RequestClass {
+    ResolveHash,
+    Download,
+    BatchTransactionResolve,
+    Multiparty,
+}
+
+impl RequestClass {
+
+    pub fn of(request: &Request) -> Vec<RequestClass> {
+        let mut classes = vec![];
+        if request.resolve_hash_request.is_some() {
+            classes.push(RequestClass::ResolveHash);
+        }
+        if request.download_request.is_some() {
+            classes.push(RequestClass::Download);
+        }
+        if request.batch_transaction_resolve_request.is_some() {
+            classes.push(RequestClass::BatchTransactionResolve);
+        }
+        if request.initiate_keygen.is_some()
+            || request.initiate_signing.is_some()
+            || request.multiparty_authentication_request.is_some()
+            || request.multiparty_check_ready_request.is_some()
+            || request.multisig_request.is_some()
+            || request.notify_multisig_creation_request.is_some()
+            || request.monero_multisig_formation_request.is_some() {
+            classes.push(RequestClass::Multiparty);
+        }
+        classes
+    }
+
+    /// Burst capacity and refill per second for a peer with no trust score.
+    pub fn base_quota(&self) -> (f64, f64) {
+        match self {
+            RequestClass::ResolveHash => (100.0, 10.0),
+            // Peers downloading history during sync issue many of these back to back
+            RequestClass::Download => (200.0, 20.0),
+            RequestClass::BatchTransactionResolve => (50.0, 5.0),
+            RequestClass::Multiparty => (20.0, 1.0),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RequestClass::ResolveHash => "resolve_hash",
+            RequestClass::Download => "download",
+            RequestClass::BatchTransactionResolve => "batch_transaction_resolve",
+            RequestClass::Multiparty => "multiparty",
+        }
+    }
+}
+
+/// Quota multiplier for a peer's trust score, unknown peers get the base quota and a fully
+/// trusted peer five times it.
+pub fn trust_multiplier(trust: Option<f64>) -> f64 {
+    1.0 + trust.unwrap_or(0.0).clamp(0.0, 1.0) * 4.0
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    pub tokens: f64,
+    pub capacity: f64,
+    pub refill_per_second: f64,
+    pub updated: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64, refill_per_second: f64, now: Instant) -> Self {
+        Self { tokens: capacity, capacity, refill_per_second, updated: now }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_second).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Take a token if one is available. Capacity is updated in place so a change in trust
+    /// applies to an existing bucket.
+    pub fn try_take(&mut self, capacity: f64, refill_per_second: f64, now: Instant) -> bool {
+        self.refill(now);
+        self.capacity = capacity;
+        self.refill_per_second = refill_per_second;
+        self.tokens = self.tokens.min(capacity);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_full(&self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens + elapsed * self.refill_per_second >= self.capacity
+    }
+}
+
+/// Token bucket quotas keyed by peer and request class. Peers are identified by their
+/// verified public key, or by origin address for unauthenticated requests.
+#[derive(Clone, Default)]
+pub struct PeerRateLimiter {
+    buckets: Arc<DashMap<(String, RequestClass), TokenBucket>>,
+    trust: Arc<DashMap<String, (Option<f64>, Instant)>>,
+}
+
+impl PeerRateLimiter {
+
+    /// Trust score recently looked up for a peer, None if it needs a refresh.
+    pub fn cached_trust(&self, peer: &String) -> Option<Option<f64>> {
+        self.trust.get(peer)
+            .filter(|e| e.1.elapsed() < RATE_LIMIT_TRUST_TTL)
+            .map(|e| e.0)
+    }
+
+    pub fn cache_trust(&self, peer: &String, trust: Option<f64>) {
+        self.trust.insert(peer.clone(), (trust, Instant::now()));
+    }
+
+    /// Drop refilled buckets and stale trust scores, run periodically rather than per request.
+    pub fn evict_idle(&self) {
+        self.evict_idle_at(Instant::now())
+    }
+
+    fn evict_idle_at(&self, now: Instant) {
+        self.buckets.retain(|_, b| !b.is_full(now));
+        self.trust.retain(|_, (_, t)| now.saturating_duration_since(*t) < RATE_LIMIT_TRUST_TTL);
+        gauge!("redgold_peer_rate_limit_buckets").set(self.buckets.len() as f64);
+    }
+
+    pub fn check(&self, peer: &String, classes: &Vec<RequestClass>, trust: Option<f64>, authenticated: bool) -> RgResult<()> {
+        let now = Instant::now();
+        let full = self.buckets.len() > MAX_RATE_LIMIT_BUCKETS;
+        let mult = trust_multiplier(trust);
+        for class in classes {
+            let (capacity, refill) = class.base_quota();
+            let (capacity, refill) = (capacity * mult, refill * mult);
+            let key = (peer.clone(), *class);
+            if full && !authenticated && !self.buckets.contains_key(&key) {
+                counter!("redgold_peer_rate_limit_table_full").increment(1);
+                return Err(error_message(ErrorCode::RateLimited, "Rate limit table full"))
+                    .with_detail("peer", peer.clone());
+            }
+            let allowed = self.buckets.entry(key)
+                .or_insert_with(|| TokenBucket::new(capacity, refill, now))
+                .try_take(capacity, refill, now);
+            if !allowed {
+                let labels = [("class".to_string(), class.name().to_string())];
+                counter!("redgold_peer_rate_limited", &labels).increment(1);
+                return Err(error_message(ErrorCode::RateLimited, "Peer request quota exceeded"))
+                    .with_detail("peer", peer.clone())
+                    .with_detail("request_class", class.name().to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn token_bucket_refill_and_trust() {
+    let now = Instant::now();
+    let mut b = TokenBucket::new(2.0, 1.0, now);
+    assert!(b.try_take(2.0, 1.0, now));
+    assert!(b.try_take(2.0, 1.0, now));
+    assert!(!b.try_take(2.0, 1.0, now));
+    let later = now + std::time::Duration::from_millis(1500);
+    assert!(b.try_take(2.0, 1.0, later));
+    assert!(!b.try_take(2.0, 1.0, later));
+    assert_eq!(trust_multiplier(None), 1.0);
+    assert_eq!(trust_multiplier(Some(1.0)), 5.0);
+    assert_eq!(trust_multiplier(Some(7.0)), 5.0);
+
+    let limiter = PeerRateLimiter::default();
+    let peer = "peer".to_string();
+    let classes = vec![RequestClass::Multiparty];
+    let (capacity, _) = RequestClass::Multiparty.base_quota();
+    for _ in 0..(capacity as usize) {
+        limiter.check(&peer, &classes, None, false).unwrap();
+    }
+    let err = limiter.check(&peer, &classes, None, false).unwrap_err();
+    assert_eq!(err.code, ErrorCode::RateLimited as i32);
+    // A separate peer has its own quota
+    limiter.check(&"other".to_string(), &classes, None, false).unwrap();
+
+    // Buckets that haven't refilled yet are kept
+    limiter.evict_idle();
+    assert_eq!(limiter.buckets.len(), 2);
+    // Once the other peer's single token is back only the drained bucket survives
+    limiter.evict_idle_at(Instant::now() + Duration::from_secs(2));
+    assert_eq!(limiter.buckets.len(), 1);
+    assert!(limiter.buckets.contains_key(&(peer.clone(), RequestClass::Multiparty)));
+
+    assert_eq!(limiter.cached_trust(&peer), None);
+    limiter.cache_trust(&peer, Some(0.5));
+    assert_eq!(limiter.cached_trust(&peer), Some(Some(0.5)));
+}
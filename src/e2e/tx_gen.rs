@@ -6,6 +6,7 @@ use redgold_keys::transaction_support::{InputSupport, TransactionSupport};
 use redgold_keys::util::mnemonic_support::MnemonicSupport;
 use redgold_keys::KeyPair;
 use redgold_keys::TestConstants;
+use redgold_schema::fee_validator::DEFAULT_REQUEST_FUEL_LIMIT;
 use redgold_schema::conf::node_config::NodeConfig;
 use redgold_schema::helpers::easy_json::EasyJson;
 use redgold_schema::keys::words_pass::WordsPass;
@@ -140,7 +141,7 @@ impl TransactionGenerator {
         let c_amount = CurrencyAmount::from(a.amount / 2);
         // TODO: Add fees / fee address, use genesis utxos or something?
         // let fee_amount = CurrencyAmount::from(a.amount / 10);
-        tb.with_contract_deploy_output_and_predicate_input(bytes, c_amount, true)?;
+        tb.with_contract_deploy_output_and_predicate_input(bytes, c_amount, true, DEFAULT_REQUEST_FUEL_LIMIT)?;
         // tb.with_fee(fee_amount);
         tb.with_remainder();
         let tx= tb.build()?.sign(&prev.key_pair)?;
@@ -170,7 +171,7 @@ impl TransactionGenerator {
         req.test_contract_update = Some(update);
         req.test_contract_update2 = Some(update2);
 
-        tb.with_contract_request_output(&address, &req.proto_serialize())?;
+        tb.with_contract_request_output(&address, &req.proto_serialize(), DEFAULT_REQUEST_FUEL_LIMIT)?;
         // tb.with_fee(fee_amount);
         tb.with_remainder();
         let tx= tb.build()?.sign(&prev.key_pair)?;
@@ -22,12 +22,12 @@ use redgold_schema::conf::node_config::NodeConfig;
 use redgold_schema::errors::into_error::ToErrorInfo;
 use redgold_schema::helpers::easy_json::{EasyJson, EasyJsonDeser};
 use redgold_schema::party::party_events::PartyEvents;
-use redgold_schema::structs::{Address, CurrencyAmount, ExternalTransactionId, GetSolanaAddress, Hash, MultisigRequest, MultisigResponse, NetworkEnvironment, PartySigningValidation, Proof, PublicKey, SupportedCurrency, Transaction};
+use redgold_schema::structs::{Address, CurrencyAmount, ErrorCode, ExternalTransactionId, GetSolanaAddress, Hash, MultisigRequest, MultisigResponse, NetworkEnvironment, PartySigningValidation, Proof, PublicKey, SupportedCurrency, Transaction};
 use redgold_schema::message::Response;
 use redgold_schema::message::Request;
 use redgold_schema::tx::external_tx::ExternalTimedTransaction;
 use redgold_schema::util::lang_util::AnyPrinter;
-use redgold_schema::{bytes_data, error_info, structs, ErrorInfoContext, RgResult, SafeOption, ShortString};
+use redgold_schema::{bytes_data, error_info, error_message, structs, ErrorInfoContext, RgResult, SafeOption, ShortString};
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -211,7 +211,7 @@ impl ExternalNetworkResources for ExternalNetworkResourcesImpl {
     ) -> RgResult<ExternalTransactionId> {
         let (destination, amount) = destination_amounts.get(0).unwrap().clone();
         let currency = destination.currency_or();
-        let req = Request::default();
+        let mut req = Request::default();
         let mut mreq = MultisigRequest::default();
         mreq.set_currency(currency);
         mreq.amount = Some(amount.clone());
@@ -226,10 +226,8 @@ impl ExternalNetworkResources for ExternalNetworkResourcesImpl {
                 ).await?;
                 let mut guard = wallet.lock().await;
                 let result = guard.create_multisig_transaction(destination_amounts, party_address)?;
-                let req = Request::default();
-                let mut mreq = MultisigRequest::default();
-                mreq.set_currency(currency);
                 mreq.encoded_tx = Some(result.json_or());
+                req.multisig_request = Some(mreq);
                 let results = broadcast.broadcast(peer_pks, req).await?;
                 let fixed = results.into_iter().flat_map(|r| r
                     .and_then(|r| r.multisig_response.ok_msg("Missing multisig response"))
@@ -257,6 +255,7 @@ impl ExternalNetworkResources for ExternalNetworkResourcesImpl {
                 ).await?;
 
                 mreq.bytes_encoded_tx = bytes_data(tx_hash.to_vec());
+                req.multisig_request = Some(mreq);
                 let results = broadcast.broadcast(peer_pks, req).await?;
                 let fixed = results.into_iter().flat_map(|r| r
                     .and_then(|r| r.multisig_response.ok_msg("Missing multisig response"))
@@ -658,7 +657,9 @@ async fn get_all_tx_for_pk(&self, pk: &PublicKey, currency: SupportedCurrency, f
                 let bytes = bytes.value.clone();
                 let (hash, sigbytes) = eth.sign_safe_tx(&party, dest, amount).await?;
                 if hash.to_vec() != bytes {
-                    error!("Hash mismatch: {:?} vs {:?}", hash, bytes);
+                    return Err(error_message(
+                        ErrorCode::PartySigningRejected, "Safe transaction hash does not match declared destination and amount"
+                    )).with_detail("expected", hex::encode(hash.to_vec())).with_detail("received", hex::encode(bytes));
                 }
                 Ok(MultisigResponse{
                     tx: None,
@@ -83,6 +83,9 @@ async fn load_configs() -> (Box<NodeConfig>, bool) {
                 cli_stake(s, &nc).await.unwrap();
             }
 
+            RgTopLevelSubcommand::Restore(r) => {
+                commands::restore(&r, &nc).await.unwrap();
+            }
             RgTopLevelSubcommand::ColdMix(c) => {
                 commands::cold_mix(c, &nc).await.unwrap();
             }
@@ -119,6 +119,9 @@ impl Node {
 
         relay.ds.peer_store.clear_all_peers().await?;
 
+        relay.load_pending_acceptances().await
+            .add("Loading pending acceptances failed")?;
+
         if let Some(p) = fs::read_to_string(relay2.node_config.env_data_folder().peer_tx_path()).ok() {
             if let Ok(tx) = p.json_from::<Transaction>() {
                 relay.ds.config_store.set_peer_tx(&tx).await?;
@@ -1,35 +1,254 @@
 use crate::core::relay::Relay;
 use crate::party::party_stream::PartyEventBuilder;
+use bdk::bitcoin::psbt::PartiallySignedTransaction;
+use bdk::database::MemoryDatabase;
+use metrics::counter;
+use redgold_keys::btc::btc_wallet::SingleKeyBitcoinWallet;
+use redgold_keys::word_pass_support::NodeConfigKeyPair;
+use redgold_rpc_integ::eth::eth_wallet::EthWalletWrapper;
+use redgold_schema::helpers::easy_json::{EasyJson, EasyJsonDeser};
+use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+use redgold_schema::observability::errors::EnhanceErrorInfo;
 use redgold_schema::party::party_events::PartyEvents;
-use redgold_schema::structs::{PartySigningValidation, SupportedCurrency};
-use redgold_schema::{error_info, RgResult, SafeOption};
+use redgold_schema::fee_validator::ContractFeeValidator;
+use redgold_schema::structs::{Address, CurrencyAmount, ErrorCode, PartySigningValidation, SupportedCurrency, Transaction};
+use redgold_schema::{error_message, RgResult, SafeOption};
+use std::collections::HashSet;
+use crate::util;
 
+// PSBT outputs may differ from the order amount by the fee adjustment made when building.
+const BTC_AMOUNT_TOLERANCE_SATS: i64 = 10_000;
+// Fees a co-signed RDG payout may pay, as a multiple of the transaction's required fee.
+const MAX_PARTY_RDG_FEE_MULTIPLE: i64 = 10;
+// How long an order stays claimed by a co-signing request before it may be signed again, in
+// case the first payment never lands.
+const PARTY_ORDER_CLAIM_MILLIS: i64 = 1000 * 60 * 60;
+
+fn rejection<T>(msg: impl Into<String>) -> RgResult<T> {
+    Err(error_message(ErrorCode::PartySigningRejected, msg))
+}
+
+fn decode_psbt_payload(psbt: &String) -> RgResult<PartiallySignedTransaction> {
+    psbt.json_from::<PartiallySignedTransaction>()
+        .map_err(|e| error_message(ErrorCode::PartySigningRejected, e.json_or()))
+}
+
+/// Orders currently claimed by an outstanding co-signing request.
+pub fn claimed_orders(r: &Relay) -> HashSet<String> {
+    let now = util::current_time_millis_i64();
+    r.party_orders_in_flight.iter()
+        .filter(|e| now - *e.value() < PARTY_ORDER_CLAIM_MILLIS)
+        .map(|e| e.key().clone())
+        .collect()
+}
+
+/// Claim the orders a co-signing request pays, refusing if another request got to any of them
+/// first. Either every order is claimed or none are.
+pub fn claim_orders(r: &Relay, order_ids: &Vec<String>) -> RgResult<()> {
+    let now = util::current_time_millis_i64();
+    r.party_orders_in_flight.retain(|_, t| now - *t < PARTY_ORDER_CLAIM_MILLIS);
+    let mut claimed = vec![];
+    for id in order_ids {
+        let fresh = match r.party_orders_in_flight.entry(id.clone()) {
+            dashmap::mapref::entry::Entry::Occupied(_) => false,
+            dashmap::mapref::entry::Entry::Vacant(e) => {
+                e.insert(now);
+                true
+            }
+        };
+        if !fresh {
+            release_orders(r, &claimed);
+            return rejection("Order is already being signed for another request")
+                .with_detail("order", id.clone());
+        }
+        claimed.push(id.clone());
+    }
+    Ok(())
+}
+
+/// Release claimed orders after the signing they were claimed for failed.
+pub fn release_orders(r: &Relay, order_ids: &Vec<String>) {
+    for id in order_ids {
+        r.party_orders_in_flight.remove(id);
+    }
+}
 
 pub trait PartyEventValidator {
-    async fn validate_event(&self, validator: PartySigningValidation, hash_to_sign: Vec<u8>, r: &Relay) -> RgResult<()>;
+    async fn validate_event(&self, validator: PartySigningValidation, hash_to_sign: Vec<u8>, r: &Relay) -> RgResult<Vec<String>>;
+    fn validate_payouts(&self, currency: SupportedCurrency, payouts: &Vec<(Address, CurrencyAmount)>, claimed: &HashSet<String>) -> RgResult<Vec<String>>;
+    fn validate_rdg_payload(&self, tx: &Transaction, hash_to_sign: &Vec<u8>, claimed: &HashSet<String>) -> RgResult<Vec<String>>;
+    fn validate_btc_payload(&self, psbt: &String, hash_to_sign: &Vec<u8>, claimed: &HashSet<String>) -> RgResult<Vec<String>>;
+    fn validate_migration(&self, validator: &PartySigningValidation, predecessor: &Address, successor: &Address) -> RgResult<()>;
 }
+
+/// Count a refused signing request by currency, passing the result through.
+pub fn record_signing_rejection<T>(result: RgResult<T>, currency: SupportedCurrency) -> RgResult<T> {
+    if result.is_err() {
+        counter!("redgold_party_signing_rejected", "currency" => currency.abbreviated()).increment(1);
+    }
+    result
+}
+
 impl PartyEventValidator for PartyEvents {
 
-    async fn validate_event(&self, validator: PartySigningValidation, hash_to_sign: Vec<u8>, r: &Relay) -> RgResult<()> {
-        // let c = validator.currency();
-        // if c == SupportedCurrency::Redgold {
-        //     let tx = validator.transaction.safe_get_msg("Missing transaction")?;
-        //     return self.validate_rdg_swap_fulfillment_transaction(tx);
-        // }
-        // let payload = validator.json_payload.safe_get_msg("Missing PSBT")?.clone();
-        // match c {
-        //     SupportedCurrency::Bitcoin => {
-        //         let arc = r.btc_wallet(&self.party_public_key).await?;
-        //         let mut w = arc.lock().await;
-        //         self.validate_btc_fulfillment(payload, hash_to_sign, &mut w)?;
-        //     }
-        //     SupportedCurrency::Ethereum => {
-        //         self.validate_eth_fulfillment(payload, hash_to_sign, r).await?;
-        //     }
-        //     _ => {
-        //         return Err(error_info("Unsupported currency"));
-        //     }
-        // }
+    /// Decode the payload a peer asked us to co-sign and refuse unless every payment it makes
+    /// corresponds to an unclaimed order derived from our own view of the party events. Returns
+    /// the identifiers of the orders it pays.
+    async fn validate_event(&self, validator: PartySigningValidation, hash_to_sign: Vec<u8>, r: &Relay) -> RgResult<Vec<String>> {
+        let c = validator.currency();
+        let claimed = claimed_orders(r);
+        let result = async {
+            match c {
+                SupportedCurrency::Redgold => {
+                    let tx = validator.transaction.safe_get_msg("Missing transaction")?;
+                    self.validate_rdg_payload(tx, &hash_to_sign, &claimed)
+                }
+                SupportedCurrency::Bitcoin => {
+                    let payload = validator.json_payload.safe_get_msg("Missing PSBT")?;
+                    self.validate_btc_payload(payload, &hash_to_sign, &claimed)
+                }
+                SupportedCurrency::Ethereum => {
+                    let payload = validator.json_payload.safe_get_msg("Missing EIP-1559 transaction")?;
+                    let fulfills = self.fulfillment_orders(c).into_iter()
+                        .map(|o| (o.destination.clone(), o.fulfilled_amount_typed.clone()))
+                        .collect::<Vec<_>>();
+                    let w = EthWalletWrapper::new(&r.node_config.keypair().to_private_hex(), &self.network)?;
+                    EthWalletWrapper::validate_eth_fulfillment(fulfills, payload, &hash_to_sign, &self.network, &w).await
+                        .map_err(|e| error_message(ErrorCode::PartySigningRejected, e.json_or()))?;
+                    // Ethereum payouts are ordered by the signing round itself rather than claimed here
+                    Ok(vec![])
+                }
+                _ => rejection("Unsupported currency for party signing"),
+            }
+        }.await;
+        record_signing_rejection(result, c)
+    }
+
+    /// Every payment must go to the destination of an outstanding order for the currency that no
+    /// other request has claimed, with each order consumed at most once.
+    fn validate_payouts(&self, currency: SupportedCurrency, payouts: &Vec<(Address, CurrencyAmount)>, claimed: &HashSet<String>) -> RgResult<Vec<String>> {
+        let mut expected = self.fulfillment_orders(currency).into_iter()
+            .map(|o| (o.primary_event.identifier(), o.destination.clone(), o.fulfilled_amount_typed.clone()))
+            .filter(|(id, _, _)| !claimed.contains(id))
+            .collect::<Vec<_>>();
+        if payouts.is_empty() {
+            return rejection("Signing payload makes no payments");
+        }
+        let mut matched_orders = vec![];
+        for (dest, amount) in payouts {
+            let tolerance = if currency == SupportedCurrency::Bitcoin { BTC_AMOUNT_TOLERANCE_SATS } else { 0 };
+            let matched = expected.iter().position(|(_, d, a)| {
+                d == dest && (a.amount - amount.amount).abs() <= tolerance && a.string_amount == amount.string_amount
+            });
+            match matched {
+                Some(i) => {
+                    matched_orders.push(expected.remove(i).0);
+                }
+                None => {
+                    return rejection("Payment does not match any outstanding order")
+                        .with_detail("destination", dest.render_string().unwrap_or_default())
+                        .with_detail("amount", amount.json_or())
+                        .with_detail("has_matching_destination", expected.iter().any(|(_, d, _)| d == dest).to_string())
+                        .with_detail("expected", expected.json_or());
+                }
+            }
+        }
+        Ok(matched_orders)
+    }
+
+    fn validate_rdg_payload(&self, tx: &Transaction, hash_to_sign: &Vec<u8>, claimed: &HashSet<String>) -> RgResult<Vec<String>> {
+        if &tx.signable_hash().vec() != hash_to_sign {
+            return rejection("Signing hash does not match transaction");
+        }
+        // Fee outputs aren't orders, so they're bounded separately to keep the party's funds
+        // from being drained into them.
+        let mut fee_paid = 0;
+        for o in &tx.outputs {
+            let to_fee_addr = o.address.as_ref().map(|a| self.default_fee_addrs.contains(a)).unwrap_or(false);
+            if o.is_fee() && !to_fee_addr {
+                return rejection("Fee output pays an address other than the fee addresses")
+                    .with_detail("destination", o.address.as_ref().and_then(|a| a.render_string().ok()).unwrap_or_default());
+            }
+            if to_fee_addr {
+                fee_paid += o.opt_amount().unwrap_or(0);
+            }
+        }
+        let max_fee = tx.required_rdg_fee() * MAX_PARTY_RDG_FEE_MULTIPLE;
+        if fee_paid > max_fee {
+            return rejection("Signing payload pays too much to fee addresses")
+                .with_detail("fee_paid", fee_paid.to_string())
+                .with_detail("max_fee", max_fee.to_string());
+        }
+        let party = self.all_party_address();
+        let payouts = tx.outputs.iter()
+            .filter(|o| !o.is_fee())
+            .filter_map(|o| o.address.as_ref().zip(o.opt_amount_typed()))
+            .filter(|(a, _)| !party.contains(a) && !self.default_fee_addrs.contains(a))
+            .map(|(a, amt)| (a.clone(), amt))
+            .collect::<Vec<_>>();
+        self.validate_payouts(SupportedCurrency::Redgold, &payouts, claimed)
+    }
+
+    /// A sweep out of a rotated instance may only pay its successor, with any change returning to
+    /// the predecessor itself.
+    fn validate_migration(&self, validator: &PartySigningValidation, predecessor: &Address, successor: &Address) -> RgResult<()> {
+        let allowed = vec![predecessor.clone(), successor.clone()];
+        let destinations = match validator.currency() {
+            SupportedCurrency::Redgold => {
+                let tx = validator.transaction.safe_get_msg("Missing transaction")?;
+                tx.outputs.iter()
+                    .filter(|o| !o.is_fee())
+                    .map(|o| o.address.clone().ok_msg("Output missing address"))
+                    .collect::<RgResult<Vec<Address>>>()?
+            }
+            SupportedCurrency::Bitcoin => {
+                let psbt = decode_psbt_payload(validator.json_payload.safe_get_msg("Missing PSBT")?)?;
+                let outputs = SingleKeyBitcoinWallet::<MemoryDatabase>::outputs_convert_static(
+                    &psbt.unsigned_tx.output, &self.network
+                );
+                if outputs.len() != psbt.unsigned_tx.output.len() {
+                    return rejection("PSBT contains outputs without a standard address");
+                }
+                let allowed = allowed.iter().flat_map(|a| a.render_string().ok()).collect::<Vec<String>>();
+                if let Some((a, _)) = outputs.iter().find(|(a, _)| !allowed.contains(a)) {
+                    return rejection("Migration pays an address other than the successor")
+                        .with_detail("destination", a.clone());
+                }
+                return Ok(());
+            }
+            // Safe transactions are rebuilt locally from the declared successor.
+            _ => vec![],
+        };
+        if let Some(a) = destinations.iter().find(|a| !allowed.contains(a)) {
+            return rejection("Migration pays an address other than the successor")
+                .with_detail("destination", a.render_string().unwrap_or_default());
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn validate_btc_payload(&self, psbt: &String, hash_to_sign: &Vec<u8>, claimed: &HashSet<String>) -> RgResult<Vec<String>> {
+        // The encoded PSBT is what gets signed, so it must be the payload validated here
+        if psbt.as_bytes() != hash_to_sign.as_slice() {
+            return rejection("Signing payload does not match PSBT");
+        }
+        let psbt = decode_psbt_payload(psbt)?;
+        let party = self.all_party_address().iter()
+            .flat_map(|a| a.render_string().ok())
+            .collect::<Vec<String>>();
+        let mut payouts = vec![];
+        let outputs = SingleKeyBitcoinWallet::<MemoryDatabase>::outputs_convert_static(
+            &psbt.unsigned_tx.output, &self.network
+        );
+        if outputs.len() != psbt.unsigned_tx.output.len() {
+            return rejection("PSBT contains outputs without a standard address");
+        }
+        for (addr, amount) in outputs {
+            if party.contains(&addr) {
+                continue;
+            }
+            let dest = Address::from_bitcoin_external(&addr);
+            payouts.push((dest, CurrencyAmount::from_btc(amount as i64)));
+        }
+        self.validate_payouts(SupportedCurrency::Bitcoin, &payouts, claimed)
+    }
+}
@@ -1,16 +1,214 @@
 use crate::party::party_watcher::PartyWatcher;
+use crate::util::current_time_millis_i64;
+use itertools::Itertools;
+use metrics::{counter, gauge};
 use redgold_common::external_resources::ExternalNetworkResources;
-use redgold_schema::party::party_internal_data::PartyInternalData;
-use redgold_schema::structs::PublicKey;
-use redgold_schema::RgResult;
-use std::collections::HashMap;
+use redgold_keys::transaction_support::TransactionSupport;
+use redgold_keys::word_pass_support::NodeConfigKeyPair;
+use redgold_schema::observability::errors::Loggable;
+use redgold_schema::parties::{PartyInstance, PartyMetadata};
+use redgold_schema::party::party_internal_data::{DrainingInstance, PartyInternalData};
+use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+use redgold_schema::structs::{AddressDescriptor, CurrencyAmount, ExternalTransactionId, PublicKey, SupportedCurrency};
+use redgold_schema::tx::tx_builder::TransactionBuilder;
+use redgold_schema::{RgResult, SafeOption};
+use std::collections::{HashMap, HashSet};
+use tracing::info;
+
+// Current members rated below this are left out of the next party, which triggers a rotation.
+const MIN_MEMBER_TRUST: f64 = 0.1;
+// Peers outside our parties need this much to be brought in, the gap to MIN_MEMBER_TRUST keeps
+// a score hovering near the threshold from rotating the party back and forth.
+const MIN_NEW_MEMBER_TRUST: f64 = 0.2;
+// An instance isn't rotated again until it has been active at least this long.
+const MIN_ROTATION_INTERVAL_MILLIS: i64 = 1000 * 60 * 60 * 24;
+// Gives an in flight sweep time to confirm before the remaining balance is swept again.
+const SWEEP_INTERVAL_MILLIS: i64 = 1000 * 60 * 30;
+// A drained predecessor keeps forwarding late deposits for this long before it is retired.
+const DRAIN_PERIOD_MILLIS: i64 = 1000 * 60 * 60 * 24 * 7;
+// Left behind on BTC sweeps to pay the transaction fee.
+const BTC_SWEEP_FEE_RESERVE_SATS: i64 = 10_000;
+
+fn min_sweep_amount(cur: SupportedCurrency) -> CurrencyAmount {
+    match cur {
+        SupportedCurrency::Redgold => CurrencyAmount::from_rdg(10_000),
+        SupportedCurrency::Bitcoin => CurrencyAmount::from_btc(BTC_SWEEP_FEE_RESERVE_SATS * 2),
+        _ => CurrencyAmount::from_fractional_cur(0.001, cur).unwrap_or(CurrencyAmount::zero(cur)),
+    }
+}
 
 impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
 
-    pub async fn handle_key_rotations(&self, data: &mut HashMap<PublicKey, PartyInternalData>) -> RgResult<()> {
-        // TODO Mark successor keys, also in party internal data mark its current state
+    /// Peers eligible for the next party formation. Members of our active parties are dropped
+    /// once their trust falls below the minimum, while other peers need a higher score to join.
+    /// Peers without a score yet are kept.
+    pub async fn trusted_party_peers(&self, peers: &Vec<PublicKey>, metadata: &PartyMetadata) -> RgResult<Vec<PublicKey>> {
+        let trust = self.relay.get_trust().await?;
+        let members = metadata.active_proposed_by(&self.relay.node_config.public_key()).iter()
+            .flat_map(|i| i.address.as_ref())
+            .flat_map(|a| metadata.members_of(a))
+            .collect::<HashSet<PublicKey>>();
+        let mut res = vec![];
+        for pk in peers {
+            let score = self.relay.peer_id_for_node_pk(pk).await?
+                .and_then(|p| trust.get(&p).cloned());
+            let min = if members.contains(pk) { MIN_MEMBER_TRUST } else { MIN_NEW_MEMBER_TRUST };
+            if score.map(|s| s >= min).unwrap_or(true) {
+                res.push(pk.clone());
+            }
+        }
+        Ok(res)
+    }
+
+    /// Mark our active instances as draining when their members no longer match the peers we'd
+    /// form a party with now, so formation creates a successor. Instances younger than the
+    /// minimum rotation interval are left alone. Returns whether anything changed.
+    pub fn mark_rotations(&self, metadata: &mut PartyMetadata, party_peers: &Vec<PublicKey>) -> bool {
+        let self_pk = self.relay.node_config.public_key();
+        let mut expected = party_peers.iter().cloned().collect::<HashSet<PublicKey>>();
+        expected.insert(self_pk.clone());
+        let now = current_time_millis_i64();
+        let mut changed = false;
+        for inst in metadata.active_proposed_by(&self_pk) {
+            let Some(addr) = inst.address.as_ref() else { continue };
+            if inst.creation_time.unwrap_or(0) + MIN_ROTATION_INTERVAL_MILLIS > now {
+                continue;
+            }
+            if metadata.members_of(addr) != expected {
+                info!("Rotating party instance {} for {} after membership change",
+                    addr.render_string().unwrap_or_default(), inst.currency().abbreviated());
+                counter!("redgold_party_rotation_started", "currency" => inst.currency().abbreviated()).increment(1);
+                changed |= metadata.mark_draining(addr, now);
+            }
+        }
+        changed
+    }
+
+    /// Link draining instances to their successors, then sweep each predecessor's balance and
+    /// any late deposits into the successor. Predecessors that have stayed empty for the drain
+    /// period are retired. Returns whether the metadata changed.
+    pub async fn handle_key_rotations(
+        &self,
+        data: &mut HashMap<PublicKey, PartyInternalData>,
+        metadata: &mut PartyMetadata
+    ) -> RgResult<bool> {
+        let now = current_time_millis_i64();
+        let mut changed = metadata.link_successors(now);
+        let self_pk = self.relay.node_config.public_key();
+
         for (key, dat) in data.iter_mut() {
+            if !dat.self_initiated_not_debug() {
+                continue;
+            }
+            let draining = metadata.draining().into_iter()
+                .filter(|i| i.proposer.as_ref() == Some(key))
+                .collect_vec();
+            dat.draining.retain(|d| draining.iter().any(|i| i.address.as_ref() == Some(&d.predecessor)));
+
+            for inst in draining.iter() {
+                let (Some(pred), Some(succ)) = (inst.address.as_ref(), inst.successor.as_ref()) else { continue };
+                if dat.draining_of(pred).is_none() {
+                    dat.draining.push(DrainingInstance {
+                        currency: inst.currency(),
+                        predecessor: pred.clone(),
+                        successor: succ.clone(),
+                        started_time: now,
+                        last_sweep_time: None,
+                        sweeps: vec![],
+                    });
+                }
+                let d = dat.draining.iter_mut().find(|d| &d.predecessor == pred).expect("inserted");
+                if d.last_sweep_time.map(|t| t + SWEEP_INTERVAL_MILLIS > now).unwrap_or(false) {
+                    continue;
+                }
+                let balance = self.predecessor_balance(inst).await?;
+                if balance < min_sweep_amount(d.currency) {
+                    if d.started_time + DRAIN_PERIOD_MILLIS < now {
+                        info!("Retiring drained party instance {}", pred.render_string().unwrap_or_default());
+                        counter!("redgold_party_rotation_retired", "currency" => d.currency.abbreviated()).increment(1);
+                        metadata.retire(pred, now);
+                        changed = true;
+                    }
+                    continue;
+                }
+                let members = metadata.members_of(pred);
+                let peers = members.iter().filter(|pk| *pk != &self_pk).cloned().collect_vec();
+                let threshold = inst.threshold.safe_get_msg("Missing threshold")?.value;
+                let swept = self.sweep(inst, &members, &peers, threshold, &balance).await.log_error();
+                d.last_sweep_time = Some(now);
+                if let Ok(Some(txid)) = swept {
+                    counter!("redgold_party_rotation_sweeps", "currency" => d.currency.abbreviated()).increment(1);
+                    d.sweeps.push(txid);
+                }
+            }
+            gauge!("redgold_party_draining_instances").set(dat.draining.len() as f64);
+        }
+        Ok(changed)
+    }
+
+    async fn predecessor_balance(&self, inst: &PartyInstance) -> RgResult<CurrencyAmount> {
+        let addr = inst.address.safe_get_msg("Missing address")?;
+        match inst.currency() {
+            SupportedCurrency::Redgold => {
+                let total = self.relay.ds.transaction_store.query_utxo_address(addr).await?
+                    .iter()
+                    .flat_map(|u| u.opt_amount())
+                    .map(|a| a.amount)
+                    .sum::<i64>();
+                Ok(CurrencyAmount::from_rdg(total))
+            }
+            _ => self.external_network_resources.get_live_balance(addr).await,
+        }
+    }
+
+    /// Move the predecessor's balance to its successor through the same multisig paths used for
+    /// order fulfillment, members accept it only because the successor matches their metadata.
+    async fn sweep(
+        &self,
+        inst: &PartyInstance,
+        members: &HashSet<PublicKey>,
+        peers: &Vec<PublicKey>,
+        threshold: i64,
+        balance: &CurrencyAmount,
+    ) -> RgResult<Option<ExternalTransactionId>> {
+        let pred = inst.address.safe_get_msg("Missing address")?;
+        let succ = inst.successor.safe_get_msg("Missing successor")?;
+        let proposer = inst.proposer.safe_get_msg("Missing proposer")?;
+        info!("Sweeping {} {} from party instance {} to successor {}",
+            balance.to_fractional(), inst.currency().abbreviated(),
+            pred.render_string().unwrap_or_default(), succ.render_string().unwrap_or_default());
+        match inst.currency() {
+            SupportedCurrency::Redgold => {
+                let members_vec = members.iter().cloned().collect_vec();
+                let descriptor = AddressDescriptor::from_multisig_public_keys_and_threshold(&members_vec, threshold);
+                let utxos = self.relay.ds.transaction_store.query_utxo_address(pred).await?;
+                // The builder deducts the fee from this output.
+                let tx = TransactionBuilder::new(&self.relay.node_config)
+                    .with_input_address_descriptor(&descriptor)
+                    .with_utxos(&utxos)?
+                    .with_output(succ, balance)
+                    .build()?
+                    .sign_multisig(&self.relay.node_config.keypair(), pred)?;
+                let hash = tx.hash_or();
+                let submitted = self.execute_rdg_multisig_send(
+                    proposer, pred, peers, threshold, succ, balance, tx
+                ).await?;
+                Ok(Some(ExternalTransactionId {
+                    identifier: hash.hex(),
+                    currency: SupportedCurrency::Redgold as i32,
+                }).filter(|_| submitted))
+            }
+            cur => {
+                let amount = if cur == SupportedCurrency::Bitcoin {
+                    CurrencyAmount::from_btc(balance.amount - BTC_SWEEP_FEE_RESERVE_SATS)
+                } else {
+                    balance.clone()
+                };
+                let txid = self.external_network_resources.execute_external_multisig_send(
+                    vec![(succ.clone(), amount)], pred, peers, &self.relay, threshold
+                ).await?;
+                Ok(Some(txid))
+            }
         }
-        Ok(())
     }
-}
\ No newline at end of file
+}
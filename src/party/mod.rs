@@ -16,4 +16,3 @@ pub mod pool_initiated_sell;
 pub mod event_validator;
 pub mod portfolio_request;
 pub mod portfolio_fulfillment_agent;
-pub mod party_wallet_validator;
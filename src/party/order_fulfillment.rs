@@ -1,3 +1,4 @@
+use crate::party::event_validator::{claim_orders, claimed_orders, record_signing_rejection, release_orders, PartyEventValidator};
 use crate::party::party_stream::PartyEventBuilder;
 use crate::party::party_watcher::PartyWatcher;
 use crate::util::current_time_millis_i64;
@@ -9,19 +10,21 @@ use redgold_common::external_resources::{EncodedTransactionPayload, ExternalNetw
 use redgold_common_no_wasm::tx_new::TransactionBuilderSupport;
 use redgold_keys::address_external::{ToBitcoinAddress, ToEthereumAddress};
 use redgold_keys::btc::btc_wallet::SingleKeyBitcoinWallet;
+use redgold_keys::monero::node_wrapper::PartySecretData;
 use redgold_rpc_integ::eth::eth_wallet::EthWalletWrapper;
 use redgold_schema::helpers::easy_json::EasyJson;
 use redgold_schema::observability::errors::{EnhanceErrorInfo, Loggable};
 use redgold_schema::party::address_event::AddressEvent;
 use redgold_schema::party::party_events::{OrderFulfillment, PartyEvents};
 use redgold_schema::party::party_internal_data::PartyInternalData;
+use redgold_schema::parties::{PartyMetadata, PartyState};
 use redgold_schema::party::price_volume::PriceVolume;
 use redgold_schema::proto_serde::ProtoSerde;
-use redgold_schema::structs::{Address, AddressDescriptor, BytesData, CurrencyAmount, ErrorInfo, ExternalTransactionId, Hash, MultipartyIdentifier, MultisigRequest, MultisigResponse, NetworkEnvironment, PartySigningValidation, PublicKey, SubmitTransactionResponse, SupportedCurrency, Transaction, UtxoEntry, UtxoId};
+use redgold_schema::structs::{Address, AddressDescriptor, BytesData, CurrencyAmount, ErrorCode, ErrorInfo, ExternalTransactionId, Hash, MultipartyIdentifier, MultisigRequest, MultisigResponse, NetworkEnvironment, PartySigningValidation, PublicKey, SubmitTransactionResponse, SupportedCurrency, Transaction, UtxoEntry, UtxoId};
 use redgold_schema::message::Request;
 use redgold_schema::tx::external_tx::ExternalTimedTransaction;
 use redgold_schema::tx::tx_builder::TransactionBuilder;
-use redgold_schema::{error_info, structs, RgResult, SafeOption};
+use redgold_schema::{error_info, error_message, structs, RgResult, SafeOption};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
@@ -34,6 +37,24 @@ use crate::core::relay::Relay;
 use crate::core::transact::tx_builder_supports::{TxBuilderApiConvert, TxBuilderApiSupport};
 use crate::util;
 
+/// Funds are only migrated into an instance we hold a key share of ourselves, so a successor
+/// recorded from someone else's metadata can't redirect a sweep.
+async fn holds_successor_share(relay: &Relay, metadata: &PartyMetadata, successor: &Address) -> RgResult<()> {
+    if !metadata.holds_share(successor, &relay.node_config.public_key()) {
+        return Err(error_message(ErrorCode::PartySigningRejected, "No key share held for migration successor"))
+            .with_detail("successor", successor.render_string().unwrap_or_default());
+    }
+    if successor.currency_or() == SupportedCurrency::Monero {
+        let secrets = relay.ds.config_store.get_json::<PartySecretData>("party_secrets").await?
+            .unwrap_or_default();
+        if !secrets.instances.iter().any(|i| &i.address == successor) {
+            return Err(error_message(ErrorCode::PartySigningRejected, "No wallet secret held for migration successor"))
+                .with_detail("successor", successor.render_string().unwrap_or_default());
+        }
+    }
+    Ok(())
+}
+
 pub async fn handle_multisig_request<E: ExternalNetworkResources>(
     multisig_request: &MultisigRequest,
     relay: &Relay,
@@ -54,30 +75,51 @@ pub async fn handle_multisig_request<E: ExternalNetworkResources>(
 
     let dest = multisig_request.destination.safe_get_msg("Missing destination")?;
     let amount = multisig_request.amount.safe_get_msg("Missing amount")?;
-    let orders = party_events.orders();
-    let orders = orders.iter()
-        .filter(|o| &o.destination == dest)
-        .collect_vec();
-    let mut valid = false;
-    for o in orders {
-        let amt = o.fulfilled_amount_typed.clone();
-        let delta = amt.to_fractional() - amount.to_fractional();
-        let abs = f64::abs(delta);
-        let pct = abs / amt.to_fractional();
-        if pct < 0.1f64 {
-            valid = true;
-        }
-    }
+    let cur = dest.currency_or();
 
-    if !valid {
-        "Invalid amount".to_error()?;
+    // Never sign what the proposer claims, only payments that match orders we derived ourselves,
+    // or a sweep of a rotated instance into the successor we have on record for it.
+    let mut validation = PartySigningValidation::default();
+    validation.set_currency(cur);
+    // The exact bytes we're about to sign, so what's validated can't differ from what's signed
+    let mut hash_to_sign = vec![];
+    match cur {
+        SupportedCurrency::Redgold => {
+            let tx = multisig_request.tx.safe_get_msg("Missing tx")?;
+            hash_to_sign = tx.signable_hash().vec();
+            validation.transaction = Some(tx.clone());
+        }
+        SupportedCurrency::Bitcoin => {
+            let encoded = multisig_request.encoded_tx.safe_get_msg("Missing encoded tx")?;
+            hash_to_sign = encoded.as_bytes().to_vec();
+            validation.json_payload = Some(encoded.clone());
+        }
+        _ => {}
     }
-
-    let cur = dest.currency_or();
+    let successor = party.metadata.instance_of_address(party_address)
+        .filter(|i| i.state() == PartyState::PendingRemoval)
+        .and_then(|i| i.successor.as_ref());
+    let order_ids = if successor == Some(dest) {
+        record_signing_rejection(holds_successor_share(relay, &party.metadata, dest).await, cur)?;
+        record_signing_rejection(party_events.validate_migration(&validation, party_address, dest), cur)?;
+        vec![]
+    } else if cur == SupportedCurrency::Redgold || cur == SupportedCurrency::Bitcoin {
+        party_events.validate_event(validation, hash_to_sign, relay).await?
+    } else {
+        // Safe transactions are rebuilt from the declared payment when participating, and
+        // refused there if the proposer's hash differs.
+        record_signing_rejection(
+            party_events.validate_payouts(cur, &vec![(dest.clone(), amount.clone())], &claimed_orders(relay)), cur
+        )?
+    };
+    let threshold = party_instance.threshold.safe_get_msg("Missing threshold")?.value;
+    // Mark the orders in flight so a second request paying them isn't co-signed as well
+    record_signing_rejection(claim_orders(relay, &order_ids), cur)?;
 
     if cur == SupportedCurrency::Redgold {
         let mut tx = multisig_request.tx.safe_get_msg("Missing tx")?.clone();
-        tx = tx.sign_multisig(&relay.node_config.keypair(), party_address)?;
+        tx = tx.sign_multisig(&relay.node_config.keypair(), party_address)
+            .map_err(|e| { release_orders(relay, &order_ids); e })?;
         let mut mr = MultisigResponse::default();
         mr.tx = Some(tx.clone());
         mr.currency = cur as i32;
@@ -87,13 +129,63 @@ pub async fn handle_multisig_request<E: ExternalNetworkResources>(
     let mut response = ext.participate_multisig_send(
                 multisig_request.clone(),
                 &party_members.iter().cloned().collect_vec(),
-                party_instance.threshold.safe_get_msg("Missing threshold")?.value
-    ).await?;
+                threshold
+    ).await.map_err(|e| { release_orders(relay, &order_ids); e })?;
     response.currency = cur as i32;
     Ok(response)
 }
 
 impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
+
+    /// Collect co-signatures from the other members for a transaction we've already signed,
+    /// submitting it once the threshold is met. Returns whether it was submitted.
+    pub async fn execute_rdg_multisig_send(
+        &self,
+        proposer: &PublicKey,
+        party_address: &Address,
+        peers: &Vec<PublicKey>,
+        threshold: i64,
+        dest: &Address,
+        amt: &CurrencyAmount,
+        orig_tx: Transaction,
+    ) -> RgResult<bool> {
+        let mut req = Request::default();
+        let mut mreq = MultisigRequest::default();
+        mreq.proposer_party_key = Some(proposer.clone());
+        mreq.destination = Some(dest.clone());
+        mreq.amount = Some(amt.clone());
+        mreq.mp_address = Some(party_address.clone());
+        mreq.currency = SupportedCurrency::Redgold as i32;
+        mreq.tx = Some(orig_tx.clone());
+        req.multisig_request = Some(mreq);
+        let responses = self.relay.broadcast_async(peers.clone(), req, None).await?;
+        let mut merged_tx = orig_tx.clone();
+        let mut valid_peer_responses = 0;
+        for r in responses.into_iter() {
+            if let Ok(tx) = r
+                .and_then(|r| r.multisig_response.clone().ok_msg("Missing multisig response"))
+                .and_then(|r| r.tx.clone().ok_msg("Missing tx")).log_error() {
+                merged_tx = merged_tx.combine_multisig_proofs(&tx, party_address)?;
+                valid_peer_responses += 1;
+            }
+        }
+        let met_thresh = merged_tx.inputs.iter()
+            .find(|x| x.proof.len() >= threshold as usize)
+            .is_some();
+        let input_proof_len = merged_tx.inputs.iter().map(|x| x.proof.len()).next().unwrap_or(0);
+        if !met_thresh {
+            error!(
+                "Failed to meet threshold for multisig tx: {} out of {} and {} peer responses",
+                input_proof_len,
+                threshold,
+                valid_peer_responses
+            );
+            return Ok(false);
+        }
+        info!("Submitting multisig tx for rdg: {}", merged_tx.hash_hex());
+        Ok(self.relay.submit_transaction_sync(&merged_tx).await.log_error().is_ok())
+    }
+
     pub async fn handle_order_fulfillment(&mut self, data: &mut HashMap<PublicKey, PartyInternalData>) -> RgResult<()> {
 
         
@@ -189,45 +281,11 @@ impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
                                 // info!("Building multisig tx for rdg party aaddress: {}", rdg_address.json_or());
                                 // info!("Building multisig tx for rdg party aaddress amm addr: {}", amm_addr.json_or());
                                 let orig_tx = b.build()?.sign_multisig(&self.relay.node_config.keypair(), &amm_addr)?;
-
-                                let mut req = Request::default();
-                                let mut mreq = MultisigRequest::default();
-                                mreq.proposer_party_key = Some(key.clone());
-                                mreq.destination = Some(dest.clone());
-                                mreq.amount = Some(amt.clone());
-                                mreq.mp_address = Some(amm_addr.clone());
-                                mreq.currency = cur as i32;
-                                mreq.tx = Some(orig_tx.clone());
-                                req.multisig_request = Some(mreq);
-                                let responses = self.relay.broadcast_async(peers.clone(), req, None).await?;
-                                let mut merged_tx = orig_tx.clone();
-                                let mut valid_peer_responses = 0;
-                                for r in responses.into_iter() {
-                                    if let Ok(tx) = r
-                                        .and_then(|r| r.multisig_response.clone().ok_msg("Missing multisig response"))
-                                        .and_then(|r| r.tx.clone().ok_msg("Missing tx")).log_error() {
-                                        merged_tx = merged_tx.combine_multisig_proofs(&tx, &amm_addr)?;
-                                        valid_peer_responses += 1;
-                                    }
-                                }
-                                let met_thresh = merged_tx.inputs.iter()
-                                    .find(|x| x.proof.len() >= threshold as usize)
-                                    .is_some();
-                                let input_proof_len = merged_tx.inputs.iter().map(|x| x.proof.len()).next().unwrap_or(0);
-                                if !met_thresh {
-                                    error!(
-                                        "Failed to meet threshold for multisig tx: {} out of {} and {} peer responses",
-                                        input_proof_len,
-                                        threshold,
-                                        valid_peer_responses
-                                    );
-                                    error!("Broke here");
-                                } else {
-                                    info!("Submitting multisig tx for rdg: {}", merged_tx.hash_hex());
-                                    let submitted = self.relay.submit_transaction_sync(&merged_tx).await.log_error();
-                                    if submitted.is_ok() {
-                                        done_orders.push(o.clone());
-                                    }
+                                let submitted = self.execute_rdg_multisig_send(
+                                    key, &amm_addr, &peers, threshold, &dest, &amt, orig_tx
+                                ).await?;
+                                if submitted {
+                                    done_orders.push(o.clone());
                                 }
                             }
                         } else { // if cur.only_one_destination_per_tx()
@@ -112,7 +112,7 @@ impl PartyEventBuilder for PartyEvents {
     async fn process_event(&mut self, e: &AddressEvent) -> RgResult<()> {
         self.events.push(e.clone());
         let seeds = self.seeds.clone();
-        let time = e.time(&seeds);
+        let time = e.time(&seeds).filter(|_| self.has_confirmation_depth(e));
         if let Some(t) = time {
             self.process_confirmed_event(e, t).await?;
         } else {
@@ -122,6 +122,9 @@ impl PartyEventBuilder for PartyEvents {
         Ok(())
     }
     async fn process_confirmed_event(&mut self, e: &AddressEvent, time: i64) -> Result<(), ErrorInfo> {
+        if self.is_party_migration(e) {
+            return Ok(());
+        }
         let ec = e.clone().clone();
         self.event_fulfillment = None;
         // First update latest USD price oracle information
@@ -175,8 +178,9 @@ impl PartyEventBuilder for PartyEvents {
 
     fn handle_internal_event(&mut self, e: &AddressEvent, time: i64, ec: AddressEvent, t: &TransactionWithObservationsAndPrice) -> RgResult<()> {
         let mut amount = CurrencyAmount::from_rdg(0);
-        // TODO: Does this need to be a detect on all addresses?
-        let party_key_rdg_address = self.key_address().ok_msg("key address")?;
+        // Amounts are relative to the instance the event was queried for, which may be a rotated
+        // predecessor rather than the latest key address.
+        let party_key_rdg_address = t.queried_address.clone();
         let incoming = ec.incoming();
 
         if ec.incoming() {
@@ -371,6 +375,10 @@ impl PartyEventBuilder for PartyEvents {
             portfolio_request_events: Default::default(),
             default_fee_addrs: relay.default_fee_addrs(),
             seeds: relay.node_config.seeds_now_pk(),
+            external_tips: Default::default(),
+            confirmation_depths: vec![SupportedCurrency::Bitcoin, SupportedCurrency::Ethereum].into_iter()
+                .map(|c| (c, relay.node_config.confirmation_depth(c)))
+                .collect(),
             party_addresses,
         }
     }
@@ -58,7 +58,7 @@ impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
                 res.clear();
                 for (pk, nm) in new_metadata.iter() {
                     if other_seeds.contains(pk) {
-                        party_metadata.combine(nm);
+                        party_metadata.combine(nm, pk);
                         self.relay.ds.config_store.set_json("party_metadata", &party_metadata).await?;
                     }
                 }
@@ -67,12 +67,15 @@ impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
 
         let mut req = message::Request::default();
         req.get_party_metadata_request = Some(message::GetPartyMetadataRequest {});
-        let responses = self.relay.broadcast_async(other_seeds.clone(), req, None).await?;
-        for r in responses {
+        let self_pk = self.relay.node_config.public_key();
+        // Responses are in the order of the peers queried, excluding ourselves
+        let queried = other_seeds.iter().filter(|p| *p != &self_pk).cloned().collect_vec();
+        let responses = self.relay.broadcast_async(queried.clone(), req, None).await?;
+        for (pk, r) in queried.iter().zip(responses) {
             if let Ok(r) = r {
                 if let Some(r) = &r.get_party_metadata_response {
                     if let Some(pmd) = r.party_metadata.as_ref() {
-                        party_metadata.combine(&pmd);
+                        party_metadata.combine(&pmd, pk);
                         self.relay.ds.config_store.set_json("party_metadata", &party_metadata).await?;
                     }
                 }
@@ -80,7 +83,7 @@ impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
         }
 
         let mut party_secrets = self.relay.ds.config_store
-            .get_json::<PartySecretData>("party_secret").await?
+            .get_json::<PartySecretData>("party_secrets").await?
             .unwrap_or(Default::default());
 
         gauge!("redgold_party_initial_formation_non_self_peers").set(other_seeds.len() as f64);
@@ -93,11 +96,16 @@ impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
         }
 
         if self.relay.node_config.enable_party_mode() {
+            let party_peers = self.trusted_party_peers(&other_seeds, &party_metadata).await?;
+            if party_peers.len() >= 2 && self.mark_rotations(&mut party_metadata, &party_peers) {
+                self.relay.ds.config_store.set_json("party_metadata", &party_metadata).await?;
+                self.notify_party_metadata(&party_metadata, &other_seeds).await?;
+            }
             let update_events = check_formations(
                 &party_metadata,
                 &self.external_network_resources,
                 &self.relay.node_config.words().to_all_addresses_default(&self.relay.node_config.network)?,
-                &other_seeds,
+                &party_peers,
                 &self.relay,
                 &self.relay.node_config.public_key(),
                 None,
@@ -115,11 +123,7 @@ impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
                     party_secrets.instances.push(s.clone());
                 }
                 self.relay.ds.config_store.set_json("party_secrets", &party_secrets).await?;
-                let mut req = message::Request::default();
-                req.notify_multisig_creation_request = Some(message::NotifyMultisigCreationRequest {
-                    party_metadata: Some(party_metadata.clone()),
-                });
-                self.relay.broadcast_async(other_seeds, req, None).await?;
+                self.notify_party_metadata(&party_metadata, &other_seeds).await?;
             }
         }
 
@@ -137,13 +141,17 @@ impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
         self.calculate_party_stream_events(&mut shared_data).await?;
         if self.relay.node_config.enable_party_mode() {
             self.handle_order_fulfillment(&mut shared_data).await?;
+            if self.handle_key_rotations(&mut shared_data, &mut party_metadata).await? {
+                self.relay.ds.config_store.set_json("party_metadata", &party_metadata).await?;
+                self.notify_party_metadata(&party_metadata, &other_seeds).await?;
+            }
         }
-        // self.handle_key_rotations(&mut shared_data).await?;
         //
         // info!("nodeid: {}, Party watcher tick num parties total {} active {} keys {}",
         //     self.relay.node_config.short_id().expect("Node ID"),
         //     party_metadata.clone().instances.len(), active.len(),
         // shared_data.keys().collect_vec().json_or());
+        self.publish_new_fulfillments(&shared_data).await;
         self.relay.external_network_shared_data.write(shared_data.clone()).await;
 
         self.relay.ds.config_store
@@ -163,9 +171,43 @@ impl<T> PartyWatcher<T> where T: ExternalNetworkResources + Send {
     }
 
 
+    /// Stream fulfillments that weren't in the previous tick's events. Parties seen for the first
+    /// time since startup only establish the baseline.
+    async fn publish_new_fulfillments(&self, data: &HashMap<PublicKey, PartyInternalData>) {
+        let prior = self.relay.external_network_shared_data.clone_read().await;
+        for (k, v) in data.iter() {
+            let (Some(pe), Some(prior_pe)) = (
+                v.party_events.as_ref(), prior.get(k).and_then(|p| p.party_events.as_ref())
+            ) else { continue };
+            let seen = prior_pe.fulfillment_history.iter()
+                .map(|(_, _, f)| f.identifier())
+                .collect::<HashSet<String>>();
+            for (of, _, f) in pe.fulfillment_history.iter() {
+                let id = f.identifier();
+                if !seen.contains(&id) {
+                    self.relay.stream_events.publish_party_fulfillment(k, of, id);
+                }
+            }
+        }
+    }
+
+    async fn notify_party_metadata(&self, party_metadata: &PartyMetadata, peers: &Vec<PublicKey>) -> RgResult<()> {
+        let mut req = message::Request::default();
+        req.notify_multisig_creation_request = Some(message::NotifyMultisigCreationRequest {
+            party_metadata: Some(party_metadata.clone()),
+        });
+        self.relay.broadcast_async(peers.clone(), req, None).await?;
+        Ok(())
+    }
+
     async fn calculate_party_stream_events(&self, data: &mut HashMap<PublicKey, PartyInternalData>) -> RgResult<()> {
         for (k,v ) in data.iter_mut() {
             let mut pe = PartyEvents::new(&self.relay.node_config.network, &self.relay, v.metadata.address_by_currency());
+            for c in pe.confirmation_depths.keys().cloned().collect_vec() {
+                if let Some(tip) = self.relay.ds.external_event.query_tip(c).await? {
+                    pe.external_tips.insert(c, tip as u64);
+                }
+            }
             for e in v.address_events.iter() {
                 pe.process_event(e).await?;
             }
@@ -1,76 +1,267 @@
-use ndarray::arr2;
+use std::collections::{HashMap, HashSet};
 
-#[test]
-fn debug() {
-    let a = arr2(&[[1., 0.9, 0.5], [0.8, 1., 0.2], [0.3, 0.4, 1.]]);
+use redgold_schema::structs::{PeerId, TrustRatingLabel};
+use serde::{Deserialize, Serialize};
 
-    println!("{:?}", a);
-}
-
-// https://docs.rs/dot/0.1.4/dot/
-// https://crates.io/crates/tabbycat
-// https://github.com/datproject/dat
-// https://www.reddit.com/r/rust/comments/g3ub83/is_anyone_using_rust_analyzer_editing_on_remote/
+// https://nlp.stanford.edu/pubs/eigentrust.pdf
 
 const THRESHOLD: f64 = 1e-9;
 const MAX_ITERATIONS: usize = 1000;
+/// Weight given to the pre-trusted (seed) distribution on every iteration, bounds the influence
+/// of malicious collectives that only vouch for each other.
+pub const DEFAULT_PRE_TRUST_WEIGHT: f64 = 0.15;
 
-fn normalize(matrix: &Vec<Vec<f64>>) -> Vec<Vec<f64>> {
-    let n = matrix.len();
-    let mut normalized = vec![vec![0.0; n]; n];
+/// A single directed rating used as an input to the trust matrix.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TrustEdge {
+    pub from: PeerId,
+    pub to: PeerId,
+    pub label: f64,
+    pub confidence: f64,
+}
 
-    for i in 0..n {
-        let sum: f64 = matrix[i].iter().sum();
-        for j in 0..n {
-            normalized[i][j] = if sum == 0.0 { 0.0 } else { matrix[i][j] / sum };
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PeerTrustScore {
+    pub peer_id: PeerId,
+    /// Global trust from the stationary distribution, sums to 1 across all peers
+    pub global_trust: f64,
+    /// Global trust rescaled so the most trusted peer is 1.0, used to weight rewards
+    pub score: f64,
+    /// Incoming labels averaged by each rater's global trust, on the same scale as the labels
+    /// themselves. None when no peer with any global trust has rated this one.
+    pub rating: Option<f64>,
+}
+
+/// Latest EigenTrust run along with the inputs it was computed from.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+pub struct EigenTrustResult {
+    pub time: i64,
+    pub iterations: usize,
+    pub pre_trust: Vec<(PeerId, f64)>,
+    pub edges: Vec<TrustEdge>,
+    pub scores: Vec<PeerTrustScore>,
+}
+
+impl EigenTrustResult {
+    pub fn score_map(&self) -> HashMap<PeerId, f64> {
+        self.scores.iter().map(|s| (s.peer_id.clone(), s.score)).collect()
+    }
+
+    /// Label scale ratings, this is what get_trust and the trust thresholds use.
+    pub fn rating_map(&self) -> HashMap<PeerId, f64> {
+        self.scores.iter()
+            .filter_map(|s| s.rating.map(|r| (s.peer_id.clone(), r)))
+            .collect()
+    }
+}
+
+/// Flatten the labels each peer has published into weighted edges. Each label contributes
+/// `label * confidence`, with confidence defaulting to 1.0 when unset. Negative ratings are
+/// kept in the inputs but clamped to zero in the matrix, as EigenTrust requires non-negative
+/// local trust.
+pub fn trust_edges(labels_by_peer: &Vec<(PeerId, Vec<TrustRatingLabel>)>) -> Vec<TrustEdge> {
+    let mut edges = vec![];
+    for (from, labels) in labels_by_peer {
+        for l in labels {
+            let Some(to) = l.peer_id.as_ref() else { continue };
+            if to == from {
+                continue;
+            }
+            for d in &l.trust_data {
+                if let Some(label) = d.maybe_label() {
+                    edges.push(TrustEdge {
+                        from: from.clone(),
+                        to: to.clone(),
+                        label,
+                        confidence: d.maybe_confidence().unwrap_or(1.0),
+                    });
+                }
+            }
         }
     }
-    normalized
+    edges
+}
+
+/// Run EigenTrust over the given edges, seeded by the pre-trusted peers.
+pub fn compute_eigen_trust(
+    edges: &Vec<TrustEdge>,
+    pre_trust: &HashMap<PeerId, f64>,
+    pre_trust_weight: f64,
+    time: i64,
+) -> EigenTrustResult {
+    let mut peers = HashSet::new();
+    for e in edges {
+        peers.insert(e.from.clone());
+        peers.insert(e.to.clone());
+    }
+    for p in pre_trust.keys() {
+        peers.insert(p.clone());
+    }
+    let mut peers = peers.into_iter().collect::<Vec<PeerId>>();
+    peers.sort_by_key(|p| p.raw_hex_or_from_public_key());
+    let index = peers.iter().enumerate()
+        .map(|(i, p)| (p.clone(), i))
+        .collect::<HashMap<PeerId, usize>>();
+    let n = peers.len();
+
+    let mut pre_trust_vector = vec![0.0; n];
+    for (p, t) in pre_trust {
+        pre_trust_vector[index[p]] = t.max(0.0);
+    }
+    let pre_trust_vector = normalize_vector(&pre_trust_vector)
+        .unwrap_or_else(|| vec![1.0 / (n.max(1) as f64); n]);
+
+    let mut matrix = vec![vec![0.0; n]; n];
+    for e in edges {
+        matrix[index[&e.from]][index[&e.to]] += (e.label * e.confidence).max(0.0);
+    }
+    let normalized = normalize(&matrix, &pre_trust_vector);
+    let (global, iterations) = power_iteration(&normalized, &pre_trust_vector, pre_trust_weight);
+
+    let ratings = calibrated_ratings(edges, &index, &global);
+    let max = global.iter().cloned().fold(0.0, f64::max);
+    let scores = peers.iter().zip(global.iter()).zip(ratings).map(|((p, g), rating)| PeerTrustScore {
+        peer_id: p.clone(),
+        global_trust: *g,
+        score: if max > 0.0 { g / max } else { 0.0 },
+        rating,
+    }).collect();
+
+    EigenTrustResult {
+        time,
+        iterations,
+        pre_trust: peers.iter().zip(pre_trust_vector.iter())
+            .filter(|(_, t)| **t > 0.0)
+            .map(|(p, t)| (p.clone(), *t))
+            .collect(),
+        edges: edges.clone(),
+        scores,
+    }
 }
 
-fn power_iteration(matrix: &Vec<Vec<f64>>) -> Vec<f64> {
+/// Average the labels each peer received, weighting every rater by its global trust and the
+/// label's confidence. Unlike the global trust vector this doesn't shrink as the network grows.
+fn calibrated_ratings(edges: &Vec<TrustEdge>, index: &HashMap<PeerId, usize>, global: &Vec<f64>) -> Vec<Option<f64>> {
+    let mut weighted = vec![0.0; global.len()];
+    let mut weights = vec![0.0; global.len()];
+    for e in edges {
+        let w = global[index[&e.from]] * e.confidence.max(0.0);
+        let to = index[&e.to];
+        weighted[to] += w * e.label.clamp(0.0, 1.0);
+        weights[to] += w;
+    }
+    weighted.iter().zip(weights.iter())
+        .map(|(s, w)| if *w > 0.0 { Some(s / w) } else { None })
+        .collect()
+}
+
+fn normalize_vector(v: &Vec<f64>) -> Option<Vec<f64>> {
+    let sum: f64 = v.iter().sum();
+    if sum <= 0.0 {
+        return None;
+    }
+    Some(v.iter().map(|x| x / sum).collect())
+}
+
+/// Row normalize local trust. Peers that haven't rated anyone defer to the pre-trust distribution.
+fn normalize(matrix: &Vec<Vec<f64>>, pre_trust: &Vec<f64>) -> Vec<Vec<f64>> {
+    matrix.iter()
+        .map(|row| normalize_vector(row).unwrap_or_else(|| pre_trust.clone()))
+        .collect()
+}
+
+/// Iterate t = (1 - a) * C^T * t + a * p until convergence, returning the vector and iterations.
+fn power_iteration(matrix: &Vec<Vec<f64>>, pre_trust: &Vec<f64>, alpha: f64) -> (Vec<f64>, usize) {
     let n = matrix.len();
-    let mut vector = vec![1.0 / (n as f64).sqrt(); n];
-    let mut previous_vector = vec![0.0; n];
+    let mut vector = pre_trust.clone();
 
-    for _ in 0..MAX_ITERATIONS {
-        // Multiply matrix with vector
+    for iteration in 1..=MAX_ITERATIONS {
         let mut new_vector = vec![0.0; n];
         for i in 0..n {
             for j in 0..n {
-                new_vector[i] += matrix[i][j] * vector[j];
+                new_vector[j] += matrix[i][j] * vector[i];
             }
         }
-
-        // Normalize the resulting vector
-        let magnitude: f64 = new_vector.iter().map(|&x| x*x).sum::<f64>().sqrt();
-        for i in 0..n {
-            new_vector[i] /= magnitude;
+        for j in 0..n {
+            new_vector[j] = (1.0 - alpha) * new_vector[j] + alpha * pre_trust[j];
         }
 
-        // Check convergence
-        let max_difference: f64 = vector.iter().zip(&new_vector).map(|(&a, &b)| (a - b).abs()).fold(0.0, f64::max);
+        let max_difference: f64 = vector.iter().zip(&new_vector)
+            .map(|(&a, &b)| (a - b).abs())
+            .fold(0.0, f64::max);
+        vector = new_vector;
         if max_difference < THRESHOLD {
-            return new_vector;
+            return (vector, iteration);
         }
-
-        previous_vector = vector;
-        vector = new_vector;
     }
-    vector
+    (vector, MAX_ITERATIONS)
 }
 
-fn eigen_trust(matrix: &Vec<Vec<f64>>) -> Vec<f64> {
-    let normalized_matrix = normalize(matrix);
-    power_iteration(&normalized_matrix)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use redgold_schema::structs::TrustData;
+
+    fn peer(i: u8) -> PeerId {
+        PeerId::from_bytes_direct(vec![i; 33])
+    }
 
-fn main() {
-    let matrix = vec![
-        vec![1.0, 0.0, 1.0],
-        vec![0.0, 1.0, 0.0],
-        vec![0.0, 0.0, 1.0]
-    ];
-    let trust = eigen_trust(&matrix);
-    println!("{:?}", trust);
-}
\ No newline at end of file
+    fn rate(from: u8, to: u8, label: f64) -> (PeerId, Vec<TrustRatingLabel>) {
+        (peer(from), vec![TrustRatingLabel {
+            peer_id: Some(peer(to)),
+            trust_data: vec![TrustData::from_label(label)],
+        }])
+    }
+
+    #[test]
+    fn seeded_peers_outrank_isolated_collective() {
+        // 1 is the seed and vouches for 2, while 3 and 4 only vouch for each other.
+        let labels = vec![
+            rate(1, 2, 0.9),
+            rate(2, 1, 0.9),
+            rate(3, 4, 1.0),
+            rate(4, 3, 1.0),
+        ];
+        let edges = trust_edges(&labels);
+        let pre_trust = HashMap::from([(peer(1), 1.0)]);
+        let result = compute_eigen_trust(&edges, &pre_trust, DEFAULT_PRE_TRUST_WEIGHT, 0);
+        let scores = result.score_map();
+        assert_eq!(scores[&peer(1)], 1.0);
+        assert!(scores[&peer(2)] > 0.5);
+        assert!(scores[&peer(3)] < 1e-6);
+        assert!(scores[&peer(4)] < 1e-6);
+        let total: f64 = result.scores.iter().map(|s| s.global_trust).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+
+        // Ratings stay on the label scale, and the isolated collective gets none
+        let ratings = result.rating_map();
+        assert!((ratings[&peer(1)] - 0.9).abs() < 1e-9);
+        assert!((ratings[&peer(2)] - 0.9).abs() < 1e-9);
+        assert!(!ratings.contains_key(&peer(3)));
+        assert!(!ratings.contains_key(&peer(4)));
+    }
+
+    #[test]
+    fn ratings_do_not_shrink_with_network_size() {
+        // A seed rating 50 peers at 0.7 should leave each of them at 0.7
+        let labels = (2..52).map(|i| rate(1, i, 0.7)).collect::<Vec<_>>();
+        let edges = trust_edges(&labels);
+        let pre_trust = HashMap::from([(peer(1), 1.0)]);
+        let result = compute_eigen_trust(&edges, &pre_trust, DEFAULT_PRE_TRUST_WEIGHT, 0);
+        let ratings = result.rating_map();
+        for i in 2..52 {
+            assert!((ratings[&peer(i)] - 0.7).abs() < 1e-9);
+        }
+        assert!(result.score_map()[&peer(2)] < 0.1);
+    }
+
+    #[test]
+    fn negative_and_self_ratings_carry_no_trust() {
+        let labels = vec![rate(1, 2, -0.5), rate(1, 1, 1.0)];
+        let edges = trust_edges(&labels);
+        assert_eq!(edges.len(), 1);
+        let pre_trust = HashMap::from([(peer(1), 1.0)]);
+        let result = compute_eigen_trust(&edges, &pre_trust, DEFAULT_PRE_TRUST_WEIGHT, 0);
+        assert!(result.score_map()[&peer(2)] < 1e-6);
+    }
+}
@@ -1,7 +1,15 @@
 use crate::core::relay::Relay;
+use crate::trust::eigentrust::{compute_eigen_trust, trust_edges, DEFAULT_PRE_TRUST_WEIGHT};
+use crate::util;
 use async_trait::async_trait;
+use metrics::gauge;
 use redgold_common_no_wasm::stream_handlers::IntervalFold;
-use redgold_schema::RgResult;
+use redgold_schema::helpers::easy_json::EasyJson;
+use redgold_schema::conf::node_config::NodeConfig;
+use redgold_schema::structs::{PeerId, PeerMetadata, TrustRatingLabel};
+use redgold_schema::{error_info, RgResult};
+use std::collections::HashMap;
+use tracing::{debug, error};
 
 pub mod eigentrust;
 pub mod moon;
@@ -9,98 +17,98 @@ pub mod rewards;
 pub mod embed;
 pub mod features;
 
-#[allow(dead_code)]
-struct Trust {
+/// Periodically rebuilds the trust matrix from every known peer's published labels and runs
+/// EigenTrust seeded by the configured seeds. The predicted scores used by get_trust are the
+/// trust weighted label averages, the raw EigenTrust scores are kept separately on the relay.
+pub struct Trust {
     relay: Relay
 }
 
-// Honestly is this even needed? Just update the internal database and use a model
-// every 5 minutes.
-#[allow(dead_code)]
+impl Trust {
+    pub fn new(relay: &Relay) -> Self {
+        Self {
+            relay: relay.clone()
+        }
+    }
+}
+
+/// Latest metadata published by each known peer, our own peer transaction takes precedence over
+/// whatever copy of it is in the peer store.
+pub async fn peer_metadata(relay: &Relay) -> RgResult<HashMap<PeerId, PeerMetadata>> {
+    let mut res = HashMap::new();
+    for tx in relay.ds.peer_store.all_peers_tx().await? {
+        match tx.peer_data() {
+            Ok(pd) => {
+                if let Some(pid) = pd.peer_id.clone() {
+                    res.insert(pid, pd);
+                }
+            }
+            Err(e) => error!("Invalid peer transaction in trust calculation: {}", e.json_or()),
+        }
+    }
+    let pd = relay.peer_tx().await?.peer_data()?;
+    if let Some(pid) = pd.peer_id.clone() {
+        res.insert(pid, pd);
+    }
+    Ok(res)
+}
+
+/// Peer metadata as of `end`, taken only from accepted peer transactions so every node derives
+/// the same snapshot regardless of what its peer store currently holds.
+pub async fn peer_metadata_at(relay: &Relay, end: i64) -> RgResult<HashMap<PeerId, PeerMetadata>> {
+    let mut res = HashMap::new();
+    for tx in relay.ds.transaction_store.query_accepted_metadata_before(end).await? {
+        // Node metadata transactions carry no peer data, later peer transactions replace earlier ones
+        if let Ok(pd) = tx.peer_data() {
+            if let Some(pid) = pd.peer_id.clone() {
+                res.insert(pid, pd);
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Sorted by peer so the resulting trust edges are in the same order on every node.
+pub fn labels_by_peer(metadata: &HashMap<PeerId, PeerMetadata>) -> Vec<(PeerId, Vec<TrustRatingLabel>)> {
+    let mut labels = metadata.iter()
+        .map(|(p, pd)| (p.clone(), pd.labels.clone()))
+        .collect::<Vec<_>>();
+    labels.sort_by_key(|(p, _)| p.raw_hex_or_from_public_key());
+    labels
+}
+
+/// Pre-trust distribution for EigenTrust taken from the seeds active at `time`.
+pub fn seed_pre_trust(node_config: &NodeConfig, time: i64) -> HashMap<PeerId, f64> {
+    node_config.seeds_at(time).iter().filter_map(|s|
+        s.peer_id.clone().map(|p| (p, s.trust.get(0).and_then(|t| t.maybe_label()).unwrap_or(0.8)))
+    ).collect()
+}
+
 #[async_trait]
 impl IntervalFold for Trust {
     async fn interval_fold(&mut self) -> RgResult<()> {
+        let now = util::current_time_millis_i64();
+        let edges = trust_edges(&labels_by_peer(&peer_metadata(&self.relay).await?));
+        let pre_trust = seed_pre_trust(&self.relay.node_config, now);
+        let result = compute_eigen_trust(&edges, &pre_trust, DEFAULT_PRE_TRUST_WEIGHT, now);
+        debug!("EigenTrust converged in {} iterations over {} peers and {} edges",
+            result.iterations, result.scores.len(), result.edges.len());
+        gauge!("redgold_trust_peers_scored").set(result.scores.len() as f64);
 
+        // Seeds are left out so get_trust falls back to their configured trust
+        let ratings = result.rating_map().into_iter()
+            .filter(|(p, _)| !pre_trust.contains_key(p))
+            .collect::<HashMap<PeerId, f64>>();
+        *self.relay.predicted_trust_overall_rating_score.lock().map_err(|e| error_info(format!(
+            "Failed to lock predicted_trust_overall_rating_score {}", e.to_string()))
+        )? = ratings;
+        *self.relay.eigen_trust.lock().map_err(|e| error_info(format!(
+            "Failed to lock eigen_trust {}", e.to_string()))
+        )? = result;
         Ok(())
     }
-    // async fn run(&mut self) {
-    //     loop {
-    //         let update = self.relay.trust.receiver.recv().unwrap();
-    //         match update.remove_peer {
-    //             None => {}
-    //             Some(remove) => {
-    //                 self.labels.remove(&remove);
-    //                 continue;
-    //             }
-    //         }
-    //         let peer_data = update.update;
-    //         if peer_data.labels.is_empty() {
-    //             continue;
-    //         }
-    //
-    //         let id = peer_data.peer_id.unwrap().peer_id.unwrap().value;
-    //         let latest = self.labels.get(&id).unwrap();
-    //
-    //         let mut label_updates: HashMap<Vec<u8>, f64> = HashMap::new();
-    //
-    //         for label in peer_data.labels {
-    //             let l = label.trust_data.get(0).expect("data").label;
-    //             label_updates.insert(label.peer_id, l);
-    //         }
-    //
-    //         if label_updates != *latest {
-    //             // self.labels.insert(self.pe, label_updates);
-    //             // self.recalculate_trust()
-    //         }
-    //     }
-    // }
-    // #[allow(dead_code)]
-    // pub fn new(relay: Relay) {
-    //     let mut b = Self {
-    //         relay,
-    //         labels: HashMap::new(),
-    //     };
-    //     tokio::spawn(async move { b.run().await });
-    // }
 }
 
-// https://texample.net/tikz/examples/neural-network/
-// https://docs.rs/ndarray/0.15.3/ndarray/doc/ndarray_for_numpy_users/index.html
-// fn convert_trust_map(
-//     peer_metadata: &Vec<PeerMetadata>,
-//     self_labels: &Vec<TrustRatingLabel>,
-//     self_peer_id: &Vec<u8>
-// ) -> HashMap<&Vec<u8>, Vec<TrustRatingLabel>> {
-//     let mut map: HashMap<&Vec<u8>, Vec<TrustRatingLabel>> = HashMap::new();
-//     map.insert(self_peer_id, self_labels.clone());
-//     for x in peer_metadata {
-//         map.insert(&x.peer_id.clone(), &x.labels);
-//     }
-//     return map;
-// }
-
-// fn calculate_trust(
-//     peer_metadata: &Vec<PeerMetadata>,
-//     self_labels: &Vec<TrustRatingLabel>
-// ) {
-//     let mut set: HashSet<Vec<u8>> = HashSet::new();
-//     for l in peer_metadata {
-//         set.insert(l.peer_id.clone());
-//     }
-//     for l in self_labels {
-//         set.insert(l.peer_id.clone());
-//     }
-//     let mut peers = set.iter().collect::<Vec<Vec<u8>>>();
-//     peers.sort();
-//     let mut idx_to_peer : HashMap<usize, &Vec<u8>> = HashMap::default();
-//     let mut peer_to_idx : HashMap<&Vec<u8>, usize> = HashMap::default();
-//     for (idx, p) in peers.iter().enumerate(){
-//         idx_to_peer.insert(idx, p);
-//         peer_to_idx.insert(p, idx);
-//     }
-//
-// }
-
 #[test]
 fn test_bed() {
     println!("wo")
@@ -1,104 +1,244 @@
-// use std::sync::Arc;
-// use std::time::Duration;
-//
-// use tokio::runtime::Runtime;
-//
-// use crate::core::relay::Relay;
-// use crate::data::data_store::RewardQueryResult;
-// use crate::schema::structs::{Input, Output, Transaction};
-// use crate::schema::transaction::amount_data;
-// use crate::util::current_time_unix;
-// use redgold_schema::constants::{MIN_FEE_RAW, REWARD_AMOUNT_RAW};
-// use redgold_schema::structs::Address;
-//
-// #[allow(dead_code)]
-// #[derive(Clone)]
-// pub struct Rewards {
-//     relay: Relay,
-//     moon_times: Vec<u64>,
-//     next_reward: u64,
-// }
-//
-// impl Rewards {
-//     fn generate_reward_transaction(&self) {
-//         let _latest = self.relay.ds.select_latest_reward_hash().unwrap();
-//         let _input = Input {
-//             transaction_hash: None,
-//             output_index: 0,
-//             proof: vec![],
-//             product_id: None,
-//             output: None,
-//         };
-//         let weights = self.relay.ds.select_reward_weights().unwrap();
-//         let sum = weights
-//             .clone()
-//             .iter()
-//             .map(|x| x.deterministic_trust)
-//             .sum::<f64>();
-//         let filtered = weights
-//             .iter()
-//             .filter(|x| {
-//                 (x.deterministic_trust / sum) * (REWARD_AMOUNT_RAW as f64) > (MIN_FEE_RAW as f64)
-//             })
-//             .collect::<Vec<&RewardQueryResult>>();
-//         let sum2 = filtered
-//             .clone()
-//             .iter()
-//             .map(|x| x.deterministic_trust)
-//             .sum::<f64>();
-//         let rounded = filtered
-//             .clone()
-//             .iter()
-//             .map(|x| ((x.deterministic_trust.clone() / sum2) * (REWARD_AMOUNT_RAW as f64)) as u64)
-//             .sum::<u64>() as i64;
-//         let delta = REWARD_AMOUNT_RAW - rounded;
-//
-//         let _outputs = filtered
-//             .clone()
-//             .iter()
-//             .enumerate()
-//             .map(|(idx, x)| {
-//                 let mut _rounding = 0 as u64;
-//                 if idx == 0 {
-//                     _rounding = delta as u64;
-//                 }
-//                 let amount =
-//                     ((x.deterministic_trust.clone() / sum2) * (REWARD_AMOUNT_RAW as f64)) as u64;
-//                 Output {
-//                     address: Address::address_data(x.reward_address.clone()),
-//                     product_id: None,
-//                     counter_party_proofs: vec![],
-//                     data: amount_data(amount + (delta as u64)),
-//                     contract: None,
-//                 }
-//             })
-//             .collect::<Vec<Output>>();
-//         // let _transaction = Transaction {
-//         //     inputs: vec![input],
-//         //     outputs,
-//         //     options: None,
-//         // };
-//     }
-//
-//     async fn run(&mut self) {
-//         let mut interval = tokio::time::interval(Duration::from_secs(
-//             self.relay.node_config.reward_poll_interval_secs,
-//         ));
-//         loop {
-//             interval.tick().await;
-//             if current_time_unix() as u64 > self.next_reward {
-//                 let _transaction = self.generate_reward_transaction();
-//             }
-//         }
-//     }
-//     // https://stackoverflow.com/questions/63347498/tokiospawn-borrowed-value-does-not-live-long-enough-argument-requires-tha
-//     pub fn new(relay: Relay, arc: Arc<Runtime>) {
-//         let vec = crate::trust::moon::load_moons();
-//         let mut b = Self {
-//             relay,
-//             moon_times: vec.clone(),
-//             next_reward: crate::trust::moon::next_reward_time(vec.clone()),
-//         };
-//         arc.spawn(async move { b.run().await });
-//     }
-// }
+use std::collections::{HashMap, HashSet};
+
+use async_trait::async_trait;
+use itertools::Itertools;
+use metrics::counter;
+use redgold_common_no_wasm::stream_handlers::IntervalFold;
+use redgold_schema::helpers::easy_json::EasyJson;
+use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
+use redgold_schema::structs::{Address, ErrorCode, PeerId, PeerMetadata, PublicKey, RewardDistribution, Transaction};
+use redgold_schema::tx::reward::reward_transaction;
+use redgold_schema::{error_message, RgResult};
+use tracing::{error, info};
+
+use crate::core::relay::Relay;
+use crate::trust::eigentrust::{compute_eigen_trust, trust_edges, DEFAULT_PRE_TRUST_WEIGHT};
+use crate::trust::{labels_by_peer, peer_metadata_at, seed_pre_trust};
+use crate::util;
+
+// Uptime is measured as the fraction of these slots in which a peer's node produced an observation.
+const UPTIME_SLOT_MILLIS: i64 = 1000 * 60 * 60;
+// Trust and uptime are combined into integer weights at this precision before splitting fees.
+const WEIGHT_SCALE: f64 = 1e6;
+const MAX_EPOCHS_PER_TICK: usize = 3;
+
+/// Reward epochs run between consecutive full moons, epoch `i` ends at moon `i`.
+pub fn epoch_bounds(moons: &Vec<u64>, epoch: usize) -> Option<(i64, i64)> {
+    let start = moons.get(epoch.checked_sub(1)?)?;
+    let end = moons.get(epoch)?;
+    Some((*start as i64 * 1000, *end as i64 * 1000))
+}
+
+/// Combine trust scores and uptime into payout weights, keyed by reward address.
+pub fn reward_weights(
+    trust: &HashMap<PeerId, f64>,
+    uptime: &HashMap<PeerId, f64>,
+    reward_addresses: &HashMap<PeerId, Address>,
+) -> Vec<(Address, i64)> {
+    reward_addresses.iter()
+        .sorted_by_key(|(p, _)| p.raw_hex_or_from_public_key())
+        .map(|(p, a)| {
+            let t = trust.get(p).cloned().unwrap_or(0.0).clamp(0.0, 1.0);
+            let u = uptime.get(p).cloned().unwrap_or(0.0).clamp(0.0, 1.0);
+            (a.clone(), (t * u * WEIGHT_SCALE).round() as i64)
+        })
+        .collect()
+}
+
+/// Fraction of uptime slots in the window in which each peer observed at least once.
+pub fn uptime_fractions(start: i64, end: i64, observed: &Vec<(PeerId, i64)>) -> HashMap<PeerId, f64> {
+    let slots = ((end - start + UPTIME_SLOT_MILLIS - 1) / UPTIME_SLOT_MILLIS).max(1);
+    let mut seen: HashMap<PeerId, HashSet<i64>> = HashMap::new();
+    for (p, time) in observed.iter().filter(|(_, t)| *t >= start && *t < end) {
+        seen.entry(p.clone()).or_default().insert((time - start) / UPTIME_SLOT_MILLIS);
+    }
+    seen.into_iter().map(|(p, s)| (p, s.len() as f64 / slots as f64)).collect()
+}
+
+/// Node keys declared in accepted peer metadata. Peers are visited in a fixed order and a key
+/// keeps the first peer declaring it, so every node resolves a contested key the same way.
+pub fn node_peers(metadata: &HashMap<PeerId, PeerMetadata>) -> HashMap<PublicKey, PeerId> {
+    let mut res = HashMap::new();
+    for (p, pd) in metadata.iter().sorted_by_key(|(p, _)| p.raw_hex_or_from_public_key()) {
+        for pk in pd.node_metadata.iter().flat_map(|n| n.public_key.as_ref()) {
+            res.entry(pk.clone()).or_insert(p.clone());
+        }
+    }
+    res
+}
+
+/// Builds each completed epoch's reward transaction and has seeds submit it. Every input is
+/// derived from transactions accepted before the epoch end, the observation chains nodes
+/// gossip and sync, and configuration, never from the local peer store, so all honest nodes
+/// build the same transaction and accept a submitted one only if it matches their own.
+pub struct Rewards {
+    relay: Relay,
+    moons: Vec<u64>,
+}
+
+impl Rewards {
+    pub fn new(relay: &Relay) -> Self {
+        Self {
+            relay: relay.clone(),
+            moons: crate::trust::moon::load_moons(),
+        }
+    }
+
+    fn fee_addresses_at(&self, time: i64) -> Vec<Address> {
+        self.relay.node_config.seeds_at(time).iter()
+            .flat_map(|s| s.peer_id.as_ref())
+            .flat_map(|p| p.peer_id.as_ref())
+            .flat_map(|p| p.address().ok())
+            .collect_vec()
+    }
+
+    fn settled(&self, end: i64) -> bool {
+        let settle = self.relay.node_config.transaction_finalization_time.as_millis() as i64;
+        end + settle <= util::current_time_millis_i64()
+    }
+
+    /// Times at which each peer's nodes signed observations in `[start, end)`. Signers are
+    /// resolved through accepted peer metadata rather than the local peer store.
+    async fn observed_peers(
+        &self, start: i64, end: i64, metadata: &HashMap<PeerId, PeerMetadata>
+    ) -> RgResult<Vec<(PeerId, i64)>> {
+        let node_peers = node_peers(metadata);
+        let mut res = vec![];
+        for entry in self.relay.ds.observation.query_time_observation(start, end - 1).await? {
+            let Some(tx) = entry.observation else { continue };
+            let Ok(pk) = tx.observation_public_key() else { continue };
+            if let Some(pid) = node_peers.get(pk) {
+                res.push((pid.clone(), *tx.time()?));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Reward transaction for the epoch, None if it collected no fees or nobody qualified.
+    pub async fn build_epoch_reward(&self, epoch: usize) -> RgResult<Option<Transaction>> {
+        let (start, end) = epoch_bounds(&self.moons, epoch).ok_or(error_message(
+            ErrorCode::InvalidReward, format!("Unknown reward epoch {}", epoch)
+        ))?;
+        let fee_addresses = self.fee_addresses_at(end);
+        let fee_utxos = self.relay.ds.transaction_store
+            .query_time_transaction_accepted_ordered(start, end).await?
+            .iter()
+            .filter(|t| !t.is_reward())
+            .flat_map(|t| t.fee_utxos(&fee_addresses))
+            .collect_vec();
+        let fees_collected = fee_utxos.iter().map(|(_, amount)| *amount).sum::<i64>();
+
+        let metadata = peer_metadata_at(&self.relay, end).await?;
+        let edges = trust_edges(&labels_by_peer(&metadata));
+        let pre_trust = seed_pre_trust(&self.relay.node_config, end);
+        let trust = compute_eigen_trust(&edges, &pre_trust, DEFAULT_PRE_TRUST_WEIGHT, end).score_map();
+        let uptime = uptime_fractions(start, end, &self.observed_peers(start, end, &metadata).await?);
+        let reward_addresses = metadata.into_iter()
+            .filter_map(|(p, pd)| pd.reward_address.map(|a| (p, a)))
+            .collect::<HashMap<PeerId, Address>>();
+
+        let distribution = RewardDistribution {
+            epoch: epoch as i64,
+            start_time: start,
+            end_time: end,
+            fees_collected,
+        };
+        let inputs = fee_utxos.into_iter().map(|(u, _)| u).collect_vec();
+        let weights = reward_weights(&trust, &uptime, &reward_addresses);
+        Ok(reward_transaction(&distribution, &inputs, &weights, &self.relay.node_config.network))
+    }
+
+    /// Reward transactions already accepted for the epoch, they're timestamped at its end.
+    async fn accepted_epoch_rewards(&self, epoch: usize) -> RgResult<Vec<Transaction>> {
+        let Some((_, end)) = epoch_bounds(&self.moons, epoch) else { return Ok(vec![]) };
+        Ok(self.relay.ds.transaction_store.query_time_transaction_accepted_ordered(end, end + 1).await?
+            .into_iter()
+            .filter(|t| t.reward_distribution().map(|d| d.epoch == epoch as i64).unwrap_or(false))
+            .collect_vec())
+    }
+
+    fn completed_epochs(&self, genesis_time: i64) -> Vec<usize> {
+        (1..self.moons.len())
+            .filter(|e| epoch_bounds(&self.moons, *e)
+                .map(|(_, end)| end > genesis_time && self.settled(end))
+                .unwrap_or(false))
+            .collect()
+    }
+}
+
+/// Reject any reward transaction that differs from the one built locally for its epoch.
+pub async fn validate_reward_transaction(relay: &Relay, tx: &Transaction) -> RgResult<()> {
+    let d = tx.reward_distribution().ok_or(error_message(
+        ErrorCode::InvalidReward, "Reward transaction missing distribution"
+    ))?;
+    let rewards = Rewards::new(relay);
+    if !rewards.settled(d.end_time) {
+        return Err(error_message(ErrorCode::InvalidReward, "Reward epoch has not settled"));
+    }
+    if rewards.accepted_epoch_rewards(d.epoch as usize).await?.iter().any(|t| t.hash_or() != tx.hash_or()) {
+        return Err(error_message(ErrorCode::InvalidReward, format!(
+            "Reward for epoch {} was already accepted", d.epoch
+        )));
+    }
+    let expected = rewards.build_epoch_reward(d.epoch as usize).await?;
+    if expected.map(|e| e.hash_or()) != Some(tx.hash_or()) {
+        return Err(error_message(ErrorCode::InvalidReward, format!(
+            "Reward transaction for epoch {} does not match locally derived payouts", d.epoch
+        )));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl IntervalFold for Rewards {
+    async fn interval_fold(&mut self) -> RgResult<()> {
+        if !self.relay.is_seed(&self.relay.node_config.public_key()).await {
+            return Ok(());
+        }
+        let Some(genesis) = self.relay.ds.config_store.get_genesis().await? else {
+            return Ok(());
+        };
+        let genesis_time = *genesis.time()?;
+        let mut built = 0;
+        for epoch in self.completed_epochs(genesis_time).into_iter().rev() {
+            if built >= MAX_EPOCHS_PER_TICK {
+                break;
+            }
+            built += 1;
+            if !self.accepted_epoch_rewards(epoch).await?.is_empty() {
+                // Earlier epochs were handled by previous ticks.
+                break;
+            }
+            let Some(tx) = self.build_epoch_reward(epoch).await? else { continue };
+            if self.relay.transaction_known(&tx.hash_or()).await? {
+                // Submitted on an earlier tick and not yet accepted
+                continue;
+            }
+            info!("Submitting reward transaction {} for epoch {}", tx.hash_or().hex(), epoch);
+            counter!("redgold_reward_transactions_submitted").increment(1);
+            if let Err(e) = self.relay.submit_transaction_sync(&tx).await {
+                error!("Reward transaction submission failed for epoch {}: {}", epoch, e.json_or());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn uptime_counts_distinct_slots() {
+    let p1 = PeerId::from_bytes_direct(vec![1; 33]);
+    let p2 = PeerId::from_bytes_direct(vec![2; 33]);
+    let observed = vec![
+        (p1.clone(), 0), (p1.clone(), 10), (p1.clone(), UPTIME_SLOT_MILLIS),
+        (p2.clone(), UPTIME_SLOT_MILLIS * 3), (p2.clone(), UPTIME_SLOT_MILLIS * 4),
+    ];
+    let uptime = uptime_fractions(0, UPTIME_SLOT_MILLIS * 4, &observed);
+    assert_eq!(uptime[&p1], 0.5);
+    assert_eq!(uptime[&p2], 0.25);
+
+    let addresses = vec![(p1.clone(), Address::from_bitcoin_external(&"a".to_string())), (p2.clone(), Address::from_bitcoin_external(&"b".to_string()))]
+        .into_iter().collect::<HashMap<_, _>>();
+    let trust = vec![(p1.clone(), 1.0), (p2.clone(), 1.0)].into_iter().collect::<HashMap<_, _>>();
+    let weights = reward_weights(&trust, &uptime, &addresses).into_iter().map(|(_, w)| w).collect_vec();
+    assert_eq!(weights, vec![500_000, 250_000]);
+}
@@ -8,6 +8,7 @@ use tokio::fs;
 use reqwest;
 
 use crate::core::backup::aws_backup::AwsBackup;
+use crate::core::backup::encrypted_backup::restore_data_store;
 use crate::core::relay::Relay;
 use crate::core::transact::tx_broadcast_support::TxBroadcastSupport;
 use crate::core::transact::tx_builder_supports::{TxBuilderApiConvert, TxBuilderApiSupport};
@@ -32,7 +33,7 @@ use redgold_keys::util::mnemonic_support::MnemonicSupport;
 use redgold_keys::word_pass_support::{NodeConfigKeyPair, WordsPassNodeConfig};
 use redgold_keys::KeyPair;
 use redgold_schema::conf::node_config::NodeConfig;
-use redgold_schema::conf::rg_args::{AddServer, BalanceCli, DebugCommand, Deploy, FaucetCli, ColdWordMixer, QueryCli, RgDebugCommand, TestTransactionCli, WalletAddress, WalletSend};
+use redgold_schema::conf::rg_args::{AddServer, BalanceCli, DebugCommand, Deploy, FaucetCli, ColdWordMixer, QueryCli, RestoreCli, RgDebugCommand, TestTransactionCli, WalletAddress, WalletSend};
 use redgold_schema::helpers::easy_json::EasyJson;
 use redgold_schema::helpers::easy_json::{json, json_from, json_pretty};
 use redgold_schema::helpers::with_metadata_hashable::WithMetadataHashable;
@@ -206,8 +207,13 @@ pub async fn balance_lookup(request: &BalanceCli, nc: &Box<NodeConfig>) -> Resul
     };
     // println!("about to query");
     let response = nc.api_client().query_hash(addr.render_string()?).await?;
-    let rounded = rounded_balance_i64(response.address_info.safe_get_msg("missing address_info")?.balance);
+    let info = response.address_info.safe_get_msg("missing address_info")?;
+    let rounded = rounded_balance_i64(info.balance);
     println!("{}", rounded.to_string());
+    if info.locked_balance > 0 {
+        println!("spendable: {} locked: {}",
+                 rounded_balance_i64(info.spendable_balance), rounded_balance_i64(info.locked_balance));
+    }
     Ok(())
 }
 
@@ -717,6 +723,12 @@ async fn copy_usb_info(p1: &Box<NodeConfig>) -> RgResult<()> {
     Ok(())
 }
 
+pub async fn restore(r: &RestoreCli, nc: &NodeConfig) -> RgResult<()> {
+    let path = restore_data_store(nc, r.target.as_ref(), r.snapshot, r.verify).await?;
+    println!("Restored data store to {}", path.to_string_lossy());
+    Ok(())
+}
+
 pub async fn cold_mix(c: ColdWordMixer, nc: &NodeConfig) -> RgResult<()> {
     let words = nc.secure_mnemonic_words().unwrap();
     let pass = get_input("Enter mixing password:", true).await.unwrap().unwrap();